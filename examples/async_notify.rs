@@ -1,12 +1,12 @@
 extern crate ssdp;
 
-use std::io::{self, Read};
+use std::io;
 use std::thread;
 use std::time::Duration;
 
 use ssdp::FieldMap;
 use ssdp::header::{HeaderMut, NT, NTS, USN};
-use ssdp::message::{NotifyListener, NotifyMessage, Listen, Multicast};
+use ssdp::message::{NotifyListener, NotifyMessage, Multicast};
 
 fn main() {
     thread::spawn(|| {
@@ -30,7 +30,7 @@ fn main() {
 
     // Wait Until User Is Done Listening For Notify Messages
     println!("Press Enter When You Wish To Exit...\n");
-    let input = io::stdin();
+    let mut input = String::new();
 
-    input.bytes().next();
+    io::stdin().read_line(&mut input).unwrap();
 }
@@ -1,6 +1,6 @@
 use log::Log;
 
-use ssdp::header::{HeaderMut, Man, MX, ST};
+use ssdp::header::ST;
 use ssdp::message::{Multicast, SearchRequest};
 
 struct SimpleLogger;
@@ -24,12 +24,7 @@ fn main() {
     log::set_max_level(log::LevelFilter::Trace);
 
     // Create Our Search Request
-    let mut request = SearchRequest::new();
-
-    // Set Our Desired Headers (Not Verified By The Library)
-    request.set(Man);
-    request.set(MX(5));
-    request.set(ST::All);
+    let request = SearchRequest::new().with_st(ST::All).with_mx(5).unwrap();
 
     // Collect Our Responses
     let responses = request.multicast().unwrap().into_iter().collect::<Vec<_>>();
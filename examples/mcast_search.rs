@@ -1,14 +1,9 @@
-use ssdp::header::{HeaderMut, Man, MX, ST};
+use ssdp::header::ST;
 use ssdp::message::{SearchRequest, Multicast};
 
 fn main() {
     // Create Our Search Request
-    let mut request = SearchRequest::new();
-
-    // Set Our Desired Headers (Not Verified By The Library)
-    request.set(Man);
-    request.set(MX(5));
-    request.set(ST::All);
+    let request = SearchRequest::new().with_st(ST::All).with_mx(5).unwrap();
 
     // Iterate Over Streaming Responses
     for (msg, src) in request.multicast().unwrap() {
@@ -0,0 +1,326 @@
+//! Infrastructure shared by receivers, advertisers and responders.
+
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::thread;
+use std::time::Instant;
+
+use crate::error::{SSDPError, SSDPResult};
+use crate::message::{self, Advertiser, Config, DeviceCache, Discovery, SearchRequest, SearchResponder, SearchResponse};
+use crate::net::NetifAddr;
+use crate::receiver::{FromRawSSDP, SSDPReceiver};
+use crate::schedule::ScheduledSender;
+
+/// A task executed in the background, usually for the lifetime of a receiver or advertiser.
+pub type Task = Box<dyn FnOnce() + Send>;
+
+/// Trait for running background tasks.
+///
+/// Tasks block on sockets and timers, so each one needs to run on its own thread.
+pub trait Spawn: Send + Sync {
+    /// Run the task in the background.
+    fn spawn(&self, task: Task);
+}
+
+/// Trait for reading the current time.
+pub trait Clock: Send + Sync {
+    /// Get the current time.
+    fn now(&self) -> Instant;
+}
+
+/// Trait for enumerating the network interfaces to use.
+pub trait InterfaceSource: Send + Sync {
+    /// Get the addresses of all local interfaces that should be used.
+    fn interfaces(&self) -> io::Result<Vec<NetifAddr>>;
+
+    /// Get the addresses of all local interfaces that should be used with the config.
+    ///
+    /// The default implementation ignores the config, the interfaces of the system are filtered
+    /// by options such as `Config::set_include_loopback`.
+    fn interfaces_for(&self, config: &Config) -> io::Result<Vec<NetifAddr>> {
+        let _ = config;
+        self.interfaces()
+    }
+}
+
+/// Trait for observing the packets sent and received, for example to collect metrics.
+///
+/// Every method does nothing by default. They are called on the sending thread and the threads of
+/// receivers, so an observer should return quickly. See `Config::set_observer`.
+pub trait SsdpObserver: Send + Sync {
+    /// A packet was sent from the socket bound to the local address.
+    fn on_send(&self, iface: &SocketAddr, bytes: &[u8]) {
+        let _ = (iface, bytes);
+    }
+
+    /// A packet was received from the peer, before it is filtered or parsed.
+    fn on_recv(&self, peer: &SocketAddr, bytes: &[u8]) {
+        let _ = (peer, bytes);
+    }
+
+    /// A packet received from the peer could not be parsed.
+    fn on_parse_error(&self, peer: &SocketAddr, err: &SSDPError) {
+        let _ = (peer, err);
+    }
+}
+
+impl fmt::Debug for dyn SsdpObserver {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("SsdpObserver")
+    }
+}
+
+impl<F: Fn(Task) + Send + Sync> Spawn for F {
+    fn spawn(&self, task: Task) {
+        self(task)
+    }
+}
+
+impl<F: Fn() -> Instant + Send + Sync> Clock for F {
+    fn now(&self) -> Instant {
+        self()
+    }
+}
+
+impl<F: Fn() -> io::Result<Vec<NetifAddr>> + Send + Sync> InterfaceSource for F {
+    fn interfaces(&self) -> io::Result<Vec<NetifAddr>> {
+        self()
+    }
+}
+
+/// Counters shared by everything created from the same context.
+#[derive(Default, Debug)]
+pub struct Stats {
+    listeners: AtomicUsize,
+    packets_received: AtomicUsize,
+    packets_filtered: AtomicUsize,
+    messages_sent: AtomicUsize,
+}
+
+impl Stats {
+    /// Get the number of listeners that have been created.
+    pub fn listeners(&self) -> usize {
+        self.listeners.load(Ordering::Relaxed)
+    }
+
+    /// Get the number of packets that have been received.
+    pub fn packets_received(&self) -> usize {
+        self.packets_received.load(Ordering::Relaxed)
+    }
+
+    /// Get the number of packets that were dropped for their source.
+    ///
+    /// See `Config::set_accept_only_local_sources`, `Config::set_ignore_self`,
+    /// `Config::set_self_uuid`, `Config::set_raw_filter` and `ParseOptions::set_location_policy`.
+    pub fn packets_filtered(&self) -> usize {
+        self.packets_filtered.load(Ordering::Relaxed)
+    }
+
+    /// Get the number of messages that have been sent.
+    pub fn messages_sent(&self) -> usize {
+        self.messages_sent.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn add_listener(&self) {
+        self.listeners.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn add_packet_received(&self) {
+        self.packets_received.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn add_packet_filtered(&self) {
+        self.packets_filtered.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn add_message_sent(&self) {
+        self.messages_sent.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Owns the infrastructure shared between several configurations.
+///
+/// Receivers, advertisers and responders created from one context share the spawner, clock,
+/// statistics and interface source while each uses its own `Config`. This allows an application
+/// to act as a control point on one network and as a device on another at the same time.
+///
+/// The free functions and trait methods of this crate use the `global` context.
+#[derive(Clone)]
+pub struct SsdpContext {
+    spawner: Arc<dyn Spawn>,
+    clock: Arc<dyn Clock>,
+    interfaces: Arc<dyn InterfaceSource>,
+    stats: Arc<Stats>,
+}
+
+impl SsdpContext {
+    /// Construct a context using threads, the system clock and the system interfaces.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Get the context used by free functions and trait methods.
+    pub fn global() -> &'static SsdpContext {
+        static GLOBAL: OnceLock<SsdpContext> = OnceLock::new();
+        GLOBAL.get_or_init(SsdpContext::new)
+    }
+
+    pub fn set_spawner<S: Spawn + 'static>(mut self, value: S) -> Self {
+        self.spawner = Arc::new(value);
+        self
+    }
+
+    pub fn set_clock<C: Clock + 'static>(mut self, value: C) -> Self {
+        self.clock = Arc::new(value);
+        self
+    }
+
+    pub fn set_interfaces<I: InterfaceSource + 'static>(mut self, value: I) -> Self {
+        self.interfaces = Arc::new(value);
+        self
+    }
+
+    /// Get the statistics shared by everything created from this context.
+    pub fn stats(&self) -> &Stats {
+        &self.stats
+    }
+
+    /// Get the current time of this context's clock.
+    pub fn now(&self) -> Instant {
+        self.clock.now()
+    }
+
+    /// Run a task in the background.
+    pub fn spawn<F: FnOnce() + Send + 'static>(&self, task: F) {
+        self.spawner.spawn(Box::new(task))
+    }
+
+    /// Get the local interfaces to use for a config.
+    ///
+    /// These are all interfaces of the interface source, restricted to the interfaces of the
+    /// config if it names any.
+    pub fn local_interfaces(&self, config: &Config) -> io::Result<Vec<NetifAddr>> {
+        let mut addrs = self.interfaces.interfaces_for(config)?;
+
+        if let Some(ref allowed) = config.interfaces {
+            addrs.retain(|addr| allowed.contains(&addr.sock));
+        }
+
+        Ok(addrs)
+    }
+
+    /// Listen for messages on the network described by the config.
+    pub fn listen<T>(&self, config: &Config) -> SSDPResult<SSDPReceiver<T>>
+    where
+        T: FromRawSSDP + Send + 'static,
+    {
+        message::listen::listen_with_context(self, config)
+    }
+
+    /// Multicast a search request on the network described by the config.
+    pub fn search(&self, request: &SearchRequest, config: &Config) -> SSDPResult<SSDPReceiver<SearchResponse>> {
+        request.multicast_with_context(self, config)
+    }
+
+    /// Start announcing the entries of an advertiser on the network described by the config.
+    pub fn advertiser(&self, config: &Config, mut advertiser: Advertiser) -> SSDPResult<Advertiser> {
+        advertiser.start_with_context(self, config)?;
+        Ok(advertiser)
+    }
+
+    /// Start discovering devices and services on the network described by the config.
+    pub fn discovery(&self, config: &Config, mut discovery: Discovery) -> SSDPResult<Discovery> {
+        discovery.start_with_context(self, config)?;
+        Ok(discovery)
+    }
+
+    /// Construct an empty device cache using the clock and spawner of this context.
+    pub fn device_cache(&self) -> DeviceCache {
+        DeviceCache::with_context(self)
+    }
+
+    /// Construct a sender for delayed packets using the clock and spawner of this context.
+    pub fn scheduled_sender(&self, capacity: usize) -> ScheduledSender {
+        ScheduledSender::with_context(self, capacity)
+    }
+
+    /// Start answering search requests on the network described by the config.
+    pub fn responder(&self, config: &Config, mut responder: SearchResponder) -> SSDPResult<SearchResponder> {
+        responder.start_with_context(self, config)?;
+        Ok(responder)
+    }
+}
+
+impl Default for SsdpContext {
+    fn default() -> Self {
+        SsdpContext {
+            spawner: Arc::new(|task: Task| {
+                thread::spawn(task);
+            }),
+            clock: Arc::new(Instant::now),
+            interfaces: Arc::new(message::SystemInterfaces),
+            stats: Arc::new(Stats::default()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::time::Duration;
+
+    use super::SsdpContext;
+    use crate::header::{HeaderMut, NT, NTS, USN};
+    use crate::message::{Config, NotifyMessage};
+    use crate::net::NetifAddr;
+    use crate::FieldMap;
+
+    fn loopback() -> io::Result<Vec<NetifAddr>> {
+        Ok(vec![NetifAddr {
+            sock: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            index: 1,
+        }])
+    }
+
+    fn config(port: u16) -> Config {
+        Config::new()
+            .set_port(port)
+            .set_mode(crate::IpVersionMode::V4Only)
+            .set_interfaces([IpAddr::V4(Ipv4Addr::LOCALHOST)])
+    }
+
+    #[test]
+    fn positive_isolated_listeners() {
+        let context = SsdpContext::new().set_interfaces(loopback);
+        let config_a = config(19_004);
+        let config_b = config(19_005).set_ttl(1);
+
+        let listener_a = context.listen::<NotifyMessage>(&config_a).unwrap();
+        let listener_b = context.listen::<NotifyMessage>(&config_b).unwrap();
+
+        let mut message = NotifyMessage::new();
+        message.set(NT(FieldMap::upnp("rootdevice")));
+        message.set(NTS::Alive);
+        message.set(USN(FieldMap::uuid("context-test"), None));
+        message.multicast_with_context(&context, &config_a).unwrap();
+
+        listener_a.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(listener_b.recv_timeout(Duration::from_millis(500)).is_err());
+
+        assert_eq!(context.stats().listeners(), 2);
+        assert_eq!(context.stats().messages_sent(), 1);
+        assert!(context.stats().packets_received() >= 1);
+    }
+
+    #[test]
+    fn positive_custom_clock() {
+        let start = std::time::Instant::now();
+        let context = SsdpContext::new().set_clock(move || start);
+
+        assert_eq!(context.now(), start);
+    }
+}
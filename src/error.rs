@@ -1,12 +1,22 @@
 use std::borrow::Cow;
+use std::fmt;
 use std::io;
-use std::net;
+use std::net::{self, SocketAddr};
 
 use quick_error::quick_error;
 
+use crate::net::packet::PacketOverflow;
+use crate::net::{IoAt, IoTarget};
+
 quick_error! {
     /// Enumerates all errors that can occur when dealing with an SSDP message.
+    ///
+    /// Earlier versions wrapped the errors of `hyper` as `Hyper` and `HyperParseError`. Messages
+    /// are now parsed with `httparse`, so malformed messages are reported as `InvalidHttp`,
+    /// `PartialHttp` or `InvalidBodyForMethod` and failures of the network as `Io`. The
+    /// underlying error, if any, is available through `Error::source`.
     #[derive(Debug)]
+    #[non_exhaustive]
     pub enum SSDPError {
         /// Message is not valid HTTP.
         ///
@@ -15,6 +25,7 @@ quick_error! {
             from()
             // description("invalid HTTP")
             display("invalid HTTP message: '{:?}'", message)
+            source(message)
         }
         /// Message is not valid HTTP.
         PartialHttp {
@@ -25,7 +36,9 @@ quick_error! {
             display("body is not allowed in method {}", method)
         }
         /// Message did not specify HTTP/1.1 as version.
-        InvalidHttpVersion { }
+        InvalidHttpVersion {
+            display("HTTP version is not HTTP/1.1")
+        }
         /// Message consists of an error code.
         ///
         /// Error code is supplied.
@@ -54,24 +67,204 @@ quick_error! {
             // description("missing header")
             display("missing header: '{}'", header)
         }
-        /// Header has an invalid value.
+        /// Header has an invalid name or value.
         ///
-        /// Header name with error message are supplied.
-        InvalidHeader(header: Cow<'static, str>) {
+        /// The name of the header and the offending value are supplied.
+        InvalidHeader { name: String, value: Vec<u8> } {
             // description("invalid header")
-            display("invalid header: '{}'", header)
+            display("invalid header {}: '{}'", name, value.escape_ascii())
+        }
+        /// A unicast message could not be sent through any of the sockets.
+        ///
+        /// The local address of every socket is supplied with its error.
+        AllSendsFailed(errors: Vec<(SocketAddr, SSDPError)>) {
+            display("sending failed through all sockets: {}", AddrFailures(errors))
+        }
+        /// No socket could be bound on any of the interfaces.
+        ///
+        /// The address of every interface is supplied with its error.
+        AllBindsFailed(errors: Vec<(SocketAddr, SSDPError)>) {
+            display("binding failed on all interfaces: {}", AddrFailures(errors))
+        }
+        /// The multicast group could not be joined on any of the interfaces.
+        ///
+        /// The address of every interface is supplied with its error.
+        AllJoinsFailed(errors: Vec<(SocketAddr, SSDPError)>) {
+            display("joining the multicast group failed on all interfaces: {}", AddrFailures(errors))
+        }
+        /// A bounded queue can not take any more items.
+        QueueFull {
+            display("queue is full")
+        }
+        /// A value of the config is out of its range.
+        InvalidConfig(message: String) {
+            display("invalid config: {}", message)
+        }
+        /// A serialized message does not fit into a single packet.
+        ///
+        /// The size is a lower bound, serialization stops at the first write over the limit. The
+        /// limit is unknown for a packet the OS refused to send.
+        PacketTooLarge { size: usize, limit: Option<usize> } {
+            display("packet of {} bytes exceeds the limit of {}", size, match limit {
+                Some(limit) => format!("{} bytes", limit),
+                None => "the OS".to_owned(),
+            })
         }
 
         Io(err: io::Error) {
-            from()
             display("IO operation failed: {}", err)
+            source(err)
+        }
+        /// An IO operation on a socket failed.
+        ///
+        /// The operation is supplied together with the address and the interface it used, where
+        /// known.
+        IoAt { source: io::Error, addr: Option<SocketAddr>, iface: Option<String>, op: &'static str } {
+            display("{} failed: {}", IoTarget { op, addr, iface }, source)
+            source(source)
         }
 
         AddrParseError(err: net::AddrParseError) {
             from()
             display("invalid address: {}", err)
+            source(err)
         }
     }
 }
 
 pub type SSDPResult<T> = Result<T, SSDPError>;
+
+/// Formats the errors of `AllSendsFailed`, `AllBindsFailed` and `AllJoinsFailed` as a list.
+struct AddrFailures<'a>(&'a [(SocketAddr, SSDPError)]);
+
+impl fmt::Display for AddrFailures<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.0.is_empty() {
+            return f.write_str("no sockets");
+        }
+
+        for (i, (addr, err)) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str("; ")?;
+            }
+
+            write!(f, "{}: {}", addr, err)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl From<io::Error> for SSDPError {
+    fn from(err: io::Error) -> Self {
+        let Some(inner) = err.get_ref() else {
+            return SSDPError::Io(err);
+        };
+
+        if let Some(overflow) = inner.downcast_ref::<PacketOverflow>() {
+            return SSDPError::PacketTooLarge {
+                size: overflow.size,
+                limit: overflow.limit,
+            };
+        }
+
+        if !inner.is::<IoAt>() {
+            return SSDPError::Io(err);
+        }
+
+        match err.into_inner().map(|inner| inner.downcast::<IoAt>()) {
+            Some(Ok(at)) => SSDPError::IoAt {
+                source: at.source,
+                addr: at.addr,
+                iface: at.iface,
+                op: at.op,
+            },
+            _ => unreachable!("the error was checked to be an `IoAt`"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error as _;
+    use std::io;
+
+    use super::SSDPError;
+
+    #[test]
+    fn positive_display() {
+        let cases = [
+            (SSDPError::InvalidHttp(httparse::Error::Token), "invalid HTTP message: 'Token'"),
+            (SSDPError::PartialHttp, "partial HTTP message"),
+            (SSDPError::InvalidBodyForMethod("M-SEARCH".into()), "body is not allowed in method M-SEARCH"),
+            (SSDPError::InvalidHttpVersion, "HTTP version is not HTTP/1.1"),
+            (SSDPError::ResponseCode(404), "HTTP Error response: 404"),
+            (SSDPError::InvalidMethod("GET".into()), "invalid SSDP method: 'GET'"),
+            (SSDPError::InvalidUri("/".into()), "invalid URI: '/'"),
+            (SSDPError::MissingHeader("NT"), "missing header: 'NT'"),
+            (
+                SSDPError::InvalidHeader {
+                    name: "MX".into(),
+                    value: b"\xff1".to_vec(),
+                },
+                "invalid header MX: '\\xff1'",
+            ),
+            (
+                SSDPError::AllSendsFailed(vec![
+                    ("127.0.0.1:4000".parse().unwrap(), SSDPError::PartialHttp),
+                    ("10.0.0.2:4000".parse().unwrap(), SSDPError::QueueFull),
+                ]),
+                "sending failed through all sockets: 127.0.0.1:4000: partial HTTP message; \
+                 10.0.0.2:4000: queue is full",
+            ),
+            (SSDPError::QueueFull, "queue is full"),
+            (
+                SSDPError::PacketTooLarge {
+                    size: 2000,
+                    limit: Some(1500),
+                },
+                "packet of 2000 bytes exceeds the limit of 1500 bytes",
+            ),
+            (
+                SSDPError::PacketTooLarge { size: 2000, limit: None },
+                "packet of 2000 bytes exceeds the limit of the OS",
+            ),
+            (SSDPError::Io(io::Error::other("unreachable")), "IO operation failed: unreachable"),
+            (
+                SSDPError::IoAt {
+                    source: io::Error::new(io::ErrorKind::AddrInUse, "Address already in use"),
+                    addr: Some("0.0.0.0:1900".parse().unwrap()),
+                    iface: Some("eth1".into()),
+                    op: "bind",
+                },
+                "bind 0.0.0.0:1900 on eth1 failed: Address already in use",
+            ),
+            (
+                SSDPError::AddrParseError("".parse::<std::net::IpAddr>().unwrap_err()),
+                "invalid address: invalid IP address syntax",
+            ),
+        ];
+
+        for (err, display) in cases {
+            assert_eq!(err.to_string(), display);
+        }
+    }
+
+    #[test]
+    fn positive_source() {
+        let err = SSDPError::from(httparse::Error::Token);
+        assert!(err.source().unwrap().is::<httparse::Error>());
+
+        let err = SSDPError::from(io::Error::other("unreachable"));
+        assert!(err.source().unwrap().is::<io::Error>());
+
+        let err = SSDPError::from("not an address".parse::<std::net::IpAddr>().unwrap_err());
+        assert!(err.source().unwrap().is::<std::net::AddrParseError>());
+    }
+
+    #[test]
+    fn negative_source() {
+        assert!(SSDPError::PartialHttp.source().is_none());
+        assert!(SSDPError::MissingHeader("NT").source().is_none());
+    }
+}
@@ -6,16 +6,17 @@
 use std::fmt::{Display, Error, Formatter};
 use std::result::Result;
 use std::borrow::Cow;
+use std::str::FromStr;
 
 /// Separator character for a `FieldMap` and it's value.
 pub const PAIR_SEPARATOR: char = ':';
 
 /// Prefix for the "upnp" field key.
-const UPNP_PREFIX: &'static str = "upnp";
+const UPNP_PREFIX: &str = "upnp";
 /// Prefix for the "uuid" field key.
-const UUID_PREFIX: &'static str = "uuid";
+const UUID_PREFIX: &str = "uuid";
 /// Prefix for the "usn" field key.
-const URN_PREFIX: &'static str = "urn";
+const URN_PREFIX: &str = "urn";
 
 /// Enumerates key value pairs embedded within SSDP header fields.
 #[derive(Clone, PartialEq, Eq, Hash, Debug)]
@@ -30,6 +31,13 @@ pub enum FieldMap {
     Unknown(String, String),
 }
 
+/// The error of a key or value that can not be part of a `FieldMap`.
+///
+/// Both must be non-empty and free of line breaks, and an unknown key must neither contain the
+/// separator nor be one of the known keys, so that the displayed field parses to the same value.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseFieldMapError(String);
+
 impl FieldMap {
     /// Breaks a field up into a single key and single value which are
     /// separated by a colon and neither of which are empty.
@@ -42,19 +50,17 @@ impl FieldMap {
     /// Breaks a field up into a single key and single value which are
     /// separated by a colon and neither of which are empty.
     ///
-    /// Separation will occur at the first colon encountered.
+    /// Separation will occur at the first colon encountered. The known keys are recognized
+    /// regardless of their case, as some devices send `UUID:` or `Urn:`, the value is kept as is.
     pub fn parse_bytes(field: &[u8]) -> Option<Self> {
-        let split_index = match field.iter().position(|&b| b == PAIR_SEPARATOR as u8) {
-            Some(n) => n,
-            None => return None,
-        };
+        let split_index = field.iter().position(|&b| b == PAIR_SEPARATOR as u8)?;
         let (key, mut value) = field.split_at(split_index);
 
         // Ignore Separator Byte
         value = &value[1..];
 
         // Check Empty Byte Slices
-        if key.len() == 0 || value.len() == 0 {
+        if key.is_empty() || value.is_empty() {
             return None;
         }
 
@@ -72,23 +78,108 @@ impl FieldMap {
         }
     }
 
+    /// Construct a field with the "upnp" key.
+    ///
+    /// The value is not checked, use `try_upnp` for values that are not known to be valid.
     pub fn upnp<'a, S: Into<Cow<'a, str>>>(value: S) -> Self {
         FieldMap::UPnP(value.into().into_owned())
     }
 
+    /// Construct a field with the "uuid" key.
+    ///
+    /// The value is not checked, use `try_uuid` for values that are not known to be valid.
     pub fn uuid<'a, S: Into<Cow<'a, str>>>(value: S) -> Self {
         FieldMap::UUID(value.into().into_owned())
     }
 
+    /// Construct a field with the "urn" key.
+    ///
+    /// The value is not checked, use `try_urn` for values that are not known to be valid.
     pub fn urn<'a, S: Into<Cow<'a, str>>>(value: S) -> Self {
         FieldMap::URN(value.into().into_owned())
     }
 
+    /// Construct a field with a key that is none of the known keys.
+    ///
+    /// Neither is checked, use `try_unknown` for keys and values that are not known to be valid.
     pub fn unknown<'a, S: Into<Cow<'a, str>>, S2: Into<Cow<'a, str>>>(key: S, value: S2) -> Self {
         FieldMap::Unknown(key.into().into_owned(), value.into().into_owned())
     }
+
+    /// Construct a field with the "upnp" key, failing if the value is empty or contains a line
+    /// break.
+    pub fn try_upnp<'a, S: Into<Cow<'a, str>>>(value: S) -> Result<Self, ParseFieldMapError> {
+        checked(FieldMap::upnp(value))
+    }
+
+    /// Construct a field with the "uuid" key, failing if the value is empty or contains a line
+    /// break.
+    pub fn try_uuid<'a, S: Into<Cow<'a, str>>>(value: S) -> Result<Self, ParseFieldMapError> {
+        checked(FieldMap::uuid(value))
+    }
+
+    /// Construct a field with the "urn" key, failing if the value is empty or contains a line
+    /// break.
+    pub fn try_urn<'a, S: Into<Cow<'a, str>>>(value: S) -> Result<Self, ParseFieldMapError> {
+        checked(FieldMap::urn(value))
+    }
+
+    /// Construct a field with a key that is none of the known keys.
+    ///
+    /// Fails if the key or value is empty or contains a line break, or if the key contains the
+    /// separator or is one of the known keys, in which case the field would be parsed as another
+    /// variant.
+    pub fn try_unknown<'a, S: Into<Cow<'a, str>>, S2: Into<Cow<'a, str>>>(
+        key: S,
+        value: S2,
+    ) -> Result<Self, ParseFieldMapError> {
+        checked(FieldMap::unknown(key, value))
+    }
+
+    /// Check that the displayed field parses to the same value.
+    fn validate(&self) -> Result<(), ParseFieldMapError> {
+        let value = match *self {
+            FieldMap::UPnP(ref v) | FieldMap::UUID(ref v) | FieldMap::URN(ref v) => v,
+            FieldMap::Unknown(ref k, ref v) => {
+                let known = matches_uuid_key(k) || matches_urn_key(k) || matches_upnp_key(k);
+
+                if k.is_empty() || has_line_break(k) || k.contains(PAIR_SEPARATOR) || known {
+                    return Err(ParseFieldMapError(format!("invalid field key '{}'", k.escape_debug())));
+                }
+
+                v
+            }
+        };
+
+        if value.is_empty() || has_line_break(value) {
+            return Err(ParseFieldMapError(format!("invalid field value '{}'", value.escape_debug())));
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for FieldMap {
+    type Err = ParseFieldMapError;
+
+    /// Parse a field the way `parse_bytes` does, rejecting line breaks.
+    fn from_str(field: &str) -> Result<FieldMap, ParseFieldMapError> {
+        let parsed = FieldMap::parse_bytes(field.as_bytes())
+            .ok_or_else(|| ParseFieldMapError(format!("invalid field '{}'", field.escape_debug())))?;
+
+        parsed.validate()?;
+        Ok(parsed)
+    }
+}
+
+impl Display for ParseFieldMapError {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        f.write_str(&self.0)
+    }
 }
 
+impl std::error::Error for ParseFieldMapError {}
+
 impl Display for FieldMap {
     fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
         let value = match *self {
@@ -115,19 +206,30 @@ impl Display for FieldMap {
     }
 }
 
+/// Returns the field if it is valid.
+fn checked(field: FieldMap) -> Result<FieldMap, ParseFieldMapError> {
+    field.validate()?;
+    Ok(field)
+}
+
+/// Returns true if the key or value would end the header line.
+fn has_line_break(s: &str) -> bool {
+    s.contains(['\r', '\n'])
+}
+
 /// Returns the header field value if the key matches the uuid key, else returns None.
 fn matches_uuid_key(key: &str) -> bool {
-    UUID_PREFIX == key
+    UUID_PREFIX.eq_ignore_ascii_case(key)
 }
 
 /// Returns the header field value if the key matches the urn key, else returns None.
 fn matches_urn_key(key: &str) -> bool {
-    URN_PREFIX == key
+    URN_PREFIX.eq_ignore_ascii_case(key)
 }
 
 /// Returns the header field value if the key matches the upnp key, else returns None.
 fn matches_upnp_key(key: &str) -> bool {
-    UPNP_PREFIX == key
+    UPNP_PREFIX.eq_ignore_ascii_case(key)
 }
 
 #[cfg(test)]
@@ -137,15 +239,15 @@ mod tests {
     #[test]
     fn positive_non_utf8() {
         let uuid_pair = FieldMap::parse_bytes(&b"uuid:some_value_\x80"[..]).unwrap();
-        assert_eq!(uuid_pair, FieldMap::uuid(String::from_utf8_lossy(&b"some_value_\x80".to_vec())));
+        assert_eq!(uuid_pair, FieldMap::uuid(String::from_utf8_lossy(b"some_value_\x80".as_ref())));
     }
 
     #[test]
     fn positive_unknown_non_utf8() {
         let unknown_pair = FieldMap::parse_bytes(&b"some_key\x80:some_value_\x80"[..]).unwrap();
         assert_eq!(unknown_pair,
-                   FieldMap::unknown(String::from_utf8_lossy(&b"some_key\x80".to_vec()),
-                                     String::from_utf8_lossy(&b"some_value_\x80".to_vec())));
+                   FieldMap::unknown(String::from_utf8_lossy(b"some_key\x80".as_ref()),
+                                     String::from_utf8_lossy(b"some_value_\x80".as_ref())));
     }
 
     #[test]
@@ -172,6 +274,86 @@ mod tests {
         assert_eq!(unknown_pair, FieldMap::unknown("some_key", "some_value"));
     }
 
+    #[test]
+    fn positive_mixed_case_keys() {
+        assert_eq!(FieldMap::new("UUID:2F402F80-da50").unwrap(), FieldMap::uuid("2F402F80-da50"));
+        assert_eq!(FieldMap::new("Urn:Schemas-upnp-org:device:Basic:1").unwrap(),
+                   FieldMap::urn("Schemas-upnp-org:device:Basic:1"));
+        assert_eq!(FieldMap::new("UPnP:RootDevice").unwrap(), FieldMap::upnp("RootDevice"));
+    }
+
+    #[test]
+    fn negative_mixed_case_unknown() {
+        let unknown_pair = FieldMap::new("Uuids:some_value").unwrap();
+        assert_eq!(unknown_pair, FieldMap::unknown("Uuids", "some_value"));
+    }
+
+    #[test]
+    fn positive_from_str() {
+        assert_eq!("uuid:some_value".parse(), Ok(FieldMap::uuid("some_value")));
+        assert_eq!("some_key:a:b".parse(), Ok(FieldMap::unknown("some_key", "a:b")));
+    }
+
+    #[test]
+    fn positive_display_roundtrip() {
+        let keys = ["upnp", "uuid", "urn", "UUID", "some_key", "x-vendor"];
+        let values = ["rootdevice", "2f402f80-da50-11e1-9b23-001788255acc", "schemas-upnp-org:device:Basic:1",
+                      "a:b:", ":", "\u{e9}t\u{e9} "];
+
+        for key in keys {
+            for value in values {
+                let field: FieldMap = format!("{}:{}", key, value).parse().unwrap();
+                assert_eq!(field.to_string().parse(), Ok(field.clone()), "{:?}", field);
+            }
+        }
+    }
+
+    #[test]
+    fn negative_from_str() {
+        for field in ["", "uuid:", ":value", "uuid:a\r\nST: ssdp:all", "some\nkey:value"] {
+            assert!(field.parse::<FieldMap>().is_err(), "{:?}", field);
+        }
+    }
+
+    #[test]
+    fn positive_try_constructors() {
+        assert_eq!(FieldMap::try_upnp("rootdevice"), Ok(FieldMap::upnp("rootdevice")));
+        assert_eq!(FieldMap::try_uuid("some_value"), Ok(FieldMap::uuid("some_value")));
+        assert_eq!(FieldMap::try_urn("schemas-upnp-org:device:Basic:1"),
+                   Ok(FieldMap::urn("schemas-upnp-org:device:Basic:1")));
+        assert_eq!(FieldMap::try_unknown("some_key", "a:b"), Ok(FieldMap::unknown("some_key", "a:b")));
+    }
+
+    #[test]
+    fn negative_empty_value() {
+        assert!(FieldMap::try_uuid("").is_err());
+        assert!(FieldMap::try_upnp("").is_err());
+        assert!(FieldMap::try_unknown("some_key", "").is_err());
+    }
+
+    #[test]
+    fn negative_value_line_break() {
+        assert!(FieldMap::try_urn("schemas-upnp-org:device:Basic:1\r\n").is_err());
+    }
+
+    #[test]
+    fn negative_unknown_known_key() {
+        assert!(FieldMap::try_unknown("Uuid", "some_value").is_err());
+    }
+
+    #[test]
+    fn negative_unknown_separator() {
+        assert!(FieldMap::try_unknown("some:key", "some_value").is_err());
+    }
+
+    #[test]
+    fn positive_unchecked_constructors() {
+        // Unchecked constructors never panic, even for fields that do not roundtrip.
+        assert_eq!(FieldMap::uuid(""), FieldMap::UUID(String::new()));
+        assert_eq!(FieldMap::unknown("Uuid", "some_value"),
+                   FieldMap::Unknown("Uuid".to_owned(), "some_value".to_owned()));
+    }
+
     #[test]
     #[should_panic]
     fn negative_no_colon() {
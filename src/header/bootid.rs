@@ -1,5 +1,7 @@
 use headers::{Header, HeaderName, HeaderValue};
 
+use crate::header::normalize_value;
+
 /// Represents a header used to denote the boot instance of a root device.
 #[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
 pub struct BootID(pub u32);
@@ -22,10 +24,10 @@ impl Header for BootID {
             return Err(headers::Error::invalid())?;
         };
 
-        let cow_str = String::from_utf8_lossy(value.as_bytes());
+        let cow_str = String::from_utf8_lossy(normalize_value(value));
 
         // Value needs to be a 31 bit non-negative integer, so convert to i32
-        let value = match i32::from_str_radix(&*cow_str, 10) {
+        let value = match cow_str.parse::<i32>() {
             Ok(n) => n,
             Err(_) => return Err(headers::Error::invalid()),
         };
@@ -53,6 +55,7 @@ impl Header for BootID {
 #[cfg(test)]
 mod tests {
     use super::BootID;
+    use crate::header::ParseHeader as _;
 
     #[test]
     fn positive_bootid() {
@@ -68,6 +71,13 @@ mod tests {
         BootID::parse_header(bootid_header_value).unwrap();
     }
 
+    #[test]
+    fn positive_padded() {
+        let bootid_header_value = &[b" \"1216907400\" "[..].to_vec()];
+
+        assert_eq!(BootID::parse_header(bootid_header_value).unwrap(), BootID(1216907400));
+    }
+
     #[test]
     fn positive_lower_bound() {
         let bootid_header_value = &[b"0"[..].to_vec()];
@@ -1,5 +1,7 @@
 use headers::{Header, HeaderName, HeaderValue};
 
+use crate::header::normalize_value;
+
 /// Represents a header used to denote the configuration of a device's DDD.
 #[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
 pub struct ConfigID(pub u32);
@@ -18,10 +20,10 @@ impl Header for ConfigID {
             return Err(headers::Error::invalid())?;
         };
 
-        let cow_str = String::from_utf8_lossy(value.as_bytes());
+        let cow_str = String::from_utf8_lossy(normalize_value(value));
 
         // Value needs to be a 31 bit non-negative integer, so convert to i32
-        let value = match i32::from_str_radix(&*cow_str, 10) {
+        let value = match cow_str.parse::<i32>() {
             Ok(n) => n,
             Err(_) => return Err(headers::Error::invalid()),
         };
@@ -54,6 +56,7 @@ impl Header for ConfigID {
 #[cfg(test)]
 mod tests {
     use super::ConfigID;
+    use crate::header::ParseHeader as _;
     use headers::{Header, HeaderValue};
 
     #[test]
@@ -65,6 +68,15 @@ mod tests {
         ConfigID::decode(&mut configid_header_value.iter()).unwrap();
     }
 
+    #[test]
+    fn positive_padded() {
+        let configid_header_value = ["1777215 "]
+            .into_iter()
+            .map(HeaderValue::from_static)
+            .collect::<Vec<_>>();
+        assert_eq!(ConfigID::decode(&mut configid_header_value.iter()).unwrap(), ConfigID(1777215));
+    }
+
     #[test]
     fn positive_reserved() {
         let configid_header_value = &["20720000"]
@@ -78,21 +90,21 @@ mod tests {
     fn positive_lower_bound() {
         let configid_header_value = &[b"0"[..].to_vec()];
 
-        ConfigID::decode(configid_header_value).unwrap();
+        ConfigID::parse_header(configid_header_value).unwrap();
     }
 
     #[test]
     fn positive_upper_bound() {
         let configid_header_value = &[b"2147483647"[..].to_vec()];
 
-        ConfigID::decode(configid_header_value).unwrap();
+        ConfigID::parse_header(configid_header_value).unwrap();
     }
 
     #[test]
     fn positive_negative_zero() {
         let configid_header_value = &[b"-0"[..].to_vec()];
 
-        ConfigID::decode(configid_header_value).unwrap();
+        ConfigID::parse_header(configid_header_value).unwrap();
     }
 
     #[test]
@@ -100,7 +112,7 @@ mod tests {
     fn negative_overflow() {
         let configid_header_value = &[b"2290649224"[..].to_vec()];
 
-        ConfigID::decode(configid_header_value).unwrap();
+        ConfigID::parse_header(configid_header_value).unwrap();
     }
 
     #[test]
@@ -108,7 +120,7 @@ mod tests {
     fn negative_negative_overflow() {
         let configid_header_value = &[b"-2290649224"[..].to_vec()];
 
-        ConfigID::decode(configid_header_value).unwrap();
+        ConfigID::parse_header(configid_header_value).unwrap();
     }
 
     #[test]
@@ -116,6 +128,6 @@ mod tests {
     fn negative_nan() {
         let configid_header_value = &[b"2290wow649224"[..].to_vec()];
 
-        ConfigID::decode(configid_header_value).unwrap();
+        ConfigID::parse_header(configid_header_value).unwrap();
     }
 }
@@ -0,0 +1,61 @@
+use headers::{Header, HeaderName, HeaderValue};
+
+/// Represents a header used to confirm that the MAN header was understood.
+///
+/// The header is required on search responses and always has an empty value.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct Ext;
+
+impl Header for Ext {
+    fn name() -> &'static HeaderName {
+        static NAME: HeaderName = HeaderName::from_static("ext");
+        &NAME
+    }
+
+    fn decode<'i, I>(values: &mut I) -> Result<Self, headers::Error>
+    where
+        I: Iterator<Item = &'i HeaderValue>,
+    {
+        let Some(value) = values.next() else {
+            return Err(headers::Error::invalid())?;
+        };
+
+        if values.next().is_some() {
+            return Err(headers::Error::invalid())?;
+        };
+
+        if value.is_empty() {
+            Ok(Ext)
+        } else {
+            Err(headers::Error::invalid())
+        }
+    }
+
+    fn encode<E>(&self, values: &mut E)
+    where
+        E: Extend<HeaderValue>,
+    {
+        values.extend([HeaderValue::from_static("")]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Ext;
+    use crate::header::ParseHeader as _;
+
+    #[test]
+    fn positive_ext() {
+        let ext_header = &[b""[..].to_vec()];
+
+        Ext::parse_header(ext_header).unwrap();
+    }
+
+    #[test]
+    #[should_panic]
+    fn negative_value() {
+        let ext_header = &[b"ssdp"[..].to_vec()];
+
+        Ext::parse_header(ext_header).unwrap();
+    }
+}
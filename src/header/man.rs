@@ -1,6 +1,6 @@
 use headers::{Header, HeaderName, HeaderValue};
 
-const MAN_HEADER_VALUE: &'static str = "\"ssdp:discover\"";
+const MAN_HEADER_VALUE: &str = "\"ssdp:discover\"";
 
 /// Represents a header used to specify HTTP extension.
 #[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
@@ -42,6 +42,7 @@ impl Header for Man {
 #[cfg(test)]
 mod tests {
     use super::Man;
+    use crate::header::ParseHeader as _;
 
     #[test]
     fn positive_man() {
@@ -0,0 +1,127 @@
+use std::time::Duration;
+
+use headers::{Header, HeaderName, HeaderValue};
+
+/// Represents the `max-age` directive of a `CACHE-CONTROL` header, the only one SSDP uses.
+///
+/// Unlike `CacheControl` the decoder accepts whitespace around `=`, as sent by older Philips Hue
+/// bridges. It also takes the directive name in any case and a quoted value, which RFC 7234 allows.
+/// Other directives are ignored.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct SsdpMaxAge(pub Duration);
+
+impl Header for SsdpMaxAge {
+    fn name() -> &'static HeaderName {
+        &http::header::CACHE_CONTROL
+    }
+
+    fn decode<'i, I>(values: &mut I) -> Result<Self, headers::Error>
+    where
+        I: Iterator<Item = &'i HeaderValue>,
+    {
+        let max_age = values
+            .flat_map(|value| value.as_bytes().split(|&byte| byte == b','))
+            .filter_map(|directive| {
+                let mut parts = directive.splitn(2, |&byte| byte == b'=');
+                let name = parts.next()?.trim_ascii();
+                let value = parts.next()?.trim_ascii();
+                name.eq_ignore_ascii_case(b"max-age").then_some(value)
+            })
+            .next()
+            .ok_or_else(headers::Error::invalid)?;
+
+        let max_age = match max_age {
+            [b'"', inner @ .., b'"'] => inner.trim_ascii(),
+            _ => max_age,
+        };
+
+        let seconds = std::str::from_utf8(max_age)
+            .ok()
+            .and_then(|seconds| seconds.parse::<u64>().ok())
+            .ok_or_else(headers::Error::invalid)?;
+
+        Ok(SsdpMaxAge(Duration::from_secs(seconds)))
+    }
+
+    fn encode<E>(&self, values: &mut E)
+    where
+        E: Extend<HeaderValue>,
+    {
+        if let Ok(value) = HeaderValue::from_str(&format!("max-age={}", self.0.as_secs())) {
+            values.extend([value]);
+        } else {
+            debug_assert!(false, "Encoding cache-control header was invalid");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use headers::{CacheControl, Header};
+
+    use super::SsdpMaxAge;
+    use crate::header::ParseHeader as _;
+
+    fn max_age(value: &str) -> Option<Duration> {
+        SsdpMaxAge::parse_header(&[value.as_bytes().to_vec()]).ok().map(|max_age| max_age.0)
+    }
+
+    #[test]
+    fn positive_plain() {
+        assert_eq!(max_age("max-age=1800"), Some(Duration::from_secs(1800)));
+    }
+
+    #[test]
+    fn positive_hue_bridge() {
+        // Older Philips Hue bridges pad the equals sign.
+        assert_eq!(max_age("max-age = 66"), Some(Duration::from_secs(66)));
+    }
+
+    #[test]
+    fn positive_roku() {
+        // The discovery response in the documentation of Roku's External Control Protocol.
+        assert_eq!(max_age("max-age=3600"), Some(Duration::from_secs(3600)));
+    }
+
+    #[test]
+    fn positive_other_directives() {
+        assert_eq!(max_age("no-cache=\"Ext\", max-age = 5000"), Some(Duration::from_secs(5000)));
+        assert_eq!(max_age("max-age=1800, must-revalidate"), Some(Duration::from_secs(1800)));
+    }
+
+    #[test]
+    fn positive_uppercase_and_quoted() {
+        assert_eq!(max_age("Max-Age=300"), Some(Duration::from_secs(300)));
+        assert_eq!(max_age("MAX-AGE=\"120\""), Some(Duration::from_secs(120)));
+    }
+
+    #[test]
+    fn positive_several_values() {
+        let values = [b"no-cache".to_vec(), b"max-age=900".to_vec()];
+
+        assert_eq!(SsdpMaxAge::parse_header(&values).unwrap(), SsdpMaxAge(Duration::from_secs(900)));
+    }
+
+    #[test]
+    fn positive_encode_roundtrip() {
+        let mut values = Vec::new();
+        SsdpMaxAge(Duration::from_secs(1800)).encode(&mut values);
+
+        let cache_control = CacheControl::decode(&mut values.iter()).unwrap();
+        assert_eq!(cache_control.max_age(), Some(Duration::from_secs(1800)));
+    }
+
+    #[test]
+    fn negative_without_max_age() {
+        assert_eq!(max_age("no-cache"), None);
+        assert_eq!(max_age("max-age"), None);
+    }
+
+    #[test]
+    fn negative_not_a_number() {
+        assert_eq!(max_age("max-age=-1"), None);
+        assert_eq!(max_age("max-age=30m"), None);
+    }
+}
@@ -5,43 +5,90 @@
 //! HTTP parsing library.
 
 use std::fmt::Debug;
+use std::io;
 
-use headers::Header;
+use headers::{Header, HeaderName, HeaderValue};
+
+use crate::{SSDPError, SSDPResult};
 
 mod bootid;
 mod configid;
+mod ext;
 mod man;
+mod maxage;
 mod mx;
+mod nextbootid;
+mod nls;
 mod nt;
 mod nts;
+mod opt;
 mod searchport;
 // mod securelocation;
 mod st;
 mod usn;
+mod vendor;
 
 pub use self::bootid::BootID;
 pub use self::configid::ConfigID;
+pub use self::ext::Ext;
 pub use self::man::Man;
-pub use self::mx::MX;
+pub use self::maxage::SsdpMaxAge;
+pub use self::mx::{MX, MX_HEADER_MAX, MX_HEADER_MAX_UPNP11, MX_HEADER_MIN};
+pub use self::nextbootid::NextBootID;
+pub use self::nls::Nls;
 pub use self::nt::NT;
 pub use self::nts::NTS;
-pub use self::searchport::SearchPort;
+pub use self::opt::Opt;
+pub use self::searchport::{SearchPort, SEARCHPORT_MIN_VALUE};
 // pub use self::securelocation::SecureLocation;
 pub use self::st::ST;
 pub use self::usn::USN;
+pub use self::vendor::{vendor_headers, VendorHeader};
 
 // Re-exports
 pub use headers::{CacheControl, Location, Server};
 
+/// Get the bytes of a header value without surrounding whitespace and quotes.
+///
+/// Some devices pad numeric or keyword values, as in `MX: 3 `, or quote them, as in
+/// `NTS: "ssdp:alive"`. Decoders of such values parse the normalized bytes.
+pub(crate) fn normalize_value(value: &HeaderValue) -> &[u8] {
+    let bytes = value.as_bytes().trim_ascii();
+
+    match bytes {
+        [b'"', inner @ .., b'"'] => inner.trim_ascii(),
+        _ => bytes,
+    }
+}
+
 /// Trait for manipulating the contents of a header structure.
 pub trait HeaderMut: Debug {
     /// Set a header to the given value.
     fn set<H>(&mut self, value: H)
     where
         H: Header;
+
+    /// Set a header without a typed representation, replacing all of its previous values.
+    ///
+    /// See `VendorHeader::set`. The default implementation fails as unsupported.
+    fn set_raw(&mut self, name: HeaderName, value: HeaderValue) -> SSDPResult<()> {
+        let _ = (name, value);
+        Err(SSDPError::Io(io::ErrorKind::Unsupported.into()))
+    }
+
+    /// Set a header like `set_raw`, keeping the spelling of its name for `HeaderCase::AsSet`.
+    ///
+    /// The default implementation forgets the spelling.
+    fn set_raw_cased(&mut self, name: &str, value: HeaderValue) -> SSDPResult<()> {
+        let header = HeaderName::from_bytes(name.as_bytes()).map_err(|_| SSDPError::InvalidHeader {
+            name: name.into(),
+            value: value.as_bytes().to_vec(),
+        })?;
+        self.set_raw(header, value)
+    }
 }
 
-impl<'a, T: ?Sized> HeaderMut for &'a mut T
+impl<T: ?Sized> HeaderMut for &mut T
 where
     T: HeaderMut,
 {
@@ -51,6 +98,14 @@ where
     {
         HeaderMut::set(*self, value)
     }
+
+    fn set_raw(&mut self, name: HeaderName, value: HeaderValue) -> SSDPResult<()> {
+        HeaderMut::set_raw(*self, name, value)
+    }
+
+    fn set_raw_cased(&mut self, name: &str, value: HeaderValue) -> SSDPResult<()> {
+        HeaderMut::set_raw_cased(*self, name, value)
+    }
 }
 
 impl HeaderMut for headers::HeaderMap {
@@ -62,8 +117,28 @@ impl HeaderMut for headers::HeaderMap {
         self.remove(H::name());
         self.typed_insert(value);
     }
+
+    fn set_raw(&mut self, name: HeaderName, value: HeaderValue) -> SSDPResult<()> {
+        self.insert(name, value);
+        Ok(())
+    }
 }
 
+/// Parse a header from raw values, the way `hyper` used to.
+#[cfg(test)]
+pub(crate) trait ParseHeader: Header + Sized {
+    fn parse_header(raw: &[Vec<u8>]) -> Result<Self, headers::Error> {
+        let values = raw
+            .iter()
+            .map(|raw| headers::HeaderValue::from_bytes(raw).map_err(|_| headers::Error::invalid()))
+            .collect::<Result<Vec<_>, _>>()?;
+        Self::decode(&mut values.iter())
+    }
+}
+
+#[cfg(test)]
+impl<H: Header> ParseHeader for H {}
+
 // #[cfg(test)]
 // pub mod mock {
 // use std::any::{Any};
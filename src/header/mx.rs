@@ -1,5 +1,8 @@
+use std::time::Duration;
+
 use headers::{Header, HeaderName, HeaderValue};
 
+use crate::header::normalize_value;
 use crate::{SSDPError, SSDPResult};
 
 /// Minimum wait time specified in the `UPnP` 1.0 standard.
@@ -8,6 +11,9 @@ pub const MX_HEADER_MIN: u8 = 1;
 /// Maximum wait time specified in the `UPnP` 1.0 standard.
 pub const MX_HEADER_MAX: u8 = 120;
 
+/// Maximum wait time specified in the `UPnP` 1.1 standard, larger values are treated as this.
+pub const MX_HEADER_MAX_UPNP11: u8 = 5;
+
 /// Represents a header used to specify the maximum time that devices should wait
 /// before sending a response.
 ///
@@ -21,12 +27,38 @@ pub struct MX(pub u8);
 
 impl MX {
     pub fn new(wait_bound: u8) -> SSDPResult<MX> {
-        if wait_bound < MX_HEADER_MIN || wait_bound > MX_HEADER_MAX {
-            Err(SSDPError::InvalidHeader(MX::name().as_str().into()))
+        if !(MX_HEADER_MIN..=MX_HEADER_MAX).contains(&wait_bound) {
+            Err(SSDPError::InvalidHeader {
+                name: MX::name().as_str().into(),
+                value: wait_bound.to_string().into_bytes(),
+            })
         } else {
             Ok(MX(wait_bound))
         }
     }
+
+    /// Construct an MX header from a wait time, rounded up to whole seconds.
+    pub fn from_duration(wait_bound: Duration) -> SSDPResult<MX> {
+        let secs = wait_bound.as_secs() + u64::from(wait_bound.subsec_nanos() > 0);
+
+        match u8::try_from(secs) {
+            Ok(secs) => MX::new(secs),
+            Err(_) => Err(SSDPError::InvalidHeader {
+                name: MX::name().as_str().into(),
+                value: secs.to_string().into_bytes(),
+            }),
+        }
+    }
+
+    /// Get the wait time that `UPnP` 1.1 devices actually honor.
+    pub fn clamped_upnp11(&self) -> MX {
+        MX(self.0.min(MX_HEADER_MAX_UPNP11))
+    }
+
+    /// Get the wait time in seconds as a duration.
+    pub fn as_duration(&self) -> Duration {
+        Duration::from_secs(self.0.into())
+    }
 }
 
 impl Header for MX {
@@ -47,10 +79,10 @@ impl Header for MX {
             return Err(headers::Error::invalid())?;
         };
 
-        let cow_string = String::from_utf8_lossy(value.as_bytes());
+        let cow_string = String::from_utf8_lossy(normalize_value(value));
 
-        match u8::from_str_radix(&cow_string, 10) {
-            Ok(n) if n >= MX_HEADER_MIN && n <= MX_HEADER_MAX => Ok(MX(n)),
+        match cow_string.parse::<u8>() {
+            Ok(n) if (MX_HEADER_MIN..=MX_HEADER_MAX).contains(&n) => Ok(MX(n)),
             _ => Err(headers::Error::invalid()),
         }
     }
@@ -69,14 +101,17 @@ impl Header for MX {
 
 #[cfg(test)]
 mod tests {
+    use std::time::Duration;
+
     use super::MX;
+    use crate::header::ParseHeader as _;
 
     #[test]
     fn positive_lower_bound() {
         let mx_lower_header = &[b"1"[..].to_vec()];
 
         match MX::parse_header(mx_lower_header) {
-            Ok(n) if n == MX(1) => (),
+            Ok(MX(1)) => (),
             _ => panic!("Failed To Accept 1 As MX Value"),
         };
     }
@@ -86,7 +121,7 @@ mod tests {
         let mx_inner_header = &[b"5"[..].to_vec()];
 
         match MX::parse_header(mx_inner_header) {
-            Ok(n) if n == MX(5) => (),
+            Ok(MX(5)) => (),
             _ => panic!("Failed To Accept 5 As MX Value"),
         };
     }
@@ -96,11 +131,40 @@ mod tests {
         let mx_upper_header = &[b"120"[..].to_vec()];
 
         match MX::parse_header(mx_upper_header) {
-            Ok(n) if n == MX(120) => (),
+            Ok(MX(120)) => (),
             _ => panic!("Failed To Accept 120 As MX Value"),
         };
     }
 
+    #[test]
+    fn positive_padded() {
+        let mx_padded_header = &[b"3 "[..].to_vec()];
+
+        assert_eq!(MX::parse_header(mx_padded_header).unwrap(), MX(3));
+    }
+
+    #[test]
+    fn positive_from_duration() {
+        assert_eq!(MX::from_duration(Duration::from_secs(3)).unwrap(), MX(3));
+        assert_eq!(MX::from_duration(Duration::from_millis(2001)).unwrap(), MX(3));
+        assert_eq!(MX::from_duration(Duration::from_millis(1)).unwrap(), MX(1));
+        assert_eq!(MX(3).as_duration(), Duration::from_secs(3));
+    }
+
+    #[test]
+    fn negative_from_duration() {
+        assert!(MX::from_duration(Duration::ZERO).is_err());
+        assert!(MX::from_duration(Duration::from_millis(120_001)).is_err());
+        assert!(MX::from_duration(Duration::from_secs(300)).is_err());
+    }
+
+    #[test]
+    fn positive_clamped_upnp11() {
+        assert_eq!(MX(1).clamped_upnp11(), MX(1));
+        assert_eq!(MX(5).clamped_upnp11(), MX(5));
+        assert_eq!(MX(120).clamped_upnp11(), MX(5));
+    }
+
     #[test]
     #[should_panic]
     fn negative_decimal_bound() {
@@ -0,0 +1,120 @@
+use headers::{Header, HeaderName, HeaderValue};
+
+use crate::header::normalize_value;
+
+/// Represents a header used to announce the boot instance a root device switches to.
+///
+/// Sent along with `BootID` in `ssdp:update` messages.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct NextBootID(pub u32);
+
+impl Header for NextBootID {
+    fn name() -> &'static HeaderName {
+        static NAME: HeaderName = HeaderName::from_static("nextbootid.upnp.org");
+        &NAME
+    }
+
+    fn decode<'i, I>(values: &mut I) -> Result<Self, headers::Error>
+    where
+        I: Iterator<Item = &'i HeaderValue>,
+    {
+        let Some(value) = values.next() else {
+            return Err(headers::Error::invalid())?;
+        };
+
+        if values.next().is_some() {
+            return Err(headers::Error::invalid())?;
+        };
+
+        let cow_str = String::from_utf8_lossy(normalize_value(value));
+
+        // Value needs to be a 31 bit non-negative integer, so convert to i32
+        let value = match cow_str.parse::<i32>() {
+            Ok(n) => n,
+            Err(_) => return Err(headers::Error::invalid()),
+        };
+
+        // Check if value is negative, then convert to u32
+        if value.is_negative() {
+            Err(headers::Error::invalid())
+        } else {
+            Ok(NextBootID(value as u32))
+        }
+    }
+
+    fn encode<E>(&self, values: &mut E)
+    where
+        E: Extend<HeaderValue>,
+    {
+        if let Ok(value) = HeaderValue::from_str(&format!("{}", self.0)) {
+            values.extend([value]);
+        } else {
+            debug_assert!(false, "Encoding nextbootid header was invalid");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::NextBootID;
+    use crate::header::ParseHeader as _;
+
+    #[test]
+    fn positive_nextbootid() {
+        let nextbootid_header_value = &[b"1216907400"[..].to_vec()];
+
+        NextBootID::parse_header(nextbootid_header_value).unwrap();
+    }
+
+    #[test]
+    fn positive_leading_zeros() {
+        let nextbootid_header_value = &[b"0000001216907400"[..].to_vec()];
+
+        NextBootID::parse_header(nextbootid_header_value).unwrap();
+    }
+
+    #[test]
+    fn positive_lower_bound() {
+        let nextbootid_header_value = &[b"0"[..].to_vec()];
+
+        NextBootID::parse_header(nextbootid_header_value).unwrap();
+    }
+
+    #[test]
+    fn positive_upper_bound() {
+        let nextbootid_header_value = &[b"2147483647"[..].to_vec()];
+
+        NextBootID::parse_header(nextbootid_header_value).unwrap();
+    }
+
+    #[test]
+    fn positive_negative_zero() {
+        let nextbootid_header_value = &[b"-0"[..].to_vec()];
+
+        NextBootID::parse_header(nextbootid_header_value).unwrap();
+    }
+
+    #[test]
+    #[should_panic]
+    fn negative_overflow() {
+        let nextbootid_header_value = &[b"2290649224"[..].to_vec()];
+
+        NextBootID::parse_header(nextbootid_header_value).unwrap();
+    }
+
+    #[test]
+    #[should_panic]
+    fn negative_negative_overflow() {
+        let nextbootid_header_value = &[b"-2290649224"[..].to_vec()];
+
+        NextBootID::parse_header(nextbootid_header_value).unwrap();
+    }
+
+    #[test]
+    #[should_panic]
+    fn negative_nan() {
+        let nextbootid_header_value = &[b"2290wow649224"[..].to_vec()];
+
+        NextBootID::parse_header(nextbootid_header_value).unwrap();
+    }
+}
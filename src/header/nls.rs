@@ -0,0 +1,112 @@
+use headers::{Header, HeaderName, HeaderValue};
+
+/// Represents the legacy `01-NLS` header used to denote the boot session of a device.
+///
+/// Devices built on older UPnP 1.0 stacks send a fresh GUID after every boot, either instead of
+/// or alongside `BOOTID.UPNP.ORG`. The value is kept verbatim.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct Nls(pub String);
+
+impl Nls {
+    /// Check if the value has the form of a UUID, `8-4-4-4-12` hexadecimal digits.
+    pub fn is_uuid(&self) -> bool {
+        let groups: Vec<&str> = self.0.split('-').collect();
+
+        groups.len() == 5
+            && groups
+                .iter()
+                .zip([8, 4, 4, 4, 12])
+                .all(|(group, len)| group.len() == len && group.bytes().all(|byte| byte.is_ascii_hexdigit()))
+    }
+}
+
+/// Check for a byte allowed in an HTTP token.
+fn is_tchar(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&byte)
+}
+
+impl Header for Nls {
+    fn name() -> &'static HeaderName {
+        static NAME: HeaderName = HeaderName::from_static("01-nls");
+        &NAME
+    }
+
+    fn decode<'i, I>(values: &mut I) -> Result<Self, headers::Error>
+    where
+        I: Iterator<Item = &'i HeaderValue>,
+    {
+        let Some(value) = values.next() else {
+            return Err(headers::Error::invalid())?;
+        };
+
+        if values.next().is_some() {
+            return Err(headers::Error::invalid())?;
+        };
+
+        let bytes = value.as_bytes();
+        if bytes.is_empty() || !bytes.iter().copied().all(is_tchar) {
+            return Err(headers::Error::invalid());
+        }
+
+        // Only visible ASCII remains, which is valid UTF-8
+        Ok(Nls(String::from_utf8_lossy(bytes).into_owned()))
+    }
+
+    fn encode<E>(&self, values: &mut E)
+    where
+        E: Extend<HeaderValue>,
+    {
+        if let Ok(value) = HeaderValue::from_str(&self.0) {
+            values.extend([value]);
+        } else {
+            debug_assert!(false, "Encoding 01-nls header was invalid");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Nls;
+    use crate::header::ParseHeader as _;
+
+    #[test]
+    fn positive_captured_uuid() {
+        let nls_header_value = &[b"1e1c46d8-1dd2-11b2-8d2c-ba2f3e1f4c44"[..].to_vec()];
+
+        let nls = Nls::parse_header(nls_header_value).unwrap();
+        assert_eq!(nls.0, "1e1c46d8-1dd2-11b2-8d2c-ba2f3e1f4c44");
+        assert!(nls.is_uuid());
+    }
+
+    #[test]
+    fn positive_plain_token() {
+        let nls_header_value = &[b"b9200ebb736d4b93bf03835149d13983"[..].to_vec()];
+
+        let nls = Nls::parse_header(nls_header_value).unwrap();
+        assert!(!nls.is_uuid());
+    }
+
+    #[test]
+    #[should_panic]
+    fn negative_empty() {
+        let nls_header_value = &[b""[..].to_vec()];
+
+        Nls::parse_header(nls_header_value).unwrap();
+    }
+
+    #[test]
+    #[should_panic]
+    fn negative_not_a_token() {
+        let nls_header_value = &[b"1e1c46d8 1dd2"[..].to_vec()];
+
+        Nls::parse_header(nls_header_value).unwrap();
+    }
+
+    #[test]
+    #[should_panic]
+    fn negative_multiple_values() {
+        let nls_header_value = &[b"1e1c46d8"[..].to_vec(), b"1dd2"[..].to_vec()];
+
+        Nls::parse_header(nls_header_value).unwrap();
+    }
+}
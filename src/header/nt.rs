@@ -53,6 +53,7 @@ impl Header for NT {
 #[cfg(test)]
 mod tests {
     use super::NT;
+    use crate::header::ParseHeader as _;
     use crate::FieldMap::{UPnP, Unknown, URN, UUID};
 
     #[test]
@@ -1,8 +1,10 @@
 use headers::{Header, HeaderName, HeaderValue};
 
-const ALIVE_HEADER: &'static str = "ssdp:alive";
-const UPDATE_HEADER: &'static str = "ssdp:update";
-const BYEBYE_HEADER: &'static str = "ssdp:byebye";
+use crate::header::normalize_value;
+
+const ALIVE_HEADER: &str = "ssdp:alive";
+const UPDATE_HEADER: &str = "ssdp:update";
+const BYEBYE_HEADER: &str = "ssdp:byebye";
 
 /// Represents a header which specifies a notification sub type.
 #[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
@@ -36,11 +38,13 @@ impl Header for NTS {
             return Err(headers::Error::invalid())?;
         };
 
-        if value.as_bytes() == ALIVE_HEADER.as_bytes() {
+        let value = normalize_value(value);
+
+        if value == ALIVE_HEADER.as_bytes() {
             Ok(NTS::Alive)
-        } else if value.as_bytes() == UPDATE_HEADER.as_bytes() {
+        } else if value == UPDATE_HEADER.as_bytes() {
             Ok(NTS::Update)
-        } else if value.as_bytes() == BYEBYE_HEADER.as_bytes() {
+        } else if value == BYEBYE_HEADER.as_bytes() {
             Ok(NTS::ByeBye)
         } else {
             Err(headers::Error::invalid())
@@ -64,6 +68,7 @@ impl Header for NTS {
 #[cfg(test)]
 mod tests {
     use super::NTS;
+    use crate::header::ParseHeader as _;
 
     #[test]
     fn positive_alive() {
@@ -95,6 +100,21 @@ mod tests {
         };
     }
 
+    #[test]
+    fn positive_quoted_alive() {
+        let quoted_header = &[b"\"ssdp:alive\""[..].to_vec()];
+
+        assert_eq!(NTS::parse_header(quoted_header).unwrap(), NTS::Alive);
+    }
+
+    #[test]
+    #[should_panic]
+    fn negative_unbalanced_quote() {
+        let unbalanced_header = &[b"\"ssdp:alive"[..].to_vec()];
+
+        NTS::parse_header(unbalanced_header).unwrap();
+    }
+
     #[test]
     #[should_panic]
     fn negative_alive_extra() {
@@ -0,0 +1,121 @@
+use headers::{Header, HeaderName, HeaderValue};
+
+/// Represents the legacy `OPT` header of the HTTP Extension Framework.
+///
+/// It names the extension `uri` whose headers are prefixed with the namespace `ns`, as in
+/// `OPT: "http://schemas.upnp.org/upnp/1/0/"; ns=01` which declares the `01-NLS` header.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct Opt {
+    pub uri: String,
+    pub ns: u8,
+}
+
+impl Opt {
+    fn parse(value: &str) -> Option<Opt> {
+        let (uri, param) = value.split_once(';')?;
+
+        let uri = uri.trim().strip_prefix('"')?.strip_suffix('"')?;
+        if uri.is_empty() || uri.contains('"') {
+            return None;
+        }
+
+        let ns = param.trim().strip_prefix("ns=")?;
+        if ns.len() != 2 || !ns.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+            return None;
+        }
+
+        Some(Opt {
+            uri: uri.to_owned(),
+            ns: u8::from_str_radix(ns, 16).ok()?,
+        })
+    }
+}
+
+impl Header for Opt {
+    fn name() -> &'static HeaderName {
+        static NAME: HeaderName = HeaderName::from_static("opt");
+        &NAME
+    }
+
+    fn decode<'i, I>(values: &mut I) -> Result<Self, headers::Error>
+    where
+        I: Iterator<Item = &'i HeaderValue>,
+    {
+        let Some(value) = values.next() else {
+            return Err(headers::Error::invalid())?;
+        };
+
+        if values.next().is_some() {
+            return Err(headers::Error::invalid())?;
+        };
+
+        let value = value.to_str().map_err(|_| headers::Error::invalid())?;
+        Opt::parse(value).ok_or_else(headers::Error::invalid)
+    }
+
+    fn encode<E>(&self, values: &mut E)
+    where
+        E: Extend<HeaderValue>,
+    {
+        let value = format!("\"{}\"; ns={:02x}", self.uri, self.ns);
+
+        if let Ok(value) = HeaderValue::from_str(&value) {
+            values.extend([value]);
+        } else {
+            debug_assert!(false, "Encoding opt header was invalid");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use headers::HeaderValue;
+
+    use super::Opt;
+    use crate::header::ParseHeader as _;
+
+    #[test]
+    fn positive_canonical() {
+        let opt_header_value = &[b"\"http://schemas.upnp.org/upnp/1/0/\"; ns=01"[..].to_vec()];
+
+        let opt = Opt::parse_header(opt_header_value).unwrap();
+        assert_eq!(opt.uri, "http://schemas.upnp.org/upnp/1/0/");
+        assert_eq!(opt.ns, 1);
+
+        let mut values: Vec<HeaderValue> = Vec::new();
+        headers::Header::encode(&opt, &mut values);
+        assert_eq!(values, [opt_header_value[0].as_slice()]);
+    }
+
+    #[test]
+    fn positive_hex_namespace() {
+        let opt_header_value = &[b"\"http://schemas.upnp.org/upnp/1/0/\";ns=1f"[..].to_vec()];
+
+        let opt = Opt::parse_header(opt_header_value).unwrap();
+        assert_eq!(opt.ns, 0x1f);
+    }
+
+    #[test]
+    #[should_panic]
+    fn negative_missing_namespace() {
+        let opt_header_value = &[b"\"http://schemas.upnp.org/upnp/1/0/\""[..].to_vec()];
+
+        Opt::parse_header(opt_header_value).unwrap();
+    }
+
+    #[test]
+    #[should_panic]
+    fn negative_unquoted_uri() {
+        let opt_header_value = &[b"http://schemas.upnp.org/upnp/1/0/; ns=01"[..].to_vec()];
+
+        Opt::parse_header(opt_header_value).unwrap();
+    }
+
+    #[test]
+    #[should_panic]
+    fn negative_namespace_digits() {
+        let opt_header_value = &[b"\"http://schemas.upnp.org/upnp/1/0/\"; ns=1"[..].to_vec()];
+
+        Opt::parse_header(opt_header_value).unwrap();
+    }
+}
@@ -1,5 +1,7 @@
 use headers::{Header, HeaderName, HeaderValue};
 
+use crate::header::normalize_value;
+
 pub const SEARCHPORT_MIN_VALUE: u16 = 49152;
 
 /// Represents a header used to specify a unicast port to send search requests to.
@@ -27,9 +29,9 @@ impl Header for SearchPort {
             return Err(headers::Error::invalid())?;
         };
 
-        let cow_str = String::from_utf8_lossy(value.as_bytes());
+        let cow_str = String::from_utf8_lossy(normalize_value(value));
 
-        let value = match u16::from_str_radix(&*cow_str, 10) {
+        let value = match cow_str.parse::<u16>() {
             Ok(n) => n,
             Err(_) => return Err(headers::Error::invalid()),
         };
@@ -56,6 +58,7 @@ impl Header for SearchPort {
 #[cfg(test)]
 mod tests {
     use super::SearchPort;
+    use crate::header::ParseHeader as _;
 
     #[test]
     fn positive_searchport() {
@@ -64,6 +67,13 @@ mod tests {
         SearchPort::parse_header(searchport_header_value).unwrap();
     }
 
+    #[test]
+    fn positive_quoted() {
+        let searchport_header_value = &[b"\"50000\""[..].to_vec()];
+
+        assert_eq!(SearchPort::parse_header(searchport_header_value).unwrap(), SearchPort(50000));
+    }
+
     #[test]
     fn positive_lower_bound() {
         let searchport_header_value = &[b"49152"[..].to_vec()];
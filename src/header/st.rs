@@ -1,8 +1,9 @@
 use headers::{Header, HeaderName, HeaderValue};
 
+use crate::header::NT;
 use crate::FieldMap;
 
-const ST_ALL_VALUE: &'static str = "ssdp:all";
+const ST_ALL_VALUE: &str = "ssdp:all";
 
 /// Represents a header which specifies the search target.
 #[derive(Clone, PartialEq, Eq, Hash, Debug)]
@@ -11,6 +12,38 @@ pub enum ST {
     Target(FieldMap),
 }
 
+impl ST {
+    /// Check if a device or service advertised with the notification type is a target.
+    ///
+    /// `ssdp:all` matches every notification type. Device and service types also match when
+    /// the advertised version is higher than the searched one, as versions are backwards
+    /// compatible.
+    pub fn matches(&self, nt: &NT) -> bool {
+        let target = match self {
+            ST::All => return true,
+            ST::Target(target) => target,
+        };
+
+        match (target, &nt.0) {
+            (FieldMap::URN(searched), FieldMap::URN(advertised)) => {
+                match (split_version(searched), split_version(advertised)) {
+                    (Some((s_kind, s_version)), Some((a_kind, a_version))) => {
+                        s_kind == a_kind && s_version <= a_version
+                    }
+                    _ => searched == advertised,
+                }
+            }
+            (target, advertised) => target == advertised,
+        }
+    }
+}
+
+/// Split a device or service type into the type and its version.
+fn split_version(urn: &str) -> Option<(&str, u32)> {
+    let (kind, version) = urn.rsplit_once(':')?;
+    Some((kind, version.parse().ok()?))
+}
+
 impl Header for ST {
     fn name() -> &'static HeaderName {
         static NAME: HeaderName = HeaderName::from_static("st");
@@ -54,6 +87,8 @@ impl Header for ST {
 #[cfg(test)]
 mod tests {
     use super::ST;
+    use crate::header::ParseHeader as _;
+    use crate::header::NT;
     use crate::FieldMap;
 
     #[test]
@@ -96,6 +131,43 @@ mod tests {
         }
     }
 
+    #[test]
+    fn positive_mixed_case_matches() {
+        let st = ST::parse_header(&[b"Urn:schemas-upnp-org:device:MediaRenderer:1".to_vec()]).unwrap();
+        let nt = NT::parse_header(&[b"URN:schemas-upnp-org:device:MediaRenderer:2".to_vec()]).unwrap();
+
+        assert_eq!(st, ST::Target(FieldMap::urn("schemas-upnp-org:device:MediaRenderer:1")));
+        assert!(st.matches(&nt));
+        assert!(st.matches(&NT(FieldMap::urn("schemas-upnp-org:device:MediaRenderer:1"))));
+    }
+
+    #[test]
+    fn positive_matches_all() {
+        assert!(ST::All.matches(&NT(FieldMap::upnp("rootdevice"))));
+    }
+
+    #[test]
+    fn positive_matches_higher_version() {
+        let st = ST::Target(FieldMap::urn("schemas-upnp-org:device:printer:1"));
+
+        assert!(st.matches(&NT(FieldMap::urn("schemas-upnp-org:device:printer:2"))));
+        assert!(!st.matches(&NT(FieldMap::urn("schemas-upnp-org:device:scanner:2"))));
+    }
+
+    #[test]
+    fn negative_matches_lower_version() {
+        let st = ST::Target(FieldMap::urn("schemas-upnp-org:device:printer:2"));
+
+        assert!(!st.matches(&NT(FieldMap::urn("schemas-upnp-org:device:printer:1"))));
+    }
+
+    #[test]
+    fn negative_matches_other_uuid() {
+        let st = ST::Target(FieldMap::uuid("some_identifier"));
+
+        assert!(!st.matches(&NT(FieldMap::uuid("other_identifier"))));
+    }
+
     #[test]
     #[should_panic]
     fn negative_multiple_headers() {
@@ -4,7 +4,7 @@ use crate::field;
 use crate::FieldMap;
 
 /// Separator for multiple key/values in header fields.
-const FIELD_PAIR_SEPARATOR: &'static str = "::";
+const FIELD_PAIR_SEPARATOR: &str = "::";
 
 /// Represents a header which specifies a unique service name.
 ///
@@ -111,6 +111,7 @@ where
 #[cfg(test)]
 mod tests {
     use super::USN;
+    use crate::header::ParseHeader as _;
     use crate::FieldMap::{UPnP, Unknown, URN, UUID};
 
     #[test]
@@ -129,6 +130,22 @@ mod tests {
         };
     }
 
+    #[test]
+    fn positive_mixed_case_keys() {
+        let mixed_case_header = &[b"UUID:2F402F80-DA50-11E1-9B23-001788255ACC::Upnp:rootdevice".to_vec()];
+        let USN(first, second) = USN::parse_header(mixed_case_header).unwrap();
+
+        match first {
+            UUID(n) => assert_eq!(n, "2F402F80-DA50-11E1-9B23-001788255ACC"),
+            _ => panic!("Didnt Match uuid"),
+        };
+
+        match second.unwrap() {
+            UPnP(n) => assert_eq!(n, "rootdevice"),
+            _ => panic!("Didnt Match upnp"),
+        };
+    }
+
     #[test]
     fn positive_single_pair() {
         let single_pair_header = &["urn:device-URN".to_string().into_bytes()];
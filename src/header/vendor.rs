@@ -0,0 +1,107 @@
+use headers::{HeaderMap, HeaderName, HeaderValue};
+
+use crate::header::HeaderMut;
+use crate::{SSDPError, SSDPResult};
+
+/// Names of the headers defined by HTTP, SSDP and the UPnP Device Architecture.
+const KNOWN_HEADERS: &[&str] = &[
+    "host",
+    "cache-control",
+    "content-length",
+    "date",
+    "ext",
+    "location",
+    "man",
+    "mx",
+    "nt",
+    "nts",
+    "server",
+    "st",
+    "user-agent",
+    "usn",
+    "bootid.upnp.org",
+    "configid.upnp.org",
+    "nextbootid.upnp.org",
+    "searchport.upnp.org",
+    "securelocation.upnp.org",
+    "tcpport.upnp.org",
+    "cpfn.upnp.org",
+    "cpuuid.upnp.org",
+];
+
+/// Access to headers without a typed representation, such as those added by vendors.
+///
+/// Names are matched ignoring case, as they are stored in lowercase.
+pub struct VendorHeader;
+
+impl VendorHeader {
+    /// Get the first value of a header, replacing bytes that are not UTF-8.
+    pub fn get(headers: &HeaderMap, name: &str) -> Option<String> {
+        let value = headers.get(name)?;
+        Some(String::from_utf8_lossy(value.as_bytes()).into_owned())
+    }
+
+    /// Set a header, replacing all of its previous values.
+    ///
+    /// Fails with `SSDPError::InvalidHeader` if the name is not an HTTP token or the value
+    /// contains control characters.
+    pub fn set<M: HeaderMut + ?Sized>(target: &mut M, name: &str, value: &str) -> SSDPResult<()> {
+        let invalid = || SSDPError::InvalidHeader {
+            name: name.to_string(),
+            value: value.as_bytes().to_vec(),
+        };
+
+        let name = HeaderName::from_bytes(name.as_bytes()).map_err(|_| invalid())?;
+        let value = HeaderValue::from_str(value).map_err(|_| invalid())?;
+        target.set_raw(name, value)
+    }
+}
+
+/// Iterate the headers that are not defined by HTTP, SSDP or UPnP, with every value separately.
+pub fn vendor_headers(headers: &HeaderMap) -> impl Iterator<Item = (&HeaderName, &HeaderValue)> {
+    headers
+        .iter()
+        .filter(|(name, _)| !KNOWN_HEADERS.contains(&name.as_str()))
+}
+
+#[cfg(test)]
+mod tests {
+    use headers::{HeaderMap, HeaderValue};
+
+    use super::{vendor_headers, VendorHeader};
+    use crate::header::{HeaderMut, NTS};
+
+    #[test]
+    fn positive_get_set() {
+        let mut headers = HeaderMap::new();
+
+        VendorHeader::set(&mut headers, "X-AV-Server-Info", "av=5.0; cn=\"Sony\"").unwrap();
+        VendorHeader::set(&mut headers, "SMARTSPEAKER.AUDIO", "1").unwrap();
+        VendorHeader::set(&mut headers, "smartspeaker.audio", "2").unwrap();
+
+        assert_eq!(VendorHeader::get(&headers, "x-av-server-info").unwrap(), "av=5.0; cn=\"Sony\"");
+        assert_eq!(VendorHeader::get(&headers, "SmartSpeaker.Audio").unwrap(), "2");
+        assert_eq!(VendorHeader::get(&headers, "01-nls"), None);
+    }
+
+    #[test]
+    fn negative_set_invalid() {
+        let mut headers = HeaderMap::new();
+
+        assert!(VendorHeader::set(&mut headers, "X Info", "1").is_err());
+        assert!(VendorHeader::set(&mut headers, "X-Info", "a\r\nb").is_err());
+        assert!(headers.is_empty());
+    }
+
+    #[test]
+    fn positive_vendor_headers() {
+        let mut headers = HeaderMap::new();
+        headers.set(NTS::Alive);
+        headers.insert("bootid.upnp.org", HeaderValue::from_static("1"));
+        headers.insert("01-nls", HeaderValue::from_static("b9200ebb"));
+        headers.append("x-user-agent", HeaderValue::from_static("redsonic"));
+
+        let names: Vec<_> = vendor_headers(&headers).map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["01-nls", "x-user-agent"]);
+    }
+}
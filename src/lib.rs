@@ -7,19 +7,101 @@
 //! HTTPMU to distribute messages across a local network for devices and services to
 //! discover each other. SSDP can most commonly be found in devices that implement
 //! `UPnP` as it is used as the discovery mechanism for that standard.
+//!
+//! Finding the devices on the network takes a single call:
+//!
+//! ```no_run
+//! use std::time::Duration;
+//!
+//! use ssdp::header::ST;
+//!
+//! for device in ssdp::discover(ST::All, Duration::from_secs(3)).unwrap() {
+//!     println!("{:?} at {} from {}", device.usn, device.location, device.peer);
+//! }
+//! ```
+//!
+//! Messages received by other means can be parsed from their bytes or text:
+//!
+//! ```
+//! use ssdp::message::{NotifyMessage, SearchResponse};
+//!
+//! let notify: NotifyMessage = "NOTIFY * HTTP/1.1\r\nHOST: 239.255.255.250:1900\r\n\r\n"
+//!     .parse()
+//!     .unwrap();
+//! println!("{}", notify);
+//!
+//! let bytes: &[u8] = b"HTTP/1.1 200 OK\r\nST: ssdp:all\r\n\r\n";
+//! let response = SearchResponse::try_from(bytes).unwrap();
+//! assert_eq!(response.status_code(), 200);
+//! assert!(NotifyMessage::try_from(bytes).is_err());
+//! ```
+//!
+//! # Extending the crate
+//!
+//! Some traits are open for implementation by other crates: `FromRawSSDP`, `Listen`,
+//! `HeaderMut`, `Spawn`, `Clock`, `InterfaceSource`, `SsdpObserver`, `NetworkConnector` and
+//! `NetworkStream`. Methods added to them in a minor release always come with a default
+//! implementation, such that existing implementations keep compiling.
+//!
+//! Other traits, such as `Multicast`, are sealed. They can be used and named but only be
+//! implemented within this crate, which leaves room to extend them at any time. Enums and
+//! structures that are expected to grow are marked `#[non_exhaustive]`.
 
+#[cfg(not(feature = "tracing"))]
 #[macro_use]
 extern crate log;
+#[cfg(feature = "tracing")]
+#[macro_use]
+extern crate tracing;
+
+/// Enter a `tracing` span until the end of the enclosing scope.
+///
+/// Expands to nothing without the `tracing` feature, the fields are not even evaluated then.
+macro_rules! enter_span {
+    ($($span:tt)*) => {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!($($span)*).entered();
+    };
+}
 
+mod context;
 mod error;
 mod field;
-mod net;
+mod limit;
+pub mod net;
 mod receiver;
+mod schedule;
+#[cfg(feature = "serde")]
+mod serialize;
+#[cfg(feature = "stream")]
+mod stream;
+
+/// Home of the marker trait of sealed traits.
+///
+/// The module is private, so other crates can not implement `Sealed` and hence none of the
+/// traits requiring it.
+mod private {
+    pub trait Sealed {}
+}
 
 pub mod header;
 pub mod message;
+#[cfg(any(test, feature = "test-util"))]
+pub mod testing;
+#[cfg(any(test, feature = "vectors"))]
+pub mod vectors;
 
+pub use crate::context::{Clock, InterfaceSource, Spawn, SsdpContext, SsdpObserver, Stats, Task};
 pub use crate::error::{SSDPError, SSDPResult};
-pub use crate::field::FieldMap;
-pub use crate::receiver::{SSDPReceiver, SSDPIter};
-pub use crate::net::IpVersionMode;
+pub use crate::field::{FieldMap, ParseFieldMapError};
+pub use crate::message::discovery::{discover, DiscoveredDevice};
+pub use crate::receiver::{
+    FromRawSSDP, MessageFilter, ParseOptions, ReceivedMessage, SSDPDetailedIter, SSDPIter, SSDPReceiver,
+    SSDPReceiverBuilder,
+};
+pub use crate::schedule::{ConnectorRef, ScheduleHandle, ScheduledSender};
+#[cfg(feature = "stream")]
+pub use crate::stream::SSDPStream;
+pub use crate::net::packet::PacketBuffer;
+pub use crate::net::sender::RetryPolicy;
+pub use crate::net::{join_multicast, IpVersionMode, NetifAddr, NetworkConnector, NetworkStream};
@@ -0,0 +1,283 @@
+//! Rate limiting of received packets.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// The number of sources with a bucket of their own, further sources share a single bucket.
+const MAX_SOURCES: usize = 1024;
+/// How often the buckets of idle sources are searched for while all are taken.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Limit for the rate of received packets.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct RateLimit {
+    pub(crate) per_second: u32,
+    /// Limit every source address separately instead of all packets together.
+    pub(crate) per_source: bool,
+}
+
+/// A bucket that is refilled with `rate` tokens per second, up to a burst of `rate` tokens.
+#[derive(Clone, Debug)]
+struct TokenBucket {
+    rate: u32,
+    tokens: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    /// Create a full bucket.
+    fn new(rate: u32, now: Instant) -> TokenBucket {
+        TokenBucket {
+            rate,
+            tokens: f64::from(rate),
+            last: now,
+        }
+    }
+
+    /// Take a token if one is available.
+    fn try_take(&mut self, now: Instant) -> bool {
+        self.refill(now);
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Check if the bucket would be full at the given time.
+    fn is_full(&self, now: Instant) -> bool {
+        let mut bucket = self.clone();
+        bucket.refill(now);
+        bucket.tokens >= f64::from(self.rate)
+    }
+
+    fn refill(&mut self, now: Instant) {
+        // A clock that went backwards adds nothing
+        let elapsed = now.saturating_duration_since(self.last);
+        self.last = self.last.max(now);
+
+        let rate = f64::from(self.rate);
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * rate).min(rate);
+    }
+}
+
+/// Limits the packets of all socket threads of a receiver.
+pub(crate) struct RateLimiter {
+    limit: RateLimit,
+    buckets: Mutex<Buckets>,
+}
+
+enum Buckets {
+    All(TokenBucket),
+    PerSource(Sources),
+}
+
+struct Sources {
+    buckets: HashMap<IpAddr, TokenBucket>,
+    /// Shared by all sources that found no free bucket, so spoofed addresses can not grow the map.
+    overflow: TokenBucket,
+    last_sweep: Instant,
+}
+
+impl Sources {
+    fn allow(&mut self, source: IpAddr, rate: u32, now: Instant) -> bool {
+        if let Some(bucket) = self.buckets.get_mut(&source) {
+            return bucket.try_take(now);
+        }
+
+        // Buckets that refilled completely behave like new ones, so they can be dropped. Sweeping is
+        // rate limited to not scan all buckets for every packet of a flood.
+        let sweep_due = now.saturating_duration_since(self.last_sweep) >= SWEEP_INTERVAL;
+        if self.buckets.len() >= MAX_SOURCES && sweep_due {
+            self.last_sweep = now;
+            self.buckets.retain(|_, bucket| !bucket.is_full(now));
+        }
+
+        if self.buckets.len() >= MAX_SOURCES {
+            return self.overflow.try_take(now);
+        }
+
+        let mut bucket = TokenBucket::new(rate, now);
+        let allowed = bucket.try_take(now);
+        self.buckets.insert(source, bucket);
+        allowed
+    }
+}
+
+impl RateLimiter {
+    pub(crate) fn new(limit: RateLimit, now: Instant) -> RateLimiter {
+        let buckets = if limit.per_source {
+            Buckets::PerSource(Sources {
+                buckets: HashMap::new(),
+                overflow: TokenBucket::new(limit.per_second, now),
+                last_sweep: now,
+            })
+        } else {
+            Buckets::All(TokenBucket::new(limit.per_second, now))
+        };
+
+        RateLimiter {
+            limit,
+            buckets: Mutex::new(buckets),
+        }
+    }
+
+    /// Check if a packet from the source may be processed.
+    pub(crate) fn allow(&self, source: IpAddr, now: Instant) -> bool {
+        let source = source.to_canonical();
+        let mut buckets = self.buckets.lock().unwrap_or_else(|err| err.into_inner());
+
+        match &mut *buckets {
+            Buckets::All(bucket) => bucket.try_take(now),
+            Buckets::PerSource(sources) => sources.allow(source, self.limit.per_second, now),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::time::{Duration, Instant};
+
+    use super::{RateLimit, RateLimiter, TokenBucket, MAX_SOURCES};
+
+    fn source(last: u8) -> IpAddr {
+        IpAddr::V4(Ipv4Addr::new(192, 168, 1, last))
+    }
+
+    #[test]
+    fn positive_bucket_burst() {
+        let now = Instant::now();
+        let mut bucket = TokenBucket::new(3, now);
+
+        assert!(bucket.try_take(now));
+        assert!(bucket.try_take(now));
+        assert!(bucket.try_take(now));
+        assert!(!bucket.try_take(now));
+    }
+
+    #[test]
+    fn positive_bucket_refill() {
+        let now = Instant::now();
+        let mut bucket = TokenBucket::new(10, now);
+        for _ in 0..10 {
+            assert!(bucket.try_take(now));
+        }
+
+        // A tenth of a second yields one token
+        let later = now + Duration::from_millis(100);
+        assert!(bucket.try_take(later));
+        assert!(!bucket.try_take(later));
+    }
+
+    #[test]
+    fn positive_bucket_refill_capped() {
+        let now = Instant::now();
+        let mut bucket = TokenBucket::new(2, now);
+
+        let later = now + Duration::from_secs(60);
+        assert!(bucket.try_take(later));
+        assert!(bucket.try_take(later));
+        assert!(!bucket.try_take(later));
+    }
+
+    #[test]
+    fn negative_bucket_clock_backwards() {
+        let now = Instant::now() + Duration::from_secs(1);
+        let mut bucket = TokenBucket::new(1, now);
+        assert!(bucket.try_take(now));
+
+        assert!(!bucket.try_take(now - Duration::from_secs(1)));
+        assert!(!bucket.try_take(now));
+    }
+
+    #[test]
+    fn negative_zero_rate() {
+        let now = Instant::now();
+        let mut bucket = TokenBucket::new(0, now);
+
+        assert!(!bucket.try_take(now + Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn positive_limiter_shared() {
+        let now = Instant::now();
+        let limit = RateLimit {
+            per_second: 2,
+            per_source: false,
+        };
+        let limiter = RateLimiter::new(limit, now);
+
+        assert!(limiter.allow(source(1), now));
+        assert!(limiter.allow(source(2), now));
+        assert!(!limiter.allow(source(3), now));
+    }
+
+    #[test]
+    fn positive_limiter_per_source() {
+        let now = Instant::now();
+        let limit = RateLimit {
+            per_second: 1,
+            per_source: true,
+        };
+        let limiter = RateLimiter::new(limit, now);
+
+        assert!(limiter.allow(source(1), now));
+        assert!(!limiter.allow(source(1), now));
+        assert!(limiter.allow(source(2), now));
+    }
+
+    #[test]
+    fn positive_limiter_forgets_idle_sources() {
+        let now = Instant::now();
+        let limit = RateLimit {
+            per_second: 1,
+            per_source: true,
+        };
+        let limiter = RateLimiter::new(limit, now);
+
+        for last in 0..=255 {
+            for third in 0..4 {
+                limiter.allow(IpAddr::V4(Ipv4Addr::new(10, 0, third, last)), now);
+            }
+        }
+
+        let later = now + Duration::from_secs(1);
+        assert!(limiter.allow(source(1), later));
+
+        let buckets = limiter.buckets.lock().unwrap();
+        match &*buckets {
+            super::Buckets::PerSource(sources) => assert_eq!(sources.buckets.len(), 1),
+            super::Buckets::All(_) => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn negative_limiter_sources_capped() {
+        let now = Instant::now();
+        let limit = RateLimit {
+            per_second: 1,
+            per_source: true,
+        };
+        let limiter = RateLimiter::new(limit, now);
+
+        for index in 0..MAX_SOURCES as u16 {
+            let [third, last] = index.to_be_bytes();
+            assert!(limiter.allow(IpAddr::V4(Ipv4Addr::new(10, 0, third, last)), now));
+        }
+
+        // Further sources share one bucket while all others are busy
+        assert!(limiter.allow(source(1), now));
+        assert!(!limiter.allow(source(2), now));
+
+        let buckets = limiter.buckets.lock().unwrap();
+        match &*buckets {
+            super::Buckets::PerSource(sources) => assert_eq!(sources.buckets.len(), MAX_SOURCES),
+            super::Buckets::All(_) => unreachable!(),
+        }
+    }
+}
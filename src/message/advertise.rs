@@ -0,0 +1,1029 @@
+//! Periodic advertisement of devices and services.
+
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use headers::{CacheControl, Location, Server};
+
+use crate::context::SsdpContext;
+use crate::error::{SSDPError, SSDPResult};
+use crate::header::{BootID, ConfigID, HeaderMut, NextBootID, SearchPort, SEARCHPORT_MIN_VALUE, NT, NTS, USN};
+use crate::message::{Config, NotifyMessage};
+use crate::schedule::ScheduledSender;
+use crate::{FieldMap, ParseFieldMapError};
+
+/// Number of times the initial set of alive messages is sent.
+///
+/// The `UPnP` device architecture recommends sending each message more than once, as UDP is
+/// unreliable.
+const INITIAL_BURST_COUNT: u32 = 3;
+
+/// Delay between the repetitions of the initial set of alive messages.
+const INITIAL_BURST_DELAY: Duration = Duration::from_millis(100);
+
+/// Longest time the announcing thread waits before consulting the clock of the context again.
+const MAX_WAIT: Duration = Duration::from_millis(100);
+
+/// Number of alive messages that may wait to be sent, across all entries and interfaces.
+const ANNOUNCE_QUEUE_CAPACITY: usize = 4096;
+
+/// Largest BOOTID.UPNP.ORG, which is a 31 bit number.
+pub(crate) const MAX_BOOT_ID: u32 = i32::MAX as u32;
+
+/// Largest CONFIGID.UPNP.ORG, larger values are reserved by `UPnP` 1.1.
+const MAX_CONFIG_ID: u32 = (1 << 24) - 1;
+
+/// A device or service that is announced by an `Advertiser`.
+#[derive(Clone, Debug)]
+pub struct Advertisement {
+    pub nt: NT,
+    pub usn: USN,
+    pub location: Location,
+    pub server: Server,
+}
+
+impl Advertisement {
+    /// Construct a new Advertisement.
+    pub fn new(nt: NT, usn: USN, location: Location, server: Server) -> Advertisement {
+        Advertisement {
+            nt,
+            usn,
+            location,
+            server,
+        }
+    }
+
+    /// Create the `ssdp:alive` message for this advertisement.
+    pub fn alive(&self, max_age: Duration) -> NotifyMessage {
+        let mut message = NotifyMessage::new();
+
+        message.set(CacheControl::new().with_max_age(max_age));
+        message.set(self.location.clone());
+        message.set(self.nt.clone());
+        message.set(NTS::Alive);
+        message.set(self.server.clone());
+        message.set(self.usn.clone());
+
+        message
+    }
+
+    /// Create the `ssdp:update` message announcing the boot id the device switches to.
+    ///
+    /// The current BOOTID is not part of the message, the `Advertiser` adds it.
+    pub fn update(&self, next: NextBootID) -> NotifyMessage {
+        let mut message = NotifyMessage::new();
+
+        message.set(self.location.clone());
+        message.set(self.nt.clone());
+        message.set(NTS::Update);
+        message.set(self.usn.clone());
+        message.set(next);
+
+        message
+    }
+
+    /// Create the `ssdp:byebye` message for this advertisement.
+    pub fn byebye(&self) -> NotifyMessage {
+        let mut message = NotifyMessage::new();
+
+        message.set(self.nt.clone());
+        message.set(NTS::ByeBye);
+        message.set(self.usn.clone());
+
+        message
+    }
+}
+
+/// The devices and services that make up a root device, as listed by its description.
+///
+/// Only the identifiers announced by SSDP are part of it. Embedded devices may embed further
+/// devices in turn.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DeviceDescriptionLite {
+    /// The UUID of the device, without the `uuid:` prefix.
+    pub uuid: String,
+    /// The type of the device, such as `schemas-upnp-org:device:MediaServer:1`.
+    pub device_type: String,
+    /// The types of the services of the device, such as
+    /// `schemas-upnp-org:service:ContentDirectory:1`.
+    pub service_types: Vec<String>,
+    /// The devices embedded in this one.
+    pub embedded: Vec<DeviceDescriptionLite>,
+}
+
+impl DeviceDescriptionLite {
+    /// Construct the description of a device without services or embedded devices.
+    pub fn new<U: Into<String>, T: Into<String>>(uuid: U, device_type: T) -> DeviceDescriptionLite {
+        DeviceDescriptionLite {
+            uuid: uuid.into(),
+            device_type: device_type.into(),
+            service_types: Vec::new(),
+            embedded: Vec::new(),
+        }
+    }
+
+    /// Add the type of a service of the device.
+    pub fn service<T: Into<String>>(mut self, service_type: T) -> Self {
+        self.service_types.push(service_type.into());
+        self
+    }
+
+    /// Add an embedded device.
+    pub fn embed(mut self, device: DeviceDescriptionLite) -> Self {
+        self.embedded.push(device);
+        self
+    }
+}
+
+/// Enumerate the notification types and unique service names announced for a root device.
+///
+/// As required by the UPnP Device Architecture these are three for the root device, the
+/// `upnp:rootdevice`, its UUID and its type, two for every embedded device, its UUID and its
+/// type, and one for every distinct service type of each device. Each is announced with the UUID
+/// of the device it belongs to.
+///
+/// Fails if any of the UUIDs or types is not a valid field value, such as an empty one.
+pub fn expand_targets(device: &DeviceDescriptionLite) -> Result<Vec<(NT, USN)>, ParseFieldMapError> {
+    let uuid = FieldMap::try_uuid(device.uuid.as_str())?;
+    let root = FieldMap::upnp("rootdevice");
+    let mut targets = vec![(NT(root.clone()), USN(uuid, Some(root)))];
+
+    expand_device(device, &mut targets)?;
+    Ok(targets)
+}
+
+/// Add the UUID, the type and the services of the device and those of its embedded devices.
+fn expand_device(
+    device: &DeviceDescriptionLite,
+    targets: &mut Vec<(NT, USN)>,
+) -> Result<(), ParseFieldMapError> {
+    let uuid = FieldMap::try_uuid(device.uuid.as_str())?;
+    let typed = |urn: &str| -> Result<(NT, USN), ParseFieldMapError> {
+        let urn = FieldMap::try_urn(urn)?;
+        Ok((NT(urn.clone()), USN(uuid.clone(), Some(urn))))
+    };
+
+    targets.push((NT(uuid.clone()), USN(uuid.clone(), None)));
+    targets.push(typed(&device.device_type)?);
+
+    let mut seen = Vec::new();
+    for service_type in &device.service_types {
+        if !seen.contains(&service_type) {
+            seen.push(service_type);
+            targets.push(typed(service_type)?);
+        }
+    }
+
+    for embedded in &device.embedded {
+        expand_device(embedded, targets)?;
+    }
+
+    Ok(())
+}
+
+/// Announces a set of advertisements on the network for as long as it is running.
+///
+/// While started, `ssdp:alive` messages for every advertisement are multicast at half the
+/// max-age interval such that control points never consider them expired. A background thread
+/// schedules each round, which a `ScheduledSender` sends by the clock of the context. When
+/// stopped, or dropped, an `ssdp:byebye` is multicast for every advertisement.
+pub struct Advertiser {
+    entries: Vec<Advertisement>,
+    max_age: Duration,
+    instance: Arc<Mutex<Instance>>,
+    running: Option<Running>,
+}
+
+/// The boot instance and configuration of a device, announced with every message once set.
+///
+/// Devices before `UPnP` 1.1 announce neither, so both are unset by default.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct Instance {
+    pub(crate) boot_id: Option<BootID>,
+    pub(crate) config_id: Option<ConfigID>,
+    /// Not announced with `ssdp:byebye`, which only needs the ids.
+    pub(crate) search_port: Option<SearchPort>,
+}
+
+/// The identity of a device that is shared by its `Advertiser` and `SearchResponder`.
+///
+/// Clones refer to the same boot id, config id and search port, so that the notify messages and
+/// the search responses of a device always carry the same values. Control points consider a
+/// device whose ids differ between the two as rebooted. Create both components with
+/// `Advertiser::with_identity` and `SearchResponder::with_identity`, changes through any clone
+/// take effect for all following messages of both.
+#[derive(Clone, Debug)]
+pub struct DeviceIdentity {
+    uuid: Arc<str>,
+    pub(crate) instance: Arc<Mutex<Instance>>,
+}
+
+struct Running {
+    context: SsdpContext,
+    config: Config,
+    stop: Sender<()>,
+    /// Disconnects once the announcing thread exited.
+    done: Receiver<()>,
+    /// Sends the alive messages at the times the announcing thread scheduled them for.
+    scheduler: Arc<ScheduledSender>,
+    /// Held while scheduling a round of messages, such that the updates of `announce_update` and
+    /// the alive messages do not interleave.
+    sending: Arc<Mutex<()>>,
+}
+
+impl Advertiser {
+    /// Construct a new Advertiser announcing its entries with the given max-age.
+    pub fn new(max_age: Duration) -> Advertiser {
+        Advertiser {
+            entries: Vec::new(),
+            max_age,
+            instance: Arc::new(Mutex::new(Instance::default())),
+            running: None,
+        }
+    }
+
+    /// Construct a new Advertiser announcing the ids of the shared identity.
+    ///
+    /// Setting the ids through the advertiser also changes them for the identity.
+    pub fn with_identity(identity: &DeviceIdentity, max_age: Duration) -> Advertiser {
+        Advertiser {
+            entries: Vec::new(),
+            max_age,
+            instance: Arc::clone(&identity.instance),
+            running: None,
+        }
+    }
+
+    /// Add the advertisements of a root device with its embedded devices and services.
+    ///
+    /// All of them are announced with the same location and server, see `expand_targets`, which
+    /// also lists the reasons for failing.
+    pub fn register_device(
+        mut self,
+        device: &DeviceDescriptionLite,
+        location: Location,
+        server: Server,
+    ) -> Result<Self, ParseFieldMapError> {
+        for (nt, usn) in expand_targets(device)? {
+            self.entries.push(Advertisement::new(nt, usn, location.clone(), server.clone()));
+        }
+        Ok(self)
+    }
+
+    /// Add an advertisement to be announced.
+    ///
+    /// This only takes effect the next time the advertiser is started.
+    pub fn register(mut self, entry: Advertisement) -> Self {
+        self.entries.push(entry);
+        self
+    }
+
+    /// Get the advertisements announced by this advertiser.
+    pub fn entries(&self) -> &[Advertisement] {
+        &self.entries
+    }
+
+    /// Get the max-age announced for all entries.
+    pub fn max_age(&self) -> Duration {
+        self.max_age
+    }
+
+    /// Check if the advertiser is currently announcing its entries.
+    pub fn is_running(&self) -> bool {
+        self.running.is_some()
+    }
+
+    /// Set the BOOTID.UPNP.ORG announced with all following messages.
+    ///
+    /// The boot id must be increased on every boot of the device, so it should be persisted
+    /// across restarts. Use `announce_update` to change it while the device stays online.
+    ///
+    /// # Panics
+    ///
+    /// If the value is larger than 2^31 - 1.
+    pub fn set_boot_id(&self, value: u32) {
+        self.instance.lock().unwrap().set_boot_id(value);
+    }
+
+    /// Get the BOOTID.UPNP.ORG announced with every message, if any.
+    pub fn boot_id(&self) -> Option<BootID> {
+        self.instance.lock().unwrap().boot_id
+    }
+
+    /// Increase the CONFIGID.UPNP.ORG announced with all following messages.
+    ///
+    /// The config id must change whenever the description of the device or one of its services
+    /// changes. An unset config id counts as zero, after 2^24 - 1 it starts over at zero.
+    pub fn bump_config_id(&self) -> ConfigID {
+        self.instance.lock().unwrap().bump_config_id()
+    }
+
+    /// Get the CONFIGID.UPNP.ORG announced with every message, if any.
+    pub fn config_id(&self) -> Option<ConfigID> {
+        self.instance.lock().unwrap().config_id
+    }
+
+    /// Switch to the next boot id, announcing it with an `ssdp:update` for every entry.
+    ///
+    /// As required by `UPnP` 1.1 the updates carry the current BOOTID and the next one as
+    /// NEXTBOOTID, all following messages carry the next one. After 2^31 - 1 the boot id starts
+    /// over at zero, which a `Discovery` takes as an increase. When the advertiser is not running
+    /// the boot id is switched without an announcement. Alive messages that still wait to be sent
+    /// carry the current boot id and are dropped, the next round announces the next one. Fails
+    /// with `SSDPError::MissingHeader` if no boot id was set. The boot id is switched even if some
+    /// updates could not be sent, after trying all of them this fails with the error of the first
+    /// one.
+    pub fn announce_update(&self) -> SSDPResult<BootID> {
+        // No alive message is sent until all updates are, so none carries the next boot id early.
+        let _sending = self.running.as_ref().map(|running| running.sending.lock().unwrap());
+
+        let (current, next) = {
+            let mut instance = self.instance.lock().unwrap();
+            let current = *instance;
+            let Some(BootID(boot_id)) = current.boot_id else {
+                return Err(SSDPError::MissingHeader("BOOTID.UPNP.ORG"));
+            };
+
+            let next = if boot_id >= MAX_BOOT_ID { 0 } else { boot_id + 1 };
+            instance.boot_id = Some(BootID(next));
+            (current, next)
+        };
+
+        let mut result = Ok(BootID(next));
+        if let Some(running) = &self.running {
+            running.scheduler.cancel_all();
+            for entry in &self.entries {
+                let mut update = entry.update(NextBootID(next));
+                current.apply(&mut update);
+                if let Err(err) = update.multicast_with_context(&running.context, &running.config) {
+                    debug!("Failed to send update message: {}", err);
+                    if result.is_ok() {
+                        result = Err(err);
+                    }
+                }
+            }
+        }
+
+        result
+    }
+
+    /// Start announcing all entries on the network described by the config.
+    ///
+    /// If the advertiser is already running it is stopped first.
+    pub fn start(&mut self, config: &Config) -> SSDPResult<()> {
+        self.start_with_context(SsdpContext::global(), config)
+    }
+
+    /// Start announcing all entries through the interfaces of the context.
+    pub(crate) fn start_with_context(&mut self, context: &SsdpContext, config: &Config) -> SSDPResult<()> {
+        self.stop()?;
+
+        let (stop, stopped) = mpsc::channel();
+        let (done_send, done) = mpsc::channel::<()>();
+        let entries = self.entries.clone();
+        let max_age = self.max_age;
+        let instance = Arc::clone(&self.instance);
+        let scheduler = Arc::new(ScheduledSender::with_config(context, ANNOUNCE_QUEUE_CAPACITY, config));
+        let thread_scheduler = Arc::clone(&scheduler);
+        let sending = Arc::new(Mutex::new(()));
+        let thread_sending = Arc::clone(&sending);
+        let thread_context = context.clone();
+        let thread_config = config.clone();
+
+        // The thread only schedules the rounds, the scheduler sends them at their time.
+        context.spawn(move || {
+            let _done = done_send;
+            let interval = max_age / 2;
+            let announce = |rounds: u32| {
+                let _sending = thread_sending.lock().unwrap();
+                let instance = *instance.lock().unwrap();
+                let now = thread_context.now();
+                let times: Vec<_> = (0..rounds).map(|round| now + INITIAL_BURST_DELAY * round).collect();
+                let scheduler = &*thread_scheduler;
+                announce(&thread_context, scheduler, &entries, max_age, &instance, &thread_config, &times);
+            };
+
+            announce(INITIAL_BURST_COUNT);
+            while !wait(&thread_context, &stopped, interval) {
+                announce(1);
+            }
+        });
+
+        self.running = Some(Running {
+            context: context.clone(),
+            config: config.clone(),
+            stop,
+            done,
+            scheduler,
+            sending,
+        });
+
+        Ok(())
+    }
+
+    /// Stop announcing and multicast an `ssdp:byebye` for every entry.
+    ///
+    /// Does nothing if the advertiser is not running. After trying to send all byebye messages
+    /// this fails with the error of the first one that could not be sent.
+    pub fn stop(&mut self) -> SSDPResult<()> {
+        let Some(running) = self.running.take() else {
+            return Ok(());
+        };
+
+        // The thread may have already exited, in which case there is no one to notify.
+        let _ = running.stop.send(());
+        let _ = running.done.recv();
+        // No alive message may follow the byebye.
+        running.scheduler.cancel_all();
+
+        let instance = Instance {
+            search_port: None,
+            ..*self.instance.lock().unwrap()
+        };
+        let mut result = Ok(());
+        for entry in &self.entries {
+            let mut byebye = entry.byebye();
+            instance.apply(&mut byebye);
+            if let Err(err) = byebye.multicast_with_context(&running.context, &running.config) {
+                debug!("Failed to send byebye message: {}", err);
+                if result.is_ok() {
+                    result = Err(err);
+                }
+            }
+        }
+
+        result
+    }
+}
+
+impl Drop for Advertiser {
+    fn drop(&mut self) {
+        if let Err(err) = self.stop() {
+            debug!("Failed to send byebye on drop: {}", err);
+        }
+    }
+}
+
+impl Instance {
+    /// Set the boot id, panicking if it has more than 31 bits.
+    pub(crate) fn set_boot_id(&mut self, value: u32) {
+        assert!(value <= MAX_BOOT_ID, "boot id {} is larger than 2^31 - 1", value);
+        self.boot_id = Some(BootID(value));
+    }
+
+    /// Increase the config id, starting over at zero after the largest one.
+    pub(crate) fn bump_config_id(&mut self) -> ConfigID {
+        let next = match self.config_id {
+            Some(ConfigID(current)) if current < MAX_CONFIG_ID => current + 1,
+            Some(_) => 0,
+            None => 1,
+        };
+
+        self.config_id = Some(ConfigID(next));
+        ConfigID(next)
+    }
+
+    /// Add the ids and the search port that are set to the message.
+    pub(crate) fn apply<M: HeaderMut>(&self, message: &mut M) {
+        if let Some(boot_id) = self.boot_id {
+            message.set(boot_id);
+        }
+
+        if let Some(config_id) = self.config_id {
+            message.set(config_id);
+        }
+
+        if let Some(search_port) = self.search_port {
+            message.set(search_port);
+        }
+    }
+}
+
+impl DeviceIdentity {
+    /// Construct the identity of the device with the given uuid, without any ids.
+    ///
+    /// The uuid is given without the `uuid:` prefix.
+    pub fn new<S: Into<String>>(uuid: S) -> DeviceIdentity {
+        DeviceIdentity {
+            uuid: uuid.into().into(),
+            instance: Arc::new(Mutex::new(Instance::default())),
+        }
+    }
+
+    /// Get the uuid of the device, without the `uuid:` prefix.
+    pub fn uuid(&self) -> &str {
+        &self.uuid
+    }
+
+    /// Set the BOOTID.UPNP.ORG of all following messages.
+    ///
+    /// See `Advertiser::set_boot_id`.
+    ///
+    /// # Panics
+    ///
+    /// If the value is larger than 2^31 - 1.
+    pub fn set_boot_id(&self, value: u32) {
+        self.instance.lock().unwrap().set_boot_id(value);
+    }
+
+    /// Get the BOOTID.UPNP.ORG of every message, if any.
+    pub fn boot_id(&self) -> Option<BootID> {
+        self.instance.lock().unwrap().boot_id
+    }
+
+    /// Increase the CONFIGID.UPNP.ORG of all following messages.
+    ///
+    /// See `Advertiser::bump_config_id`.
+    pub fn bump_config_id(&self) -> ConfigID {
+        self.instance.lock().unwrap().bump_config_id()
+    }
+
+    /// Get the CONFIGID.UPNP.ORG of every message, if any.
+    pub fn config_id(&self) -> Option<ConfigID> {
+        self.instance.lock().unwrap().config_id
+    }
+
+    /// Set the SEARCHPORT.UPNP.ORG of all following messages, other than `ssdp:byebye`.
+    ///
+    /// Devices that can not answer unicast searches on port 1900 announce the port they answer
+    /// them on instead, none announces the default port.
+    ///
+    /// # Panics
+    ///
+    /// If the port is below 49152, which `UPnP` 1.1 does not allow.
+    pub fn set_search_port(&self, port: Option<u16>) {
+        if let Some(port) = port {
+            assert!(port >= SEARCHPORT_MIN_VALUE, "search port {} is below 49152", port);
+        }
+        self.instance.lock().unwrap().search_port = port.map(SearchPort);
+    }
+
+    /// Get the SEARCHPORT.UPNP.ORG of every message, if any.
+    pub fn search_port(&self) -> Option<SearchPort> {
+        self.instance.lock().unwrap().search_port
+    }
+}
+
+/// Schedule an alive message for every entry to be multicast at each of the times.
+fn announce(
+    context: &SsdpContext,
+    scheduler: &ScheduledSender,
+    entries: &[Advertisement],
+    max_age: Duration,
+    instance: &Instance,
+    config: &Config,
+    times: &[Instant],
+) {
+    for entry in entries {
+        let mut alive = entry.alive(max_age);
+        instance.apply(&mut alive);
+
+        if let Err(err) = alive.schedule_multicast_with_context(context, scheduler, config, times) {
+            debug!("Failed to schedule alive message: {}", err);
+        }
+    }
+}
+
+/// Sleep for the duration by the clock of the context, returns `true` if the advertiser should stop.
+fn wait(context: &SsdpContext, stopped: &Receiver<()>, duration: Duration) -> bool {
+    let deadline = context.now() + duration;
+
+    loop {
+        let remaining = deadline.saturating_duration_since(context.now());
+        if remaining.is_zero() {
+            return false;
+        }
+
+        match stopped.recv_timeout(remaining.min(MAX_WAIT)) {
+            Err(RecvTimeoutError::Timeout) => {}
+            Ok(()) | Err(RecvTimeoutError::Disconnected) => return true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+
+    use headers::{HeaderMap, HeaderMapExt as _, HeaderValue, Location, Server};
+
+    use super::{expand_targets, Advertisement, Advertiser, DeviceDescriptionLite, DeviceIdentity, Instance};
+    use super::{MAX_BOOT_ID, MAX_CONFIG_ID};
+    use crate::context::{SsdpContext, SsdpObserver};
+    use crate::header::{BootID, ConfigID, HeaderMut, Man, NextBootID, SearchPort, NT, NTS, ST, USN};
+    use crate::message::{Config, NotifyListener, NotifyMessage, SearchRequest, SearchResponder};
+    use crate::net::NetifAddr;
+    use crate::receiver::FromRawSSDP;
+    use crate::{FieldMap, SSDPError};
+
+    /// Records the packets sent, in order.
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<Vec<u8>>>);
+
+    impl SsdpObserver for Recorder {
+        fn on_send(&self, _: &SocketAddr, bytes: &[u8]) {
+            self.0.lock().unwrap().push(bytes.to_vec());
+        }
+    }
+
+    fn entry() -> Advertisement {
+        let location = HeaderValue::from_static("http://127.0.0.1:8080/description.xml");
+        let location = headers::Header::decode(&mut [location].iter()).unwrap();
+
+        Advertisement::new(
+            NT(FieldMap::upnp("rootdevice")),
+            USN(FieldMap::uuid("advertiser-test"), Some(FieldMap::upnp("rootdevice"))),
+            location,
+            "test/1.0 UPnP/1.1 ssdp/0.7".parse::<Server>().unwrap(),
+        )
+    }
+
+    #[test]
+    fn positive_alive_headers() {
+        let message = entry().alive(Duration::from_secs(1800));
+        let headers = message.headers();
+
+        assert_eq!(headers.typed_get::<NTS>(), Some(NTS::Alive));
+        assert!(headers.typed_get::<Location>().is_some());
+        assert_eq!(
+            headers.typed_get::<headers::CacheControl>().unwrap().max_age(),
+            Some(Duration::from_secs(1800))
+        );
+    }
+
+    #[test]
+    fn positive_byebye_headers() {
+        let message = entry().byebye();
+        let headers = message.headers();
+
+        assert_eq!(headers.typed_get::<NTS>(), Some(NTS::ByeBye));
+        assert!(headers.typed_get::<Location>().is_none());
+    }
+
+    fn media_server() -> DeviceDescriptionLite {
+        DeviceDescriptionLite::new("root", "schemas-upnp-org:device:MediaServer:1")
+            .service("schemas-upnp-org:service:ContentDirectory:1")
+            .embed(
+                DeviceDescriptionLite::new("embedded", "schemas-upnp-org:device:Printer:1")
+                    .service("schemas-upnp-org:service:PrintBasic:1")
+                    .service("schemas-upnp-org:service:PrintBasic:1"),
+            )
+    }
+
+    #[test]
+    fn positive_expand_targets() {
+        let root = FieldMap::uuid("root");
+        let embedded = FieldMap::uuid("embedded");
+        let typed = |uuid: &FieldMap, urn: &str| {
+            (NT(FieldMap::urn(urn)), USN(uuid.clone(), Some(FieldMap::urn(urn))))
+        };
+
+        let expected = vec![
+            (NT(FieldMap::upnp("rootdevice")), USN(root.clone(), Some(FieldMap::upnp("rootdevice")))),
+            (NT(root.clone()), USN(root.clone(), None)),
+            typed(&root, "schemas-upnp-org:device:MediaServer:1"),
+            typed(&root, "schemas-upnp-org:service:ContentDirectory:1"),
+            (NT(embedded.clone()), USN(embedded.clone(), None)),
+            typed(&embedded, "schemas-upnp-org:device:Printer:1"),
+            typed(&embedded, "schemas-upnp-org:service:PrintBasic:1"),
+        ];
+        // 3 + 2 * 1 embedded device + 2 distinct services
+        assert_eq!(expand_targets(&media_server()), Ok(expected));
+
+        let advertiser = Advertiser::new(Duration::from_secs(1800))
+            .register_device(&media_server(), entry().location, entry().server)
+            .unwrap();
+        assert_eq!(advertiser.entries().len(), 7);
+    }
+
+    #[test]
+    fn negative_expand_targets() {
+        assert!(expand_targets(&DeviceDescriptionLite::default()).is_err());
+
+        let empty_service = media_server().service("");
+        assert!(expand_targets(&empty_service).is_err());
+
+        let broken_embedded = media_server().embed(DeviceDescriptionLite::new("a\r\nST: ssdp:all", "x"));
+        assert!(expand_targets(&broken_embedded).is_err());
+
+        let advertiser = Advertiser::new(Duration::from_secs(1800));
+        let empty = DeviceDescriptionLite::default();
+        assert!(advertiser.register_device(&empty, entry().location, entry().server).is_err());
+    }
+
+    #[test]
+    fn positive_config_id_wraps() {
+        let mut instance = Instance::default();
+        assert_eq!(instance.bump_config_id(), ConfigID(1));
+
+        instance.config_id = Some(ConfigID(MAX_CONFIG_ID));
+        assert_eq!(instance.bump_config_id(), ConfigID(0));
+    }
+
+    #[test]
+    fn positive_update_not_running() {
+        let advertiser = Advertiser::new(Duration::from_secs(1800)).register(entry());
+        assert!(matches!(advertiser.announce_update(), Err(SSDPError::MissingHeader(_))));
+
+        advertiser.set_boot_id(MAX_BOOT_ID - 1);
+        assert_eq!(advertiser.announce_update().unwrap(), BootID(MAX_BOOT_ID));
+        assert_eq!(advertiser.announce_update().unwrap(), BootID(0));
+        assert_eq!(advertiser.boot_id(), Some(BootID(0)));
+    }
+
+    #[test]
+    #[should_panic]
+    fn negative_boot_id_too_large() {
+        Advertiser::new(Duration::from_secs(1800)).set_boot_id(MAX_BOOT_ID + 1);
+    }
+
+    #[test]
+    #[should_panic]
+    fn negative_search_port_too_small() {
+        DeviceIdentity::new("advertiser-test").set_search_port(Some(1900));
+    }
+
+    #[test]
+    fn positive_update_sequence() {
+        let context = SsdpContext::new().set_interfaces(|| -> io::Result<Vec<NetifAddr>> {
+            Ok(vec![NetifAddr {
+                sock: IpAddr::V4(Ipv4Addr::LOCALHOST),
+                index: 1,
+            }])
+        });
+        let clock = Arc::new(Mutex::new(Instant::now()));
+        let thread_clock = Arc::clone(&clock);
+        let context = context.set_clock(move || *thread_clock.lock().unwrap());
+        let advance = |duration: Duration| *clock.lock().unwrap() += duration;
+
+        let recorder = Arc::new(Recorder::default());
+        // Advance the clock in small steps until the messages were sent, the thread notices a step
+        // within a tenth of a second.
+        let advance_until_sent = |count: usize| {
+            let deadline = Instant::now() + Duration::from_secs(5);
+            while recorder.0.lock().unwrap().len() < count {
+                assert!(Instant::now() < deadline, "{} messages were not sent", count);
+                advance(Duration::from_millis(50));
+                std::thread::sleep(Duration::from_millis(20));
+            }
+        };
+        let config = Config::new()
+            .set_port(19_028)
+            .set_mode(crate::IpVersionMode::V4Only)
+            .set_canonical_header_order(true)
+            .set_observer(recorder.clone());
+
+        let mut advertiser = Advertiser::new(Duration::from_secs(1)).register(entry());
+        advertiser.set_boot_id(5);
+        assert_eq!(advertiser.bump_config_id(), ConfigID(1));
+        advertiser.start_with_context(&context, &config).unwrap();
+
+        // The initial burst of alive messages, then the update is sent right away.
+        advance_until_sent(3);
+        assert_eq!(advertiser.announce_update().unwrap(), BootID(6));
+        assert_eq!(recorder.0.lock().unwrap().len(), 4);
+        // The next alive message is sent after half the max-age.
+        advance_until_sent(5);
+        advertiser.stop().unwrap();
+
+        let packets = recorder.0.lock().unwrap().clone();
+        let messages: Vec<_> = packets
+            .iter()
+            .map(|packet| NotifyMessage::from_packet(packet).unwrap())
+            .collect();
+        let ids = |message: &NotifyMessage| {
+            let headers = message.headers();
+            (
+                headers.typed_get::<NTS>().unwrap(),
+                headers.typed_get::<BootID>(),
+                headers.typed_get::<NextBootID>(),
+                headers.typed_get::<ConfigID>(),
+            )
+        };
+
+        let update = messages
+            .iter()
+            .position(|message| message.headers().typed_get::<NTS>() == Some(NTS::Update))
+            .unwrap();
+        for message in &messages[..update] {
+            assert_eq!(ids(message), (NTS::Alive, Some(BootID(5)), None, Some(ConfigID(1))));
+        }
+        assert!(update >= 1);
+
+        let expected = (NTS::Update, Some(BootID(5)), Some(NextBootID(6)), Some(ConfigID(1)));
+        assert_eq!(ids(&messages[update]), expected);
+
+        let (last, after) = messages[update + 1..].split_last().unwrap();
+        assert!(!after.is_empty());
+        for message in after {
+            assert_eq!(ids(message), (NTS::Alive, Some(BootID(6)), None, Some(ConfigID(1))));
+        }
+        assert_eq!(ids(last), (NTS::ByeBye, Some(BootID(6)), None, Some(ConfigID(1))));
+
+        // The ids follow the SSDP headers in the canonical order.
+        let packet = String::from_utf8_lossy(&packets[update]).into_owned();
+        let offsets: Vec<_> = ["USN:", "BOOTID.UPNP.ORG:", "CONFIGID.UPNP.ORG:", "NEXTBOOTID.UPNP.ORG:"]
+            .iter()
+            .map(|name| packet.find(name).unwrap())
+            .collect();
+        assert!(offsets.windows(2).all(|pair| pair[0] < pair[1]), "{}", packet);
+    }
+
+    #[test]
+    fn positive_burst_by_clock() {
+        let context = SsdpContext::new().set_interfaces(|| -> io::Result<Vec<NetifAddr>> {
+            Ok(vec![NetifAddr {
+                sock: IpAddr::V4(Ipv4Addr::LOCALHOST),
+                index: 1,
+            }])
+        });
+        let clock = Arc::new(Mutex::new(Instant::now()));
+        let thread_clock = Arc::clone(&clock);
+        let context = context.set_clock(move || *thread_clock.lock().unwrap());
+
+        let recorder = Arc::new(Recorder::default());
+        let sent = || recorder.0.lock().unwrap().len();
+        let wait_until_sent = |count: usize| {
+            let deadline = Instant::now() + Duration::from_secs(5);
+            while sent() < count {
+                assert!(Instant::now() < deadline, "{} messages were not sent", count);
+                std::thread::sleep(Duration::from_millis(20));
+            }
+        };
+        let config = Config::new()
+            .set_port(19_045)
+            .set_mode(crate::IpVersionMode::V4Only)
+            .set_observer(recorder.clone());
+
+        let mut advertiser = Advertiser::new(Duration::from_secs(1800)).register(entry());
+        advertiser.start_with_context(&context, &config).unwrap();
+
+        // The repetitions of the burst wait for the clock of the context.
+        wait_until_sent(1);
+        std::thread::sleep(Duration::from_millis(300));
+        assert_eq!(sent(), 1);
+        *clock.lock().unwrap() += Duration::from_millis(100);
+        wait_until_sent(2);
+
+        // The last repetition is dropped, nothing follows the byebye.
+        advertiser.stop().unwrap();
+        *clock.lock().unwrap() += Duration::from_millis(100);
+        std::thread::sleep(Duration::from_millis(300));
+
+        let nts: Vec<_> = recorder
+            .0
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|packet| NotifyMessage::from_packet(packet).unwrap().nts().unwrap())
+            .collect();
+        assert_eq!(nts, vec![NTS::Alive, NTS::Alive, NTS::ByeBye]);
+    }
+
+    #[test]
+    fn negative_stop_sends_every_byebye() {
+        use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+        // Binding the sockets of a byebye fails once the interfaces can not be listed anymore.
+        let failing = Arc::new(AtomicBool::new(false));
+        let calls = Arc::new(AtomicUsize::new(0));
+        let failed = Arc::new(AtomicUsize::new(0));
+        let (thread_failing, thread_calls, thread_failed) = (failing.clone(), calls.clone(), failed.clone());
+        let context = SsdpContext::new().set_interfaces(move || -> io::Result<Vec<NetifAddr>> {
+            thread_calls.fetch_add(1, Ordering::Relaxed);
+            if thread_failing.load(Ordering::Relaxed) {
+                thread_failed.fetch_add(1, Ordering::Relaxed);
+                return Err(io::ErrorKind::NotFound.into());
+            }
+
+            Ok(vec![NetifAddr {
+                sock: IpAddr::V4(Ipv4Addr::LOCALHOST),
+                index: 1,
+            }])
+        });
+        let config = Config::new()
+            .set_port(19_048)
+            .set_mode(crate::IpVersionMode::V4Only);
+
+        let mut second = entry();
+        second.usn = USN(FieldMap::uuid("advertiser-test"), None);
+        let mut advertiser = Advertiser::new(Duration::from_secs(1800)).register(entry()).register(second);
+        advertiser.start_with_context(&context, &config).unwrap();
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        // One round of alive messages for both entries.
+        while calls.load(Ordering::Relaxed) < 2 {
+            assert!(Instant::now() < deadline, "The advertiser did not announce");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        failing.store(true, Ordering::Relaxed);
+        assert!(advertiser.stop().is_err());
+        assert_eq!(failed.load(Ordering::Relaxed), 2);
+        assert!(!advertiser.is_running());
+    }
+
+    #[test]
+    fn positive_shared_identity() {
+        let context = SsdpContext::new().set_interfaces(|| -> io::Result<Vec<NetifAddr>> {
+            Ok(vec![NetifAddr {
+                sock: IpAddr::V4(Ipv4Addr::LOCALHOST),
+                index: 1,
+            }])
+        });
+        let recorder = Arc::new(Recorder::default());
+        let config = Config::new()
+            .set_port(19_042)
+            .set_mode(crate::IpVersionMode::V4Only)
+            .set_include_loopback(true)
+            .set_observer(recorder.clone());
+
+        let identity = DeviceIdentity::new("advertiser-test");
+        identity.set_boot_id(7);
+        identity.bump_config_id();
+        identity.bump_config_id();
+        identity.set_search_port(Some(49_200));
+
+        let entry = entry();
+        let max_age = Duration::from_secs(1);
+        let responder = SearchResponder::with_identity(&identity, entry.server.clone(), max_age)
+            .target(entry.nt.clone())
+            .unwrap();
+        responder.set_location(entry.location.clone());
+        let _responder = context.responder(&config, responder).unwrap();
+
+        let mut advertiser = Advertiser::with_identity(&identity, max_age).register(entry);
+        advertiser.start_with_context(&context, &config).unwrap();
+
+        let search = || {
+            let mut request = SearchRequest::new();
+            request.set(Man);
+            request.set(ST::All);
+
+            let dst = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 19_042);
+            let receiver = request.unicast_with_context(&context, dst, &config).unwrap();
+            receiver.recv_timeout(Duration::from_secs(5)).unwrap().0
+        };
+        let ids = |headers: &HeaderMap| {
+            (
+                headers.typed_get::<BootID>(),
+                headers.typed_get::<ConfigID>(),
+                headers.typed_get::<SearchPort>(),
+            )
+        };
+
+        let before = (Some(BootID(7)), Some(ConfigID(2)), Some(SearchPort(49_200)));
+        assert_eq!(ids(search().headers()), before);
+
+        // Bumped through the identity, the advertiser and the responder follow at once.
+        assert_eq!(identity.bump_config_id(), ConfigID(3));
+        let after = (Some(BootID(7)), Some(ConfigID(3)), Some(SearchPort(49_200)));
+        assert_eq!(ids(search().headers()), after);
+        assert_eq!(advertiser.config_id(), Some(ConfigID(3)));
+
+        // Long enough for the next alive message, which is sent after half the max-age.
+        std::thread::sleep(Duration::from_millis(600));
+        advertiser.stop().unwrap();
+
+        let notifies: Vec<_> = recorder
+            .0
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|packet| NotifyMessage::from_packet(packet).ok())
+            .collect();
+        let (byebye, alive) = notifies.split_last().unwrap();
+
+        assert_eq!(ids(alive[0].headers()), before);
+        assert_eq!(ids(alive.last().unwrap().headers()), after);
+        assert_eq!(byebye.nts(), Some(NTS::ByeBye));
+        assert_eq!(ids(byebye.headers()), (Some(BootID(7)), Some(ConfigID(3)), None));
+    }
+
+    #[test]
+    fn positive_alive_then_byebye() {
+        let config = Config::new().set_port(19_001).set_mode(crate::IpVersionMode::V4Only);
+        let listener = NotifyListener::listen_with_config(&config).unwrap();
+
+        let mut advertiser = Advertiser::new(Duration::from_secs(2)).register(entry());
+        advertiser.start(&config).unwrap();
+
+        let (first, _) = listener.recv().unwrap();
+        assert_eq!(first.headers().typed_get::<NTS>(), Some(NTS::Alive));
+
+        drop(advertiser);
+
+        loop {
+            let (message, _) = listener.recv().unwrap();
+            match message.headers().typed_get::<NTS>() {
+                Some(NTS::Alive) => continue,
+                Some(NTS::ByeBye) => break,
+                other => panic!("Unexpected NTS {:?}", other),
+            }
+        }
+    }
+}
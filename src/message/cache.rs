@@ -0,0 +1,339 @@
+//! Tracking of the devices and services that are currently alive.
+
+use std::collections::HashMap;
+use std::sync::mpsc::{self, Receiver, RecvError, RecvTimeoutError, Sender, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use headers::{HeaderMap, HeaderMapExt as _, Location, Server};
+
+use crate::context::SsdpContext;
+use crate::header::{SsdpMaxAge, NTS, USN};
+use crate::message::{NotifyMessage, SearchResponse};
+
+/// Enumerates changes to the cache that were not caused by an ingested message.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CacheEvent {
+    /// The max-age of an entry elapsed without it being refreshed.
+    Expired(USN),
+}
+
+/// A device or service that is currently alive.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DeviceRecord {
+    pub usn: USN,
+    pub location: Location,
+    pub server: Option<Server>,
+    /// The time after which the entry is considered gone, unless refreshed.
+    pub expires: Instant,
+}
+
+/// Tracks the devices and services that are currently alive.
+///
+/// Entries are added or refreshed by ingesting search responses and `ssdp:alive` messages and
+/// removed by `ssdp:byebye` messages. Entries whose max-age elapsed are removed by `tick`, which
+/// is either called by the user or periodically by a background thread after `start` was called.
+pub struct DeviceCache {
+    shared: Arc<Shared>,
+    events: Receiver<CacheEvent>,
+    running: Option<Running>,
+}
+
+/// The state shared with the expiry thread.
+struct Shared {
+    context: SsdpContext,
+    entries: Mutex<HashMap<USN, DeviceRecord>>,
+    events: Sender<CacheEvent>,
+}
+
+struct Running {
+    stop: Sender<()>,
+    /// Disconnects once the expiry thread exited.
+    done: Receiver<()>,
+}
+
+impl DeviceCache {
+    /// Construct an empty DeviceCache.
+    pub fn new() -> DeviceCache {
+        DeviceCache::with_context(SsdpContext::global())
+    }
+
+    /// Construct an empty DeviceCache using the clock and spawner of the context.
+    pub(crate) fn with_context(context: &SsdpContext) -> DeviceCache {
+        let (events_send, events) = mpsc::channel();
+
+        DeviceCache {
+            shared: Arc::new(Shared {
+                context: context.clone(),
+                entries: Mutex::new(HashMap::new()),
+                events: events_send,
+            }),
+            events,
+            running: None,
+        }
+    }
+
+    /// Add or refresh the entry announced by a search response.
+    pub fn ingest_response(&self, response: &SearchResponse) {
+        self.shared.alive(response.headers());
+    }
+
+    /// Add, refresh or remove the entry announced by a notify message.
+    pub fn ingest_notify(&self, message: &NotifyMessage) {
+        let headers = message.headers();
+
+        match headers.typed_get::<NTS>() {
+            Some(NTS::Alive) => self.shared.alive(headers),
+            Some(NTS::ByeBye) => self.shared.byebye(headers),
+            Some(NTS::Update) | None => {}
+        }
+    }
+
+    /// Remove all entries whose max-age elapsed, emitting an event for each.
+    pub fn tick(&self) {
+        self.shared.tick();
+    }
+
+    /// Get all entries that are currently alive.
+    pub fn snapshot(&self) -> Vec<DeviceRecord> {
+        self.shared.entries.lock().unwrap().values().cloned().collect()
+    }
+
+    /// Check if the expiry thread is currently running.
+    pub fn is_running(&self) -> bool {
+        self.running.is_some()
+    }
+
+    /// Start a background thread calling `tick` in the given interval.
+    ///
+    /// If the thread is already running it is stopped first.
+    pub fn start(&mut self, interval: Duration) {
+        self.stop();
+
+        let (stop, stopped) = mpsc::channel();
+        let (done_send, done) = mpsc::channel::<()>();
+        let shared = Arc::clone(&self.shared);
+
+        self.shared.context.spawn(move || {
+            let _done = done_send;
+
+            loop {
+                shared.tick();
+
+                match stopped.recv_timeout(interval) {
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    Ok(()) | Err(RecvTimeoutError::Disconnected) => return,
+                }
+            }
+        });
+
+        self.running = Some(Running { stop, done });
+    }
+
+    /// Stop the expiry thread.
+    ///
+    /// Does nothing if the thread is not running.
+    pub fn stop(&mut self) {
+        let Some(running) = self.running.take() else {
+            return;
+        };
+
+        // The thread may have already exited, in which case there is no one to notify.
+        let _ = running.stop.send(());
+        let _ = running.done.recv();
+    }
+
+    /// Non-blocking method that attempts to read an event.
+    pub fn try_recv(&self) -> Result<CacheEvent, TryRecvError> {
+        self.events.try_recv()
+    }
+
+    /// Blocking method that reads an event until one is available.
+    pub fn recv(&self) -> Result<CacheEvent, RecvError> {
+        self.events.recv()
+    }
+
+    /// Blocking method that reads an event until one is available or the timeout elapsed.
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<CacheEvent, RecvTimeoutError> {
+        self.events.recv_timeout(timeout)
+    }
+}
+
+impl Default for DeviceCache {
+    fn default() -> Self {
+        DeviceCache::new()
+    }
+}
+
+impl Drop for DeviceCache {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+impl Shared {
+    fn alive(&self, headers: &HeaderMap) {
+        let Some(usn) = headers.typed_get::<USN>() else {
+            return;
+        };
+
+        let Some(location) = headers.typed_get::<Location>() else {
+            debug!("Ignoring {:?} without a location", usn);
+            return;
+        };
+
+        let Some(SsdpMaxAge(max_age)) = headers.typed_get::<SsdpMaxAge>() else {
+            debug!("Ignoring {:?} without a max-age", usn);
+            return;
+        };
+
+        let record = DeviceRecord {
+            usn: usn.clone(),
+            location,
+            server: headers.typed_get::<Server>(),
+            expires: self.context.now() + max_age,
+        };
+
+        self.entries.lock().unwrap().insert(usn, record);
+    }
+
+    fn byebye(&self, headers: &HeaderMap) {
+        if let Some(usn) = headers.typed_get::<USN>() {
+            self.entries.lock().unwrap().remove(&usn);
+        }
+    }
+
+    fn tick(&self) {
+        let now = self.context.now();
+        let mut expired = Vec::new();
+
+        self.entries.lock().unwrap().retain(|usn, record| {
+            if record.expires > now {
+                return true;
+            }
+
+            expired.push(usn.clone());
+            false
+        });
+
+        for usn in expired {
+            let _ = self.events.send(CacheEvent::Expired(usn));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::time::{Duration, Instant};
+
+    use headers::{HeaderValue, Server};
+
+    use super::{CacheEvent, DeviceCache};
+    use crate::context::SsdpContext;
+    use crate::header::{NT, USN};
+    use crate::message::{Advertisement, Config, NotifyMessage};
+    use crate::net::NetifAddr;
+    use crate::receiver::FromRawSSDP;
+    use crate::FieldMap;
+
+    fn loopback() -> io::Result<Vec<NetifAddr>> {
+        Ok(vec![NetifAddr {
+            sock: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            index: 1,
+        }])
+    }
+
+    fn usn() -> USN {
+        USN(FieldMap::uuid("cache-test"), Some(FieldMap::upnp("rootdevice")))
+    }
+
+    fn entry() -> Advertisement {
+        let location = HeaderValue::from_static("http://127.0.0.1:8080/description.xml");
+
+        Advertisement::new(
+            NT(FieldMap::upnp("rootdevice")),
+            usn(),
+            headers::Header::decode(&mut [location].iter()).unwrap(),
+            "test/1.0 UPnP/1.1 ssdp/0.7".parse::<Server>().unwrap(),
+        )
+    }
+
+    /// Send a single alive message with a max-age of one second and receive it on loopback.
+    fn received_alive(context: &SsdpContext, port: u16) -> NotifyMessage {
+        let config = Config::new()
+            .set_port(port)
+            .set_mode(crate::IpVersionMode::V4Only)
+            .set_interfaces([IpAddr::V4(Ipv4Addr::LOCALHOST)]);
+        let listener = context.listen::<NotifyMessage>(&config).unwrap();
+
+        let alive = entry().alive(Duration::from_secs(1));
+        alive.multicast_with_context(context, &config).unwrap();
+
+        listener.recv_timeout(Duration::from_secs(5)).unwrap().0
+    }
+
+    #[test]
+    fn positive_tick_expires() {
+        let context = SsdpContext::new().set_interfaces(loopback);
+        let cache = DeviceCache::with_context(&context);
+
+        cache.ingest_notify(&received_alive(&context, 19_007));
+        assert_eq!(cache.snapshot().len(), 1);
+        assert_eq!(cache.snapshot()[0].usn, usn());
+
+        cache.tick();
+        assert!(cache.try_recv().is_err());
+
+        std::thread::sleep(Duration::from_millis(1100));
+        cache.tick();
+
+        assert_eq!(cache.try_recv(), Ok(CacheEvent::Expired(usn())));
+        assert!(cache.snapshot().is_empty());
+    }
+
+    #[test]
+    fn positive_thread_expires() {
+        let context = SsdpContext::new().set_interfaces(loopback);
+        let mut cache = DeviceCache::with_context(&context);
+
+        cache.ingest_notify(&received_alive(&context, 19_008));
+        cache.start(Duration::from_millis(100));
+
+        let event = cache.recv_timeout(Duration::from_secs(3)).unwrap();
+        assert_eq!(event, CacheEvent::Expired(usn()));
+        assert!(cache.snapshot().is_empty());
+    }
+
+    #[test]
+    fn positive_padded_max_age() {
+        let cache = DeviceCache::new();
+        let raw = "NOTIFY * HTTP/1.1\r\nHOST: 239.255.255.250:1900\r\nCACHE-CONTROL: max-age = 66\r\n\
+            LOCATION: http://192.168.1.2:80/description.xml\r\nNT: upnp:rootdevice\r\nNTS: ssdp:alive\r\n\
+            USN: uuid:2f402f80-da50-11e1-9b23-001788255acc::upnp:rootdevice\r\n\r\n";
+
+        let before = Instant::now();
+        cache.ingest_notify(&NotifyMessage::from_packet(raw.as_bytes()).unwrap());
+
+        let snapshot = cache.snapshot();
+        assert_eq!(snapshot.len(), 1);
+        assert!(snapshot[0].expires >= before + Duration::from_secs(66));
+        assert!(snapshot[0].expires <= Instant::now() + Duration::from_secs(66));
+    }
+
+    #[test]
+    fn positive_byebye_removes() {
+        let cache = DeviceCache::new();
+
+        cache.ingest_notify(&entry().alive(Duration::from_secs(1800)));
+        assert_eq!(cache.snapshot().len(), 1);
+
+        cache.ingest_notify(&entry().byebye());
+        assert!(cache.snapshot().is_empty());
+
+        cache.tick();
+        assert!(cache.try_recv().is_err());
+    }
+}
@@ -0,0 +1,366 @@
+//! Checks of messages against the SSDP rules of the UPnP Device Architecture 1.1.
+//!
+//! Parsing only rejects messages that are not valid HTTP. The validators here instead report
+//! every rule that a message breaks, for example to certify a device or to find out why a device
+//! is not discovered by stricter control points.
+
+use std::net::IpAddr;
+
+use headers::{CacheControl, Header, HeaderMap, HeaderMapExt as _, Host, Location, Server};
+
+use crate::header::{BootID, ConfigID, Ext, Man, NextBootID, MX, NT, NTS, ST, USN};
+use crate::message::{NotifyMessage, SearchRequest, SearchResponse};
+use crate::FieldMap;
+
+/// The range of MX values allowed for multicast searches since UPnP 1.1.
+const MX_RANGE: std::ops::RangeInclusive<u8> = 1..=5;
+
+/// A rule of UPnP 1.1 that a message breaks.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Violation {
+    /// A header that is required for the kind of message is missing.
+    MissingHeader(&'static str),
+    /// A header is present but its value can not be decoded.
+    InvalidHeader(&'static str),
+    /// The MAN header is `ssdp:discover` without the required quotes.
+    ManNotQuoted,
+    /// The MX of a multicast search is not between 1 and 5 seconds.
+    MxOutOfRange(u8),
+    /// The CACHE-CONTROL header has no `max-age` directive.
+    MaxAgeMissing,
+    /// An `ssdp:byebye` message carries a LOCATION.
+    ByeByeWithLocation,
+    /// A search response does not carry the empty EXT header.
+    ResponseMissingExt,
+    /// The USN does not name the notification type or search target of the message.
+    UsnMismatch,
+    /// A search response has a status code other than 200.
+    StatusCode(u16),
+}
+
+/// Validate a notify message with the rules for its NTS.
+///
+/// All messages need HOST, NT, NTS, USN, BOOTID and CONFIGID. An `ssdp:alive` additionally needs
+/// CACHE-CONTROL, LOCATION and SERVER, an `ssdp:update` needs LOCATION and NEXTBOOTID, and an
+/// `ssdp:byebye` must not have a LOCATION.
+pub fn validate_notify(message: &NotifyMessage) -> Vec<Violation> {
+    let headers = message.headers();
+    let mut violations = Vec::new();
+
+    require::<Host>(headers, &mut violations);
+    let nt = require::<NT>(headers, &mut violations);
+    let nts = require::<NTS>(headers, &mut violations);
+    let usn = require::<USN>(headers, &mut violations);
+    require::<BootID>(headers, &mut violations);
+    require::<ConfigID>(headers, &mut violations);
+
+    match nts {
+        Some(NTS::Alive) => {
+            require_max_age(headers, &mut violations);
+            require::<Location>(headers, &mut violations);
+            require::<Server>(headers, &mut violations);
+        }
+        Some(NTS::Update) => {
+            require::<Location>(headers, &mut violations);
+            require::<NextBootID>(headers, &mut violations);
+        }
+        Some(NTS::ByeBye) if headers.contains_key(Location::name()) => {
+            violations.push(Violation::ByeByeWithLocation);
+        }
+        Some(NTS::ByeBye) | None => {}
+    }
+
+    if let (Some(NT(target)), Some(usn)) = (nt, usn) {
+        if !usn_names(&usn, &target) {
+            violations.push(Violation::UsnMismatch);
+        }
+    }
+
+    violations
+}
+
+/// Validate a search request.
+///
+/// All requests need HOST, MAN and ST. Requests to a multicast group also need an MX between 1
+/// and 5, while unicast requests are answered immediately and may leave it out.
+pub fn validate_search(message: &SearchRequest) -> Vec<Violation> {
+    let headers = message.headers();
+    let mut violations = Vec::new();
+
+    let host = require::<Host>(headers, &mut violations);
+
+    match headers.get(Man::name()) {
+        None => violations.push(Violation::MissingHeader(Man::name().as_str())),
+        Some(value) if value == "ssdp:discover" => violations.push(Violation::ManNotQuoted),
+        Some(_) => {
+            if headers.typed_try_get::<Man>().is_err() {
+                violations.push(Violation::InvalidHeader(Man::name().as_str()));
+            }
+        }
+    }
+
+    require::<ST>(headers, &mut violations);
+
+    // Without a readable HOST, assume the common case of a multicast search.
+    let multicast = host.is_none_or(|host| is_multicast(&host));
+    match headers.get(MX::name()) {
+        None if multicast => violations.push(Violation::MissingHeader(MX::name().as_str())),
+        None => {}
+        Some(value) => match value.to_str().ok().and_then(|value| value.parse::<u8>().ok()) {
+            Some(mx) if multicast && !MX_RANGE.contains(&mx) => {
+                violations.push(Violation::MxOutOfRange(mx));
+            }
+            Some(_) => {}
+            None => violations.push(Violation::InvalidHeader(MX::name().as_str())),
+        },
+    }
+
+    violations
+}
+
+/// Validate a search response.
+///
+/// Responses need CACHE-CONTROL with a max-age, EXT, LOCATION, SERVER, ST, USN, BOOTID and
+/// CONFIGID. Responses with a status code other than 200 are only received when parsing
+/// leniently.
+pub fn validate_response(message: &SearchResponse) -> Vec<Violation> {
+    let headers = message.headers();
+    let mut violations = Vec::new();
+
+    if message.status_code() != 200 {
+        violations.push(Violation::StatusCode(message.status_code()));
+    }
+
+    require_max_age(headers, &mut violations);
+    match headers.typed_try_get::<Ext>() {
+        Ok(Some(_)) => {}
+        Ok(None) => violations.push(Violation::ResponseMissingExt),
+        Err(_) => violations.push(Violation::InvalidHeader(Ext::name().as_str())),
+    }
+    require::<Location>(headers, &mut violations);
+    require::<Server>(headers, &mut violations);
+    let st = require::<ST>(headers, &mut violations);
+    let usn = require::<USN>(headers, &mut violations);
+    require::<BootID>(headers, &mut violations);
+    require::<ConfigID>(headers, &mut violations);
+
+    if let (Some(ST::Target(target)), Some(usn)) = (st, usn) {
+        if !usn_names(&usn, &target) {
+            violations.push(Violation::UsnMismatch);
+        }
+    }
+
+    violations
+}
+
+/// Decode a header, recording a violation if it is missing or invalid.
+fn require<H: Header>(headers: &HeaderMap, violations: &mut Vec<Violation>) -> Option<H> {
+    let name = H::name().as_str();
+
+    match headers.typed_try_get::<H>() {
+        Ok(Some(value)) => Some(value),
+        Ok(None) => {
+            violations.push(Violation::MissingHeader(name));
+            None
+        }
+        Err(_) => {
+            violations.push(Violation::InvalidHeader(name));
+            None
+        }
+    }
+}
+
+fn require_max_age(headers: &HeaderMap, violations: &mut Vec<Violation>) {
+    if let Some(cache_control) = require::<CacheControl>(headers, violations) {
+        if cache_control.max_age().is_none() {
+            violations.push(Violation::MaxAgeMissing);
+        }
+    }
+}
+
+/// Check if the USN names the type, either as its suffix or as the UUID of a device itself.
+fn usn_names(usn: &USN, target: &FieldMap) -> bool {
+    match (target, &usn.1) {
+        (FieldMap::UUID(_), None) => usn.0 == *target,
+        (_, Some(suffix)) => suffix == target,
+        (_, None) => false,
+    }
+}
+
+fn is_multicast(host: &Host) -> bool {
+    let hostname = host.hostname();
+    let hostname = hostname
+        .strip_prefix('[')
+        .and_then(|host| host.strip_suffix(']'))
+        .unwrap_or(hostname);
+
+    hostname.parse::<IpAddr>().is_ok_and(|ip| ip.is_multicast())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Violation::{self, *};
+    use super::{validate_notify, validate_response, validate_search};
+    use crate::message::{NotifyMessage, SearchRequest, SearchResponse};
+    use crate::receiver::{FromRawSSDP, ParseOptions};
+
+    const USN: &str = "uuid:2fac1234-31f8-11b4-a222-08002b34c003::upnp:rootdevice";
+
+    fn notify(head: &str) -> NotifyMessage {
+        let packet = format!("NOTIFY * HTTP/1.1\r\nHOST: 239.255.255.250:1900\r\n{}\r\n", head);
+        NotifyMessage::from_packet(packet.as_bytes()).unwrap()
+    }
+
+    fn search(head: &str) -> SearchRequest {
+        let packet = format!("M-SEARCH * HTTP/1.1\r\n{}\r\n", head);
+        SearchRequest::from_packet(packet.as_bytes()).unwrap()
+    }
+
+    fn response(status: &str, head: &str) -> SearchResponse {
+        let packet = format!("HTTP/1.1 {}\r\n{}\r\n", status, head);
+        let options = ParseOptions::new().set_lenient(true);
+        SearchResponse::from_packet_with_options(packet.as_bytes(), &options).unwrap()
+    }
+
+    #[test]
+    fn positive_notify_table() {
+        let ids = "BOOTID.UPNP.ORG: 1\r\nCONFIGID.UPNP.ORG: 1\r\n";
+        let alive = "CACHE-CONTROL: max-age=1800\r\nLOCATION: http://192.168.1.2:8080/description.xml\r\n\
+                     NT: upnp:rootdevice\r\nNTS: ssdp:alive\r\nSERVER: Linux/5.10 UPnP/1.1 ssdp/0.7\r\n";
+
+        let cases: Vec<(String, Vec<Violation>)> = vec![
+            (format!("{alive}USN: {USN}\r\n{ids}"), vec![]),
+            (format!("NT: upnp:rootdevice\r\nNTS: ssdp:byebye\r\nUSN: {USN}\r\n{ids}"), vec![]),
+            (
+                format!(
+                    "LOCATION: http://192.168.1.2/\r\nNT: upnp:rootdevice\r\nNTS: ssdp:update\r\n\
+                     USN: {USN}\r\n{ids}NEXTBOOTID.UPNP.ORG: 2\r\n"
+                ),
+                vec![],
+            ),
+            // A UPnP 1.0 device without the identifiers of the boot and configuration
+            (
+                format!("{alive}USN: {USN}\r\n"),
+                vec![
+                    MissingHeader("bootid.upnp.org"),
+                    MissingHeader("configid.upnp.org"),
+                ],
+            ),
+            (
+                format!(
+                    "LOCATION: http://192.168.1.2/\r\nNT: upnp:rootdevice\r\nNTS: ssdp:byebye\r\n\
+                     USN: {USN}\r\n{ids}"
+                ),
+                vec![ByeByeWithLocation],
+            ),
+            (
+                format!(
+                    "CACHE-CONTROL: no-cache\r\nNT: upnp:rootdevice\r\nNTS: ssdp:alive\r\n\
+                     USN: {USN}\r\n{ids}"
+                ),
+                vec![MaxAgeMissing, MissingHeader("location"), MissingHeader("server")],
+            ),
+            (
+                format!(
+                    "LOCATION: http://192.168.1.2/\r\nNT: upnp:rootdevice\r\nNTS: ssdp:update\r\n\
+                     USN: {USN}\r\n{ids}"
+                ),
+                vec![MissingHeader("nextbootid.upnp.org")],
+            ),
+            (format!("{alive}USN: uuid:2fac1234-31f8-11b4-a222-08002b34c003\r\n{ids}"), vec![UsnMismatch]),
+            (
+                format!("NT: upnp:rootdevice\r\nNTS: ssdp:sleeping\r\nUSN: {USN}\r\n{ids}"),
+                vec![InvalidHeader("nts")],
+            ),
+        ];
+
+        for (head, expected) in cases {
+            assert_eq!(validate_notify(&notify(&head)), expected, "{}", head);
+        }
+    }
+
+    #[test]
+    fn positive_root_uuid_notify() {
+        let uuid = "uuid:2fac1234-31f8-11b4-a222-08002b34c003";
+        let head = format!(
+            "NT: {uuid}\r\nNTS: ssdp:byebye\r\nUSN: {uuid}\r\nBOOTID.UPNP.ORG: 1\r\nCONFIGID.UPNP.ORG: 1\r\n"
+        );
+
+        assert_eq!(validate_notify(&notify(&head)), vec![]);
+    }
+
+    #[test]
+    fn positive_search_table() {
+        let cases = [
+            ("HOST: 239.255.255.250:1900\r\nMAN: \"ssdp:discover\"\r\nMX: 1\r\nST: ssdp:all\r\n", vec![]),
+            ("HOST: [FF02::C]:1900\r\nMAN: \"ssdp:discover\"\r\nMX: 5\r\nST: upnp:rootdevice\r\n", vec![]),
+            // Unicast searches are answered without delay
+            ("HOST: 192.168.1.2:1900\r\nMAN: \"ssdp:discover\"\r\nST: ssdp:all\r\n", vec![]),
+            (
+                "HOST: 239.255.255.250:1900\r\nMAN: ssdp:discover\r\nMX: 10\r\nST: ssdp:all\r\n",
+                vec![ManNotQuoted, MxOutOfRange(10)],
+            ),
+            (
+                "HOST: 239.255.255.250:1900\r\nMAN: \"ssdp:discover\"\r\nST: ssdp:all\r\n",
+                vec![MissingHeader("mx")],
+            ),
+            (
+                "HOST: 239.255.255.250:1900\r\nMX: soon\r\nST: ssdp:all\r\n",
+                vec![MissingHeader("man"), InvalidHeader("mx")],
+            ),
+            (
+                "HOST: 239.255.255.250:1900\r\nMAN: \"ssdp:discover\"\r\nMX: 0\r\n",
+                vec![MissingHeader("st"), MxOutOfRange(0)],
+            ),
+        ];
+
+        for (head, expected) in cases {
+            assert_eq!(validate_search(&search(head)), expected, "{}", head);
+        }
+    }
+
+    #[test]
+    fn positive_response_table() {
+        let compliant = "CACHE-CONTROL: max-age=1800\r\nEXT:\r\nLOCATION: http://192.168.1.2/\r\n\
+                         SERVER: Linux/5.10 UPnP/1.1 ssdp/0.7\r\nST: upnp:rootdevice\r\n";
+        let ids = "BOOTID.UPNP.ORG: 1\r\nCONFIGID.UPNP.ORG: 1\r\n";
+
+        let cases: Vec<(&str, String, Vec<Violation>)> = vec![
+            ("200 OK", format!("{compliant}USN: {USN}\r\n{ids}"), vec![]),
+            // A UPnP 1.0 router that leaves out EXT
+            (
+                "200 OK",
+                format!(
+                    "CACHE-CONTROL: max-age=120\r\nLOCATION: http://192.168.1.1:5000/rootDesc.xml\r\n\
+                     SERVER: Linux UPnP/1.0 miniupnpd/2.0\r\nST: upnp:rootdevice\r\nUSN: {USN}\r\n"
+                ),
+                vec![
+                    ResponseMissingExt,
+                    MissingHeader("bootid.upnp.org"),
+                    MissingHeader("configid.upnp.org"),
+                ],
+            ),
+            (
+                "200 OK",
+                format!("{compliant}USN: uuid:2fac1234-31f8-11b4-a222-08002b34c003::urn:x\r\n{ids}"),
+                vec![UsnMismatch],
+            ),
+            ("404 Not Found", format!("{compliant}USN: {USN}\r\n{ids}"), vec![StatusCode(404)]),
+            (
+                "200 OK",
+                format!("EXT: yes\r\nST: ssdp:all\r\nUSN: {USN}\r\n{ids}"),
+                vec![
+                    MissingHeader("cache-control"),
+                    InvalidHeader("ext"),
+                    MissingHeader("location"),
+                    MissingHeader("server"),
+                ],
+            ),
+        ];
+
+        for (status, head, expected) in cases {
+            assert_eq!(validate_response(&response(status, &head)), expected, "{}", head);
+        }
+    }
+}
@@ -0,0 +1,350 @@
+//! Fetching and parsing the device description behind the location of a discovered device.
+
+use std::io;
+use std::time::Duration;
+
+use quick_error::quick_error;
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use url::Url;
+
+use crate::message::DiscoveredDevice;
+
+quick_error! {
+    /// Enumerates the errors of fetching and parsing a device description.
+    #[derive(Debug)]
+    #[non_exhaustive]
+    pub enum DescribeError {
+        /// The request failed or the server responded with an error status.
+        Http(err: Box<ureq::Error>) {
+            display("failed to fetch the description: {}", err)
+            source(err)
+        }
+        /// The body of the response could not be read.
+        Io(err: io::Error) {
+            from()
+            display("failed to read the description: {}", err)
+            source(err)
+        }
+        /// The description is not well-formed XML.
+        Xml(err: quick_xml::Error) {
+            from()
+            display("invalid description XML: {}", err)
+            source(err)
+        }
+        /// A URL of the description can not be resolved against its base.
+        Url(err: url::ParseError) {
+            from()
+            display("invalid URL in the description: {}", err)
+            source(err)
+        }
+        /// A required element of the root device or one of its services is missing.
+        MissingElement(name: &'static str) {
+            display("missing element in the description: '{}'", name)
+        }
+    }
+}
+
+/// The root device of a description document.
+///
+/// Embedded devices and their services are not part of it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct DeviceDescription {
+    pub friendly_name: String,
+    pub device_type: String,
+    pub manufacturer: Option<String>,
+    /// The unique device name, a `uuid:` field that is also the prefix of the USN.
+    pub udn: String,
+    pub services: Vec<ServiceDescription>,
+}
+
+/// A service of the root device, with its URLs resolved against the base of the description.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ServiceDescription {
+    pub service_type: String,
+    pub control_url: Url,
+    pub scpd_url: Url,
+}
+
+/// The elements of a service collected so far.
+#[derive(Default)]
+struct PartialService {
+    service_type: Option<String>,
+    control_url: Option<String>,
+    scpd_url: Option<String>,
+}
+
+impl DiscoveredDevice {
+    /// Fetch the description of the device with an HTTP GET and parse its root device.
+    ///
+    /// The SECURELOCATION is fetched instead of the LOCATION if the response had one. The timeout
+    /// applies to the whole request, including the connection and reading the body.
+    pub fn fetch_description(&self, timeout: Duration) -> Result<DeviceDescription, DescribeError> {
+        let url = self.secure_location.as_ref().unwrap_or(&self.location);
+        let agent = ureq::AgentBuilder::new().timeout(timeout).build();
+
+        let document = agent
+            .get(url.as_str())
+            .call()
+            .map_err(|err| DescribeError::Http(Box::new(err)))?
+            .into_string()?;
+
+        DeviceDescription::parse(&document, url)
+    }
+}
+
+impl DeviceDescription {
+    /// Parse the root device of a description document fetched from the URL.
+    ///
+    /// Relative URLs of services are resolved against the `URLBase` of the document, if any, and
+    /// else against the URL of the document itself.
+    pub fn parse(document: &str, url: &Url) -> Result<DeviceDescription, DescribeError> {
+        let mut reader = Reader::from_str(document);
+        reader.config_mut().trim_text(true);
+
+        let mut path: Vec<String> = Vec::new();
+        let mut url_base = None;
+        let mut friendly_name = None;
+        let mut device_type = None;
+        let mut manufacturer = None;
+        let mut udn = None;
+        let mut service = PartialService::default();
+        let mut services = Vec::new();
+
+        loop {
+            match reader.read_event()? {
+                Event::Start(start) => {
+                    let name = String::from_utf8_lossy(start.local_name().as_ref()).into_owned();
+                    path.push(name);
+                }
+                Event::End(_) => {
+                    if path_is(&path, &["root", "device", "serviceList", "service"]) {
+                        services.push(std::mem::take(&mut service));
+                    }
+
+                    path.pop();
+                }
+                Event::Text(text) => {
+                    let text = text.unescape()?.into_owned();
+                    let Some((element, parent)) = path.split_last() else {
+                        continue;
+                    };
+
+                    let field = match (parent_of(parent), element.as_str()) {
+                        (Parent::Root, "URLBase") => &mut url_base,
+                        (Parent::Device, "friendlyName") => &mut friendly_name,
+                        (Parent::Device, "deviceType") => &mut device_type,
+                        (Parent::Device, "manufacturer") => &mut manufacturer,
+                        (Parent::Device, "UDN") => &mut udn,
+                        (Parent::Service, "serviceType") => &mut service.service_type,
+                        (Parent::Service, "controlURL") => &mut service.control_url,
+                        (Parent::Service, "SCPDURL") => &mut service.scpd_url,
+                        _ => continue,
+                    };
+
+                    *field = Some(text);
+                }
+                Event::Eof => break,
+                _ => {}
+            }
+        }
+
+        let base = match url_base {
+            Some(base) => Url::parse(&base)?,
+            None => url.clone(),
+        };
+
+        let services = services
+            .into_iter()
+            .map(|service| service.resolve(&base))
+            .collect::<Result<_, _>>()?;
+
+        Ok(DeviceDescription {
+            friendly_name: friendly_name.ok_or(DescribeError::MissingElement("friendlyName"))?,
+            device_type: device_type.ok_or(DescribeError::MissingElement("deviceType"))?,
+            manufacturer,
+            udn: udn.ok_or(DescribeError::MissingElement("UDN"))?,
+            services,
+        })
+    }
+}
+
+impl PartialService {
+    fn resolve(self, base: &Url) -> Result<ServiceDescription, DescribeError> {
+        let control_url = self.control_url.ok_or(DescribeError::MissingElement("controlURL"))?;
+        let scpd_url = self.scpd_url.ok_or(DescribeError::MissingElement("SCPDURL"))?;
+
+        Ok(ServiceDescription {
+            service_type: self.service_type.ok_or(DescribeError::MissingElement("serviceType"))?,
+            control_url: base.join(&control_url)?,
+            scpd_url: base.join(&scpd_url)?,
+        })
+    }
+}
+
+/// The elements whose children are part of the description.
+enum Parent {
+    Root,
+    Device,
+    Service,
+    Other,
+}
+
+/// Classify the path of the parent of a text element.
+fn parent_of(path: &[String]) -> Parent {
+    if path_is(path, &["root"]) {
+        Parent::Root
+    } else if path_is(path, &["root", "device"]) {
+        Parent::Device
+    } else if path_is(path, &["root", "device", "serviceList", "service"]) {
+        Parent::Service
+    } else {
+        Parent::Other
+    }
+}
+
+fn path_is(path: &[String], expected: &[&str]) -> bool {
+    path.len() == expected.len() && path.iter().zip(expected).all(|(name, expected)| name == expected)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::{SocketAddr, TcpListener};
+    use std::sync::mpsc::{self, Receiver};
+    use std::time::Duration;
+
+    use url::Url;
+
+    use super::{DescribeError, DeviceDescription};
+    use crate::header::{ST, USN};
+    use crate::message::DiscoveredDevice;
+    use crate::FieldMap;
+
+    const DESCRIPTION: &str = include_str!("fixtures/description.xml");
+
+    /// Serve the description at `/description.xml` for a single request, reporting its path.
+    fn serve_once() -> (SocketAddr, Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (paths, received) = mpsc::channel();
+
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request_line = String::new();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            reader.read_line(&mut request_line).unwrap();
+
+            // Skip the headers up to the empty line.
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 2 {
+                line.clear();
+            }
+
+            let path = request_line.split(' ').nth(1).unwrap().to_owned();
+            let response = if path == "/description.xml" {
+                format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: text/xml\r\nContent-Length: {}\r\n\r\n{}",
+                    DESCRIPTION.len(),
+                    DESCRIPTION
+                )
+            } else {
+                "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n".to_owned()
+            };
+
+            stream.write_all(response.as_bytes()).unwrap();
+            paths.send(path).unwrap();
+        });
+
+        (addr, received)
+    }
+
+    fn device(location: Url, secure_location: Option<Url>) -> DiscoveredDevice {
+        DiscoveredDevice {
+            location,
+            secure_location,
+            usn: USN(FieldMap::uuid("2fac1234-31f8-11b4-a222-08002b34c003"), None),
+            st: ST::All,
+            server: None,
+            peer: "127.0.0.1:1900".parse().unwrap(),
+            max_age: None,
+        }
+    }
+
+    #[test]
+    fn positive_parse() {
+        let url = Url::parse("http://192.168.1.20:49152/desc/root.xml").unwrap();
+        let description = DeviceDescription::parse(DESCRIPTION, &url).unwrap();
+
+        assert_eq!(description.friendly_name, "Living Room & Kitchen");
+        assert_eq!(description.device_type, "urn:schemas-upnp-org:device:MediaRenderer:1");
+        assert_eq!(description.manufacturer.as_deref(), Some("Example Audio"));
+        assert_eq!(description.udn, "uuid:2fac1234-31f8-11b4-a222-08002b34c003");
+
+        let services = &description.services;
+        assert_eq!(services.len(), 2, "{:?}", services);
+        assert_eq!(services[0].service_type, "urn:schemas-upnp-org:service:RenderingControl:1");
+        assert_eq!(services[0].control_url.as_str(), "http://192.168.1.20:49152/RenderingControl/control");
+        assert_eq!(services[1].scpd_url.as_str(), "http://192.168.1.20:49152/desc/AVTransport/scpd.xml");
+    }
+
+    #[test]
+    fn positive_parse_url_base() {
+        let document = DESCRIPTION.replace(
+            "<specVersion>",
+            "<URLBase>http://10.0.0.7:8080/base/</URLBase><specVersion>",
+        );
+        let url = Url::parse("http://192.168.1.20:49152/desc/root.xml").unwrap();
+        let description = DeviceDescription::parse(&document, &url).unwrap();
+
+        let services = &description.services;
+        assert_eq!(services[0].scpd_url.as_str(), "http://10.0.0.7:8080/RenderingControl/scpd.xml");
+        assert_eq!(services[1].scpd_url.as_str(), "http://10.0.0.7:8080/base/AVTransport/scpd.xml");
+        assert_eq!(services[1].control_url.as_str(), "http://192.168.1.20:49152/AVTransport/control");
+    }
+
+    #[test]
+    fn positive_fetch_description() {
+        let (addr, paths) = serve_once();
+        let location = Url::parse(&format!("http://{}/description.xml", addr)).unwrap();
+
+        let description = device(location, None).fetch_description(Duration::from_secs(5)).unwrap();
+        assert_eq!(paths.recv().unwrap(), "/description.xml");
+        assert_eq!(description.friendly_name, "Living Room & Kitchen");
+        assert_eq!(description.services.len(), 2);
+    }
+
+    #[test]
+    fn positive_fetch_secure_location() {
+        let (addr, paths) = serve_once();
+        let location = Url::parse(&format!("http://{}/insecure.xml", addr)).unwrap();
+        let secure_location = Url::parse(&format!("http://{}/description.xml", addr)).unwrap();
+
+        let device = device(location, Some(secure_location));
+        device.fetch_description(Duration::from_secs(5)).unwrap();
+        assert_eq!(paths.recv().unwrap(), "/description.xml");
+    }
+
+    #[test]
+    fn negative_fetch_not_found() {
+        let (addr, _paths) = serve_once();
+        let location = Url::parse(&format!("http://{}/missing.xml", addr)).unwrap();
+
+        let result = device(location, None).fetch_description(Duration::from_secs(5));
+        assert!(matches!(result, Err(DescribeError::Http(_))), "{:?}", result);
+    }
+
+    #[test]
+    fn negative_missing_element() {
+        let document = DESCRIPTION.replace("<UDN>uuid:2fac1234-31f8-11b4-a222-08002b34c003</UDN>", "");
+        let url = Url::parse("http://192.168.1.20:49152/desc/root.xml").unwrap();
+
+        let result = DeviceDescription::parse(&document, &url);
+        assert!(matches!(result, Err(DescribeError::MissingElement("UDN"))), "{:?}", result);
+
+        let result = DeviceDescription::parse("<root><device>", &url);
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,735 @@
+//! Continuous discovery of devices and services.
+
+use std::cmp;
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Iter, Receiver, RecvError, RecvTimeoutError, Sender, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use headers::{HeaderMap, HeaderMapExt as _, Location};
+use http::header::{LOCATION, SERVER};
+use url::Url;
+
+use crate::context::SsdpContext;
+use crate::error::SSDPResult;
+use crate::header::{BootID, NextBootID, Nls, SsdpMaxAge, NTS, ST, USN};
+use crate::message::advertise::MAX_BOOT_ID;
+use crate::message::{Config, NotifyMessage, SearchRequest, SearchResponse};
+
+/// Default interval in which the search request is repeated.
+const DEFAULT_SEARCH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Header of the HTTPS URL of the device description, which takes precedence over LOCATION.
+const SECURELOCATION: &str = "securelocation.upnp.org";
+
+/// Interval in which the discovery threads check whether they were stopped.
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Enumerates changes to the set of devices and services on the network.
+#[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
+pub enum DiscoveryEvent {
+    /// A device or service appeared or changed its location.
+    Alive {
+        usn: USN,
+        location: Location,
+        max_age: Option<Duration>,
+    },
+    /// A device or service left the network.
+    ByeBye { usn: USN },
+    /// A device or service announced the boot instance it switches to.
+    Update { usn: USN, bootid: BootID },
+    /// A device or service was restarted, its description should be fetched again.
+    Rebooted { usn: USN, old: BootID, new: BootID },
+    /// A device or service without a boot id announced a new `01-NLS` boot session.
+    RebootedNls { usn: USN, old: Nls, new: Nls },
+}
+
+/// A device or service that responded to `discover`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DiscoveredDevice {
+    pub location: Url,
+    /// The SECURELOCATION.UPNP.ORG header, the HTTPS URL of the same description.
+    pub secure_location: Option<Url>,
+    pub usn: USN,
+    pub st: ST,
+    /// The SERVER header, which names the operating system and UPnP version of the device.
+    pub server: Option<String>,
+    /// The address the response was received from.
+    pub peer: SocketAddr,
+    pub max_age: Option<Duration>,
+}
+
+/// Search the network for devices and services, collecting the responses until the timeout.
+///
+/// The MX of the search is the timeout in whole seconds, within the range of 1 to 5 seconds that
+/// devices honor. Responses are deduplicated by their USN, keeping the first one, and responses
+/// without a USN, an ST or a LOCATION that is an absolute URL are skipped. A SECURELOCATION that
+/// is not an absolute URL is ignored.
+///
+/// Use `SearchRequest` or `Discovery` for anything beyond a single search on all interfaces.
+pub fn discover(target: ST, timeout: Duration) -> SSDPResult<Vec<DiscoveredDevice>> {
+    discover_with_context(SsdpContext::global(), &Config::new(), target, timeout)
+}
+
+/// Search the network like `discover`, through the interfaces of the context.
+pub(crate) fn discover_with_context(
+    context: &SsdpContext,
+    config: &Config,
+    target: ST,
+    timeout: Duration,
+) -> SSDPResult<Vec<DiscoveredDevice>> {
+    let mx = timeout.as_secs().clamp(1, 5) as u8;
+    let request = SearchRequest::new().with_st(target).with_mx(mx)?;
+    let config = config.clone().set_response_window(Some(timeout));
+    let responses = request.multicast_with_context(context, &config)?;
+
+    let mut seen = HashSet::new();
+    let mut devices = Vec::new();
+    for (response, peer) in responses {
+        let Some(device) = DiscoveredDevice::from_response(&response, peer) else {
+            debug!("Ignoring incomplete search response from {}", peer);
+            continue;
+        };
+
+        if seen.insert(device.usn.clone()) {
+            devices.push(device);
+        }
+    }
+
+    Ok(devices)
+}
+
+impl DiscoveredDevice {
+    /// Extract the device from a search response, if it has all required headers.
+    fn from_response(response: &SearchResponse, peer: SocketAddr) -> Option<DiscoveredDevice> {
+        let headers = response.headers();
+        let location = headers.get(LOCATION)?.to_str().ok()?.parse::<Url>().ok()?;
+        let server = headers.get(SERVER).and_then(|value| value.to_str().ok());
+        let secure_location = headers.get(SECURELOCATION).and_then(|value| value.to_str().ok());
+
+        Some(DiscoveredDevice {
+            location,
+            secure_location: secure_location.and_then(|url| url.parse().ok()),
+            usn: headers.typed_get::<USN>()?,
+            st: headers.typed_get::<ST>()?,
+            server: server.map(str::to_owned),
+            peer,
+            max_age: response.max_age(),
+        })
+    }
+}
+
+/// Discovers devices and services through both search responses and notify messages.
+///
+/// While started, one background thread listens for notify messages and another one repeats
+/// the search request at a configurable interval. Both are translated into a single stream of
+/// events keyed by the unique service name, where repeated announcements of an already known
+/// entry are suppressed unless its location changed or it was rebooted.
+pub struct Discovery {
+    request: SearchRequest,
+    interval: Duration,
+    events: Receiver<DiscoveryEvent>,
+    sender: Sender<DiscoveryEvent>,
+    running: Option<Running>,
+}
+
+struct Running {
+    stopped: Arc<AtomicBool>,
+    /// Disconnects once both discovery threads exited.
+    done: Receiver<()>,
+}
+
+/// The entries seen so far, shared between the discovery threads.
+#[derive(Default)]
+struct Tracker {
+    known: HashMap<USN, Known>,
+}
+
+struct Known {
+    location: Location,
+    bootid: Option<BootID>,
+    nls: Option<Nls>,
+}
+
+impl Discovery {
+    /// Construct a new Discovery repeating the search request.
+    ///
+    /// The request should carry an MX header, as is required for multicast searches.
+    pub fn new(request: SearchRequest) -> Discovery {
+        let (sender, events) = mpsc::channel();
+
+        Discovery {
+            request,
+            interval: DEFAULT_SEARCH_INTERVAL,
+            events,
+            sender,
+            running: None,
+        }
+    }
+
+    /// Set the interval in which the search request is repeated.
+    ///
+    /// This only takes effect the next time the discovery is started.
+    pub fn set_interval(mut self, value: Duration) -> Self {
+        self.interval = value;
+        self
+    }
+
+    /// Get the interval in which the search request is repeated.
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Check if the discovery is currently running.
+    pub fn is_running(&self) -> bool {
+        self.running.is_some()
+    }
+
+    /// Start discovering on the network described by the config.
+    ///
+    /// If the discovery is already running it is stopped first. Entries seen during a previous
+    /// run are forgotten.
+    pub fn start(&mut self, config: &Config) -> SSDPResult<()> {
+        self.start_with_context(SsdpContext::global(), config)
+    }
+
+    /// Start discovering through the interfaces of the context.
+    pub(crate) fn start_with_context(&mut self, context: &SsdpContext, config: &Config) -> SSDPResult<()> {
+        self.stop();
+
+        let notifies = context.listen::<NotifyMessage>(config)?;
+        let stopped = Arc::new(AtomicBool::new(false));
+        let tracker = Arc::new(Mutex::new(Tracker::default()));
+        let (done_send, done) = mpsc::channel::<()>();
+
+        {
+            let tracker = Arc::clone(&tracker);
+            let events = self.sender.clone();
+            let stopped = Arc::clone(&stopped);
+            let done_send = done_send.clone();
+
+            context.spawn(move || {
+                let _done = done_send;
+
+                while !stopped.load(Ordering::Relaxed) {
+                    let message = match notifies.recv_timeout(STOP_POLL_INTERVAL) {
+                        Ok((message, _)) => message,
+                        Err(RecvTimeoutError::Timeout) => continue,
+                        Err(RecvTimeoutError::Disconnected) => {
+                            debug!("Listener of the discovery stopped receiving");
+                            break;
+                        }
+                    };
+
+                    let event = tracker.lock().unwrap().notify(message.headers());
+                    if let Some(event) = event {
+                        let _ = events.send(event);
+                    }
+                }
+            });
+        }
+
+        let request = self.request.clone();
+        let interval = self.interval;
+        let events = self.sender.clone();
+        let thread_stopped = Arc::clone(&stopped);
+        let thread_context = context.clone();
+        let thread_config = config.clone();
+
+        context.spawn(move || {
+            let _done = done_send;
+
+            while !thread_stopped.load(Ordering::Relaxed) {
+                let started = thread_context.now();
+
+                match request.multicast_with_context(&thread_context, &thread_config) {
+                    Ok(responses) => loop {
+                        if thread_stopped.load(Ordering::Relaxed) {
+                            return;
+                        }
+
+                        match responses.recv_timeout(STOP_POLL_INTERVAL) {
+                            Ok((response, _)) => {
+                                let event = tracker.lock().unwrap().alive(response.headers());
+                                if let Some(event) = event {
+                                    let _ = events.send(event);
+                                }
+                            }
+                            Err(RecvTimeoutError::Timeout) => continue,
+                            Err(RecvTimeoutError::Disconnected) => break,
+                        }
+                    },
+                    Err(err) => debug!("Failed to send search request: {}", err),
+                }
+
+                while thread_context.now().duration_since(started) < interval {
+                    if thread_stopped.load(Ordering::Relaxed) {
+                        return;
+                    }
+
+                    std::thread::sleep(STOP_POLL_INTERVAL);
+                }
+            }
+        });
+
+        self.running = Some(Running { stopped, done });
+
+        Ok(())
+    }
+
+    /// Stop discovering.
+    ///
+    /// Events that were already produced can still be received. Does nothing if the discovery is
+    /// not running.
+    pub fn stop(&mut self) {
+        let Some(running) = self.running.take() else {
+            return;
+        };
+
+        running.stopped.store(true, Ordering::Relaxed);
+        let _ = running.done.recv();
+    }
+
+    /// Non-blocking method that attempts to read an event.
+    pub fn try_recv(&self) -> Result<DiscoveryEvent, TryRecvError> {
+        self.events.try_recv()
+    }
+
+    /// Blocking method that reads an event until one is available.
+    ///
+    /// The discovery holds on to a sender itself, such that this blocks forever when it is not
+    /// running.
+    pub fn recv(&self) -> Result<DiscoveryEvent, RecvError> {
+        self.events.recv()
+    }
+
+    /// Blocking method that reads an event until one is available or the timeout elapsed.
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<DiscoveryEvent, RecvTimeoutError> {
+        self.events.recv_timeout(timeout)
+    }
+}
+
+impl Drop for Discovery {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+impl<'a> IntoIterator for &'a Discovery {
+    type Item = DiscoveryEvent;
+    type IntoIter = Iter<'a, DiscoveryEvent>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.events.iter()
+    }
+}
+
+impl Tracker {
+    /// Translate a notify message into an event.
+    fn notify(&mut self, headers: &HeaderMap) -> Option<DiscoveryEvent> {
+        match headers.typed_get::<NTS>()? {
+            NTS::Alive => self.alive(headers),
+            NTS::Update => self.update(headers),
+            NTS::ByeBye => self.byebye(headers),
+        }
+    }
+
+    /// Translate an alive message or search response into an event.
+    fn alive(&mut self, headers: &HeaderMap) -> Option<DiscoveryEvent> {
+        let usn = headers.typed_get::<USN>()?;
+        let location = headers.typed_get::<Location>()?;
+        let bootid = headers.typed_get::<BootID>();
+        let nls = headers.typed_get::<Nls>();
+        let max_age = headers.typed_get::<SsdpMaxAge>().map(|max_age| max_age.0);
+
+        let Some(known) = self.known.get_mut(&usn) else {
+            let known = Known {
+                location: location.clone(),
+                bootid,
+                nls,
+            };
+
+            self.known.insert(usn.clone(), known);
+            return Some(DiscoveryEvent::Alive {
+                usn,
+                location,
+                max_age,
+            });
+        };
+
+        // Legacy devices only send a boot session, any other value than the known one is a reboot.
+        if let (None, None, Some(old), Some(new)) = (known.bootid, bootid, &known.nls, &nls) {
+            if old != new {
+                let (old, new) = (old.clone(), new.clone());
+                known.nls = nls;
+                known.location = location;
+                return Some(DiscoveryEvent::RebootedNls { usn, old, new });
+            }
+        }
+
+        // Devices that never send a boot id are only tracked by their location.
+        match (known.bootid, bootid) {
+            (Some(old), Some(new)) if cmp_boot_id(old, new) == cmp::Ordering::Less => {
+                debug!("Ignoring {:?} of previous boot {}", usn, new.0);
+                return None;
+            }
+            (Some(old), Some(new)) if cmp_boot_id(old, new) == cmp::Ordering::Greater => {
+                known.bootid = bootid;
+                known.location = location;
+                return Some(DiscoveryEvent::Rebooted { usn, old, new });
+            }
+            _ => {}
+        }
+
+        known.bootid = bootid.or(known.bootid);
+        known.nls = nls.or(known.nls.take());
+        if known.location == location {
+            return None;
+        }
+
+        known.location = location.clone();
+        Some(DiscoveryEvent::Alive {
+            usn,
+            location,
+            max_age,
+        })
+    }
+
+    /// Translate an update message into an event.
+    ///
+    /// The next boot id is stored such that the following announcements are not mistaken for a
+    /// reboot.
+    fn update(&mut self, headers: &HeaderMap) -> Option<DiscoveryEvent> {
+        let usn = headers.typed_get::<USN>()?;
+        let bootid = BootID(headers.typed_get::<NextBootID>()?.0);
+
+        // Without a location the entry can not be tracked, wait for it to be announced.
+        let known = self.known.get_mut(&usn)?;
+        if known.bootid == Some(bootid) {
+            return None;
+        }
+
+        known.bootid = Some(bootid);
+        Some(DiscoveryEvent::Update { usn, bootid })
+    }
+
+    /// Translate a byebye message into an event.
+    fn byebye(&mut self, headers: &HeaderMap) -> Option<DiscoveryEvent> {
+        let usn = headers.typed_get::<USN>()?;
+
+        self.known.remove(&usn).map(|_| DiscoveryEvent::ByeBye { usn })
+    }
+}
+
+/// Compare a boot id to the known one like a serial number.
+///
+/// Boot ids are 31 bit numbers, an `Advertiser` starts over at zero after the largest one. The
+/// half of all boot ids following the known one counts as an increase, such that this wrap is
+/// still recognized as a reboot.
+fn cmp_boot_id(old: BootID, new: BootID) -> cmp::Ordering {
+    match new.0.wrapping_sub(old.0) & MAX_BOOT_ID {
+        0 => cmp::Ordering::Equal,
+        ahead if ahead <= MAX_BOOT_ID / 2 => cmp::Ordering::Greater,
+        _ => cmp::Ordering::Less,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::net::{IpAddr, Ipv4Addr, UdpSocket};
+    use std::time::Duration;
+
+    use headers::{HeaderMapExt as _, HeaderValue, Location, Server};
+
+    use super::{discover_with_context, Discovery, DiscoveryEvent, Tracker};
+    use crate::message::advertise::MAX_BOOT_ID;
+    use crate::context::SsdpContext;
+    use crate::header::{BootID, HeaderMut, Man, NextBootID, Nls, MX, NT, NTS, ST, USN};
+    use crate::message::{Advertisement, Advertiser, Config, NotifyMessage, SearchRequest};
+    use crate::net::NetifAddr;
+    use crate::FieldMap;
+
+    fn location(url: &'static str) -> Location {
+        headers::Header::decode(&mut [HeaderValue::from_static(url)].iter()).unwrap()
+    }
+
+    fn usn() -> USN {
+        USN(FieldMap::uuid("discovery-test"), Some(FieldMap::upnp("rootdevice")))
+    }
+
+    fn entry() -> Advertisement {
+        Advertisement::new(
+            NT(FieldMap::upnp("rootdevice")),
+            usn(),
+            location("http://127.0.0.1:8080/description.xml"),
+            "test/1.0 UPnP/1.1 ssdp/0.7".parse::<Server>().unwrap(),
+        )
+    }
+
+    fn alive(bootid: Option<u32>) -> NotifyMessage {
+        let mut alive = entry().alive(Duration::from_secs(1800));
+        if let Some(bootid) = bootid {
+            alive.set(BootID(bootid));
+        }
+
+        alive
+    }
+
+    fn alive_nls(nls: &str) -> NotifyMessage {
+        let mut alive = alive(None);
+        alive.set(Nls(nls.to_owned()));
+        alive
+    }
+
+    fn rebooted(old: u32, new: u32) -> Option<DiscoveryEvent> {
+        Some(DiscoveryEvent::Rebooted {
+            usn: usn(),
+            old: BootID(old),
+            new: BootID(new),
+        })
+    }
+
+    #[test]
+    fn positive_deduplicate_alive() {
+        let mut tracker = Tracker::default();
+        let mut alive = alive(None);
+
+        assert!(matches!(tracker.notify(alive.headers()), Some(DiscoveryEvent::Alive { .. })));
+        assert_eq!(tracker.notify(alive.headers()), None);
+
+        alive.set(location("http://127.0.0.1:8081/description.xml"));
+        assert!(matches!(tracker.notify(alive.headers()), Some(DiscoveryEvent::Alive { .. })));
+        assert_eq!(tracker.notify(alive.headers()), None);
+    }
+
+    #[test]
+    fn positive_bootid_increase() {
+        let mut tracker = Tracker::default();
+
+        tracker.notify(alive(Some(1)).headers());
+        assert_eq!(tracker.notify(alive(Some(2)).headers()), rebooted(1, 2));
+        assert_eq!(tracker.notify(alive(Some(2)).headers()), None);
+    }
+
+    #[test]
+    fn positive_bootid_wraps() {
+        let mut tracker = Tracker::default();
+
+        tracker.notify(alive(Some(MAX_BOOT_ID)).headers());
+        assert_eq!(tracker.notify(alive(Some(0)).headers()), rebooted(MAX_BOOT_ID, 0));
+        assert_eq!(tracker.notify(alive(Some(MAX_BOOT_ID)).headers()), None);
+        assert_eq!(tracker.notify(alive(Some(1)).headers()), rebooted(0, 1));
+    }
+
+    #[test]
+    fn positive_bootid_equal() {
+        let mut tracker = Tracker::default();
+
+        tracker.notify(alive(Some(1)).headers());
+        assert_eq!(tracker.notify(alive(Some(1)).headers()), None);
+        assert_eq!(tracker.notify(alive(None).headers()), None);
+    }
+
+    #[test]
+    fn negative_bootid_decrease() {
+        let mut tracker = Tracker::default();
+
+        tracker.notify(alive(Some(5)).headers());
+        assert_eq!(tracker.notify(alive(Some(4)).headers()), None);
+        assert_eq!(tracker.notify(alive(Some(6)).headers()), rebooted(5, 6));
+    }
+
+    #[test]
+    fn positive_bootid_first_seen() {
+        let mut tracker = Tracker::default();
+
+        tracker.notify(alive(None).headers());
+        assert_eq!(tracker.notify(alive(Some(3)).headers()), None);
+        assert_eq!(tracker.notify(alive(Some(4)).headers()), rebooted(3, 4));
+    }
+
+    #[test]
+    fn positive_nls_changed() {
+        let mut tracker = Tracker::default();
+        let old = "1e1c46d8-1dd2-11b2-8d2c-ba2f3e1f4c44";
+        let new = "5e0f72c2-1dd2-11b2-8d2c-ba2f3e1f4c44";
+
+        tracker.notify(alive_nls(old).headers());
+        assert_eq!(tracker.notify(alive_nls(old).headers()), None);
+        assert_eq!(tracker.notify(alive(None).headers()), None);
+
+        let expected = DiscoveryEvent::RebootedNls {
+            usn: usn(),
+            old: Nls(old.to_owned()),
+            new: Nls(new.to_owned()),
+        };
+        assert_eq!(tracker.notify(alive_nls(new).headers()), Some(expected));
+        assert_eq!(tracker.notify(alive_nls(new).headers()), None);
+    }
+
+    #[test]
+    fn negative_nls_with_bootid() {
+        let mut tracker = Tracker::default();
+
+        let mut first = alive_nls("1e1c46d8-1dd2-11b2-8d2c-ba2f3e1f4c44");
+        first.set(BootID(1));
+        tracker.notify(first.headers());
+
+        let mut second = alive_nls("5e0f72c2-1dd2-11b2-8d2c-ba2f3e1f4c44");
+        second.set(BootID(1));
+        assert_eq!(tracker.notify(second.headers()), None);
+    }
+
+    #[test]
+    fn positive_update_and_byebye() {
+        let mut tracker = Tracker::default();
+        let mut update = entry().byebye();
+        update.set(NTS::Update);
+        update.set(BootID(1));
+        update.set(NextBootID(2));
+
+        assert_eq!(tracker.notify(update.headers()), None);
+        tracker.notify(alive(Some(1)).headers());
+
+        let expected = DiscoveryEvent::Update {
+            usn: usn(),
+            bootid: BootID(2),
+        };
+        assert_eq!(tracker.notify(update.headers()), Some(expected));
+        assert_eq!(tracker.notify(update.headers()), None);
+        assert_eq!(tracker.notify(alive(Some(2)).headers()), None);
+
+        let byebye = entry().byebye();
+        assert_eq!(tracker.notify(byebye.headers()), Some(DiscoveryEvent::ByeBye { usn: usn() }));
+        assert_eq!(tracker.notify(byebye.headers()), None);
+    }
+
+    #[test]
+    fn positive_discover_responses() {
+        let context = SsdpContext::new().set_interfaces(|| -> io::Result<Vec<NetifAddr>> {
+            Ok(vec![NetifAddr {
+                sock: IpAddr::V4(Ipv4Addr::LOCALHOST),
+                index: 1,
+            }])
+        });
+        let config = Config::new()
+            .set_port(19_027)
+            .set_mode(crate::IpVersionMode::V4Only);
+        let listener = context.listen::<SearchRequest>(&config).unwrap();
+
+        // Answers the search with a duplicate, a response without USN and one with a relative
+        // location, none of which are part of the result.
+        let responder = std::thread::spawn(move || {
+            let (request, src) = listener.recv().unwrap();
+            assert_eq!(request.headers().typed_get::<MX>(), Some(MX(1)));
+
+            let device = UdpSocket::bind("127.0.0.1:0").unwrap();
+            let usn = "USN: uuid:discover-test::upnp:rootdevice\r\n";
+            let st = "ST: upnp:rootdevice\r\n";
+            let responses = [
+                format!("LOCATION: http://127.0.0.1:8080/d.xml\r\nCACHE-CONTROL: max-age=1800\r\n{usn}\
+                         SERVER: test/1.0 UPnP/1.1 ssdp/0.7\r\n"),
+                format!("LOCATION: http://127.0.0.1:8081/d.xml\r\n{usn}"),
+                "LOCATION: http://127.0.0.1:8082/d.xml\r\n".to_owned(),
+                "LOCATION: /d.xml\r\nUSN: uuid:relative\r\n".to_owned(),
+            ];
+            for headers in responses {
+                let response = format!("HTTP/1.1 200 OK\r\n{st}{headers}\r\n");
+                device.send_to(response.as_bytes(), src).unwrap();
+            }
+        });
+
+        let target = ST::Target(FieldMap::upnp("rootdevice"));
+        let devices = discover_with_context(&context, &config, target, Duration::from_millis(800)).unwrap();
+        responder.join().unwrap();
+
+        assert_eq!(devices.len(), 1, "{:?}", devices);
+        let device = &devices[0];
+        assert_eq!(device.location.as_str(), "http://127.0.0.1:8080/d.xml");
+        assert_eq!(device.secure_location, None);
+        assert_eq!(device.usn, USN(FieldMap::uuid("discover-test"), Some(FieldMap::upnp("rootdevice"))));
+        assert_eq!(device.st, ST::Target(FieldMap::upnp("rootdevice")));
+        assert_eq!(device.server.as_deref(), Some("test/1.0 UPnP/1.1 ssdp/0.7"));
+        assert_eq!(device.peer.ip(), IpAddr::V4(Ipv4Addr::LOCALHOST));
+        assert_eq!(device.max_age, Some(Duration::from_secs(1800)));
+    }
+
+    #[test]
+    fn negative_listener_disconnected() {
+        use std::sync::mpsc::RecvTimeoutError;
+        use std::sync::{Arc, Mutex};
+
+        use crate::context::Task;
+
+        // Only the thread tracking notify messages runs, it is spawned right before the one that
+        // searches. The socket threads of its listener never do, so the listener disconnects.
+        let tasks = Arc::new(Mutex::new(Vec::new()));
+        let spawned = Arc::clone(&tasks);
+        let context = SsdpContext::new()
+            .set_interfaces(|| -> io::Result<Vec<NetifAddr>> {
+                Ok(vec![NetifAddr {
+                    sock: IpAddr::V4(Ipv4Addr::LOCALHOST),
+                    index: 1,
+                }])
+            })
+            .set_spawner(move |task: Task| spawned.lock().unwrap().push(task));
+        let config = Config::new()
+            .set_port(19_047)
+            .set_mode(crate::IpVersionMode::V4Only)
+            .set_include_loopback(true);
+
+        let mut discovery = Discovery::new(SearchRequest::new());
+        discovery.start_with_context(&context, &config).unwrap();
+        let task = {
+            let mut tasks = tasks.lock().unwrap();
+            tasks.pop();
+            let task = tasks.pop().unwrap();
+            tasks.clear();
+            task
+        };
+        let thread = std::thread::spawn(task);
+
+        // The thread exits instead of spinning on the disconnected listener.
+        let running = discovery.running.as_ref().unwrap();
+        let done = running.done.recv_timeout(Duration::from_secs(5));
+        assert_eq!(done, Err(RecvTimeoutError::Disconnected));
+        thread.join().unwrap();
+    }
+
+    #[test]
+    fn positive_discover_advertiser() {
+        let config = Config::new()
+            .set_port(19_006)
+            .set_mode(crate::IpVersionMode::V4Only);
+
+        let mut request = SearchRequest::new();
+        request.set(Man);
+        request.set(MX(1));
+        request.set(ST::All);
+
+        let mut discovery = Discovery::new(request).set_interval(Duration::from_secs(1));
+        discovery.start(&config).unwrap();
+
+        let mut advertiser = Advertiser::new(Duration::from_secs(1800)).register(entry());
+        advertiser.start(&config).unwrap();
+
+        match discovery.recv_timeout(Duration::from_secs(5)).unwrap() {
+            DiscoveryEvent::Alive {
+                usn: found, max_age, ..
+            } => {
+                assert_eq!(found, usn());
+                assert_eq!(max_age, Some(Duration::from_secs(1800)));
+            }
+            other => panic!("Unexpected event {:?}", other),
+        }
+
+        drop(advertiser);
+
+        let event = discovery.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(event, DiscoveryEvent::ByeBye { usn: usn() });
+    }
+}
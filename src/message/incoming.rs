@@ -0,0 +1,196 @@
+//! Receiving notify messages, searches and responses on the same socket.
+
+use std::net::SocketAddr;
+
+use headers::HeaderMap;
+
+use crate::context::SsdpContext;
+use crate::error::SSDPResult;
+use crate::header::USN;
+use crate::message::listen::{self, Listen};
+use crate::message::ssdp::SSDPMessage;
+use crate::message::{Config, MessageType, NotifyMessage, SearchRequest, SearchResponse};
+use crate::receiver::{FromRawSSDP, ParseOptions, SSDPReceiver};
+
+/// A received message of any type, as given by its start line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum IncomingMessage {
+    Notify(NotifyMessage),
+    Search(SearchRequest),
+    Response(SearchResponse),
+}
+
+impl IncomingMessage {
+    /// Get the type of the message.
+    pub fn message_type(&self) -> MessageType {
+        match self {
+            IncomingMessage::Notify(_) => MessageType::Notify,
+            IncomingMessage::Search(_) => MessageType::Search,
+            IncomingMessage::Response(_) => MessageType::Response,
+        }
+    }
+
+    /// Get the headers contained in the message.
+    pub fn headers(&self) -> &HeaderMap {
+        match self {
+            IncomingMessage::Notify(notify) => notify.headers(),
+            IncomingMessage::Search(request) => request.headers(),
+            IncomingMessage::Response(response) => response.headers(),
+        }
+    }
+}
+
+impl From<SSDPMessage> for IncomingMessage {
+    fn from(message: SSDPMessage) -> IncomingMessage {
+        match message.message_type() {
+            MessageType::Notify => IncomingMessage::Notify(NotifyMessage { message }),
+            MessageType::Search => IncomingMessage::Search(SearchRequest { message }),
+            MessageType::Response => IncomingMessage::Response(SearchResponse { message }),
+        }
+    }
+}
+
+impl From<IncomingMessage> for SSDPMessage {
+    fn from(incoming: IncomingMessage) -> SSDPMessage {
+        match incoming {
+            IncomingMessage::Notify(notify) => notify.into(),
+            IncomingMessage::Search(request) => request.into(),
+            IncomingMessage::Response(response) => response.into(),
+        }
+    }
+}
+
+/// Every check is that of the wrapped message, so a receiver drops the same messages as the
+/// receiver of the single type would.
+impl FromRawSSDP for IncomingMessage {
+    fn from_packet(bytes: &[u8]) -> SSDPResult<IncomingMessage> {
+        Self::from_packet_with_options(bytes, &Default::default())
+    }
+
+    fn from_packet_with_options(bytes: &[u8], options: &ParseOptions) -> SSDPResult<IncomingMessage> {
+        SSDPMessage::from_packet_with_options(bytes, options).map(IncomingMessage::from)
+    }
+
+    fn verify_peer(&self, peer: &SocketAddr, options: &ParseOptions) -> bool {
+        match self {
+            IncomingMessage::Notify(notify) => notify.verify_peer(peer, options),
+            IncomingMessage::Search(request) => request.verify_peer(peer, options),
+            IncomingMessage::Response(response) => response.verify_peer(peer, options),
+        }
+    }
+
+    fn is_from_device(&self, uuid: &str) -> bool {
+        match self {
+            IncomingMessage::Notify(notify) => notify.is_from_device(uuid),
+            IncomingMessage::Search(request) => request.is_from_device(uuid),
+            IncomingMessage::Response(response) => response.is_from_device(uuid),
+        }
+    }
+
+    fn usn(&self) -> Option<USN> {
+        match self {
+            IncomingMessage::Response(response) => FromRawSSDP::usn(response),
+            IncomingMessage::Notify(_) | IncomingMessage::Search(_) => None,
+        }
+    }
+}
+
+/// Listener for every message sent to the multicast group, whatever its type.
+///
+/// Notify messages and searches share the port, so a device that both tracks other devices and
+/// answers searches can receive both from one socket instead of a `NotifyListener` and a
+/// `SearchListener` competing for it.
+#[derive(Clone, Default)]
+pub struct SsdpListener {
+    config: Config,
+}
+
+impl SsdpListener {
+    /// Construct a listener for the network described by the config.
+    pub fn new(config: Config) -> Self {
+        SsdpListener { config }
+    }
+
+    /// Listen for messages on all interfaces of the context.
+    pub(crate) fn listen_with_context(
+        &self,
+        context: &SsdpContext,
+    ) -> SSDPResult<SSDPReceiver<IncomingMessage>> {
+        listen::listen_with_context(context, &self.config)
+    }
+}
+
+impl Listen for SsdpListener {
+    type Message = IncomingMessage;
+
+    fn listen(&self) -> SSDPResult<SSDPReceiver<Self::Message>> {
+        self.listen_with_context(SsdpContext::global())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr, UdpSocket};
+    use std::time::Duration;
+
+    use super::{IncomingMessage, SsdpListener};
+    use crate::message::{Config, Listen, MessageType, SSDPMessage};
+    use crate::receiver::FromRawSSDP;
+
+    const NOTIFY: &[u8] = b"NOTIFY * HTTP/1.1\r\nHOST: 239.255.255.250:1900\r\nNT: upnp:rootdevice\r\n\
+                            NTS: ssdp:alive\r\nUSN: uuid:device-1::upnp:rootdevice\r\n\r\n";
+    const SEARCH: &[u8] = b"M-SEARCH * HTTP/1.1\r\nHOST: 239.255.255.250:1900\r\n\
+                            MAN: \"ssdp:discover\"\r\nMX: 1\r\nST: ssdp:all\r\n\r\n";
+    const RESPONSE: &[u8] = b"HTTP/1.1 200 OK\r\nCACHE-CONTROL: max-age=1800\r\nEXT:\r\n\
+                              LOCATION: http://127.0.0.1/description.xml\r\nST: ssdp:all\r\n\
+                              USN: uuid:device-1\r\n\r\n";
+
+    #[test]
+    fn positive_classify() {
+        for (packet, message_type) in [
+            (NOTIFY, MessageType::Notify),
+            (SEARCH, MessageType::Search),
+            (RESPONSE, MessageType::Response),
+        ] {
+            let incoming = IncomingMessage::from_packet(packet).unwrap();
+            assert_eq!(incoming.message_type(), message_type);
+
+            let message = SSDPMessage::from(incoming.clone());
+            assert_eq!(message.message_type(), message_type);
+            assert_eq!(IncomingMessage::from(message), incoming);
+        }
+    }
+
+    #[test]
+    fn negative_classify() {
+        assert!(IncomingMessage::from_packet(b"GET / HTTP/1.1\r\n\r\n").is_err());
+    }
+
+    #[test]
+    fn positive_listen_demultiplexes() {
+        let config = Config::new()
+            .set_port(19_041)
+            .set_mode(crate::IpVersionMode::V4Only)
+            .set_include_loopback(true)
+            .set_interfaces([IpAddr::V4(Ipv4Addr::LOCALHOST)]);
+        let receiver = SsdpListener::new(config).listen().unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        for packet in [NOTIFY, SEARCH, RESPONSE] {
+            sender.send_to(packet, "127.0.0.1:19041").unwrap();
+        }
+
+        let mut received = Vec::new();
+        for _ in 0..3 {
+            let (message, peer) = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+            assert_eq!(peer, sender.local_addr().unwrap());
+            received.push(message);
+        }
+
+        assert!(matches!(received[0], IncomingMessage::Notify(_)));
+        assert!(matches!(received[1], IncomingMessage::Search(_)));
+        assert!(matches!(received[2], IncomingMessage::Response(_)));
+        assert!(receiver.recv_timeout(Duration::from_millis(500)).is_err());
+    }
+}
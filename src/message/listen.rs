@@ -1,97 +1,699 @@
-use std::net::{IpAddr, SocketAddr};
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use crate::error::SSDPResult;
-use crate::message::{self, Config};
+use crate::error::{SSDPError, SSDPResult};
+use crate::context::SsdpContext;
+use crate::message::Config;
 use crate::net;
-use crate::receiver::{FromRawSSDP, SSDPReceiver};
+use crate::net::{IoAt, NetifAddr};
+use crate::receiver::{
+    AttachedSocket, FromRawSSDP, JoinedGroup, MessageFilter, ReceiverOptions, SSDPReceiver, SocketAttacher,
+};
 
+/// How often the monitor thread checks whether the receiver was dropped.
+const MONITOR_STOP_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A source of received messages.
+///
+/// Listeners are usually configured on construction, which allows code to be generic over where
+/// messages come from. The trait is object safe and implemented for references and boxes.
+///
+/// This trait is open for implementation, for example to substitute a fake source in tests.
+/// Methods added in a minor release will have a default implementation.
 pub trait Listen {
     type Message: FromRawSSDP + Send + 'static;
 
-    /// Listen for messages on all local network interfaces.
-    ///
-    /// This will call `listen_with_config()` with _default_ values.
-    fn listen() -> SSDPResult<SSDPReceiver<Self::Message>> {
-        Self::listen_with_config(&Default::default())
+    /// Start listening for messages.
+    fn listen(&self) -> SSDPResult<SSDPReceiver<Self::Message>>;
+}
+
+impl<L: Listen + ?Sized> Listen for &'_ L {
+    type Message = L::Message;
+
+    fn listen(&self) -> SSDPResult<SSDPReceiver<Self::Message>> {
+        (**self).listen()
     }
+}
 
-    /// Listen for messages on all local network interfaces.
-    ///
-    /// # Notes
-    /// This will _bind_ to each interface, **NOT** to `INADDR_ANY`.
-    ///
-    /// If you are on an environment where the network interface will be changing,
-    /// you will have to stop listening and start listening again,
-    /// or we recommend using `listen_anyaddr_with_config()` instead.
-    fn listen_with_config(config: &Config) -> SSDPResult<SSDPReceiver<Self::Message>> {
-        let mut ipv4_sock = None;
-        let mut ipv6_sock = None;
-
-        // Generate a list of reused sockets on the standard multicast address.
-        let addrs: Vec<_> = message::map_local(|&addr| Ok(Some(addr)))?;
-
-        for iface in addrs {
-            match &iface.sock {
-                IpAddr::V4(v4) => {
-                    let mcast_ip = config.ipv4_addr.parse().unwrap();
-
-                    if ipv4_sock.is_none() {
-                        ipv4_sock = Some(net::bind_reuse(("0.0.0.0", config.port))?);
-                    }
-
-                    let ref sock = ipv4_sock.as_ref().unwrap();
-
-                    debug!("Joining ipv4 multicast {} at iface: {}", mcast_ip, iface.sock);
-                    let addr = SocketAddr::V4(std::net::SocketAddrV4::new(*v4, 0));
-                    net::join_multicast(&sock, &addr, &mcast_ip)?;
-                }
-                IpAddr::V6(v6) => {
-                    let mcast_ip = config.ipv6_addr.parse().unwrap();
+impl<L: Listen + ?Sized> Listen for Box<L> {
+    type Message = L::Message;
 
-                    if ipv6_sock.is_none() {
-                        ipv6_sock = Some(net::bind_reuse(("::", config.port))?);
-                    }
+    fn listen(&self) -> SSDPResult<SSDPReceiver<Self::Message>> {
+        (**self).listen()
+    }
+}
 
-                    let ref sock = ipv6_sock.as_ref().unwrap();
+/// Listen for messages on all local network interfaces.
+///
+/// # Notes
+/// This will _bind_ to each interface, **NOT** to `INADDR_ANY`.
+///
+/// If you are on an environment where the network interface will be changing,
+/// you will have to stop listening and start listening again,
+/// or we recommend using `listen_with_config_monitored()` or `listen_anyaddr_with_config()`
+/// instead.
+pub fn listen_with_config<T>(config: &Config) -> SSDPResult<SSDPReceiver<T>>
+where
+    T: FromRawSSDP + Send + 'static,
+{
+    listen_with_context(SsdpContext::global(), config)
+}
+
+/// Listen for messages on all interfaces of the context.
+///
+/// See `listen_with_config`.
+pub(crate) fn listen_with_context<T>(context: &SsdpContext, config: &Config) -> SSDPResult<SSDPReceiver<T>>
+where
+    T: FromRawSSDP + Send + 'static,
+{
+    listen_filtered_with_context(context, config, None)
+}
+
+/// Listen for messages on all interfaces of the context, dropping those rejected by the filter.
+///
+/// Unlike `SSDPReceiver::set_filter` the filter applies to the very first message.
+pub(crate) fn listen_filtered_with_context<T>(
+    context: &SsdpContext,
+    config: &Config,
+    filter: Option<MessageFilter<T>>,
+) -> SSDPResult<SSDPReceiver<T>>
+where
+    T: FromRawSSDP + Send + 'static,
+{
+    let mut ipv4_sock = None;
+    let mut ipv6_sock = None;
+    let mut groups = Vec::new();
+    let mut failures = Vec::new();
+    let ipv4_mcast = IpAddr::V4(config.ipv4_addr.parse()?);
+    let ipv6_mcast = IpAddr::V6(config.ipv6_multicast_addr().parse()?);
+
+    // Generate a list of reused sockets on the standard multicast address.
+    let addrs = context.local_interfaces(config)?;
 
-                    debug!("Joining ipv6 multicast {} at iface: {}", mcast_ip, iface.sock);
-                    let addr = SocketAddr::V6(std::net::SocketAddrV6::new(*v6, 0, 0, iface.index));
-                    net::join_multicast(&sock, &addr, &IpAddr::V6(mcast_ip))?;
+    for iface in addrs {
+        let (sock, addr, mcast_ip) = match iface.sock {
+            IpAddr::V4(v4) => {
+                if ipv4_sock.is_none() {
+                    ipv4_sock = Some(Arc::new(bind_listener(("0.0.0.0", config.port), config)?));
                 }
+
+                let addr = SocketAddr::V4(std::net::SocketAddrV4::new(v4, 0));
+                (ipv4_sock.as_ref().unwrap(), addr, ipv4_mcast)
+            }
+            IpAddr::V6(v6) => {
+                if ipv6_sock.is_none() {
+                    ipv6_sock = Some(Arc::new(bind_listener(("::", config.port), config)?));
+                }
+
+                let addr = SocketAddr::V6(std::net::SocketAddrV6::new(v6, 0, 0, iface.index));
+                (ipv6_sock.as_ref().unwrap(), addr, ipv6_mcast)
+            }
+        };
+
+        // A stale interface, such as a lingering VPN adapter, must not prevent listening on the
+        // healthy ones.
+        debug!("Joining multicast {} at iface: {}", mcast_ip, iface.sock);
+        match net::join_multicast(sock, &addr, &mcast_ip) {
+            Ok(()) => groups.push(JoinedGroup::new(Arc::clone(sock), addr, mcast_ip)),
+            Err(err) => {
+                debug!("Failed to join multicast at iface {}: {}", iface.sock, err);
+                failures.push((addr, err.into()));
             }
         }
+    }
 
-        let sockets = vec![ipv4_sock, ipv6_sock]
-            .into_iter()
-            .flat_map(|opt_interface| opt_interface)
-            .map(std::sync::Arc::new)
-            .collect();
+    if groups.is_empty() && !failures.is_empty() {
+        return Err(SSDPError::AllJoinsFailed(failures));
+    }
+
+    // A socket without any group would not receive multicast messages.
+    let sockets = vec![ipv4_sock, ipv6_sock]
+        .into_iter()
+        .flatten()
+        .filter(|sock| groups.iter().any(|group| group.is_on(sock)))
+        .collect();
+
+    context.stats().add_listener();
+    let options = ReceiverOptions::from_config(config);
+    let mut receiver = SSDPReceiver::with_filter(context, sockets, None, &options, filter)?;
+    receiver.leave_on_drop(groups);
+    receiver.report_join_failures(failures);
+    Ok(receiver)
+}
+
+/// Listen for messages on all local network interfaces, following changes of the interfaces.
+///
+/// The interfaces are enumerated again in the given interval. The multicast groups are joined on
+/// interfaces that appeared and left on those that disappeared, while the receiver stays the same.
+pub fn listen_with_config_monitored<T>(
+    config: &Config,
+    poll_interval: Duration,
+) -> SSDPResult<SSDPReceiver<T>>
+where
+    T: FromRawSSDP + Send + 'static,
+{
+    listen_monitored_with_context(SsdpContext::global(), config, poll_interval)
+}
+
+/// Listen for messages on all interfaces of the context, following changes of the interfaces.
+///
+/// See `listen_with_config_monitored`.
+pub(crate) fn listen_monitored_with_context<T>(
+    context: &SsdpContext,
+    config: &Config,
+    poll_interval: Duration,
+) -> SSDPResult<SSDPReceiver<T>>
+where
+    T: FromRawSSDP + Send + 'static,
+{
+    let (receiver, attacher) =
+        SSDPReceiver::with_attacher(context, Vec::new(), None, &ReceiverOptions::from_config(config), None)?;
+    let mut membership = Membership::new(config.clone(), attacher)?;
+    membership.update(context.local_interfaces(config)?)?;
+
+    let thread_context = context.clone();
+    context.spawn(move || loop {
+        let deadline = Instant::now() + poll_interval;
+
+        while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+            if membership.attacher.is_stopped() {
+                return;
+            }
+
+            std::thread::sleep(remaining.min(MONITOR_STOP_INTERVAL));
+        }
+
+        let result = thread_context
+            .local_interfaces(&membership.config)
+            .map_err(SSDPError::from)
+            .and_then(|addrs| membership.update(addrs));
+
+        if let Err(err) = result {
+            debug!("Failed to update the listened interfaces: {}", err);
+        }
+    });
+
+    context.stats().add_listener();
+    Ok(receiver)
+}
+
+/// The sockets of a monitored listener and the interfaces they joined the groups on.
+struct Membership<T> {
+    config: Config,
+    /// The multicast groups of the config, parsed once so leaving them cannot fail.
+    ipv4_group: Ipv4Addr,
+    ipv6_group: Ipv6Addr,
+    attacher: SocketAttacher<T>,
+    ipv4: Option<MembershipSocket>,
+    ipv6: Option<MembershipSocket>,
+    joined: Vec<NetifAddr>,
+}
+
+struct MembershipSocket {
+    sock: Arc<UdpSocket>,
+    /// Detaches the socket from the receiver when dropped.
+    _attached: AttachedSocket,
+}
 
-        Ok(SSDPReceiver::new(sockets, None)?)
+impl<T> Membership<T>
+where
+    T: FromRawSSDP + Send + 'static,
+{
+    fn new(config: Config, attacher: SocketAttacher<T>) -> SSDPResult<Self> {
+        Ok(Membership {
+            ipv4_group: config.ipv4_addr.parse()?,
+            ipv6_group: config.ipv6_multicast_addr().parse()?,
+            config,
+            attacher,
+            ipv4: None,
+            ipv6: None,
+            joined: Vec::new(),
+        })
     }
 
-    /// Listen on any interface
+    /// Join the groups on interfaces that appeared and leave them on those that disappeared.
     ///
-    /// # Important
-    ///
-    /// This version of the `listen`()` will _bind_ to `INADDR_ANY` instead of binding to each interface
-    #[cfg(target_os = "linux")]
-    fn listen_anyaddr_with_config(config: &Config) -> SSDPResult<SSDPReceiver<Self::Message>> {
-        // Ipv4
-        let mcast_ip = config.ipv4_addr.parse().unwrap();
-        let ipv4_sock = net::bind_reuse(("0.0.0.0", config.port))?;
-        ipv4_sock.join_multicast_v4(&mcast_ip, &"0.0.0.0".parse().unwrap())?;
-
-        // Ipv6
-        let mcast_ip = config.ipv6_addr.parse().unwrap();
-        let ipv6_sock = net::bind_reuse(("::", config.port))?;
-        ipv6_sock.join_multicast_v6(&mcast_ip, 0)?;
-
-        let sockets = [ipv4_sock, ipv6_sock]
+    /// The socket of an IP version is closed once no interface of that version is left.
+    fn update(&mut self, addrs: Vec<NetifAddr>) -> SSDPResult<()> {
+        fn same(a: &NetifAddr, b: &NetifAddr) -> bool {
+            a.sock == b.sock && a.index == b.index
+        }
+
+        let (kept, removed): (Vec<_>, Vec<_>) = std::mem::take(&mut self.joined)
             .into_iter()
-            .map(std::sync::Arc::new)
-            .collect();
+            .partition(|old| addrs.iter().any(|new| same(old, new)));
+        self.joined = kept;
+
+        for iface in &removed {
+            self.leave(iface);
+        }
+
+        for iface in &addrs {
+            if self.joined.iter().any(|old| same(old, iface)) {
+                continue;
+            }
+
+            match self.join(iface) {
+                Ok(()) => self.joined.push(*iface),
+                Err(err) => debug!("Failed to join multicast at iface {}: {}", iface.sock, err),
+            }
+        }
+
+        if !self.joined.iter().any(|iface| iface.sock.is_ipv4()) {
+            self.ipv4 = None;
+        }
+
+        if !self.joined.iter().any(|iface| iface.sock.is_ipv6()) {
+            self.ipv6 = None;
+        }
+
+        Ok(())
+    }
+
+    fn join(&mut self, iface: &NetifAddr) -> SSDPResult<()> {
+        match iface.sock {
+            IpAddr::V4(v4) => {
+                let mcast_ip = IpAddr::V4(self.ipv4_group);
+                let sock = self.socket(true)?;
+
+                debug!("Joining ipv4 multicast {} at iface: {}", mcast_ip, iface.sock);
+                let addr = SocketAddr::V4(std::net::SocketAddrV4::new(v4, 0));
+                net::join_multicast(&sock, &addr, &mcast_ip)?;
+            }
+            IpAddr::V6(_) => {
+                // The group is joined per interface, not per address.
+                if self.joined.iter().any(|old| old.sock.is_ipv6() && old.index == iface.index) {
+                    return Ok(());
+                }
+
+                let mcast_ip = self.ipv6_group;
+                let sock = self.socket(false)?;
+
+                debug!("Joining ipv6 multicast {} at iface: {}", mcast_ip, iface.sock);
+                sock.join_multicast_v6(&mcast_ip, iface.index).map_err(|err| {
+                    let group = SocketAddr::new(mcast_ip.into(), 0);
+                    IoAt::wrap(err, "join", Some(group), Some(net::iface_label(iface.index)))
+                })?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Get the IPv4 or IPv6 socket, binding and attaching it if necessary.
+    fn socket(&mut self, ipv4: bool) -> SSDPResult<Arc<UdpSocket>> {
+        let (local_ip, slot) = match ipv4 {
+            true => ("0.0.0.0", &mut self.ipv4),
+            false => ("::", &mut self.ipv6),
+        };
+
+        if let Some(socket) = slot {
+            return Ok(Arc::clone(&socket.sock));
+        }
+
+        let sock = Arc::new(bind_listener((local_ip, self.config.port), &self.config)?);
+        let attached = self.attacher.attach(Arc::clone(&sock))?;
+        *slot = Some(MembershipSocket {
+            sock: Arc::clone(&sock),
+            _attached: attached,
+        });
+
+        Ok(sock)
+    }
+}
+
+impl<T> Membership<T> {
+    fn leave(&self, iface: &NetifAddr) {
+        let result = match (iface.sock, &self.ipv4, &self.ipv6) {
+            (IpAddr::V4(v4), Some(socket), _) => socket.sock.leave_multicast_v4(&self.ipv4_group, &v4),
+            (IpAddr::V6(_), _, Some(socket)) => {
+                // Other addresses of the interface still need the group.
+                if self.joined.iter().any(|other| other.sock.is_ipv6() && other.index == iface.index) {
+                    return;
+                }
+
+                socket.sock.leave_multicast_v6(&self.ipv6_group, iface.index)
+            }
+            _ => return,
+        };
+
+        if let Err(err) = result {
+            debug!("Failed to leave multicast at iface {}: {}", iface.sock, err);
+        }
+    }
+}
+
+impl<T> Drop for Membership<T> {
+    fn drop(&mut self) {
+        // Leave one address at a time, so IPv6 groups are left once the last address of an
+        // interface is gone.
+        while let Some(iface) = self.joined.pop() {
+            self.leave(&iface);
+        }
+    }
+}
+
+/// Listen on any interface
+///
+/// # Important
+///
+/// This version of the `listen`()` will _bind_ to `INADDR_ANY` instead of binding to each interface
+///
+/// On Linux the groups are joined for the unspecified interface. Other platforms then only join
+/// on a single interface, so the groups are joined on every interface of the config instead.
+pub fn listen_anyaddr_with_config<T>(config: &Config) -> SSDPResult<SSDPReceiver<T>>
+where
+    T: FromRawSSDP + Send + 'static,
+{
+    listen_anyaddr_with_context(SsdpContext::global(), config)
+}
+
+/// Listen on any interface, enumerating the interfaces through the context.
+///
+/// See `listen_anyaddr_with_config`.
+pub(crate) fn listen_anyaddr_with_context<T>(
+    context: &SsdpContext,
+    config: &Config,
+) -> SSDPResult<SSDPReceiver<T>>
+where
+    T: FromRawSSDP + Send + 'static,
+{
+    let ipv4_sock = Arc::new(bind_listener(("0.0.0.0", config.port), config)?);
+    let ipv6_sock = Arc::new(bind_listener(("::", config.port), config)?);
+    let groups = join_anyaddr(context, config, &ipv4_sock, &ipv6_sock)?;
+
+    context.stats().add_listener();
+    let sockets = vec![ipv4_sock, ipv6_sock];
+    let options = ReceiverOptions::from_config(config);
+    let mut receiver = SSDPReceiver::with_context(context, sockets, None, &options)?;
+    receiver.leave_on_drop(groups);
+    Ok(receiver)
+}
+
+/// Join the multicast groups on sockets bound to the unspecified address.
+#[cfg(target_os = "linux")]
+fn join_anyaddr(
+    _: &SsdpContext,
+    config: &Config,
+    ipv4_sock: &Arc<UdpSocket>,
+    ipv6_sock: &Arc<UdpSocket>,
+) -> SSDPResult<Vec<JoinedGroup>> {
+    // Ipv4
+    let mcast_ip = IpAddr::V4(config.ipv4_addr.parse()?);
+    let iface = SocketAddr::from(([0, 0, 0, 0], 0));
+    net::join_multicast(ipv4_sock, &iface, &mcast_ip)?;
+    let ipv4_group = JoinedGroup::new(Arc::clone(ipv4_sock), iface, mcast_ip);
+
+    // Ipv6
+    let mcast_ip = IpAddr::V6(config.ipv6_multicast_addr().parse()?);
+    let iface = SocketAddr::from(([0u16; 8], 0));
+    net::join_multicast(ipv6_sock, &iface, &mcast_ip)?;
+    let ipv6_group = JoinedGroup::new(Arc::clone(ipv6_sock), iface, mcast_ip);
+
+    Ok(vec![ipv4_group, ipv6_group])
+}
+
+/// Join the multicast groups on sockets bound to the unspecified address.
+#[cfg(not(target_os = "linux"))]
+fn join_anyaddr(
+    context: &SsdpContext,
+    config: &Config,
+    ipv4_sock: &Arc<UdpSocket>,
+    ipv6_sock: &Arc<UdpSocket>,
+) -> SSDPResult<Vec<JoinedGroup>> {
+    let ipv4_mcast = IpAddr::V4(config.ipv4_addr.parse()?);
+    let ipv6_mcast = IpAddr::V6(config.ipv6_multicast_addr().parse()?);
+    // An interface with several addresses must join each group once only.
+    let mut joined_v6 = std::collections::HashSet::new();
+    let mut groups = Vec::new();
+
+    for iface in context.local_interfaces(config)? {
+        let (sock, mcast_ip, addr) = match iface.sock {
+            IpAddr::V4(v4) => (ipv4_sock, ipv4_mcast, SocketAddr::from((v4, 0))),
+            IpAddr::V6(_) if !joined_v6.insert(iface.index) => continue,
+            IpAddr::V6(v6) => {
+                let addr = SocketAddr::V6(std::net::SocketAddrV6::new(v6, 0, 0, iface.index));
+                (ipv6_sock, ipv6_mcast, addr)
+            }
+        };
+
+        // The adapter may have gone away in the meantime, the other ones are still useful.
+        match net::join_multicast(sock, &addr, &mcast_ip) {
+            Ok(()) => groups.push(JoinedGroup::new(Arc::clone(sock), addr, mcast_ip)),
+            Err(err) => debug!("Failed to join multicast at iface {}: {}", iface.sock, err),
+        }
+    }
+
+    Ok(groups)
+}
+
+/// Listen for messages on sockets bound by the caller.
+///
+/// The library performs no bind of its own, the sockets should already have joined the multicast
+/// groups, for example with `join_multicast`. If a timeout is provided, the receiver is shut down
+/// once no message was received for that duration.
+pub fn listen_on_sockets<T>(sockets: Vec<UdpSocket>, timeout: Option<Duration>) -> SSDPResult<SSDPReceiver<T>>
+where
+    T: FromRawSSDP + Send + 'static,
+{
+    let sockets = sockets.into_iter().map(std::sync::Arc::new).collect();
+    SsdpContext::global().stats().add_listener();
+    Ok(SSDPReceiver::new(sockets, timeout)?)
+}
+
+/// Bind a reused socket for listening, configured as described by the config.
+fn bind_listener<A: ToSocketAddrs>(local_addr: A, config: &Config) -> io::Result<UdpSocket> {
+    let local_addr = net::addr_from_trait(local_addr)?;
+    let sock = net::bind_reuse(local_addr, config.reuse_port)?;
+
+    if let Some(multicast_loop) = config.multicast_loop {
+        net::set_multicast_loop(&sock, &local_addr, multicast_loop)?;
+    }
+
+    Ok(sock)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+    use std::time::Duration;
+
+    use net2::UdpSocketExt as _;
+
+    use super::{Listen, Membership};
+    use crate::context::SsdpContext;
+    use crate::message::{Config, Multicast, NotifyListener, NotifyMessage};
+    use crate::net;
+    use crate::net::NetifAddr;
+    use crate::receiver::SSDPReceiver;
+    use crate::{SSDPError, SSDPResult};
+
+    /// A listener that never receives any message.
+    struct FakeListener;
+
+    impl Listen for FakeListener {
+        type Message = NotifyMessage;
+
+        fn listen(&self) -> SSDPResult<SSDPReceiver<Self::Message>> {
+            Ok(SSDPReceiver::new(Vec::new(), None)?)
+        }
+    }
+
+    fn first_message<L: Listen>(listener: L) -> Option<L::Message> {
+        listener.listen().unwrap().try_recv().ok().map(|(message, _)| message)
+    }
+
+    #[test]
+    fn positive_trait_objects() {
+        let listeners: Vec<Box<dyn Listen<Message = NotifyMessage>>> = vec![
+            Box::new(NotifyListener::new(Config::new())),
+            Box::new(FakeListener),
+        ];
+
+        assert_eq!(listeners.len(), 2);
+    }
+
+    #[test]
+    fn positive_generic_fake() {
+        assert!(first_message(FakeListener).is_none());
+        assert!(first_message(&FakeListener).is_none());
+
+        let boxed: Box<dyn Listen<Message = NotifyMessage>> = Box::new(FakeListener);
+        assert!(first_message(&boxed).is_none());
+        assert!(first_message(boxed).is_none());
+    }
+
+    #[test]
+    fn positive_listen_on_sockets() {
+        let config = Config::new()
+            .set_port(19_012)
+            .set_mode(crate::IpVersionMode::V4Only)
+            .set_include_loopback(true)
+            .set_interfaces([IpAddr::V4(Ipv4Addr::LOCALHOST)]);
+
+        let socket = net::bind_reuse((Ipv4Addr::UNSPECIFIED, 19_012), true).unwrap();
+        let iface = SocketAddr::from((Ipv4Addr::LOCALHOST, 0));
+        crate::join_multicast(&socket, &iface, &config.ipv4_addr.parse().unwrap()).unwrap();
+
+        let receiver =
+            super::listen_on_sockets::<NotifyMessage>(vec![socket], Some(Duration::from_secs(5))).unwrap();
+        NotifyMessage::new().multicast_with_config(&config).unwrap();
+
+        assert!(receiver.recv_timeout(Duration::from_secs(5)).is_ok());
+    }
+
+    #[test]
+    fn positive_reuse_port() {
+        let config = Config::new()
+            .set_port(19_013)
+            .set_mode(crate::IpVersionMode::V4Only)
+            .set_reuse_port(true);
+
+        let first = super::bind_listener((Ipv4Addr::UNSPECIFIED, config.port), &config);
+        let second = super::bind_listener((Ipv4Addr::UNSPECIFIED, config.port), &config);
+
+        assert!(first.is_ok());
+        assert!(second.is_ok());
+    }
+
+    #[test]
+    fn positive_listen_anyaddr() {
+        let config = Config::new()
+            .set_port(19_014)
+            .set_mode(crate::IpVersionMode::V4Only);
+        let listener = super::listen_anyaddr_with_config::<NotifyMessage>(&config).unwrap();
+
+        NotifyMessage::new().multicast_with_config(&config).unwrap();
+
+        assert!(listener.recv_timeout(Duration::from_secs(5)).is_ok());
+    }
+
+    /// A context with the loopback interface and an address that no interface has.
+    fn stale_context(with_loopback: bool) -> SsdpContext {
+        SsdpContext::new().set_interfaces(move || -> io::Result<Vec<NetifAddr>> {
+            let stale = NetifAddr {
+                sock: IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)),
+                index: 99,
+            };
+            let loopback = NetifAddr {
+                sock: IpAddr::V4(Ipv4Addr::LOCALHOST),
+                index: 1,
+            };
+
+            Ok(if with_loopback { vec![stale, loopback] } else { vec![stale] })
+        })
+    }
+
+    #[test]
+    fn positive_listen_despite_stale_interface() {
+        let config = Config::new()
+            .set_port(19_036)
+            .set_mode(crate::IpVersionMode::V4Only)
+            .set_include_loopback(true);
+        let receiver = stale_context(true).listen::<NotifyMessage>(&config).unwrap();
+
+        let failures = receiver.join_failures();
+        assert_eq!(failures.len(), 1, "{:?}", failures);
+        assert_eq!(failures[0].0.ip(), IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)));
+        assert_eq!(receiver.local_addrs().len(), 1);
+    }
+
+    #[test]
+    fn negative_listen_all_joins_failed() {
+        let config = Config::new()
+            .set_port(19_037)
+            .set_mode(crate::IpVersionMode::V4Only);
+
+        match stale_context(false).listen::<NotifyMessage>(&config) {
+            Err(SSDPError::AllJoinsFailed(errors)) => assert_eq!(errors.len(), 1),
+            Err(other) => panic!("unexpected error: {}", other),
+            Ok(_) => panic!("listened without any group"),
+        }
+    }
+
+    #[test]
+    fn negative_listen_invalid_group() {
+        let config = Config::new().set_port(19_044).set_ipv4_addr("239.255.255");
+        let context = SsdpContext::global();
+
+        let result = context.listen::<NotifyMessage>(&config);
+        assert!(matches!(result, Err(SSDPError::AddrParseError(_))));
+        let interval = Duration::from_secs(1);
+        let result = super::listen_monitored_with_context::<NotifyMessage>(context, &config, interval);
+        assert!(matches!(result, Err(SSDPError::AddrParseError(_))));
+    }
+
+    /// The IPv6 socket must not receive the IPv4 packets as well, as mapped addresses.
+    #[test]
+    fn positive_dual_stack_delivers_once() {
+        let context = SsdpContext::new().set_interfaces(|| -> io::Result<Vec<NetifAddr>> {
+            Ok(vec![
+                NetifAddr {
+                    sock: IpAddr::V4(Ipv4Addr::LOCALHOST),
+                    index: 1,
+                },
+                NetifAddr {
+                    sock: IpAddr::V6(Ipv6Addr::LOCALHOST),
+                    index: 1,
+                },
+            ])
+        });
+        let config = Config::new().set_port(19_038).set_include_loopback(true);
+        let receiver = context.listen::<NotifyMessage>(&config).unwrap();
+        assert_eq!(receiver.local_addrs().len(), 2);
+
+        let sender = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        sender.set_multicast_if_v4(&Ipv4Addr::LOCALHOST).unwrap();
+        let notify = b"NOTIFY * HTTP/1.1\r\nHOST: 239.255.255.250:1900\r\n\r\n";
+        sender.send_to(notify, (Ipv4Addr::new(239, 255, 255, 250), 19_038)).unwrap();
+
+        let (_, peer) = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(peer, sender.local_addr().unwrap());
+        assert!(receiver.recv_timeout(Duration::from_millis(300)).is_err());
+    }
+
+    /// Winsock rejects joining the IPv6 group with the IPv4 index of an interface.
+    #[test]
+    #[cfg(windows)]
+    fn positive_listen_v6_windows() {
+        let config = Config::new().set_port(19_035).set_mode(crate::IpVersionMode::V6Only);
+        let addrs = SsdpContext::global().local_interfaces(&config).unwrap();
+        assert!(addrs.iter().all(|addr| addr.index != 0), "{:?}", addrs);
+
+        SsdpContext::global().listen::<NotifyMessage>(&config).unwrap();
+    }
+
+    #[test]
+    fn positive_membership_follows_interfaces() {
+        let config = Config::new()
+            .set_port(19_015)
+            .set_mode(crate::IpVersionMode::V4Only)
+            .set_include_loopback(true)
+            .set_interfaces([IpAddr::V4(Ipv4Addr::LOCALHOST)]);
+        let loopback = NetifAddr {
+            sock: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            index: 1,
+        };
+
+        let context = SsdpContext::global();
+        let (receiver, attacher) =
+            SSDPReceiver::<NotifyMessage>::with_attacher(context, Vec::new(), None, &Default::default(), None)
+                .unwrap();
+        let mut membership = Membership::new(config.clone(), attacher).unwrap();
+
+        membership.update(vec![loopback]).unwrap();
+        NotifyMessage::new().multicast_with_config(&config).unwrap();
+        assert!(receiver.recv_timeout(Duration::from_secs(5)).is_ok());
+
+        membership.update(Vec::new()).unwrap();
+        assert!(membership.ipv4.is_none());
+        std::thread::sleep(Duration::from_millis(300));
+        while receiver.try_recv().is_ok() {}
 
-        Ok(SSDPReceiver::new(sockets, None)?)
+        NotifyMessage::new().multicast_with_config(&config).unwrap();
+        assert!(receiver.recv_timeout(Duration::from_millis(500)).is_err());
     }
 }
@@ -1,32 +1,72 @@
 //! Messaging primitives for discovering devices and services.
 
+use std::fmt;
 use std::io;
-use std::net::IpAddr;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
 
-use crate::net::connector::UdpConnector;
-use crate::net::{IpVersionMode, NetifAddr};
+use crate::context::{InterfaceSource, SsdpObserver};
+use crate::error::{SSDPError, SSDPResult};
+use crate::net::connector::{ConnectorOptions, UdpConnector};
+use crate::net::httpu::{HeaderOrder, Style};
+use crate::net::sender::RetryPolicy;
+use crate::net::{self, IpVersionMode, NetifAddr};
+use crate::receiver::ParseOptions;
 
+pub mod advertise;
+pub mod cache;
+pub mod compliance;
+#[cfg(feature = "description")]
+mod description;
+pub mod discovery;
+mod incoming;
 pub mod listen;
 pub mod multicast;
 mod notify;
+mod raw;
+mod responder;
 mod search;
-mod ssdp;
+mod session;
+pub(crate) mod ssdp;
+mod timeout;
+mod transport;
 
 use netdev::get_interfaces;
 
+pub use crate::message::advertise::{Advertisement, Advertiser, DeviceDescriptionLite, DeviceIdentity};
+pub use crate::message::cache::{CacheEvent, DeviceCache, DeviceRecord};
+#[cfg(feature = "description")]
+pub use crate::message::description::{DescribeError, DeviceDescription, ServiceDescription};
+pub use crate::message::discovery::{DiscoveredDevice, Discovery, DiscoveryEvent};
+pub use crate::message::incoming::{IncomingMessage, SsdpListener};
 pub use crate::message::listen::Listen;
 pub use crate::message::multicast::Multicast;
 pub use crate::message::notify::{NotifyListener, NotifyMessage};
-pub use crate::message::search::{SearchListener, SearchRequest, SearchResponse};
+pub use crate::message::raw::{RawFilter, RawSSDPMessage};
+pub use crate::message::responder::SearchResponder;
+pub use crate::message::search::{LocationPolicy, SearchListener, SearchRequest, SearchResponse};
+pub use crate::message::session::SearchSession;
+pub use crate::message::ssdp::{HostMismatch, SSDPMessage};
+pub use crate::message::timeout::{SendKind, Timeouts};
+pub use crate::message::transport::TransportSet;
 
 /// Multicast Socket Information
-pub const UPNP_MULTICAST_IPV4_ADDR: &'static str = "239.255.255.250";
-pub const UPNP_MULTICAST_IPV6_LINK_LOCAL_ADDR: &'static str = "FF05::C";
+pub const UPNP_MULTICAST_IPV4_ADDR: &str = "239.255.255.250";
+pub const UPNP_MULTICAST_IPV6_LINK_SCOPE_ADDR: &str = "FF02::C";
+pub const UPNP_MULTICAST_IPV6_SITE_SCOPE_ADDR: &str = "FF05::C";
+pub const UPNP_MULTICAST_IPV6_GLOBAL_SCOPE_ADDR: &str = "FF0E::C";
+#[deprecated(note = "this is the site-local group, use `UPNP_MULTICAST_IPV6_SITE_SCOPE_ADDR` \
+                     or `UPNP_MULTICAST_IPV6_LINK_SCOPE_ADDR`")]
+pub const UPNP_MULTICAST_IPV6_LINK_LOCAL_ADDR: &str = UPNP_MULTICAST_IPV6_SITE_SCOPE_ADDR;
 pub const UPNP_MULTICAST_PORT: u16 = 1900;
 
 /// Default TTL For Multicast
 pub const UPNP_MULTICAST_TTL: u32 = 2;
 
+/// Default overhead to add to device response times to account for transport time.
+pub const DEFAULT_TIMEOUT_OVERHEAD: Duration = Duration::from_secs(1);
+
 /// Enumerates different types of SSDP messages.
 #[derive(Copy, Clone, Hash, Eq, PartialEq, Debug)]
 pub enum MessageType {
@@ -38,13 +78,146 @@ pub enum MessageType {
     Response,
 }
 
-#[derive(Clone)]
+/// Enumerates the versions of the UPnP Device Architecture that a device may speak.
+///
+/// Later minor versions of UPnP 1 are reported as `V11`, which they are compatible with.
+#[derive(Copy, Clone, Hash, Eq, PartialEq, Debug)]
+#[non_exhaustive]
+pub enum UpnpVersion {
+    /// UPnP 1.0, without BOOTID, CONFIGID and SEARCHPORT.
+    V10,
+    /// UPnP 1.1.
+    V11,
+    /// UPnP 2.0.
+    V20,
+    /// The message names no version and has no header that requires one.
+    Unknown,
+}
+
+impl UpnpVersion {
+    /// Get the version of a `UPnP/major.minor` product token.
+    pub fn from_token(major: u8, minor: u8) -> UpnpVersion {
+        match (major, minor) {
+            (1, 0) => UpnpVersion::V10,
+            (1, _) => UpnpVersion::V11,
+            (2, _) => UpnpVersion::V20,
+            _ => UpnpVersion::Unknown,
+        }
+    }
+}
+
+/// Enumerates the scopes of the IPv6 multicast groups defined by UPnP.
+#[derive(Copy, Clone, Debug, Default, Hash, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Ipv6Scope {
+    /// The group `FF02::C`, reaching the local link.
+    #[default]
+    LinkLocal,
+    /// The group `FF05::C`, reaching the local site.
+    SiteLocal,
+    /// The group `FF0E::C`, reaching the whole internet.
+    Global,
+}
+
+/// Enumerates the ways of choosing the interfaces that send a unicast message.
+#[derive(Copy, Clone, Debug, Default, Hash, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum UnicastPolicy {
+    /// Send through every interface and let the system route each copy.
+    #[default]
+    AllInterfaces,
+    /// Send only through the interface the system routes the destination to.
+    ///
+    /// Falls back to all interfaces if that interface is not one of the configured ones.
+    BestRoute,
+}
+
+/// Enumerates the ways of writing the names of the headers of sent messages.
+#[derive(Copy, Clone, Debug, Default, Hash, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum HeaderCase {
+    /// All uppercase, as in `CACHE-CONTROL`.
+    #[default]
+    Upper,
+    /// The first letter of every word uppercase, as in `Cache-Control`.
+    ///
+    /// The `UPnP` extension headers, such as `BOOTID.UPNP.ORG` and `01-NLS`, are written all
+    /// uppercase as in the specification.
+    Canonical,
+    /// The spelling the name was set with by `HeaderMut::set_raw_cased`, as in `cache-Control`.
+    ///
+    /// Other names, such as those of typed headers, are written all uppercase.
+    AsSet,
+}
+
+impl Ipv6Scope {
+    /// Get the address of the multicast group with this scope.
+    pub fn multicast_addr(self) -> &'static str {
+        match self {
+            Ipv6Scope::LinkLocal => UPNP_MULTICAST_IPV6_LINK_SCOPE_ADDR,
+            Ipv6Scope::SiteLocal => UPNP_MULTICAST_IPV6_SITE_SCOPE_ADDR,
+            Ipv6Scope::Global => UPNP_MULTICAST_IPV6_GLOBAL_SCOPE_ADDR,
+        }
+    }
+}
+
+/// Options for sending and receiving messages.
+///
+/// With the `serde` feature, fields that are missing when deserializing take their default and
+/// unknown fields are an error. `Display` summarizes the network settings for logging.
+#[derive(Clone, Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default, deny_unknown_fields)
+)]
+#[non_exhaustive]
 pub struct Config {
     pub ipv4_addr: String,
-    pub ipv6_addr: String,
+    /// Overrides the group derived from `ipv6_scope`.
+    pub ipv6_addr: Option<String>,
+    pub ipv6_scope: Ipv6Scope,
     pub port: u16,
     pub ttl: u32,
     pub mode: IpVersionMode,
+    pub timeout_overhead: Duration,
+    /// How long to wait for responses to a search regardless of its MX.
+    pub response_window: Option<Duration>,
+    pub interfaces: Option<Vec<IpAddr>>,
+    pub receive_capacity: Option<usize>,
+    /// Whether multicast messages are looped back to the sending host, the default of the
+    /// operating system if not set.
+    pub multicast_loop: Option<bool>,
+    pub include_loopback: bool,
+    pub allow_global_v6: bool,
+    pub reuse_port: bool,
+    /// The local port searches are sent from, an ephemeral one if not set.
+    pub source_port: Option<u16>,
+    /// The DSCP of sent packets, the default of the operating system if not set.
+    pub dscp: Option<u8>,
+    pub bind_to_device: bool,
+    pub parse_options: ParseOptions,
+    pub accept_only_local_sources: bool,
+    pub max_packets_per_second: Option<u32>,
+    pub rate_limit_per_source: bool,
+    pub ignore_self: bool,
+    /// The UUID of our own device, without the `uuid:` prefix.
+    pub self_uuid: Option<String>,
+    pub clamp_mx: bool,
+    /// The MX of multicast searches that have none, in seconds.
+    pub default_mx: Option<u8>,
+    pub unicast_policy: UnicastPolicy,
+    pub keep_raw_packets: bool,
+    /// Not serialized, a deserialized config has no filter.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub raw_filter: Option<RawFilter>,
+    pub canonical_header_order: bool,
+    pub header_case: HeaderCase,
+    pub send_retry: RetryPolicy,
+    /// Not serialized, a deserialized config has no observer.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub observer: Option<Arc<dyn SsdpObserver>>,
 }
 
 impl Config {
@@ -57,16 +230,32 @@ impl Config {
         self
     }
 
+    /// Use the given IPv6 multicast group regardless of the configured scope.
     pub fn set_ipv6_addr<S: Into<String>>(mut self, value: S) -> Self {
-        self.ipv6_addr = value.into();
+        self.ipv6_addr = Some(value.into());
+        self
+    }
+
+    pub fn set_ipv6_scope(mut self, value: Ipv6Scope) -> Self {
+        self.ipv6_scope = value;
         self
     }
 
+    /// Get the IPv6 multicast group to join and send to.
+    pub fn ipv6_multicast_addr(&self) -> &str {
+        match self.ipv6_addr {
+            Some(ref addr) => addr,
+            None => self.ipv6_scope.multicast_addr(),
+        }
+    }
+
     pub fn set_port(mut self, value: u16) -> Self {
         self.port = value;
         self
     }
 
+    /// Set the TTL of sent multicast packets, or their hop limit on IPv6, by default 2 as
+    /// recommended by `UPnP`.
     pub fn set_ttl(mut self, value: u32) -> Self {
         self.ttl = value;
         self
@@ -76,67 +265,476 @@ impl Config {
         self.mode = value;
         self
     }
+
+    /// Restrict sending and listening to the local interfaces with the given addresses.
+    pub fn set_interfaces<I: IntoIterator<Item = IpAddr>>(mut self, value: I) -> Self {
+        self.interfaces = Some(value.into_iter().collect());
+        self
+    }
+
+    /// Set the time added to the response delay of devices while waiting for responses.
+    pub fn set_timeout_overhead(mut self, value: Duration) -> Self {
+        self.timeout_overhead = value;
+        self
+    }
+
+    /// Wait this long for responses to searches, instead of the time derived from the MX.
+    ///
+    /// The MX that is sent is unaffected, a short MX with a longer window lets devices answer
+    /// quickly while still catching late responses.
+    pub fn set_response_window(mut self, value: Option<Duration>) -> Self {
+        self.response_window = value;
+        self
+    }
+
+    /// Choose whether our own multicast messages are received by listeners on the same host.
+    pub fn set_multicast_loop(mut self, value: bool) -> Self {
+        self.multicast_loop = Some(value);
+        self
+    }
+
+    /// Also use the loopback interfaces of the system, which are skipped by default.
+    ///
+    /// This allows running a device and a control point on a host without any network.
+    pub fn set_include_loopback(mut self, value: bool) -> Self {
+        self.include_loopback = value;
+        self
+    }
+
+    /// Also use global unicast IPv6 addresses of the system, which are skipped by default.
+    ///
+    /// On networks without unique local addresses these may be the only usable addresses. The
+    /// multicast group is still selected by `set_ipv6_scope`: the link-local group is reached
+    /// through the interface of the address regardless of its scope, while the site-local and
+    /// global groups may additionally be routed beyond the link.
+    pub fn set_allow_global_v6(mut self, value: bool) -> Self {
+        self.allow_global_v6 = value;
+        self
+    }
+
+    /// Choose whether listening sockets share their port with other processes through
+    /// `SO_REUSEPORT`, enabled by default where the option exists.
+    ///
+    /// Without it another SSDP stack on the host, such as `minissdpd`, may receive the packets
+    /// instead of us.
+    pub fn set_reuse_port(mut self, value: bool) -> Self {
+        self.reuse_port = value;
+        self
+    }
+
+    /// Send searches and other messages from this local port instead of an ephemeral one.
+    ///
+    /// Some gateways only answer searches from port 1900, and firewalls may only let SSDP through
+    /// on that port. The sockets are bound with `SO_REUSEADDR`, and `SO_REUSEPORT` if
+    /// `set_reuse_port` is enabled, so they can share the port with a listener on the host.
+    /// Interfaces on which another socket holds the port exclusively are skipped, only if that is
+    /// the case on all of them binding fails with `SSDPError::AllBindsFailed`.
+    pub fn set_source_port(mut self, value: Option<u16>) -> Self {
+        self.source_port = value;
+        self
+    }
+
+    /// Mark the packets sent by us with this DSCP, such as 8 for CS1.
+    ///
+    /// This lets switches prioritize other traffic over discovery. The code point is set as
+    /// `IP_TOS` on IPv4 and `IPV6_TCLASS` on IPv6 sockets on Unix platforms. Windows ignores these
+    /// options and requires a QoS policy instead, there the packets are not marked. Values above
+    /// 63 are rejected by `validate`.
+    pub fn set_dscp(mut self, value: Option<u8>) -> Self {
+        self.dscp = value;
+        self
+    }
+
+    /// Restrict the sending sockets to the device of their interface instead of only binding to
+    /// its address.
+    ///
+    /// This is required on hosts where several interfaces share a subnet. Where the platform has
+    /// no such option the sockets are only bound to the address.
+    pub fn set_bind_to_device(mut self, value: bool) -> Self {
+        self.bind_to_device = value;
+        self
+    }
+
+    /// Bound the number of received messages buffered for a slow consumer.
+    ///
+    /// See `SSDPReceiver::with_capacity` for the behavior once the buffer is full.
+    pub fn set_receive_capacity(mut self, value: usize) -> Self {
+        self.receive_capacity = Some(value);
+        self
+    }
+
+    /// Keep the packet of every received message, for logging or replaying it.
+    ///
+    /// The packet is available through `SSDPReceiver::into_detailed_iter` as
+    /// `ReceivedMessage::raw`. This costs the memory of the packet for every buffered message.
+    pub fn set_keep_raw_packets(mut self, value: bool) -> Self {
+        self.keep_raw_packets = value;
+        self
+    }
+
+    /// Drop received packets for which the predicate returns false, before they are parsed.
+    ///
+    /// The predicate sees the packet as a `RawSSDPMessage`, which is much cheaper to construct
+    /// than a message. Listeners on a busy network can thus discard packets that do not interest
+    /// them, for example by their NT, at a fraction of the cost. Packets that are not even valid
+    /// as a raw view are parsed as usual. Dropped packets are counted as filtered in the `Stats`.
+    pub fn set_raw_filter<F>(mut self, predicate: F) -> Self
+    where
+        F: Fn(&RawSSDPMessage<'_>, &SocketAddr) -> bool + Send + Sync + 'static,
+    {
+        self.raw_filter = Some(RawFilter::new(predicate));
+        self
+    }
+
+    /// Report the packets sent and received with this config to the observer.
+    pub fn set_observer(mut self, value: Arc<dyn SsdpObserver>) -> Self {
+        self.observer = Some(value);
+        self
+    }
+
+    /// Set the limits for parsing the messages received by listeners and searches.
+    pub fn set_parse_options(mut self, value: ParseOptions) -> Self {
+        self.parse_options = value;
+        self
+    }
+
+    /// Drop received packets whose source is not on the network of a local interface.
+    ///
+    /// This guards against spoofed requests from the internet on a misconfigured host. The
+    /// networks are those of all interfaces of the system when the receiver is created, and
+    /// link-local IPv6 sources are always accepted. Dropped packets are counted by
+    /// `Stats::packets_filtered`.
+    pub fn set_accept_only_local_sources(mut self, value: bool) -> Self {
+        self.accept_only_local_sources = value;
+        self
+    }
+
+    /// Drop received packets beyond the given number per second, before they are parsed.
+    ///
+    /// Bursts of up to a second worth of packets pass, which is enough for regular traffic while
+    /// a device flooding the network no longer occupies a core. Dropped packets are counted by
+    /// `SSDPReceiver::packets_rate_limited`.
+    pub fn set_max_packets_per_second(mut self, value: Option<u32>) -> Self {
+        self.max_packets_per_second = value;
+        self
+    }
+
+    /// Apply the limit of `set_max_packets_per_second` to every source address separately.
+    ///
+    /// A single flooding device then does not drown out the others.
+    pub fn set_rate_limit_per_source(mut self, value: bool) -> Self {
+        self.rate_limit_per_source = value;
+        self
+    }
+
+    /// Drop received packets sent from any address of this host.
+    ///
+    /// This keeps a process that advertises and listens from receiving its own messages. It also
+    /// hides other processes on the host, use `set_self_uuid` to only ignore our own device.
+    /// Dropped packets are counted by `Stats::packets_filtered`.
+    pub fn set_ignore_self(mut self, value: bool) -> Self {
+        self.ignore_self = value;
+        self
+    }
+
+    /// Drop received messages whose USN carries the UUID of our own device.
+    ///
+    /// The UUID is given without the `uuid:` prefix and compared ignoring case.
+    pub fn set_self_uuid<S: Into<String>>(mut self, value: S) -> Self {
+        self.self_uuid = Some(value.into());
+        self
+    }
+
+    /// Clamp the MX of multicast searches to the maximum of `UPnP` 1.1.
+    ///
+    /// Devices of `UPnP` 1.1 treat any larger MX as 5 seconds, waiting longer for responses only
+    /// delays the search. The clamped value is sent and determines the receive timeout.
+    pub fn set_clamp_mx(mut self, value: bool) -> Self {
+        self.clamp_mx = value;
+        self
+    }
+
+    /// Send multicast searches without a valid MX with this one instead of failing.
+    ///
+    /// The MX is added to the sent packet and determines the receive timeout. A value outside the
+    /// range of `MX::new` is ignored.
+    pub fn set_default_mx(mut self, value: Option<u8>) -> Self {
+        self.default_mx = value;
+        self
+    }
+
+    /// Choose the interfaces that send unicast messages.
+    ///
+    /// On hosts with several interfaces, sending through all of them duplicates the message with
+    /// different source addresses. See `UnicastPolicy::BestRoute`.
+    pub fn set_unicast_policy(mut self, value: UnicastPolicy) -> Self {
+        self.unicast_policy = value;
+        self
+    }
+
+    /// Write the headers of sent messages in the order of the examples in the UPnP device
+    /// architecture.
+    ///
+    /// HOST, CACHE-CONTROL, LOCATION, NT, NTS, SERVER, USN, BOOTID, CONFIGID and SEARCHPORT come
+    /// first, all other headers follow sorted by name. This makes packets comparable to reference
+    /// captures, by default headers are written in the order they were set.
+    pub fn set_canonical_header_order(mut self, value: bool) -> Self {
+        self.canonical_header_order = value;
+        self
+    }
+
+    /// Choose the case of the header names of sent requests and responses.
+    ///
+    /// Some control points only recognize headers such as `Cache-Control` in a particular case.
+    pub fn set_header_case(mut self, value: HeaderCase) -> Self {
+        self.header_case = value;
+        self
+    }
+
+    /// Choose how sends are retried after transient errors such as full network buffers.
+    ///
+    /// By default a send is made up to 3 times, 10ms after the first failure and 20ms after the
+    /// second one. Sends through sockets that were bound by the caller always use the default.
+    pub fn set_send_retry(mut self, value: RetryPolicy) -> Self {
+        self.send_retry = value;
+        self
+    }
+
+    /// Check that the values of the config are in their range.
+    ///
+    /// Sockets are only created for a valid config, this reports the problem earlier.
+    pub fn validate(&self) -> SSDPResult<()> {
+        if let Some(dscp) = self.dscp.filter(|&dscp| dscp > net::MAX_DSCP) {
+            return Err(SSDPError::InvalidConfig(format!(
+                "DSCP {} is larger than {}",
+                dscp,
+                net::MAX_DSCP
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Get how the headers of sent messages are written.
+    pub(crate) fn header_style(&self) -> Style {
+        let order = if self.canonical_header_order {
+            HeaderOrder::Canonical
+        } else {
+            HeaderOrder::Map
+        };
+
+        Style {
+            order,
+            case: self.header_case,
+        }
+    }
 }
 
 impl Default for Config {
     fn default() -> Self {
         Config {
             ipv4_addr: UPNP_MULTICAST_IPV4_ADDR.to_string(),
-            ipv6_addr: UPNP_MULTICAST_IPV6_LINK_LOCAL_ADDR.to_string(),
+            ipv6_addr: None,
+            ipv6_scope: Ipv6Scope::default(),
             port: UPNP_MULTICAST_PORT,
             ttl: UPNP_MULTICAST_TTL,
             mode: IpVersionMode::Any,
+            timeout_overhead: DEFAULT_TIMEOUT_OVERHEAD,
+            response_window: None,
+            interfaces: None,
+            receive_capacity: None,
+            multicast_loop: None,
+            include_loopback: false,
+            allow_global_v6: false,
+            reuse_port: cfg!(unix),
+            source_port: None,
+            dscp: None,
+            bind_to_device: false,
+            parse_options: ParseOptions::default(),
+            accept_only_local_sources: false,
+            max_packets_per_second: None,
+            rate_limit_per_source: false,
+            ignore_self: false,
+            self_uuid: None,
+            clamp_mx: false,
+            default_mx: None,
+            unicast_policy: UnicastPolicy::AllInterfaces,
+            keep_raw_packets: false,
+            raw_filter: None,
+            canonical_header_order: false,
+            header_case: HeaderCase::Upper,
+            send_retry: RetryPolicy::default(),
+            observer: None,
         }
     }
 }
 
-/// Generate `UdpConnector` objects for all local `IPv4` interfaces.
-fn all_local_connectors(multicast_ttl: Option<u32>, filter: &IpVersionMode) -> io::Result<Vec<UdpConnector>> {
-    trace!("Fetching all local connectors");
-    map_local(|iface| match (filter, iface.sock) {
-        (&IpVersionMode::V4Only, IpAddr::V4(n)) | (&IpVersionMode::Any, IpAddr::V4(n)) => {
-            Ok(Some(UdpConnector::new((n, 0), iface.index, multicast_ttl)?))
+impl fmt::Display for Config {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "mode={} groups={},{} port={} ttl={} interfaces=",
+            self.mode,
+            self.ipv4_addr,
+            self.ipv6_multicast_addr(),
+            self.port,
+            self.ttl
+        )?;
+
+        match &self.interfaces {
+            None => f.write_str("all"),
+            Some(interfaces) => {
+                for (i, addr) in interfaces.iter().enumerate() {
+                    let separator = if i == 0 { "" } else { "," };
+                    write!(f, "{}{}", separator, addr)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Options for the sockets created by `all_local_connectors`.
+struct LocalConnectorOptions {
+    socket: ConnectorOptions,
+    /// The port to bind, an ephemeral one if zero.
+    port: u16,
+    mode: IpVersionMode,
+    bind_to_device: bool,
+    observer: Option<Arc<dyn SsdpObserver>>,
+}
+
+impl LocalConnectorOptions {
+    /// Options for sending to the multicast group of the config.
+    fn multicast(config: &Config) -> Self {
+        LocalConnectorOptions {
+            socket: ConnectorOptions {
+                broadcast: false,
+                ttl: Some(config.ttl),
+                loop_: config.multicast_loop,
+                retry: config.send_retry,
+                reuse_port: config.source_port.map(|_| config.reuse_port),
+                dscp: config.dscp,
+            },
+            port: config.source_port.unwrap_or(0),
+            mode: config.mode,
+            bind_to_device: config.bind_to_device,
+            observer: config.observer.clone(),
+        }
+    }
+
+    /// Options for sending to single hosts of the given IP version.
+    fn unicast(config: &Config, mode: IpVersionMode) -> Self {
+        LocalConnectorOptions {
+            socket: ConnectorOptions {
+                broadcast: false,
+                ttl: None,
+                loop_: config.multicast_loop,
+                retry: config.send_retry,
+                reuse_port: config.source_port.map(|_| config.reuse_port),
+                dscp: config.dscp,
+            },
+            port: config.source_port.unwrap_or(0),
+            mode,
+            bind_to_device: config.bind_to_device,
+            observer: config.observer.clone(),
         }
-        (&IpVersionMode::V6Only, IpAddr::V6(n)) | (&IpVersionMode::Any, IpAddr::V6(n)) => {
-            // Skip addresses we can not bind to..
-            Ok(Some(UdpConnector::new((n, 0), iface.index, multicast_ttl)?))
+    }
+
+    /// Options for sending to the broadcast addresses of the IPv4 interfaces.
+    fn broadcast(config: &Config) -> Self {
+        let mut options = LocalConnectorOptions::unicast(config, IpVersionMode::V4Only);
+        options.socket.broadcast = true;
+        options
+    }
+
+    /// Create a connector bound to the address of the interface.
+    fn connector(&self, addr: IpAddr, index: u32) -> io::Result<UdpConnector> {
+        Ok(self.bind(addr, index)?.with_observer(self.observer.clone()))
+    }
+
+    fn bind(&self, addr: IpAddr, index: u32) -> io::Result<UdpConnector> {
+        if self.bind_to_device {
+            match net::interface_name(index) {
+                Ok(name) => {
+                    return UdpConnector::new_bound_to_device((addr, self.port), &name, index, &self.socket);
+                }
+                Err(err) => debug!("No name for interface {}, not binding to it: {}", index, err),
+            }
         }
-        _ => Ok(None),
-    })
+
+        UdpConnector::new((addr, self.port), index, &self.socket)
+    }
 }
 
-/// Invoke the closure for every local address found on the system
+/// Generate `UdpConnector` objects for all given interfaces.
 ///
-/// This method filters out _loopback_ and _global_ addresses.
-fn map_local<F, R>(mut f: F) -> io::Result<Vec<R>>
+/// The interfaces are expected to be filtered by the config already, see `SsdpContext::local_interfaces`.
+/// Interfaces that can not be bound, such as a lingering VPN adapter, are skipped. This only fails
+/// if none of them could be bound, with the error of every interface.
+fn all_local_connectors(
+    addrs: Vec<NetifAddr>,
+    options: &LocalConnectorOptions,
+) -> SSDPResult<Vec<UdpConnector>> {
+    trace!("Fetching all local connectors");
+    let mut failures = Vec::new();
+
+    let connectors = map_local(addrs, |iface| {
+        let bound = match (&options.mode, iface.sock) {
+            (&IpVersionMode::V4Only, IpAddr::V4(_))
+            | (&IpVersionMode::V6Only, IpAddr::V6(_))
+            | (&IpVersionMode::Any, _) => options.connector(iface.sock, iface.index),
+            _ => return Ok(None),
+        };
+
+        match bound {
+            Ok(connector) => Ok(Some(connector)),
+            Err(err) => {
+                debug!("Failed to bind at iface {}: {}", iface.sock, err);
+                failures.push((SocketAddr::new(iface.sock, options.port), err.into()));
+                Ok(None)
+            }
+        }
+    })?;
+
+    if connectors.is_empty() && !failures.is_empty() {
+        return Err(SSDPError::AllBindsFailed(failures));
+    }
+
+    Ok(connectors)
+}
+
+/// Invoke the closure for every given local address.
+fn map_local<F, R>(addrs: Vec<NetifAddr>, mut f: F) -> io::Result<Vec<R>>
 where
     F: FnMut(&NetifAddr) -> io::Result<Option<R>>,
 {
-    let addrs_iter = get_local_addrs()?;
+    let mut obj_list = Vec::with_capacity(addrs.len());
 
-    let mut obj_list = Vec::with_capacity(addrs_iter.len());
-
-    for addr in addrs_iter {
+    for addr in addrs {
         trace!("Found {} @ {}", addr.sock, addr.index);
-        match addr.sock {
-            IpAddr::V4(n) if !n.is_loopback() => {
-                if let Some(x) = f(&addr)? {
-                    obj_list.push(x);
-                }
-            }
-            // Filter all loopback and global IPv6 addresses
-            IpAddr::V6(n) if !n.is_loopback() && is_not_global_v6(n) => {
-                if let Some(x) = f(&addr)? {
-                    obj_list.push(x);
-                }
-            }
-            _ => (),
+        if let Some(x) = f(&addr)? {
+            obj_list.push(x);
         }
     }
 
     Ok(obj_list)
 }
 
+/// Check if the interface can be used to send and receive messages.
+///
+/// This filters out _loopback_ and _global_ addresses, unless the config allows them. Link-local
+/// addresses are kept since SSDP over IPv6 is mostly used on the local link.
+fn is_usable_interface(addr: &NetifAddr, config: &Config) -> bool {
+    match addr.sock {
+        IpAddr::V4(n) => config.include_loopback || !n.is_loopback(),
+        // Filter all loopback and global IPv6 addresses
+        IpAddr::V6(n) => {
+            (config.include_loopback || !n.is_loopback()) && (config.allow_global_v6 || is_not_global_v6(n))
+        }
+    }
+}
+
 /// Determine if an address is not global.
 ///
 /// This may return incorrectly return `false` for some addresses that are not actually global. We
@@ -153,34 +751,205 @@ fn is_not_global_v6(addr: std::net::Ipv6Addr) -> bool {
         || addr.is_unique_local()
         // Second most relevant case, at least by my judgement.
         || is_6to4(addr)
+        // Bound together with the interface index as their scope.
+        || addr.is_unicast_link_local()
 
     // There are two more cases (unstable features) that are less relevant. We only want interfaces
     // which are probably useful to the user (they can provide a specific configuration if they
     // whish).
     // || addr.is_benchmarking()
     // || addr.is_documentation()
-    //
-    // Do not try to bind to link-local address.
-    // || addr.is_unicast_link_local()
 }
 
-/// Generate a list of some object R constructed from all local `Ipv4Addr` objects.
-///
-/// If any of the `SocketAddr`'s fail to resolve, this function will not return an error.
-fn get_local_addrs() -> io::Result<Vec<NetifAddr>> {
-    let iface_iter = get_interfaces().into_iter();
-    Ok(iface_iter
+/// The interfaces of the system, filtered as described by the config.
+pub(crate) struct SystemInterfaces;
+
+impl InterfaceSource for SystemInterfaces {
+    fn interfaces(&self) -> io::Result<Vec<NetifAddr>> {
+        get_local_addrs(&Config::default())
+    }
+
+    fn interfaces_for(&self, config: &Config) -> io::Result<Vec<NetifAddr>> {
+        get_local_addrs(config)
+    }
+}
+
+/// Get the networks of all interfaces of the system, including loopback and global ones.
+pub(crate) fn local_subnets() -> Vec<net::Subnet> {
+    get_interfaces()
+        .into_iter()
+        .flat_map(|iface| {
+            let ipv4 = iface.ipv4.into_iter().map(|net| net::Subnet {
+                addr: net.addr().into(),
+                prefix_len: net.prefix_len(),
+            });
+            let ipv6 = iface.ipv6.into_iter().map(|net| net::Subnet {
+                addr: net.addr().into(),
+                prefix_len: net.prefix_len(),
+            });
+
+            ipv4.chain(ipv6)
+        })
+        .collect()
+}
+
+/// Get the addresses of all interfaces of the system, including loopback and global ones.
+pub(crate) fn local_addrs() -> Vec<IpAddr> {
+    get_interfaces()
+        .into_iter()
+        .flat_map(|iface| {
+            let ipv4 = iface.ipv4.into_iter().map(|net| IpAddr::from(net.addr()));
+            let ipv6 = iface.ipv6.into_iter().map(|net| IpAddr::from(net.addr()));
+
+            ipv4.chain(ipv6)
+        })
+        .collect()
+}
+
+/// Get the interfaces of the system with their usable addresses, leaving out those without any.
+pub(crate) fn system_interfaces(config: &Config) -> Vec<net::Interface> {
+    get_interfaces()
+        .into_iter()
         // NOTE: this is incomplete. With IPv6 all link-local addresses need to be annotated with
         // the network interface, i.e. scope identifier, to which the belong. The scope id can be
         // parsed by std's `IPv6Addr as FromStr` but is just a literal integer `u32`. *Usually*
         // that can be set as the interface index however this is subject to the platform
         // implementation and need not generally be the identity mapping.
-        .flat_map(|iface| {
+        .filter_map(|iface| {
+            let index = iface.index;
             let ipv4 = iface.ipv4.into_iter().map(|ip| IpAddr::from(ip.addr()));
             let ipv6 = iface.ipv6.into_iter().map(|ip| IpAddr::from(ip.addr()));
-            let index = iface.index;
+            let addrs: Vec<_> = ipv4
+                .chain(ipv6)
+                .filter(|&sock| is_usable_interface(&NetifAddr { sock, index }, config))
+                .collect();
 
-            ipv4.chain(ipv6).map(move |ip| NetifAddr { sock: ip, index })
+            (!addrs.is_empty()).then_some(net::Interface {
+                name: iface.name,
+                index,
+                addrs,
+            })
         })
-        .collect())
+        .collect()
+}
+
+/// Generate a list of all usable local interface addresses found on the system.
+///
+/// If any of the `SocketAddr`'s fail to resolve, this function will not return an error.
+fn get_local_addrs(config: &Config) -> io::Result<Vec<NetifAddr>> {
+    let addrs = system_interfaces(config)
+        .into_iter()
+        .flat_map(|iface| {
+            let index = iface.index;
+            iface.addrs.into_iter().map(move |sock| NetifAddr { sock, index })
+        })
+        .collect();
+
+    Ok(net::with_ipv6_indices(addrs))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+    use super::{Config, LocalConnectorOptions, Ipv6Scope};
+    use crate::net::NetifAddr;
+    use crate::SSDPError;
+
+    fn global_v6() -> NetifAddr {
+        NetifAddr {
+            sock: IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1)),
+            index: 2,
+        }
+    }
+
+    #[test]
+    fn positive_display() {
+        let config = Config::new().set_ttl(4);
+        let expected = "mode=any groups=239.255.255.250,FF02::C port=1900 ttl=4 interfaces=all";
+        assert_eq!(config.to_string(), expected);
+
+        let addrs = ["192.168.1.2".parse().unwrap(), "fd00::2".parse().unwrap()];
+        let config = Config::new().set_interfaces(addrs);
+        assert!(config.to_string().ends_with(" interfaces=192.168.1.2,fd00::2"));
+    }
+
+    #[test]
+    fn positive_ipv6_scope_default() {
+        assert_eq!(Config::new().ipv6_multicast_addr(), "FF02::C");
+    }
+
+    #[test]
+    fn positive_ipv6_scope() {
+        let config = Config::new().set_ipv6_scope(Ipv6Scope::Global);
+        assert_eq!(config.ipv6_multicast_addr(), "FF0E::C");
+    }
+
+    #[test]
+    fn positive_ipv6_addr_overrides_scope() {
+        let config = Config::new()
+            .set_ipv6_addr("FF05::C")
+            .set_ipv6_scope(Ipv6Scope::Global);
+        assert_eq!(config.ipv6_multicast_addr(), "FF05::C");
+    }
+
+    #[test]
+    fn positive_allow_global_v6() {
+        let config = Config::new().set_allow_global_v6(true);
+        assert!(super::is_usable_interface(&global_v6(), &config));
+    }
+
+    #[test]
+    fn negative_global_v6() {
+        assert!(!super::is_usable_interface(&global_v6(), &Config::new()));
+    }
+
+    fn stale_v4() -> NetifAddr {
+        NetifAddr {
+            sock: IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)),
+            index: 99,
+        }
+    }
+
+    #[test]
+    fn positive_validate_dscp() {
+        assert!(Config::new().validate().is_ok());
+        assert!(Config::new().set_dscp(Some(8)).validate().is_ok());
+        assert!(Config::new().set_dscp(Some(63)).validate().is_ok());
+    }
+
+    #[test]
+    fn negative_validate_dscp() {
+        match Config::new().set_dscp(Some(64)).validate() {
+            Err(SSDPError::InvalidConfig(message)) => assert!(message.contains("64"), "{}", message),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn positive_connectors_skip_stale_interface() {
+        let loopback = NetifAddr {
+            sock: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            index: 1,
+        };
+        let options = LocalConnectorOptions::multicast(&Config::new());
+
+        let connectors = super::all_local_connectors(vec![stale_v4(), loopback], &options).unwrap();
+        assert_eq!(connectors.len(), 1);
+        assert_eq!(connectors[0].local_addr().unwrap().ip(), IpAddr::V4(Ipv4Addr::LOCALHOST));
+    }
+
+    #[test]
+    fn negative_connectors_all_binds_failed() {
+        let options = LocalConnectorOptions::multicast(&Config::new());
+
+        match super::all_local_connectors(vec![stale_v4()], &options) {
+            Err(SSDPError::AllBindsFailed(errors)) => {
+                assert_eq!(errors.len(), 1);
+                assert_eq!(errors[0].0.ip(), stale_v4().sock);
+            }
+            Err(other) => panic!("unexpected error: {}", other),
+            Ok(_) => panic!("bound an address that no interface has"),
+        }
+    }
 }
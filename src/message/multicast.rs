@@ -1,56 +1,293 @@
 use std::net::{SocketAddr, SocketAddrV6};
 use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Instant;
 
+use crate::context::SsdpContext;
 use crate::error::SSDPResult;
 use crate::message::ssdp::SSDPMessage;
-use crate::message::{self, Config};
+use crate::message::{Config, TransportSet};
 use crate::net::connector::UdpConnector;
+use crate::net::packet::PacketBuffer;
+use crate::net::{self, NetworkConnector as _, NetworkStream};
+use crate::private::Sealed;
+use crate::schedule::ScheduledSender;
 
-pub trait Multicast {
+/// A message that can be sent to the multicast group.
+///
+/// The trait is object safe and implemented for references and boxes. It is sealed, so it can
+/// only be implemented within this crate.
+///
+/// ```compile_fail
+/// use ssdp::message::{Config, Multicast};
+/// use ssdp::SSDPResult;
+///
+/// struct Custom;
+///
+/// impl Multicast for Custom {
+///     type Item = ();
+///
+///     fn multicast_with_config(&self, _: &Config) -> SSDPResult<()> {
+///         Ok(())
+///     }
+/// }
+/// ```
+pub trait Multicast: Sealed {
     type Item;
 
-    fn multicast(&mut self) -> SSDPResult<Self::Item> {
+    fn multicast(&self) -> SSDPResult<Self::Item> {
         self.multicast_with_config(&Default::default())
     }
 
     fn multicast_with_config(&self, config: &Config) -> SSDPResult<Self::Item>;
 }
 
+impl<M: Multicast + ?Sized> Sealed for &'_ M {}
+
+impl<M: Multicast + ?Sized> Sealed for &'_ mut M {}
+
+impl<M: Multicast + ?Sized> Sealed for Box<M> {}
+
+impl<M: Multicast + ?Sized> Multicast for &'_ M {
+    type Item = M::Item;
+
+    fn multicast_with_config(&self, config: &Config) -> SSDPResult<Self::Item> {
+        (**self).multicast_with_config(config)
+    }
+}
+
+impl<M: Multicast + ?Sized> Multicast for &'_ mut M {
+    type Item = M::Item;
+
+    fn multicast_with_config(&self, config: &Config) -> SSDPResult<Self::Item> {
+        (**self).multicast_with_config(config)
+    }
+}
+
+impl<M: Multicast + ?Sized> Multicast for Box<M> {
+    type Item = M::Item;
+
+    fn multicast_with_config(&self, config: &Config) -> SSDPResult<Self::Item> {
+        (**self).multicast_with_config(config)
+    }
+}
+
 pub fn send(message: &SSDPMessage, config: &Config) -> SSDPResult<Vec<UdpConnector>> {
-    let mut connectors = message::all_local_connectors(Some(config.ttl), &config.mode)?;
-
-    connectors.retain_mut(|conn| {
-        let success = conn
-            .local_addr()
-            .map_err(crate::error::SSDPError::from)
-            .and_then(|addr| match addr {
-                SocketAddr::V4(n) => {
-                    let mcast_addr = (config.ipv4_addr.as_str(), config.port);
-                    debug!("Sending ipv4 multicast through {} to {:?}", n, mcast_addr);
-                    message.send(conn, &mcast_addr)
-                }
-                SocketAddr::V6(n) => {
-                    debug!("Sending Ipv6 multicast through {} to [{}]:{}", n, config.ipv6_addr, config.port);
-                    //try!(message.send(conn, &mcast_addr));
-                    message.send(
-                        conn,
-                        &SocketAddrV6::new(
-                            FromStr::from_str(config.ipv6_addr.as_str()).unwrap(),
-                            config.port,
-                            n.flowinfo(),
-                            n.scope_id(),
-                        ),
-                    )
-                }
-            });
+    send_with_context(SsdpContext::global(), message, config)
+}
+
+/// Send the message to the multicast group through every interface of the context.
+pub fn send_with_context(
+    context: &SsdpContext,
+    message: &SSDPMessage,
+    config: &Config,
+) -> SSDPResult<Vec<UdpConnector>> {
+    enter_span!("ssdp_multicast", message_type = ?message.message_type());
+    let mut connectors = TransportSet::bind_with_context(context, config)?.into_connectors();
+    let sent = send_each(context, message, config, &connectors);
+    let mut sent = sent.into_iter();
+    connectors.retain(|_| sent.next().unwrap_or(false));
+    Ok(connectors)
+}
+
+/// Send the message to the multicast group through every connector, reporting which succeeded.
+///
+/// The message is serialized once per IP version, the host header does not depend on the
+/// interface.
+pub(crate) fn send_each(
+    context: &SsdpContext,
+    message: &SSDPMessage,
+    config: &Config,
+    connectors: &[UdpConnector],
+) -> Vec<bool> {
+    let mut ipv4_packet = None;
+    let mut ipv6_packet = None;
+
+    connectors.iter().map(|conn| {
+        let result = multicast_addr(config, conn).and_then(|dst| {
+            let slot = match dst {
+                SocketAddr::V4(_) => &mut ipv4_packet,
+                SocketAddr::V6(_) => &mut ipv6_packet,
+            };
+
+            let packet = match slot {
+                Some(packet) => packet,
+                None => slot.insert(message.to_packet(dst, config.header_style())?),
+            };
 
-        if let Err(e) = success {
+            send_packet(context, packet, conn, dst)
+        });
+
+        if let Err(e) = result {
             debug!("Dropping due to {e:?}");
             false
         } else {
             true
         }
-    });
+    })
+    .collect()
+}
 
-    Ok(connectors)
+/// Schedule the message to the multicast group through every interface of the context.
+///
+/// The message is sent at each of the times, through sockets bound once for all of them. After
+/// trying every interface this fails with the error of the first that the message could not be
+/// scheduled through.
+pub(crate) fn schedule_with_context(
+    context: &SsdpContext,
+    scheduler: &ScheduledSender,
+    message: &SSDPMessage,
+    config: &Config,
+    times: &[Instant],
+) -> SSDPResult<()> {
+    let connectors = TransportSet::bind_with_context(context, config)?.into_connectors();
+    let mut ipv4_packet: Option<Arc<[u8]>> = None;
+    let mut ipv6_packet = None;
+    let mut result = Ok(());
+
+    for conn in &connectors {
+        let scheduled = multicast_addr(config, conn).and_then(|dst| {
+            let slot = match dst {
+                SocketAddr::V4(_) => &mut ipv4_packet,
+                SocketAddr::V6(_) => &mut ipv6_packet,
+            };
+
+            let packet = match slot {
+                Some(packet) => Arc::clone(packet),
+                None => {
+                    let packet = message.to_packet(dst, config.header_style())?;
+                    Arc::clone(slot.insert(packet.as_slice().into()))
+                }
+            };
+
+            for &at in times {
+                scheduler.schedule(Arc::clone(&packet), dst, conn.socket(), at)?;
+            }
+            Ok(())
+        });
+
+        if let Err(err) = scheduled {
+            debug!("Failed to schedule multicast: {}", err);
+            if result.is_ok() {
+                result = Err(err);
+            }
+        }
+    }
+
+    result
+}
+
+/// Send the message to the multicast group of the socket's IP version through a single connector.
+pub(crate) fn send_through(
+    context: &SsdpContext,
+    message: &SSDPMessage,
+    config: &Config,
+    conn: &mut UdpConnector,
+) -> SSDPResult<()> {
+    let dst = multicast_addr(config, conn)?;
+    send_packet(context, &message.to_packet(dst, config.header_style())?, conn, dst)
+}
+
+/// Get the multicast group of the connector's IP version.
+fn multicast_addr(config: &Config, conn: &UdpConnector) -> SSDPResult<SocketAddr> {
+    match conn.local_addr()? {
+        SocketAddr::V4(n) => {
+            let mcast_addr = net::addr_from_trait((config.ipv4_addr.as_str(), config.port))?;
+            debug!("Sending ipv4 multicast through {} to {}", n, mcast_addr);
+            Ok(mcast_addr)
+        }
+        SocketAddr::V6(n) => {
+            let mcast_addr = config.ipv6_multicast_addr();
+            debug!("Sending Ipv6 multicast through {} to [{}]:{}", n, mcast_addr, config.port);
+            Ok(SocketAddr::V6(SocketAddrV6::new(
+                FromStr::from_str(mcast_addr)?,
+                config.port,
+                n.flowinfo(),
+                n.scope_id(),
+            )))
+        }
+    }
+}
+
+/// Send an already serialized message through the connector.
+fn send_packet(
+    context: &SsdpContext,
+    packet: &PacketBuffer,
+    conn: &UdpConnector,
+    dst: SocketAddr,
+) -> SSDPResult<()> {
+    enter_span!(
+        "ssdp_send",
+        interface = conn.local_addr().ok().map(tracing::field::display),
+        peer = %dst,
+    );
+    let mut stream: Box<dyn NetworkStream + Send> = conn.connect_addr(dst)?.into();
+    stream.send(packet)?;
+
+    context.stats().add_message_sent();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::time::{Duration, Instant};
+
+    use super::Multicast;
+    use crate::context::SsdpContext;
+    use crate::message::ssdp::SSDPMessage;
+    use crate::message::{self, Config, LocalConnectorOptions, NotifyMessage, SearchRequest, SearchResponse};
+    use crate::net::NetifAddr;
+    use crate::receiver::{FromRawSSDP, SSDPReceiver};
+
+    #[test]
+    fn positive_trait_objects() {
+        let notify = NotifyMessage::new();
+        let messages: Vec<Box<dyn Multicast<Item = ()> + '_>> =
+            vec![Box::new(NotifyMessage::new()), Box::new(&notify)];
+
+        let searches: Vec<Box<dyn Multicast<Item = SSDPReceiver<SearchResponse>>>> =
+            vec![Box::new(SearchRequest::new())];
+
+        assert_eq!(messages.len() + searches.len(), 3);
+    }
+
+    /// Multicast a message with 20 headers through 8 loopback connectors.
+    ///
+    /// Run with `cargo test --release -- --ignored bench_`.
+    #[test]
+    #[ignore = "benchmark"]
+    fn bench_multicast_many_headers() {
+        const ROUNDS: u32 = 20_000;
+
+        let config = Config::new()
+            .set_port(19_016)
+            .set_mode(crate::IpVersionMode::V4Only)
+            .set_include_loopback(true);
+        let addrs = (1..=8)
+            .map(|host| NetifAddr {
+                sock: IpAddr::V4(Ipv4Addr::new(127, 0, 0, host)),
+                index: 1,
+            })
+            .collect();
+
+        let mut raw = String::from("NOTIFY * HTTP/1.1\r\nHOST: 239.255.255.250:1900\r\n");
+        for header in 0..19 {
+            raw.push_str(&format!("X-VENDOR-{header}: value-of-vendor-header-{header}\r\n"));
+        }
+        raw.push_str("\r\n");
+
+        let message = SSDPMessage::from_packet(raw.as_bytes()).unwrap();
+        let options = LocalConnectorOptions::multicast(&config);
+        let connectors = message::all_local_connectors(addrs, &options).unwrap();
+
+        let start = Instant::now();
+        for _ in 0..ROUNDS {
+            let sent = super::send_each(SsdpContext::global(), &message, &config, &connectors);
+            assert!(sent.iter().all(|&sent| sent));
+        }
+
+        let per_multicast = start.elapsed() / ROUNDS;
+        assert!(per_multicast < Duration::from_millis(1), "{:?} per multicast", per_multicast);
+    }
 }
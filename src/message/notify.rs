@@ -1,18 +1,27 @@
-use std::fmt::Debug;
+use std::fmt::{self, Debug};
+use std::net::{SocketAddr, UdpSocket};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use headers::Header;
+use headers::{Header, HeaderMap, HeaderMapExt as _, HeaderName, HeaderValue, Location};
+use url::Url;
 
-use crate::error::{SSDPError::InvalidMethod, SSDPResult};
-use crate::header::HeaderMut;
+use crate::context::SsdpContext;
+use crate::error::{SSDPError, SSDPError::InvalidMethod, SSDPResult};
+use crate::header::{self, BootID, HeaderMut, SearchPort, SsdpMaxAge, VendorHeader, NT, NTS, USN};
 use crate::message::multicast::{self, Multicast};
-use crate::message::ssdp::SSDPMessage;
-use crate::message::{Config, Listen, MessageType};
-use crate::receiver::FromRawSSDP;
+use crate::message::ssdp::{HostMismatch, SSDPMessage};
+use crate::message::listen::{self, Listen};
+use crate::message::{Config, MessageType, UpnpVersion};
+use crate::private::Sealed;
+use crate::receiver::{FromRawSSDP, ParseOptions, SSDPReceiver};
+use crate::schedule::ScheduledSender;
 
 /// Notify message that can be sent via multicast to devices on the network.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct NotifyMessage {
-    message: SSDPMessage,
+    pub(crate) message: SSDPMessage,
 }
 
 impl NotifyMessage {
@@ -22,14 +31,135 @@ impl NotifyMessage {
             message: SSDPMessage::new(MessageType::Notify),
         }
     }
+
+    /// Get the headers contained in this message.
+    pub fn headers(&self) -> &HeaderMap {
+        self.message.headers()
+    }
+
+    /// Get the headers contained in this message for modification.
+    ///
+    /// Unlike `HeaderMut::set` this allows removing headers.
+    pub fn headers_mut(&mut self) -> &mut HeaderMap {
+        self.message.headers_mut()
+    }
+
+    /// Destroy the notify message and return the underlying message.
+    pub fn into_message(self) -> SSDPMessage {
+        self.message
+    }
+
+    /// Check that the HOST names the multicast group and port of the config.
+    ///
+    /// See `SSDPMessage::validate_host`.
+    pub fn validate_host(&self, expected: &Config) -> Result<(), HostMismatch> {
+        self.message.validate_host(expected)
+    }
+
+    /// Get the first value of a header without a typed representation.
+    ///
+    /// See `VendorHeader::get`.
+    pub fn vendor_header(&self, name: &str) -> Option<String> {
+        VendorHeader::get(self.headers(), name)
+    }
+
+    /// Iterate the headers that are not defined by HTTP, SSDP or UPnP.
+    pub fn vendor_headers(&self) -> impl Iterator<Item = (&HeaderName, &HeaderValue)> {
+        header::vendor_headers(self.headers())
+    }
+
+    /// Detect the version of UPnP that the device speaks.
+    ///
+    /// See `SSDPMessage::upnp_version`.
+    pub fn upnp_version(&self) -> UpnpVersion {
+        self.message.upnp_version()
+    }
+
+    /// Get the port on which the device accepts unicast search requests, if not 1900.
+    pub fn search_port(&self) -> Option<SearchPort> {
+        self.headers().typed_get::<SearchPort>()
+    }
+
+    /// Get whether the device announces itself or leaves, from the NTS header.
+    ///
+    /// This and the other accessors return `None` if the header is missing or malformed.
+    pub fn nts(&self) -> Option<NTS> {
+        self.headers().typed_get::<NTS>()
+    }
+
+    /// Get the notification type.
+    pub fn nt(&self) -> Option<NT> {
+        self.headers().typed_get::<NT>()
+    }
+
+    /// Get the unique service name.
+    pub fn usn(&self) -> Option<USN> {
+        self.headers().typed_get::<USN>()
+    }
+
+    /// Get the URL of the device description from the LOCATION header.
+    pub fn location(&self) -> Option<Url> {
+        let location = self.headers().get(Location::name())?.to_str().ok()?;
+        Url::parse(location.trim()).ok()
+    }
+
+    /// Get how long the announcement is valid, from the `max-age` of the CACHE-CONTROL header.
+    ///
+    /// See `SsdpMaxAge` for the values that are accepted.
+    pub fn max_age(&self) -> Option<Duration> {
+        self.headers().typed_get::<SsdpMaxAge>().map(|max_age| max_age.0)
+    }
+
+    /// Get the BOOTID.UPNP.ORG, which changes whenever the device rejoins the network.
+    pub fn boot_id(&self) -> Option<BootID> {
+        self.headers().typed_get::<BootID>()
+    }
+
+    /// Send this notify message to the destination on a socket owned by the caller.
+    ///
+    /// See `SSDPMessage::send_to`.
+    pub fn send_to(&self, socket: &UdpSocket, dst_addr: SocketAddr) -> SSDPResult<()> {
+        self.message.send_to(socket, dst_addr)
+    }
+
+    /// Serialize this notify message into the bytes that are sent to the destination.
+    ///
+    /// See `SSDPMessage::to_bytes`.
+    pub fn to_bytes(&self, dst_addr: Option<SocketAddr>) -> SSDPResult<Vec<u8>> {
+        self.message.to_bytes(dst_addr)
+    }
+
+    /// Send this notify message to the multicast group through the interfaces of the context.
+    pub(crate) fn multicast_with_context(&self, context: &SsdpContext, config: &Config) -> SSDPResult<()> {
+        multicast::send_with_context(context, &self.message, config)?;
+        Ok(())
+    }
+
+    /// Multicast this notify message at each of the times through the scheduler.
+    pub(crate) fn schedule_multicast_with_context(
+        &self,
+        context: &SsdpContext,
+        scheduler: &ScheduledSender,
+        config: &Config,
+        times: &[Instant],
+    ) -> SSDPResult<()> {
+        multicast::schedule_with_context(context, scheduler, &self.message, config, times)
+    }
 }
 
+impl Sealed for NotifyMessage {}
+
 impl Multicast for NotifyMessage {
     type Item = ();
 
     fn multicast_with_config(&self, config: &Config) -> SSDPResult<Self::Item> {
-        multicast::send(&self.message, config)?;
-        Ok(())
+        self.multicast_with_context(SsdpContext::global(), config)
+    }
+}
+
+impl fmt::Display for NotifyMessage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.message, f)
     }
 }
 
@@ -41,16 +171,82 @@ impl Default for NotifyMessage {
 
 impl FromRawSSDP for NotifyMessage {
     fn from_packet(bytes: &[u8]) -> SSDPResult<NotifyMessage> {
-        let message = SSDPMessage::from_packet(bytes)?;
+        Self::from_packet_with_options(bytes, &Default::default())
+    }
+
+    fn from_packet_with_options(bytes: &[u8], options: &ParseOptions) -> SSDPResult<NotifyMessage> {
+        NotifyMessage::try_from(SSDPMessage::from_packet_with_options(bytes, options)?)
+    }
 
+    fn is_from_device(&self, uuid: &str) -> bool {
+        self.message.is_from_device(uuid)
+    }
+}
+
+/// Fails with `InvalidMethod` for the other types of messages.
+impl TryFrom<SSDPMessage> for NotifyMessage {
+    type Error = SSDPError;
+
+    fn try_from(message: SSDPMessage) -> SSDPResult<NotifyMessage> {
         if message.message_type() != MessageType::Notify {
             Err(InvalidMethod("SSDP Message Received Is Not A NotifyMessage".into()))
         } else {
-            Ok(NotifyMessage { message: message })
+            Ok(NotifyMessage { message })
         }
     }
 }
 
+impl TryFrom<&[u8]> for NotifyMessage {
+    type Error = SSDPError;
+
+    fn try_from(bytes: &[u8]) -> SSDPResult<NotifyMessage> {
+        NotifyMessage::from_packet(bytes)
+    }
+}
+
+impl FromStr for NotifyMessage {
+    type Err = SSDPError;
+
+    fn from_str(message: &str) -> SSDPResult<NotifyMessage> {
+        NotifyMessage::try_from(message.as_bytes())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for NotifyMessage {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.message.serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for NotifyMessage {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let message = crate::serialize::deserialize_message(deserializer, MessageType::Notify)?;
+        Ok(NotifyMessage { message })
+    }
+}
+
+impl From<NotifyMessage> for SSDPMessage {
+    fn from(notify: NotifyMessage) -> SSDPMessage {
+        notify.into_message()
+    }
+}
+
+impl From<&NotifyMessage> for http::Request<()> {
+    fn from(message: &NotifyMessage) -> http::Request<()> {
+        http::Request::try_from(&message.message).expect("a notify message is a request")
+    }
+}
+
+impl TryFrom<http::Request<()>> for NotifyMessage {
+    type Error = SSDPError;
+
+    fn try_from(request: http::Request<()>) -> SSDPResult<NotifyMessage> {
+        NotifyMessage::try_from(SSDPMessage::try_from(request)?)
+    }
+}
+
 impl HeaderMut for NotifyMessage {
     fn set<H>(&mut self, value: H)
     where
@@ -58,19 +254,106 @@ impl HeaderMut for NotifyMessage {
     {
         self.message.set(value)
     }
+
+    fn set_raw(&mut self, name: HeaderName, value: HeaderValue) -> SSDPResult<()> {
+        self.message.set_raw(name, value)
+    }
+
+    fn set_raw_cased(&mut self, name: &str, value: HeaderValue) -> SSDPResult<()> {
+        self.message.set_raw_cased(name, value)
+    }
 }
 
 /// Notify listener that can listen to notify messages sent within the network.
-pub struct NotifyListener;
+#[derive(Clone, Default)]
+pub struct NotifyListener {
+    config: Config,
+}
+
+impl NotifyListener {
+    /// Construct a listener for the network described by the config.
+    pub fn new(config: Config) -> Self {
+        NotifyListener { config }
+    }
+
+    /// Listen for messages on all local network interfaces.
+    ///
+    /// This will call `listen_with_config()` with _default_ values.
+    pub fn listen() -> SSDPResult<SSDPReceiver<NotifyMessage>> {
+        listen::listen_with_config(&Default::default())
+    }
+
+    /// Listen for messages on all local network interfaces.
+    ///
+    /// See `listen::listen_with_config`.
+    pub fn listen_with_config(config: &Config) -> SSDPResult<SSDPReceiver<NotifyMessage>> {
+        listen::listen_with_config(config)
+    }
+
+    /// Listen on any interface.
+    ///
+    /// See `listen::listen_anyaddr_with_config`.
+    pub fn listen_anyaddr_with_config(config: &Config) -> SSDPResult<SSDPReceiver<NotifyMessage>> {
+        listen::listen_anyaddr_with_config(config)
+    }
+
+    /// Listen only for messages about the notification type.
+    ///
+    /// All other messages are dropped on the socket threads, see `SSDPReceiver::set_filter`.
+    pub fn listen_for_nt(&self, nt: NT) -> SSDPResult<SSDPReceiver<NotifyMessage>> {
+        self.listen_for_nt_with_context(SsdpContext::global(), nt)
+    }
+
+    /// Listen only for messages about the notification type on the interfaces of the context.
+    pub(crate) fn listen_for_nt_with_context(
+        &self,
+        context: &SsdpContext,
+        nt: NT,
+    ) -> SSDPResult<SSDPReceiver<NotifyMessage>> {
+        let filter = move |notify: &NotifyMessage, _: &SocketAddr| {
+            notify.headers().typed_get::<NT>().is_some_and(|received| received == nt)
+        };
+        listen::listen_filtered_with_context(context, &self.config, Some(Arc::new(filter)))
+    }
+}
 
 impl Listen for NotifyListener {
     type Message = NotifyMessage;
+
+    fn listen(&self) -> SSDPResult<SSDPReceiver<Self::Message>> {
+        listen::listen_with_config(&self.config)
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::NotifyMessage;
-    use crate::receiver::FromRawSSDP;
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::time::Duration;
+
+    use headers::HeaderMapExt as _;
+
+    use super::{NotifyListener, NotifyMessage};
+    use crate::header::{BootID, HeaderMut, VendorHeader, NT, NTS, USN};
+    use crate::message::{Config, MessageType, Multicast, SSDPMessage};
+    use crate::receiver::{FromRawSSDP, ParseOptions};
+    use crate::FieldMap;
+
+    fn loopback_config(port: u16) -> Config {
+        Config::new()
+            .set_port(port)
+            .set_mode(crate::IpVersionMode::V4Only)
+            .set_include_loopback(true)
+            .set_interfaces([IpAddr::V4(Ipv4Addr::LOCALHOST)])
+    }
+
+    fn notify_of(uuid: &str) -> NotifyMessage {
+        let mut message = NotifyMessage::new();
+        message.set(USN(FieldMap::uuid(uuid), Some(FieldMap::upnp("rootdevice"))));
+        message
+    }
+
+    /// A packet of an IP camera with LF-only line endings and spaces before the colons.
+    const CAMERA_NOTIFY: &[u8] = include_bytes!("fixtures/camera_notify.txt");
 
     #[test]
     fn positive_notify_message_type() {
@@ -79,6 +362,106 @@ mod tests {
         NotifyMessage::from_packet(raw_message.as_bytes()).unwrap();
     }
 
+    #[test]
+    fn positive_lenient_camera_notify() {
+        let options = ParseOptions::new().set_lenient(true);
+        let message = NotifyMessage::from_packet_with_options(CAMERA_NOTIFY, &options).unwrap();
+
+        assert_eq!(message.nts(), Some(NTS::Alive));
+        assert_eq!(message.nt(), Some(NT(FieldMap::upnp("rootdevice"))));
+        assert_eq!(message.max_age(), Some(Duration::from_secs(1800)));
+        assert_eq!(message.location().unwrap().as_str(), "http://192.168.1.64:49152/rootDesc.xml");
+    }
+
+    #[test]
+    fn positive_accessors() {
+        let raw_message = "NOTIFY * HTTP/1.1\r\nHOST: 239.255.255.250:1900\r\nCACHE-CONTROL: max-age=900\r\n\
+                           LOCATION: http://192.168.1.20:49152/desc.xml\r\nNT: upnp:rootdevice\r\n\
+                           NTS: ssdp:alive\r\nUSN: uuid:device-1::upnp:rootdevice\r\n\
+                           BOOTID.UPNP.ORG: 7\r\n\r\n";
+        let message = NotifyMessage::from_packet(raw_message.as_bytes()).unwrap();
+
+        assert_eq!(message.nts(), Some(NTS::Alive));
+        assert_eq!(message.nt(), Some(NT(FieldMap::upnp("rootdevice"))));
+        let usn = USN(FieldMap::uuid("device-1"), Some(FieldMap::upnp("rootdevice")));
+        assert_eq!(message.usn(), Some(usn));
+        assert_eq!(message.location().unwrap().as_str(), "http://192.168.1.20:49152/desc.xml");
+        assert_eq!(message.max_age(), Some(Duration::from_secs(900)));
+        assert_eq!(message.boot_id(), Some(BootID(7)));
+    }
+
+    #[test]
+    fn positive_remove_header() {
+        let raw_message = "NOTIFY * HTTP/1.1\r\nHOST: 239.255.255.250:1900\r\nNT: upnp:rootdevice\r\n\
+                           NTS: ssdp:alive\r\nBOOTID.UPNP.ORG: 7\r\n\r\n";
+        let mut message = NotifyMessage::from_packet(raw_message.as_bytes()).unwrap();
+        message.headers_mut().remove("BOOTID.UPNP.ORG");
+        assert_eq!(message.boot_id(), None);
+
+        let message = SSDPMessage::from(message);
+        assert_eq!(message.message_type(), MessageType::Notify);
+        assert_eq!(message.headers().typed_get::<NTS>(), Some(NTS::Alive));
+    }
+
+    #[test]
+    fn positive_try_from_message() {
+        let raw_message = "NOTIFY * HTTP/1.1\r\nHOST: 239.255.255.250:1900\r\nNTS: ssdp:alive\r\n\r\n";
+        let message = SSDPMessage::from_packet(raw_message.as_bytes()).unwrap();
+
+        let notify = NotifyMessage::try_from(message.clone()).unwrap();
+        assert_eq!(notify.nts(), Some(NTS::Alive));
+        assert_eq!(SSDPMessage::from(notify), message);
+    }
+
+    #[test]
+    fn negative_accessors() {
+        let raw_message = "NOTIFY * HTTP/1.1\r\nHOST: 239.255.255.250:1900\r\nCACHE-CONTROL: no-cache\r\n\
+                           LOCATION: not a url\r\nNTS: ssdp:sleeping\r\nBOOTID.UPNP.ORG: -1\r\n\r\n";
+        let message = NotifyMessage::from_packet(raw_message.as_bytes()).unwrap();
+
+        assert_eq!(message.nts(), None);
+        assert_eq!(message.nt(), None);
+        assert_eq!(message.usn(), None);
+        assert_eq!(message.location(), None);
+        assert_eq!(message.max_age(), None);
+        assert_eq!(message.boot_id(), None);
+    }
+
+    #[test]
+    fn negative_strict_camera_notify() {
+        assert!(NotifyMessage::from_packet(CAMERA_NOTIFY).is_err());
+    }
+
+    #[test]
+    fn positive_vendor_headers_roundtrip() {
+        let raw_message = "NOTIFY * HTTP/1.1\r\nHOST: 239.255.255.250:1900\r\nNTS: ssdp:alive\r\n\
+                           01-NLS: b9200ebb-736d-4b93-bf03-835149d13983\r\nX-AV-Server-Info: av=5.0\r\n\r\n";
+        let mut message = NotifyMessage::from_packet(raw_message.as_bytes()).unwrap();
+        VendorHeader::set(&mut message, "SMARTSPEAKER.AUDIO", "1").unwrap();
+
+        let message = NotifyMessage::from_packet(&message.to_bytes(None).unwrap()).unwrap();
+
+        let names: Vec<_> = message.vendor_headers().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["01-nls", "x-av-server-info", "smartspeaker.audio"]);
+        assert_eq!(message.vendor_header("X-AV-Server-Info").unwrap(), "av=5.0");
+    }
+
+    #[test]
+    fn positive_from_str() {
+        let raw_message = "NOTIFY * HTTP/1.1\r\nHOST: 192.168.1.1\r\nNTS: ssdp:byebye\r\n\r\n";
+
+        let message: NotifyMessage = raw_message.parse().unwrap();
+        assert_eq!(message.nts(), Some(NTS::ByeBye));
+        assert_eq!(NotifyMessage::try_from(raw_message.as_bytes()).unwrap(), message);
+    }
+
+    #[test]
+    fn negative_from_str() {
+        let raw_message = "M-SEARCH * HTTP/1.1\r\nHOST: 192.168.1.1\r\n\r\n";
+
+        assert!(raw_message.parse::<NotifyMessage>().is_err());
+    }
+
     #[test]
     #[should_panic]
     fn negative_search_message_type() {
@@ -94,4 +477,67 @@ mod tests {
 
         NotifyMessage::from_packet(raw_message.as_bytes()).unwrap();
     }
+
+    #[test]
+    fn positive_multicast_loop() {
+        let config = Config::new()
+            .set_port(19_009)
+            .set_mode(crate::IpVersionMode::V4Only)
+            .set_multicast_loop(true);
+        let listener = NotifyListener::listen_with_config(&config).unwrap();
+
+        NotifyMessage::new().multicast_with_config(&config).unwrap();
+
+        assert!(listener.recv_timeout(Duration::from_secs(5)).is_ok());
+    }
+
+    #[test]
+    fn positive_ignore_self() {
+        let config = loopback_config(19_017);
+
+        let listener = NotifyListener::listen_with_config(&config).unwrap();
+        NotifyMessage::new().multicast_with_config(&config).unwrap();
+        assert!(listener.recv_timeout(Duration::from_secs(5)).is_ok());
+        drop(listener);
+
+        let config = config.set_ignore_self(true);
+        let listener = NotifyListener::listen_with_config(&config).unwrap();
+        NotifyMessage::new().multicast_with_config(&config).unwrap();
+        assert!(listener.recv_timeout(Duration::from_millis(500)).is_err());
+    }
+
+    #[test]
+    fn positive_ignore_self_uuid() {
+        let config = loopback_config(19_018).set_self_uuid("2FAC1234-31F8-11B4-A222-08002B34C003");
+        let listener = NotifyListener::listen_with_config(&config).unwrap();
+
+        notify_of("2fac1234-31f8-11b4-a222-08002b34c003").multicast_with_config(&config).unwrap();
+        notify_of("other-device").multicast_with_config(&config).unwrap();
+
+        let (message, _) = listener.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(message.usn().unwrap().0, FieldMap::uuid("other-device"));
+        assert!(listener.recv_timeout(Duration::from_millis(500)).is_err());
+    }
+
+    #[test]
+    fn positive_listen_for_nt() {
+        let config = loopback_config(19_025);
+        let renderer = NT(FieldMap::urn("schemas-upnp-org:device:MediaRenderer:1"));
+        let listener = NotifyListener::new(config.clone()).listen_for_nt(renderer.clone()).unwrap();
+
+        for nt in [
+            NT(FieldMap::upnp("rootdevice")),
+            renderer.clone(),
+            NT(FieldMap::urn("schemas-upnp-org:device:MediaServer:1")),
+        ] {
+            let mut message = notify_of("filtered-device");
+            message.set(nt);
+            message.multicast_with_config(&config).unwrap();
+        }
+
+        let (message, _) = listener.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(message.headers().typed_get::<NT>(), Some(renderer));
+        assert!(listener.recv_timeout(Duration::from_millis(500)).is_err());
+        assert_eq!(listener.messages_filtered(), 2);
+    }
 }
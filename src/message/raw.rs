@@ -0,0 +1,274 @@
+//! A borrowed view of a packet that is cheaper to parse than a message.
+
+use std::fmt;
+use std::net::SocketAddr;
+use std::ops::Range;
+use std::sync::Arc;
+
+use crate::message::{MessageType, SSDPMessage};
+use crate::receiver::FromRawSSDP;
+use crate::{SSDPError, SSDPResult};
+
+/// A packet split into its start line and headers, without copying or validating them.
+///
+/// Parsing only records where the name and value of each header are within the packet, which
+/// makes it suitable to discard uninteresting packets before they are parsed into a message. The
+/// values are not decoded, convert the view with `to_message` to access the typed headers.
+///
+/// Lines may end with CRLF or LF alone, surrounding whitespace of values is not part of them.
+#[derive(Clone, Debug)]
+pub struct RawSSDPMessage<'a> {
+    packet: &'a [u8],
+    message_type: MessageType,
+    /// The name and value of every header, in the order of the packet.
+    headers: Vec<(Range<usize>, Range<usize>)>,
+}
+
+/// A predicate on the raw view of received packets, see `Config::set_raw_filter`.
+#[derive(Clone)]
+pub struct RawFilter(Arc<Predicate>);
+
+type Predicate = dyn Fn(&RawSSDPMessage<'_>, &SocketAddr) -> bool + Send + Sync;
+
+impl<'a> RawSSDPMessage<'a> {
+    /// Split the packet into its start line and headers.
+    ///
+    /// Fails if the start line is not that of a notify, search or response, with
+    /// `SSDPError::InvalidHttp` if a header line has no name or one with whitespace, and with
+    /// `SSDPError::PartialHttp` if the empty line after the headers is missing.
+    pub fn parse(packet: &'a [u8]) -> SSDPResult<RawSSDPMessage<'a>> {
+        let mut lines = Lines { packet, offset: 0 };
+        let start = lines.next().ok_or(SSDPError::PartialHttp)?;
+        let message_type = message_type(&packet[start])?;
+
+        let mut headers = Vec::new();
+        loop {
+            let line = lines.next().ok_or(SSDPError::PartialHttp)?;
+            if line.is_empty() {
+                break;
+            }
+
+            let bytes = &packet[line.clone()];
+            let colon = bytes
+                .iter()
+                .position(|&byte| byte == b':')
+                .filter(|&colon| colon > 0 && !bytes[..colon].iter().any(u8::is_ascii_whitespace))
+                .ok_or(SSDPError::InvalidHttp(httparse::Error::HeaderName))?;
+
+            let name = line.start..line.start + colon;
+            let value = trim(packet, line.start + colon + 1..line.end);
+            headers.push((name, value));
+        }
+
+        Ok(RawSSDPMessage {
+            packet,
+            message_type,
+            headers,
+        })
+    }
+
+    /// Get the type of the message, as given by the start line.
+    pub fn message_type(&self) -> MessageType {
+        self.message_type
+    }
+
+    /// Get the first value of a header, matching the name ignoring case.
+    pub fn header(&self, name: &str) -> Option<&'a [u8]> {
+        self.headers()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name.as_bytes()))
+            .map(|(_, value)| value)
+    }
+
+    /// Iterate the names and values of all headers, in the order of the packet.
+    pub fn headers(&self) -> impl Iterator<Item = (&'a [u8], &'a [u8])> + '_ {
+        let packet = self.packet;
+        self.headers
+            .iter()
+            .map(move |(name, value)| (&packet[name.clone()], &packet[value.clone()]))
+    }
+
+    /// Get the whole packet.
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.packet
+    }
+
+    /// Parse the packet into a message with all its headers.
+    ///
+    /// This validates what the view does not, and may thus fail even though the view did not.
+    /// Convert the message into the message of its type with `TryFrom`.
+    pub fn to_message(&self) -> SSDPResult<SSDPMessage> {
+        SSDPMessage::from_packet(self.packet)
+    }
+}
+
+impl RawFilter {
+    /// Construct a filter that accepts the packets for which the predicate returns true.
+    pub fn new<F>(predicate: F) -> RawFilter
+    where
+        F: Fn(&RawSSDPMessage<'_>, &SocketAddr) -> bool + Send + Sync + 'static,
+    {
+        RawFilter(Arc::new(predicate))
+    }
+
+    /// Check if the packet from the peer is accepted.
+    pub fn accepts(&self, message: &RawSSDPMessage<'_>, peer: &SocketAddr) -> bool {
+        (self.0)(message, peer)
+    }
+}
+
+impl fmt::Debug for RawFilter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("RawFilter")
+    }
+}
+
+/// Iterates the byte ranges of the lines of a packet, without their line endings.
+struct Lines<'a> {
+    packet: &'a [u8],
+    offset: usize,
+}
+
+impl Iterator for Lines<'_> {
+    type Item = Range<usize>;
+
+    fn next(&mut self) -> Option<Range<usize>> {
+        let start = self.offset;
+        let length = self.packet.get(start..)?.iter().position(|&byte| byte == b'\n')?;
+        self.offset = start + length + 1;
+
+        let end = start + length;
+        let end = if end > start && self.packet[end - 1] == b'\r' {
+            end - 1
+        } else {
+            end
+        };
+
+        Some(start..end)
+    }
+}
+
+/// Detect the type of message from its start line.
+fn message_type(start: &[u8]) -> SSDPResult<MessageType> {
+    if start.starts_with(b"HTTP/") {
+        return Ok(MessageType::Response);
+    }
+
+    let method = start.split(|&byte| byte == b' ').next().unwrap_or_default();
+    match method {
+        b"NOTIFY" => Ok(MessageType::Notify),
+        b"M-SEARCH" => Ok(MessageType::Search),
+        _ => Err(SSDPError::InvalidMethod(String::from_utf8_lossy(method).into_owned())),
+    }
+}
+
+/// Shrink the range to exclude surrounding spaces and tabs.
+fn trim(packet: &[u8], mut range: Range<usize>) -> Range<usize> {
+    while range.start < range.end && matches!(packet[range.start], b' ' | b'\t') {
+        range.start += 1;
+    }
+    while range.end > range.start && matches!(packet[range.end - 1], b' ' | b'\t') {
+        range.end -= 1;
+    }
+
+    range
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use headers::HeaderMapExt as _;
+
+    use super::RawSSDPMessage;
+    use crate::header::NT;
+    use crate::message::{MessageType, NotifyMessage};
+    use crate::receiver::FromRawSSDP;
+    use crate::{FieldMap, SSDPError};
+
+    const ALIVE: &[u8] = b"NOTIFY * HTTP/1.1\r\nHOST: 239.255.255.250:1900\r\nNT:  upnp:rootdevice \r\n\
+        NTS: ssdp:alive\r\nUSN: uuid:2fac1234-31f8-11b4-a222-08002b34c003::upnp:rootdevice\r\n\
+        X-EMPTY:\r\n\r\n";
+
+    #[test]
+    fn positive_headers() {
+        let raw = RawSSDPMessage::parse(ALIVE).unwrap();
+
+        assert_eq!(raw.message_type(), MessageType::Notify);
+        assert_eq!(raw.header("nt"), Some(&b"upnp:rootdevice"[..]));
+        assert_eq!(raw.header("Nts"), Some(&b"ssdp:alive"[..]));
+        assert_eq!(raw.header("x-empty"), Some(&b""[..]));
+        assert_eq!(raw.header("location"), None);
+        assert_eq!(raw.headers().count(), 5);
+    }
+
+    #[test]
+    fn positive_types_and_line_endings() {
+        let search = b"M-SEARCH * HTTP/1.1\nHOST: 239.255.255.250:1900\nST: ssdp:all\n\n";
+        let response = b"HTTP/1.1 200 OK\r\nST: ssdp:all\r\n\r\n";
+
+        let search = RawSSDPMessage::parse(search).unwrap();
+        assert_eq!(search.message_type(), MessageType::Search);
+        assert_eq!(search.header("st"), Some(&b"ssdp:all"[..]));
+        assert_eq!(RawSSDPMessage::parse(response).unwrap().message_type(), MessageType::Response);
+    }
+
+    #[test]
+    fn positive_to_message() {
+        let raw = RawSSDPMessage::parse(ALIVE).unwrap();
+        let message = raw.to_message().unwrap();
+        assert_eq!(message.message_type(), MessageType::Notify);
+
+        let notify = NotifyMessage::try_from(message).unwrap();
+
+        assert_eq!(notify.headers().len(), 5);
+    }
+
+    /// Filter alive messages by their NT, once by parsing them into messages and once as raw views.
+    ///
+    /// Run with `cargo test --release -- --ignored bench_`.
+    #[test]
+    #[ignore = "benchmark"]
+    fn bench_filter_by_nt() {
+        const ROUNDS: u32 = 200_000;
+        let renderer = "urn:schemas-upnp-org:device:MediaRenderer:1";
+
+        let start = Instant::now();
+        let mut parsed = 0;
+        for _ in 0..ROUNDS {
+            let notify = NotifyMessage::from_packet(ALIVE).unwrap();
+            parsed += usize::from(notify.headers().typed_get::<NT>() == Some(NT(FieldMap::urn(renderer))));
+        }
+        let parsing = start.elapsed();
+
+        let start = Instant::now();
+        let mut raw = 0;
+        for _ in 0..ROUNDS {
+            let view = RawSSDPMessage::parse(ALIVE).unwrap();
+            raw += usize::from(view.header("nt") == Some(renderer.as_bytes()));
+        }
+        let viewing = start.elapsed();
+
+        assert_eq!((parsed, raw), (0, 0));
+        assert!(viewing < parsing, "raw view took {:?}, parsing {:?}", viewing, parsing);
+    }
+
+    #[test]
+    fn negative_invalid_method() {
+        let result = RawSSDPMessage::parse(b"GET / HTTP/1.1\r\n\r\n");
+        assert!(matches!(result, Err(SSDPError::InvalidMethod(method)) if method == "GET"));
+    }
+
+    #[test]
+    fn negative_malformed() {
+        let without_colon = b"NOTIFY * HTTP/1.1\r\nNT upnp:rootdevice\r\n\r\n";
+        let without_name = b"NOTIFY * HTTP/1.1\r\n: x\r\n\r\n";
+
+        for packet in [&without_colon[..], &without_name[..]] {
+            let result = RawSSDPMessage::parse(packet);
+            assert!(matches!(result, Err(SSDPError::InvalidHttp(_))), "{:?}", result);
+        }
+
+        let result = RawSSDPMessage::parse(b"NOTIFY * HTTP/1.1\r\nNT: upnp:rootdevice\r\n");
+        assert!(matches!(result, Err(SSDPError::PartialHttp)));
+    }
+}
@@ -0,0 +1,825 @@
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hasher};
+use std::net::UdpSocket;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use headers::{CacheControl, HeaderMapExt as _, Location, Server};
+
+use crate::context::SsdpContext;
+use crate::error::SSDPResult;
+use crate::header::{ConfigID, Ext, HeaderMut, MX, NT, ST, USN};
+use crate::message::advertise::{self, DeviceDescriptionLite, DeviceIdentity, Instance};
+use crate::message::{self, Config, LocalConnectorOptions, SearchRequest, SearchResponse};
+use crate::net::httpu::Style;
+use crate::net::{self, NetifAddr};
+use crate::receiver::ReceivedMessage;
+use crate::schedule::ScheduledSender;
+use crate::{FieldMap, ParseFieldMapError};
+
+/// Interval in which the responder checks whether it was stopped.
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Maximum number of delayed responses waiting to be sent.
+const RESPONSE_QUEUE_CAPACITY: usize = 256;
+
+/// Maximum number of requests remembered to ignore their repetitions.
+///
+/// Every remembered request has a response in the queue, so more would never be answered anyway.
+const MAX_PENDING_REQUESTS: usize = RESPONSE_QUEUE_CAPACITY;
+
+/// Time between the responses to the same request, such as the many responses to `ssdp:all`.
+///
+/// Sent back-to-back they can overflow the receive buffer of the requester.
+const DEFAULT_RESPONSE_GAP: Duration = Duration::from_millis(5);
+
+/// Answers search requests for a device and its services.
+///
+/// While started, a background thread listens for search requests. Each request with a search
+/// target matching one of the advertised notification types is answered, after a random delay
+/// bounded by the MX of the request, with a unicast response to its source address.
+pub struct SearchResponder {
+    device: Arc<Device>,
+    running: Option<Running>,
+}
+
+/// The description of a device that is shared with the responder thread.
+struct Device {
+    uuid: String,
+    /// The notification types answered and the unique service names they are answered with.
+    targets: Vec<(NT, USN)>,
+    server: Server,
+    max_age: Duration,
+    locations: Mutex<Locations>,
+    instance: Arc<Mutex<Instance>>,
+    response_gap: Duration,
+    /// The requests that are being answered, until the time of their last response.
+    pending: Mutex<HashMap<(SocketAddr, ST), Instant>>,
+}
+
+#[derive(Default)]
+struct Locations {
+    default: Option<Location>,
+    interfaces: HashMap<IpAddr, Location>,
+}
+
+struct Running {
+    stopped: Arc<AtomicBool>,
+    /// Disconnects once the listening thread exited.
+    done: Receiver<()>,
+}
+
+impl SearchResponder {
+    /// Construct a new SearchResponder for the device with the given uuid.
+    ///
+    /// The uuid is given without the `uuid:` prefix.
+    pub fn new<S: Into<String>>(uuid: S, server: Server, max_age: Duration) -> SearchResponder {
+        let instance = Arc::new(Mutex::new(Instance::default()));
+        SearchResponder::with_instance(uuid.into(), instance, server, max_age)
+    }
+
+    /// Construct a new SearchResponder for the device of the shared identity.
+    ///
+    /// The responses carry the ids and search port of the identity, which are the same as those
+    /// of an `Advertiser` created with it.
+    pub fn with_identity(identity: &DeviceIdentity, server: Server, max_age: Duration) -> SearchResponder {
+        let instance = Arc::clone(&identity.instance);
+        SearchResponder::with_instance(identity.uuid().to_string(), instance, server, max_age)
+    }
+
+    fn with_instance(
+        uuid: String,
+        instance: Arc<Mutex<Instance>>,
+        server: Server,
+        max_age: Duration,
+    ) -> SearchResponder {
+        SearchResponder {
+            device: Arc::new(Device {
+                uuid,
+                targets: Vec::new(),
+                server,
+                max_age,
+                locations: Mutex::new(Locations::default()),
+                instance,
+                response_gap: DEFAULT_RESPONSE_GAP,
+                pending: Mutex::new(HashMap::new()),
+            }),
+            running: None,
+        }
+    }
+
+    /// Add a notification type for which search requests are answered.
+    ///
+    /// Fails if the uuid of the responder is not a valid field value, such as an empty one.
+    ///
+    /// # Panics
+    /// When called on a responder that was already started.
+    pub fn target(mut self, nt: NT) -> Result<Self, ParseFieldMapError> {
+        let device = Arc::get_mut(&mut self.device)
+            .expect("targets can not be changed after the responder was started");
+        let usn = device.usn(&nt)?;
+        device.targets.push((nt, usn));
+        Ok(self)
+    }
+
+    /// Add every notification type of a root device with its embedded devices and services.
+    ///
+    /// Each is answered with the UUID of the device it belongs to, which differs from the UUID of
+    /// the responder for embedded devices. See `advertise::expand_targets`, which also lists the
+    /// reasons for failing.
+    ///
+    /// # Panics
+    /// When called on a responder that was already started.
+    pub fn device(mut self, device: &DeviceDescriptionLite) -> Result<Self, ParseFieldMapError> {
+        let targets = advertise::expand_targets(device)?;
+        Arc::get_mut(&mut self.device)
+            .expect("targets can not be changed after the responder was started")
+            .targets
+            .extend(targets);
+        Ok(self)
+    }
+
+    /// Set the time between the responses to the same request, by default 5 milliseconds.
+    ///
+    /// The responses to a request are sent within its MX together, so the gap shrinks if there are
+    /// too many responses for it.
+    ///
+    /// # Panics
+    /// When called on a responder that was already started.
+    pub fn response_gap(mut self, gap: Duration) -> Self {
+        Arc::get_mut(&mut self.device)
+            .expect("the gap can not be changed after the responder was started")
+            .response_gap = gap;
+        self
+    }
+
+    /// Set the location announced on all interfaces without a specific location.
+    ///
+    /// This can be called while the responder is running, for example when the port of the HTTP
+    /// server serving the description changes.
+    pub fn set_location(&self, location: Location) {
+        self.device.locations.lock().unwrap().default = Some(location);
+    }
+
+    /// Set the location announced to requests reaching us through the interface address.
+    ///
+    /// This can be called while the responder is running.
+    pub fn set_interface_location(&self, iface: IpAddr, location: Location) {
+        self.device
+            .locations
+            .lock()
+            .unwrap()
+            .interfaces
+            .insert(iface, location);
+    }
+
+    /// Set the BOOTID.UPNP.ORG of all following responses.
+    ///
+    /// This can be called while the responder is running and should follow the boot id of the
+    /// `Advertiser` announcing the same device, see `Advertiser::set_boot_id`. Both follow each
+    /// other when created with the same `DeviceIdentity`.
+    ///
+    /// # Panics
+    ///
+    /// If the value is larger than 2^31 - 1.
+    pub fn set_boot_id(&self, value: u32) {
+        self.device.instance.lock().unwrap().set_boot_id(value);
+    }
+
+    /// Increase the CONFIGID.UPNP.ORG of all following responses.
+    ///
+    /// This can be called while the responder is running, see `Advertiser::bump_config_id`.
+    pub fn bump_config_id(&self) -> ConfigID {
+        self.device.instance.lock().unwrap().bump_config_id()
+    }
+
+    /// Check if the responder is currently answering search requests.
+    pub fn is_running(&self) -> bool {
+        self.running.is_some()
+    }
+
+    /// Start answering search requests on the network described by the config.
+    ///
+    /// If the responder is already running it is stopped first.
+    pub fn start(&mut self, config: &Config) -> SSDPResult<()> {
+        self.start_with_context(SsdpContext::global(), config)
+    }
+
+    /// Start answering search requests through the interfaces of the context.
+    pub(crate) fn start_with_context(&mut self, context: &SsdpContext, config: &Config) -> SSDPResult<()> {
+        self.stop();
+
+        config.validate()?;
+        let receiver = context.listen::<SearchRequest>(config)?;
+        let addrs = context.local_interfaces(config)?;
+        let ifaces = addrs.clone();
+        let options = LocalConnectorOptions::unicast(config, config.mode);
+        let sockets: Vec<_> = message::all_local_connectors(addrs, &options)?
+            .into_iter()
+            .map(|conn| conn.deconstruct())
+            .collect();
+
+        let stopped = Arc::new(AtomicBool::new(false));
+        let (done_send, done) = mpsc::channel::<()>();
+
+        let device = Arc::clone(&self.device);
+        let scheduler = ScheduledSender::with_config(context, RESPONSE_QUEUE_CAPACITY, config);
+        let thread_context = context.clone();
+        let style = config.header_style();
+        let thread_stopped = Arc::clone(&stopped);
+
+        context.spawn(move || {
+            let _done = done_send;
+            // Dropped before signalling completion, which discards all delayed responses.
+            let scheduler = scheduler;
+
+            while !thread_stopped.load(Ordering::Relaxed) {
+                let received = match receiver.recv_detailed_timeout(STOP_POLL_INTERVAL) {
+                    Ok(received) => received,
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    Err(RecvTimeoutError::Disconnected) => {
+                        debug!("Listener of the search responder stopped receiving");
+                        break;
+                    }
+                };
+
+                device.respond(&thread_context, &scheduler, &sockets, style, &received, &ifaces);
+            }
+        });
+
+        self.running = Some(Running { stopped, done });
+
+        Ok(())
+    }
+
+    /// Stop answering search requests.
+    ///
+    /// Responses which are still delayed are not sent. Does nothing if the responder is not
+    /// running.
+    pub fn stop(&mut self) {
+        let Some(running) = self.running.take() else {
+            return;
+        };
+
+        running.stopped.store(true, Ordering::Relaxed);
+        let _ = running.done.recv();
+    }
+}
+
+impl Drop for SearchResponder {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+impl Device {
+    /// Answer a single search request after a random delay.
+    ///
+    /// The location is the one of the interface the request arrived on, see `arrival_addrs`. The
+    /// responses follow each other after the gap, starting at a random time such that the last
+    /// one is sent within the MX. A repeated request is ignored while its responses are pending.
+    fn respond(
+        &self,
+        context: &SsdpContext,
+        scheduler: &ScheduledSender,
+        sockets: &[Arc<UdpSocket>],
+        style: Style,
+        received: &ReceivedMessage<SearchRequest>,
+        ifaces: &[NetifAddr],
+    ) {
+        let (request, src) = (&received.message, received.peer);
+
+        let Some(st) = request.headers().typed_get::<ST>() else {
+            debug!("Ignoring search request from {} without a search target", src);
+            return;
+        };
+
+        let responses = self.responses(&st);
+        if responses.is_empty() {
+            return;
+        }
+
+        // Unicast searches carry no MX and must be answered within a second.
+        let max_delay = match request.headers().typed_get::<MX>() {
+            Some(MX(n)) => Duration::from_secs(n.into()),
+            None => Duration::from_secs(1),
+        };
+
+        let Some(location) = self.location(&arrival_addrs(ifaces, received)) else {
+            debug!("No location to announce to {}", src);
+            return;
+        };
+
+        let now = context.now();
+        let gap = match responses.len() {
+            1 => Duration::ZERO,
+            count => self.response_gap.min(max_delay / (count as u32 - 1)),
+        };
+        let span = gap * (responses.len() as u32 - 1);
+
+        let key = (src, st.clone());
+        {
+            let mut pending = self.pending.lock().unwrap();
+            pending.retain(|_, &mut last| last >= now);
+
+            if pending.contains_key(&key) {
+                debug!("Already answering the search for {:?} from {}", st, src);
+                return;
+            }
+        }
+
+        let start = now + random_delay(max_delay - span);
+
+        // Not all interfaces can reach the requester, so send through every one of its family. A
+        // scoped requester is only reachable through the interface of its scope.
+        let sockets: Vec<_> = sockets
+            .iter()
+            .filter_map(|sock| {
+                let local = sock.local_addr().ok()?;
+                let dst = net::with_local_scope(src, local);
+                let same_scope = match (dst, local) {
+                    (SocketAddr::V6(dst), SocketAddr::V6(local)) => {
+                        local.scope_id() == 0 || local.scope_id() == dst.scope_id()
+                    }
+                    _ => true,
+                };
+
+                (local.is_ipv4() == src.is_ipv4() && same_scope).then_some((sock, dst))
+            })
+            .collect();
+
+        let mut scheduled = false;
+        for (index, (st, usn)) in responses.into_iter().enumerate() {
+            let response = self.response(st, usn, location.clone());
+            let at = start + gap * index as u32;
+
+            let packet: Arc<[u8]> = match response.to_packet(src, style) {
+                Ok(packet) => Arc::from(packet.as_slice()),
+                Err(err) => {
+                    debug!("Failed to serialize response to {}: {}", src, err);
+                    continue;
+                }
+            };
+
+            for &(sock, dst) in &sockets {
+                match scheduler.schedule(Arc::clone(&packet), dst, Arc::clone(sock), at) {
+                    Ok(_) => scheduled = true,
+                    Err(err) => debug!("Failed to respond to {}: {}", src, err),
+                }
+            }
+        }
+
+        // A request none of whose responses is queued is answered again when repeated.
+        let mut pending = self.pending.lock().unwrap();
+        if scheduled && pending.len() < MAX_PENDING_REQUESTS {
+            pending.insert(key, start + span);
+        }
+    }
+
+    /// Determine the search targets and unique service names to respond with.
+    ///
+    /// Responses to `ssdp:all` carry the notification type as their search target while all
+    /// others echo the searched target. A type shared by several devices, such as that of two
+    /// embedded devices of the same kind, is answered once for each.
+    fn responses(&self, st: &ST) -> Vec<(ST, USN)> {
+        match st {
+            ST::All => self
+                .targets
+                .iter()
+                .map(|(nt, usn)| (ST::Target(nt.0.clone()), usn.clone()))
+                .collect(),
+            ST::Target(_) => {
+                let mut responses: Vec<(ST, USN)> = Vec::new();
+                for (_, usn) in self.targets.iter().filter(|(nt, _)| st.matches(nt)) {
+                    // Of the versions of a type only the first is answered.
+                    if !responses.iter().any(|(_, answered)| answered.0 == usn.0) {
+                        responses.push((st.clone(), usn.clone()));
+                    }
+                }
+                responses
+            }
+        }
+    }
+
+    /// The unique service name advertised for the notification type.
+    fn usn(&self, nt: &NT) -> Result<USN, ParseFieldMapError> {
+        let uuid = FieldMap::try_uuid(self.uuid.as_str())?;
+
+        Ok(match nt.0 {
+            FieldMap::UUID(_) => USN(uuid, None),
+            ref other => USN(uuid, Some(other.clone())),
+        })
+    }
+
+    /// The location to announce through the first of the local addresses that has one.
+    fn location(&self, arrival: &[IpAddr]) -> Option<Location> {
+        let locations = self.locations.lock().unwrap();
+
+        arrival
+            .iter()
+            .find_map(|iface| locations.interfaces.get(iface))
+            .or(locations.default.as_ref())
+            .cloned()
+    }
+
+    fn response(&self, st: ST, usn: USN, location: Location) -> SearchResponse {
+        let mut response = SearchResponse::new();
+
+        response.set(CacheControl::new().with_max_age(self.max_age));
+        response.set(Ext);
+        response.set(location);
+        response.set(self.server.clone());
+        response.set(st);
+        response.set(usn);
+        self.instance.lock().unwrap().apply(&mut response);
+
+        response
+    }
+}
+
+/// The local addresses a request reached us through, the most specific first.
+///
+/// This is the address the request was sent to if it was sent to one of ours, then the addresses
+/// of the interface it arrived on. Where the platform reports neither, it is the address the system
+/// routes the requester through.
+fn arrival_addrs(ifaces: &[NetifAddr], received: &ReceivedMessage<SearchRequest>) -> Vec<IpAddr> {
+    let src = received.peer;
+    let mut addrs: Vec<_> = received.local_dst.filter(|dst| !dst.is_multicast()).into_iter().collect();
+
+    if let Some(index) = received.iface_index {
+        let same_family = |addr: &&NetifAddr| addr.index == index && addr.sock.is_ipv4() == src.is_ipv4();
+        addrs.extend(ifaces.iter().filter(same_family).map(|addr| addr.sock));
+    }
+
+    if addrs.is_empty() {
+        addrs.extend(net::route_source_addr(src).ok());
+    }
+
+    addrs
+}
+
+/// Choose a random delay up to the given maximum.
+fn random_delay(max: Duration) -> Duration {
+    // Hashers are randomly keyed for each `RandomState`, which is good enough for jitter.
+    let random = RandomState::new().build_hasher().finish();
+    max.mul_f64(random as f64 / u64::MAX as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr, UdpSocket};
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+
+    use headers::{HeaderMapExt as _, HeaderValue, Location, Server};
+
+    use super::{SearchResponder, MAX_PENDING_REQUESTS};
+    use crate::header::{BootID, ConfigID, HeaderMut, Man, MX, NT, ST, USN};
+    use crate::message::{Config, DeviceDescriptionLite, Multicast, SearchRequest};
+    use crate::net::httpu::Style;
+    use crate::receiver::ReceivedMessage;
+    use crate::schedule::ScheduledSender;
+    use crate::{FieldMap, SsdpContext};
+
+    fn responder() -> SearchResponder {
+        let location = HeaderValue::from_static("http://127.0.0.1:8080/description.xml");
+        let location: Location = headers::Header::decode(&mut [location].iter()).unwrap();
+
+        let responder = SearchResponder::new(
+            "responder-test",
+            "test/1.0 UPnP/1.1 ssdp/0.7".parse::<Server>().unwrap(),
+            Duration::from_secs(1800),
+        )
+        .target(NT(FieldMap::upnp("rootdevice")))
+        .and_then(|responder| responder.target(NT(FieldMap::uuid("responder-test"))))
+        .and_then(|responder| responder.target(NT(FieldMap::urn("schemas-upnp-org:device:printer:2"))))
+        .unwrap();
+
+        responder.set_location(location);
+        responder
+    }
+
+    #[test]
+    fn positive_all_responses() {
+        let responses = responder().device.responses(&ST::All);
+
+        assert_eq!(responses.len(), 3);
+        assert_eq!(responses[1].1, USN(FieldMap::uuid("responder-test"), None));
+    }
+
+    #[test]
+    fn positive_versioned_response() {
+        let st = ST::Target(FieldMap::urn("schemas-upnp-org:device:printer:1"));
+        let responses = responder().device.responses(&st);
+
+        assert_eq!(responses.len(), 1);
+        assert_eq!(responses[0].0, st);
+    }
+
+    #[test]
+    fn positive_response_ids() {
+        let responder = responder();
+        let location = responder.device.location(&[IpAddr::V4(Ipv4Addr::LOCALHOST)]).unwrap();
+        let usn = USN(FieldMap::uuid("responder-test"), None);
+
+        let response = responder.device.response(ST::All, usn.clone(), location.clone());
+        assert_eq!(response.headers().typed_get::<BootID>(), None);
+        assert_eq!(response.headers().typed_get::<ConfigID>(), None);
+
+        responder.set_boot_id(7);
+        responder.bump_config_id();
+        assert_eq!(responder.bump_config_id(), ConfigID(2));
+
+        let response = responder.device.response(ST::All, usn, location);
+        assert_eq!(response.headers().typed_get::<BootID>(), Some(BootID(7)));
+        assert_eq!(response.headers().typed_get::<ConfigID>(), Some(ConfigID(2)));
+    }
+
+    #[test]
+    fn positive_device_responses() {
+        let device = DeviceDescriptionLite::new("root", "schemas-upnp-org:device:MediaServer:1")
+            .service("schemas-upnp-org:service:ContentDirectory:1")
+            .embed(
+                DeviceDescriptionLite::new("embedded", "schemas-upnp-org:device:MediaServer:2")
+                    .service("schemas-upnp-org:service:ContentDirectory:1"),
+            );
+        let server = "test/1.0 UPnP/1.1 ssdp/0.7".parse::<Server>().unwrap();
+        let responder = SearchResponder::new("root", server, Duration::from_secs(1800))
+            .device(&device)
+            .unwrap();
+
+        assert_eq!(responder.device.responses(&ST::All).len(), 7);
+
+        // Both devices are media servers, the embedded one also of the older version.
+        let st = ST::Target(FieldMap::urn("schemas-upnp-org:device:MediaServer:1"));
+        let uuids: Vec<_> = responder.device.responses(&st).into_iter().map(|(_, usn)| usn.0).collect();
+        assert_eq!(uuids, vec![FieldMap::uuid("root"), FieldMap::uuid("embedded")]);
+
+        let st = ST::Target(FieldMap::uuid("embedded"));
+        let responses = responder.device.responses(&st);
+        assert_eq!(responses, vec![(st, USN(FieldMap::uuid("embedded"), None))]);
+    }
+
+    #[test]
+    fn positive_responses_spaced_and_coalesced() {
+        let clock = Arc::new(Mutex::new(Instant::now()));
+        let thread_clock = Arc::clone(&clock);
+        let context = SsdpContext::new().set_clock(move || *thread_clock.lock().unwrap());
+        let advance = |duration: Duration| *clock.lock().unwrap() += duration;
+
+        let scheduler = ScheduledSender::with_context(&context, 16);
+        let sockets = [Arc::new(UdpSocket::bind("127.0.0.1:0").unwrap())];
+        let requester = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut buffer = [0; 1024];
+        let mut recv = |timeout: Duration| {
+            requester.set_read_timeout(Some(timeout)).unwrap();
+            requester.recv(&mut buffer).is_ok()
+        };
+
+        // Three responses half a second apart fill the whole MX, leaving no room for jitter.
+        let responder = responder().response_gap(Duration::from_millis(500));
+        let mut request = SearchRequest::new();
+        request.set(Man);
+        request.set(MX(1));
+        request.set(ST::All);
+        let received = ReceivedMessage {
+            message: request,
+            peer: requester.local_addr().unwrap(),
+            local_dst: None,
+            iface_index: None,
+            received_at: context.now(),
+            raw: None,
+        };
+        let style = Style::default();
+        let respond = || responder.device.respond(&context, &scheduler, &sockets, style, &received, &[]);
+
+        respond();
+        // The same search again while the responses are pending is not answered twice.
+        respond();
+
+        assert!(recv(Duration::from_secs(5)));
+        assert!(!recv(Duration::from_millis(300)));
+        advance(Duration::from_millis(499));
+        assert!(!recv(Duration::from_millis(300)));
+        advance(Duration::from_millis(1));
+        assert!(recv(Duration::from_secs(5)));
+        advance(Duration::from_millis(500));
+        assert!(recv(Duration::from_secs(5)));
+        advance(Duration::from_secs(1));
+        assert!(!recv(Duration::from_millis(300)));
+        assert_eq!(scheduler.pending(), 0);
+
+        // Once all responses were sent, the search is answered again.
+        respond();
+        assert!(recv(Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn negative_pending_requests() {
+        let context = SsdpContext::new();
+        let sockets = [Arc::new(UdpSocket::bind("127.0.0.1:0").unwrap())];
+        let responder = responder();
+        let mut request = SearchRequest::new();
+        request.set(Man);
+        request.set(MX(1));
+        request.set(ST::All);
+        let received = |port: u16| ReceivedMessage {
+            message: request.clone(),
+            peer: (Ipv4Addr::LOCALHOST, port).into(),
+            local_dst: None,
+            iface_index: None,
+            received_at: context.now(),
+            raw: None,
+        };
+        let pending = || responder.device.pending.lock().unwrap().len();
+
+        // A request without any queued response is not remembered, its repetition is answered.
+        let full = ScheduledSender::with_context(&context, 0);
+        responder.device.respond(&context, &full, &sockets, Style::default(), &received(1), &[]);
+        assert_eq!(pending(), 0);
+
+        // Spoofed sources can not grow the remembered requests beyond the bound.
+        let scheduler = ScheduledSender::with_context(&context, 4 * MAX_PENDING_REQUESTS);
+        for port in 1..=(MAX_PENDING_REQUESTS as u16 + 10) {
+            responder.device.respond(&context, &scheduler, &sockets, Style::default(), &received(port), &[]);
+        }
+        assert_eq!(pending(), MAX_PENDING_REQUESTS);
+    }
+
+    #[test]
+    fn negative_unknown_target() {
+        let st = ST::Target(FieldMap::urn("schemas-upnp-org:device:scanner:1"));
+
+        assert!(responder().device.responses(&st).is_empty());
+    }
+
+    #[test]
+    fn negative_empty_uuid() {
+        let server = "test/1.0 UPnP/1.1 ssdp/0.7".parse::<Server>().unwrap();
+        let responder = SearchResponder::new("", server, Duration::from_secs(1800));
+
+        assert!(responder.target(NT(FieldMap::upnp("rootdevice"))).is_err());
+    }
+
+    #[test]
+    fn positive_answer_search() {
+        let config = Config::new()
+            .set_port(19_002)
+            .set_mode(crate::IpVersionMode::V4Only);
+
+        let mut responder = responder();
+        responder.start(&config).unwrap();
+
+        let mut request = SearchRequest::new();
+        request.set(Man);
+        request.set(MX(1));
+        request.set(ST::All);
+
+        let responses: Vec<_> = request
+            .multicast_with_config(&config)
+            .unwrap()
+            .into_iter()
+            .collect();
+
+        assert_eq!(responses.len(), 3);
+        for (response, _) in responses {
+            assert!(response.headers().typed_get::<Location>().is_some());
+        }
+    }
+
+    #[test]
+    fn positive_answer_search_over_loopback() {
+        let config = Config::new()
+            .set_port(19_010)
+            .set_mode(crate::IpVersionMode::V4Only)
+            .set_include_loopback(true)
+            .set_interfaces([IpAddr::V4(Ipv4Addr::LOCALHOST)]);
+
+        let mut responder = responder();
+        responder.start(&config).unwrap();
+
+        let mut request = SearchRequest::new();
+        request.set(Man);
+        request.set(MX(1));
+        request.set(ST::Target(FieldMap::upnp("rootdevice")));
+
+        let responses: Vec<_> = request
+            .multicast_with_config(&config)
+            .unwrap()
+            .into_iter()
+            .collect();
+
+        assert_eq!(responses.len(), 1);
+        assert_eq!(responses[0].1.ip(), IpAddr::V4(Ipv4Addr::LOCALHOST));
+    }
+
+    /// A request sent to one of our addresses is answered with the location of that address.
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn positive_location_of_destination() {
+        use std::io;
+        use std::net::SocketAddr;
+
+        use crate::net::NetifAddr;
+
+        let context = SsdpContext::new().set_interfaces(|| -> io::Result<Vec<NetifAddr>> {
+            Ok(vec![NetifAddr {
+                sock: IpAddr::V4(Ipv4Addr::LOCALHOST),
+                index: 1,
+            }])
+        });
+        let config = Config::new()
+            .set_port(19_039)
+            .set_mode(crate::IpVersionMode::V4Only)
+            .set_include_loopback(true);
+
+        let responder = responder();
+        for alias in ["127.0.0.2", "127.0.0.3"] {
+            let location = HeaderValue::from_str(&format!("http://{alias}/description.xml")).unwrap();
+            let location: Location = headers::Header::decode(&mut [location].iter()).unwrap();
+            responder.set_interface_location(alias.parse().unwrap(), location);
+        }
+        let _responder = context.responder(&config, responder).unwrap();
+
+        for alias in ["127.0.0.2", "127.0.0.3"] {
+            let mut request = SearchRequest::new();
+            request.set(Man);
+            request.set(ST::Target(FieldMap::upnp("rootdevice")));
+
+            let dst: SocketAddr = format!("{alias}:19039").parse().unwrap();
+            let receiver = request.unicast_with_context(&context, dst, &config).unwrap();
+            let (response, _) = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+
+            let location = response.headers().get("LOCATION").unwrap();
+            assert_eq!(location, &format!("http://{alias}/description.xml"));
+        }
+    }
+
+    #[test]
+    fn negative_listener_disconnected() {
+        use std::io;
+        use std::sync::mpsc::RecvTimeoutError;
+
+        use crate::context::Task;
+        use crate::net::NetifAddr;
+
+        // Only the last task, the thread of the responder, runs. The socket threads of its
+        // listener never do, so the listener disconnects at once.
+        let tasks = Arc::new(Mutex::new(Vec::new()));
+        let spawned = Arc::clone(&tasks);
+        let context = SsdpContext::new()
+            .set_interfaces(|| -> io::Result<Vec<NetifAddr>> {
+                Ok(vec![NetifAddr {
+                    sock: IpAddr::V4(Ipv4Addr::LOCALHOST),
+                    index: 1,
+                }])
+            })
+            .set_spawner(move |task: Task| spawned.lock().unwrap().push(task));
+        let config = Config::new()
+            .set_port(19_046)
+            .set_mode(crate::IpVersionMode::V4Only)
+            .set_include_loopback(true);
+
+        let mut responder = responder();
+        responder.start_with_context(&context, &config).unwrap();
+        let task = tasks.lock().unwrap().pop().unwrap();
+        tasks.lock().unwrap().clear();
+        let thread = std::thread::spawn(task);
+
+        // The thread exits instead of spinning on the disconnected listener.
+        let running = responder.running.as_ref().unwrap();
+        let done = running.done.recv_timeout(Duration::from_secs(5));
+        assert_eq!(done, Err(RecvTimeoutError::Disconnected));
+        thread.join().unwrap();
+    }
+
+    #[test]
+    fn positive_search_on_socket() {
+        let config = Config::new()
+            .set_port(19_011)
+            .set_mode(crate::IpVersionMode::V4Only)
+            .set_include_loopback(true)
+            .set_interfaces([IpAddr::V4(Ipv4Addr::LOCALHOST)]);
+
+        let mut responder = responder();
+        responder.start(&config).unwrap();
+
+        let mut request = SearchRequest::new();
+        request.set(Man);
+        request.set(MX(1));
+        request.set(ST::Target(FieldMap::upnp("rootdevice")));
+
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let responses: Vec<_> = request
+            .multicast_on_socket(socket, 0, &config)
+            .unwrap()
+            .into_iter()
+            .collect();
+
+        assert_eq!(responses.len(), 1);
+    }
+}
@@ -1,76 +1,484 @@
-use std::fmt::Debug;
-use std::io;
-use std::net::ToSocketAddrs;
+use std::borrow::Cow;
+use std::fmt::{self, Debug};
+use std::net::{Ipv4Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use std::time::Duration;
 
-use headers::{Header, HeaderMapExt as _};
+use headers::{Header, HeaderMap, HeaderMapExt as _, HeaderName, HeaderValue, UserAgent};
 
+use crate::context::{SsdpContext, SsdpObserver};
 use crate::error::{
-    SSDPError::{InvalidMethod, MissingHeader},
+    SSDPError::{self, AllSendsFailed, InvalidMethod, MissingHeader},
     SSDPResult,
 };
 
-use crate::header::{HeaderMut, MX};
+use crate::header::{self, HeaderMut, Man, SsdpMaxAge, VendorHeader, MX, NT, ST, USN};
 use crate::message::multicast::{self, Multicast};
-use crate::message::ssdp::SSDPMessage;
-use crate::message::{self, Config, Listen, MessageType};
+use crate::message::ssdp::{HostMismatch, SSDPMessage};
+use crate::message::listen::{self, Listen};
+use crate::message::timeout::{self, SendKind, Timeouts};
+use crate::message::{
+    self, Config, LocalConnectorOptions, MessageType, NotifyMessage, TransportSet, UnicastPolicy, UpnpVersion,
+};
 use crate::net;
-use crate::receiver::{FromRawSSDP, SSDPReceiver};
+use crate::net::connector::UdpConnector;
+use crate::net::httpu::Style;
+use crate::net::NetworkConnector;
+use crate::net::packet::PacketBuffer;
+use crate::private::Sealed;
+use crate::receiver::{FromRawSSDP, ParseOptions, ReceiverOptions, SSDPReceiver};
+
+/// How long the IPv4 address of a host name is given to answer a unicast search before its IPv6
+/// address is searched as well, the connection attempt delay of RFC 8305.
+const FALLBACK_DELAY: Duration = Duration::from_millis(250);
 
-/// Overhead to add to device response times to account for transport time.
-const NETWORK_TIMEOUT_OVERHEAD: u8 = 1;
+/// Longest time the fallback waits before consulting the clock of the context again.
+const FALLBACK_POLL_INTERVAL: Duration = Duration::from_millis(50);
 
-/// Devices are required to respond within 1 second of receiving unicast message.
-const DEFAULT_UNICAST_TIMEOUT: u8 = 1 + NETWORK_TIMEOUT_OVERHEAD;
+/// How the LOCATION of a search response is checked against the peer that sent it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum LocationPolicy {
+    /// Accept any location.
+    #[default]
+    Off,
+    /// The host of the location must be the address of the peer.
+    Exact,
+    /// The host of the location must be on the network of the peer, assumed to have a /24 prefix
+    /// for IPv4 and a /64 prefix for IPv6.
+    SameSubnet,
+}
 
 /// Search request that can be sent via unicast or multicast to devices on the network.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct SearchRequest {
-    message: SSDPMessage,
+    pub(crate) message: SSDPMessage,
 }
 
 impl SearchRequest {
     /// Construct a new SearchRequest.
+    ///
+    /// The MAN header is set already, a search without it is invalid. The search target and for
+    /// multicast searches the MX are up to the caller, see `with_st` and `with_mx`.
     pub fn new() -> SearchRequest {
-        SearchRequest {
-            message: SSDPMessage::new(MessageType::Search),
-        }
+        let mut message = SSDPMessage::new(MessageType::Search);
+        message.set(Man);
+
+        SearchRequest { message }
+    }
+
+    /// Set the search target.
+    pub fn with_st(self, st: ST) -> SearchRequest {
+        self.with_header(st)
+    }
+
+    /// Set the maximum wait time in seconds, which fails if it is out of range for an MX.
+    pub fn with_mx(self, wait_bound: u8) -> SSDPResult<SearchRequest> {
+        Ok(self.with_header(MX::new(wait_bound)?))
+    }
+
+    /// Set the MAN header, which `new` did already.
+    pub fn with_man(self) -> SearchRequest {
+        self.with_header(Man)
+    }
+
+    /// Set the user agent of the control point.
+    pub fn with_user_agent(self, user_agent: UserAgent) -> SearchRequest {
+        self.with_header(user_agent)
+    }
+
+    /// Set any typed header, replacing an earlier value.
+    pub fn with_header<H: Header>(mut self, value: H) -> SearchRequest {
+        self.message.set(value);
+        self
+    }
+
+    /// Get the headers contained in this message.
+    pub fn headers(&self) -> &HeaderMap {
+        self.message.headers()
+    }
+
+    /// Get the headers contained in this message for modification.
+    ///
+    /// Unlike `HeaderMut::set` this allows removing headers.
+    pub fn headers_mut(&mut self) -> &mut HeaderMap {
+        self.message.headers_mut()
+    }
+
+    /// Destroy the search request and return the underlying message.
+    pub fn into_message(self) -> SSDPMessage {
+        self.message
+    }
+
+    /// Check that the HOST names the multicast group and port of the config.
+    ///
+    /// Only multicast searches carry the group, see `SSDPMessage::validate_host`.
+    pub fn validate_host(&self, expected: &Config) -> Result<(), HostMismatch> {
+        self.message.validate_host(expected)
+    }
+
+    /// Serialize this search request into the bytes that are sent to the destination.
+    ///
+    /// See `SSDPMessage::to_bytes`.
+    pub fn to_bytes(&self, dst_addr: Option<SocketAddr>) -> SSDPResult<Vec<u8>> {
+        self.message.to_bytes(dst_addr)
     }
 
     /// Send this search request to a single host.
     ///
     /// Currently this sends the unicast message on all available network
     /// interfaces. This assumes that the network interfaces are operating
-    /// on either different subnets or different ip address ranges.
+    /// on either different subnets or different ip address ranges. Responses are received
+    /// through the interfaces that could send the request, see `SSDPError::AllSendsFailed` for
+    /// the error if none could.
+    ///
+    /// A host name with addresses of both IP versions is sent to its IPv4 address first. Its IPv6
+    /// address is searched instead if that failed, and as well if no response arrived within a
+    /// quarter of a second. The peer of each response tells which address answered, the local
+    /// addresses of the receiver which versions were searched.
     pub fn unicast<A: ToSocketAddrs>(&mut self, dst_addr: A) -> SSDPResult<SSDPReceiver<SearchResponse>> {
-        let mode = net::IpVersionMode::from_addr(&dst_addr)?;
-        let mut connectors = message::all_local_connectors(None, &mode)?;
+        self.unicast_with_config(dst_addr, &Default::default())
+    }
+
+    /// Send this search request to a single host through the interfaces chosen by the config.
+    ///
+    /// See `Config::set_unicast_policy`.
+    pub fn unicast_with_config<A: ToSocketAddrs>(
+        &mut self,
+        dst_addr: A,
+        config: &Config,
+    ) -> SSDPResult<SSDPReceiver<SearchResponse>> {
+        self.unicast_with_context(SsdpContext::global(), dst_addr, config)
+    }
+
+    /// Send this search request to the device that sent the notify message from the peer address.
+    ///
+    /// The request goes to the address of the peer, and to the port of the SEARCHPORT.UPNP.ORG
+    /// header or 1900 if there is none. The LOCATION is not consulted, its host may be a name or
+    /// an address other than the one the device sends from. See `unicast`.
+    pub fn unicast_to_device(
+        &mut self,
+        notify: &NotifyMessage,
+        peer: SocketAddr,
+    ) -> SSDPResult<SSDPReceiver<SearchResponse>> {
+        self.unicast_to_device_with_config(notify, peer, &Default::default())
+    }
+
+    /// Send this search request to the device of the notify message through the interfaces chosen
+    /// by the config.
+    ///
+    /// See `unicast_to_device` and `unicast_with_config`.
+    pub fn unicast_to_device_with_config(
+        &mut self,
+        notify: &NotifyMessage,
+        peer: SocketAddr,
+        config: &Config,
+    ) -> SSDPResult<SSDPReceiver<SearchResponse>> {
+        self.unicast_to_device_with_context(SsdpContext::global(), notify, peer, config)
+    }
+
+    /// Send this search request to the device of the notify message through the context.
+    pub(crate) fn unicast_to_device_with_context(
+        &mut self,
+        context: &SsdpContext,
+        notify: &NotifyMessage,
+        peer: SocketAddr,
+        config: &Config,
+    ) -> SSDPResult<SSDPReceiver<SearchResponse>> {
+        self.unicast_with_context(context, device_search_addr(notify, peer), config)
+    }
+
+    /// Send this search request to the destination on a socket owned by the caller.
+    ///
+    /// See `SSDPMessage::send_to`.
+    pub fn send_to(&self, socket: &UdpSocket, dst_addr: SocketAddr) -> SSDPResult<()> {
+        self.message.send_to(socket, dst_addr)
+    }
+
+    /// Send this search request to a single host through the connector.
+    ///
+    /// Unlike `unicast` this does not enumerate the network interfaces, the connector alone
+    /// decides how the request reaches the host. Receiving the responses is up to the caller.
+    pub fn unicast_via<C, A>(&mut self, connector: &mut C, dst_addr: A) -> SSDPResult<()>
+    where
+        C: NetworkConnector,
+        A: ToSocketAddrs,
+    {
+        self.message.send(connector, dst_addr)
+    }
+
+    /// Send this search request to a single host through the interfaces of the context.
+    pub(crate) fn unicast_with_context<A: ToSocketAddrs>(
+        &mut self,
+        context: &SsdpContext,
+        dst_addr: A,
+        config: &Config,
+    ) -> SSDPResult<SSDPReceiver<SearchResponse>> {
+        // A host name with addresses of both versions is tried over IPv4 first, then over IPv6 if
+        // no interface could send to the IPv4 address.
+        let (preferred, mut fallback) = net::resolve_families(&dst_addr)?;
+        let connectors = match unicast_family(context, config, &self.message, preferred) {
+            Ok(connectors) if !connectors.is_empty() => connectors,
+            result => match fallback.take() {
+                Some(fallback) => {
+                    debug!("Could not send unicast to {}, falling back to {}", preferred, fallback);
+                    unicast_family(context, config, &self.message, fallback)?
+                }
+                None => result?,
+            },
+        };
+
+        let raw_connectors = connectors.into_iter().map(|conn| conn.deconstruct()).collect();
+        let timeouts = timeout::effective_timeouts(&self.message, config, SendKind::Unicast);
+        let mut options = ReceiverOptions::from_config(config);
+        let Some(fallback) = fallback else {
+            return Ok(SSDPReceiver::with_context(context, raw_connectors, timeouts.receive, &options)?);
+        };
+
+        // The IPv6 address is searched as well if the IPv4 one did not answer in time.
+        let answered = Arc::new(Answered {
+            answered: AtomicBool::new(false),
+            observer: options.observer.take(),
+        });
+        options.observer = Some(Arc::clone(&answered) as Arc<dyn SsdpObserver>);
+        let (receiver, attacher) =
+            SSDPReceiver::with_attacher(context, raw_connectors, timeouts.receive, &options, None)?;
+
+        let thread_context = context.clone();
+        let config = config.clone();
+        let message = self.message.clone();
+        context.spawn(move || {
+            let deadline = thread_context.now() + FALLBACK_DELAY;
+            while let Some(remaining) = deadline.checked_duration_since(thread_context.now()) {
+                if attacher.is_stopped() {
+                    return;
+                }
+
+                std::thread::sleep(remaining.min(FALLBACK_POLL_INTERVAL));
+            }
+
+            if attacher.is_stopped() || answered.answered.load(Ordering::Relaxed) {
+                return;
+            }
+
+            debug!("No response from {} yet, also searching {}", preferred, fallback);
+            let connectors = match unicast_family(&thread_context, &config, &message, fallback) {
+                Ok(connectors) => connectors,
+                Err(err) => return debug!("Could not send unicast to {}: {}", fallback, err),
+            };
+
+            for conn in connectors {
+                if let Err(err) = attacher.adopt(conn.deconstruct()) {
+                    debug!("Could not receive responses from {}: {}", fallback, err);
+                }
+            }
+        });
+
+        Ok(receiver)
+    }
+
+    /// Send this search request to the broadcast address of every IPv4 interface.
+    ///
+    /// Some old devices only answer searches sent to the broadcast address of their subnet, not
+    /// to the multicast group. This is never done by `multicast`, broadcasts reach every host of
+    /// the subnet and should only be sent as a fallback. The broadcast addresses are derived from
+    /// the prefixes of the interfaces, interfaces without one are skipped. The MX and the receive
+    /// timeout are the ones of a multicast search.
+    pub fn broadcast_with_config(&self, config: &Config) -> SSDPResult<SSDPReceiver<SearchResponse>> {
+        self.broadcast_with_context(SsdpContext::global(), config)
+    }
+
+    /// Send this search request to the broadcast addresses of the interfaces of the context.
+    pub(crate) fn broadcast_with_context(
+        &self,
+        context: &SsdpContext,
+        config: &Config,
+    ) -> SSDPResult<SSDPReceiver<SearchResponse>> {
+        let message = self.multicast_message(config)?;
+        let timeouts = timeout::effective_timeouts(&message, config, SendKind::Multicast);
+        let timeout = timeouts
+            .receive
+            .ok_or(MissingHeader("Multicast Searches Require An MX Header"))?;
+
+        enter_span!("ssdp_broadcast", message_type = ?message.message_type());
+        let mut connectors = broadcast_connectors(context, config)?;
+        let mut failures = Vec::new();
+
+        connectors.retain_mut(|(conn, dst)| match message.send_styled(conn, *dst, config.header_style()) {
+            Ok(()) => {
+                context.stats().add_message_sent();
+                true
+            }
+            Err(err) => {
+                debug!("Failed to send broadcast to {}: {}", dst, err);
+                failures.push((*dst, err));
+                false
+            }
+        });
+
+        if connectors.is_empty() && !failures.is_empty() {
+            return Err(AllSendsFailed(failures));
+        }
+
+        let socks = connectors.into_iter().map(|(conn, _)| conn.deconstruct()).collect();
+        let options = ReceiverOptions::from_config(config);
+        Ok(SSDPReceiver::with_context(context, socks, Some(timeout), &options)?)
+    }
+
+    /// Send this search request to the multicast group through the interfaces of the context.
+    pub(crate) fn multicast_with_context(
+        &self,
+        context: &SsdpContext,
+        config: &Config,
+    ) -> SSDPResult<SSDPReceiver<SearchResponse>> {
+        self.multicast_on_transports(&mut TransportSet::bind_with_context(context, config)?)
+    }
+
+    /// Send this search request to the multicast group through the sockets of the set.
+    ///
+    /// The responses are received on the same sockets, so successive searches through one set are
+    /// sent from and answered at the same local ports. The config of the set applies to the
+    /// search. Sending stops the receiver of the previous search through the set.
+    pub fn multicast_on_transports(
+        &self,
+        transports: &mut TransportSet,
+    ) -> SSDPResult<SSDPReceiver<SearchResponse>> {
+        self.multicast_on_transports_within(transports, None)
+    }
+
+    /// Send this search request through the sockets of the set, receiving for the window if any.
+    ///
+    /// The window replaces the timeout derived from the MX and the config.
+    pub(crate) fn multicast_on_transports_within(
+        &self,
+        transports: &mut TransportSet,
+        window: Option<Duration>,
+    ) -> SSDPResult<SSDPReceiver<SearchResponse>> {
+        let message = self.multicast_message(transports.config())?;
+        let timeouts = timeout::effective_timeouts(&message, transports.config(), SendKind::Multicast);
+        let mcast_timeout = timeouts
+            .receive
+            .ok_or(MissingHeader("Multicast Searches Require An MX Header"))?;
+        let mcast_timeout = window.unwrap_or(mcast_timeout);
+
+        enter_span!("ssdp_multicast", message_type = ?message.message_type());
+        let socks = transports.send(&message);
+        trace!("Sending to {} connectors with {:?}", socks.len(), mcast_timeout);
+        transports.receive(socks, Some(mcast_timeout))
+    }
+
+    /// Send this search request to a single host through the sockets of the set of its IP version.
+    ///
+    /// The window replaces the timeout derived from the MX and the config, if any.
+    pub(crate) fn unicast_on_transports_within(
+        &self,
+        transports: &mut TransportSet,
+        dst_addr: SocketAddr,
+        window: Option<Duration>,
+    ) -> SSDPResult<SSDPReceiver<SearchResponse>> {
+        let timeouts = timeout::effective_timeouts(&self.message, transports.config(), SendKind::Unicast);
+        let socks = transports.send_unicast(&self.message, dst_addr)?;
+        transports.receive(socks, window.or(timeouts.receive))
+    }
+
+    /// Send this search request to the multicast group through a socket bound by the caller.
+    ///
+    /// The responses are received on the same socket. For IPv6 the multicast interface is the
+    /// one with the given index, see `UdpConnector::from_socket`.
+    pub fn multicast_on_socket(
+        &self,
+        socket: UdpSocket,
+        index: u32,
+        config: &Config,
+    ) -> SSDPResult<SSDPReceiver<SearchResponse>> {
+        self.multicast_on_socket_with_context(SsdpContext::global(), socket, index, config)
+    }
+
+    /// Send this search request through a socket bound by the caller, counted by the context.
+    pub(crate) fn multicast_on_socket_with_context(
+        &self,
+        context: &SsdpContext,
+        socket: UdpSocket,
+        index: u32,
+        config: &Config,
+    ) -> SSDPResult<SSDPReceiver<SearchResponse>> {
+        let message = self.multicast_message(config)?;
+        let timeouts = timeout::effective_timeouts(&message, config, SendKind::Multicast);
+        let mcast_timeout = timeouts
+            .receive
+            .ok_or(MissingHeader("Multicast Searches Require An MX Header"))?;
+
+        let mut connector = UdpConnector::from_socket(socket, index)?.with_observer(config.observer.clone());
+        multicast::send_through(context, &message, config, &mut connector)?;
+
+        Ok(SSDPReceiver::with_context(
+            context,
+            vec![connector.deconstruct()],
+            Some(mcast_timeout),
+            &ReceiverOptions::from_config(config),
+        )?)
+    }
+
+    /// Send this search request to the multicast group and wait for responses for the window.
+    ///
+    /// The window overrides the `Config::response_window` and the timeout derived from the MX,
+    /// which is still sent as is.
+    pub fn multicast_with_timeout(
+        &self,
+        config: &Config,
+        window: Duration,
+    ) -> SSDPResult<SSDPReceiver<SearchResponse>> {
+        let config = config.clone().set_response_window(Some(window));
+        self.multicast_with_context(SsdpContext::global(), &config)
+    }
 
-        // Send On All Connectors
-        for connector in &mut connectors {
-            self.message.send(connector, &dst_addr)?;
+    /// Get the message sent to the multicast group, with the MX that determines the timeout.
+    ///
+    /// The MX is clamped or defaulted if the config says so. Fails without a search target, no
+    /// device would respond to the search.
+    fn multicast_message(&self, config: &Config) -> SSDPResult<Cow<'_, SSDPMessage>> {
+        if !self.message.headers().contains_key(ST::name()) {
+            return Err(MissingHeader("ST"));
         }
 
-        let mut raw_connectors = Vec::with_capacity(connectors.len());
-        raw_connectors.extend(connectors.into_iter().map(|conn| conn.deconstruct()));
+        let timeouts = timeout::effective_timeouts(&self.message, config, SendKind::Multicast);
+        Ok(match timeouts.mx {
+            Some(mx) if self.message.headers().typed_get::<MX>() != Some(mx) => {
+                let mut message = self.message.clone();
+                message.set(mx);
+                Cow::Owned(message)
+            }
+            _ => Cow::Borrowed(&self.message),
+        })
+    }
 
-        let opt_timeout = opt_unicast_timeout(self.message.headers().typed_get::<MX>());
+    /// Get the timeouts that apply when sending this request.
+    pub fn timeouts(&self, config: &Config, kind: SendKind) -> Timeouts {
+        timeout::effective_timeouts(&self.message, config, kind)
+    }
 
-        Ok(SSDPReceiver::new(raw_connectors, opt_timeout)?)
+    /// Describe which rules determine the timeouts when sending this request.
+    #[doc(hidden)]
+    pub fn explain_timeouts(&self, config: &Config, kind: SendKind) -> String {
+        timeout::explain(&self.message, config, kind)
     }
 }
 
+impl Sealed for SearchRequest {}
+
 impl Multicast for SearchRequest {
     type Item = SSDPReceiver<SearchResponse>;
 
     fn multicast_with_config(&self, config: &Config) -> SSDPResult<Self::Item> {
-        let connectors = multicast::send(&self.message, config)?;
-        let mcast_timeout = multicast_timeout(self.message.headers().typed_get::<MX>())?;
-        trace!("Sending to {} connectors with {:?}", connectors.len(), mcast_timeout);
-        let mut raw_connectors = Vec::with_capacity(connectors.len());
-        raw_connectors.extend(connectors.into_iter().map(|conn| conn.deconstruct()));
+        self.multicast_with_context(SsdpContext::global(), config)
+    }
+}
 
-        Ok(SSDPReceiver::new(raw_connectors, Some(mcast_timeout))?)
+impl fmt::Display for SearchRequest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.message, f)
     }
 }
 
@@ -80,34 +488,80 @@ impl Default for SearchRequest {
     }
 }
 
-/// Get the require timeout to use for a multicast search request.
-fn multicast_timeout(mx: Option<MX>) -> SSDPResult<Duration> {
-    match mx {
-        Some(MX(n)) => Ok(Duration::new((n + NETWORK_TIMEOUT_OVERHEAD) as u64, 0)),
-        None => Err(MissingHeader("Multicast Searches Require An MX Header")),
+impl FromRawSSDP for SearchRequest {
+    fn from_packet(bytes: &[u8]) -> SSDPResult<SearchRequest> {
+        Self::from_packet_with_options(bytes, &Default::default())
     }
-}
 
-/// Get the default timeout to use for a unicast search request.
-fn opt_unicast_timeout(mx: Option<MX>) -> Option<Duration> {
-    match mx {
-        Some(MX(n)) => Some(Duration::new((n + NETWORK_TIMEOUT_OVERHEAD) as u64, 0)),
-        None => Some(Duration::new(DEFAULT_UNICAST_TIMEOUT as u64, 0)),
+    fn from_packet_with_options(bytes: &[u8], options: &ParseOptions) -> SSDPResult<SearchRequest> {
+        SearchRequest::try_from(SSDPMessage::from_packet_with_options(bytes, options)?)
     }
 }
 
-impl FromRawSSDP for SearchRequest {
-    fn from_packet(bytes: &[u8]) -> SSDPResult<SearchRequest> {
-        let message = SSDPMessage::from_packet(bytes)?;
+/// Fails with `InvalidMethod` for the other types of messages.
+impl TryFrom<SSDPMessage> for SearchRequest {
+    type Error = SSDPError;
 
+    fn try_from(message: SSDPMessage) -> SSDPResult<SearchRequest> {
         if message.message_type() != MessageType::Search {
             Err(InvalidMethod("SSDP Message Received Is Not A SearchRequest".into()))
         } else {
-            Ok(SearchRequest { message: message })
+            Ok(SearchRequest { message })
         }
     }
 }
 
+impl TryFrom<&[u8]> for SearchRequest {
+    type Error = SSDPError;
+
+    fn try_from(bytes: &[u8]) -> SSDPResult<SearchRequest> {
+        SearchRequest::from_packet(bytes)
+    }
+}
+
+impl FromStr for SearchRequest {
+    type Err = SSDPError;
+
+    fn from_str(message: &str) -> SSDPResult<SearchRequest> {
+        SearchRequest::try_from(message.as_bytes())
+    }
+}
+
+impl From<SearchRequest> for SSDPMessage {
+    fn from(request: SearchRequest) -> SSDPMessage {
+        request.into_message()
+    }
+}
+
+impl From<&SearchRequest> for http::Request<()> {
+    fn from(message: &SearchRequest) -> http::Request<()> {
+        http::Request::try_from(&message.message).expect("a search request is a request")
+    }
+}
+
+impl TryFrom<http::Request<()>> for SearchRequest {
+    type Error = SSDPError;
+
+    fn try_from(request: http::Request<()>) -> SSDPResult<SearchRequest> {
+        SearchRequest::try_from(SSDPMessage::try_from(request)?)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for SearchRequest {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.message.serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for SearchRequest {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let message = crate::serialize::deserialize_message(deserializer, MessageType::Search)?;
+        Ok(SearchRequest { message })
+    }
+}
+
 impl HeaderMut for SearchRequest {
     fn set<H>(&mut self, value: H)
     where
@@ -115,12 +569,20 @@ impl HeaderMut for SearchRequest {
     {
         self.message.set(value)
     }
+
+    fn set_raw(&mut self, name: HeaderName, value: HeaderValue) -> SSDPResult<()> {
+        self.message.set_raw(name, value)
+    }
+
+    fn set_raw_cased(&mut self, name: &str, value: HeaderValue) -> SSDPResult<()> {
+        self.message.set_raw_cased(name, value)
+    }
 }
 
 /// Search response that can be received or sent via unicast to devices on the network.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct SearchResponse {
-    message: SSDPMessage,
+    pub(crate) message: SSDPMessage,
 }
 
 impl SearchResponse {
@@ -131,31 +593,297 @@ impl SearchResponse {
         }
     }
 
+    /// Get the headers contained in this message.
+    pub fn headers(&self) -> &HeaderMap {
+        self.message.headers()
+    }
+
+    /// Get the headers contained in this message for modification.
+    ///
+    /// Unlike `HeaderMut::set` this allows removing headers.
+    pub fn headers_mut(&mut self) -> &mut HeaderMap {
+        self.message.headers_mut()
+    }
+
+    /// Destroy the search response and return the underlying message.
+    pub fn into_message(self) -> SSDPMessage {
+        self.message
+    }
+
+    /// Get how long the response is valid, from the `max-age` of the CACHE-CONTROL header.
+    ///
+    /// See `SsdpMaxAge` for the values that are accepted.
+    pub fn max_age(&self) -> Option<Duration> {
+        self.message.headers().typed_get::<SsdpMaxAge>().map(|max_age| max_age.0)
+    }
+
+    /// Check that the LOCATION points at the peer that sent the response.
+    pub fn location_matches_peer(&self, peer: &SocketAddr) -> bool {
+        self.location_matches(peer, LocationPolicy::Exact)
+    }
+
+    /// Check the LOCATION against the peer that sent the response.
+    ///
+    /// Only locations with an IP address as their host can match. Host names are not resolved,
+    /// since the answer of the name server would be as little trusted as the response itself.
+    pub fn location_matches(&self, peer: &SocketAddr, policy: LocationPolicy) -> bool {
+        if policy == LocationPolicy::Off {
+            return true;
+        }
+
+        let Some(host) = self.message.location_host() else {
+            return false;
+        };
+
+        let peer = peer.ip().to_canonical();
+        match policy {
+            LocationPolicy::Off => true,
+            LocationPolicy::Exact => host == peer,
+            LocationPolicy::SameSubnet => {
+                let prefix_len = if peer.is_ipv4() { 24 } else { 64 };
+                net::Subnet { addr: peer, prefix_len }.contains(host)
+            }
+        }
+    }
+
+    /// Get the status code of the response.
+    ///
+    /// Responses other than 200 are only received with `ParseOptions::set_lenient`.
+    pub fn status_code(&self) -> u16 {
+        self.message.status_code()
+    }
+
+    /// Get the first value of a header without a typed representation.
+    ///
+    /// See `VendorHeader::get`.
+    pub fn vendor_header(&self, name: &str) -> Option<String> {
+        VendorHeader::get(self.headers(), name)
+    }
+
+    /// Iterate the headers that are not defined by HTTP, SSDP or UPnP.
+    pub fn vendor_headers(&self) -> impl Iterator<Item = (&HeaderName, &HeaderValue)> {
+        header::vendor_headers(self.headers())
+    }
+
+    /// Detect the version of UPnP that the device speaks.
+    ///
+    /// See `SSDPMessage::upnp_version`.
+    pub fn upnp_version(&self) -> UpnpVersion {
+        self.message.upnp_version()
+    }
+
+    /// Serialize this search response into the bytes that are sent.
+    ///
+    /// See `SSDPMessage::to_bytes`.
+    pub fn to_bytes(&self, dst_addr: Option<SocketAddr>) -> SSDPResult<Vec<u8>> {
+        self.message.to_bytes(dst_addr)
+    }
+
+    /// Serialize this response as it is sent to the destination address.
+    pub(crate) fn to_packet(&self, dst_addr: SocketAddr, style: Style) -> SSDPResult<PacketBuffer> {
+        self.message.to_packet(dst_addr, style)
+    }
+
     /// Send this search response to a single host.
     ///
     /// Currently this sends the unicast message on all available network
     /// interfaces. This assumes that the network interfaces are operating
-    /// on either different subnets or different ip address ranges.
+    /// on either different subnets or different ip address ranges. Sending fails with
+    /// `SSDPError::AllSendsFailed` only if none of the interfaces could send the response.
     pub fn unicast<A: ToSocketAddrs>(&mut self, dst_addr: A) -> SSDPResult<()> {
-        let mode = net::IpVersionMode::from_addr(&dst_addr)?;
-        let mut connectors = message::all_local_connectors(None, &mode)?;
-
-        let mut success_count = 0;
-        let mut error_count = 0;
-        // Send On All Connectors
-        for conn in &mut connectors {
-            // Some routing errors are expected, not all interfaces can find the target addresses
-            match self.message.send(conn, &dst_addr) {
-                Ok(_) => success_count += 1,
-                Err(_) => error_count += 1,
+        self.unicast_with_config(dst_addr, &Default::default())
+    }
+
+    /// Send this search response to a single host through the interfaces chosen by the config.
+    ///
+    /// See `Config::set_unicast_policy`.
+    pub fn unicast_with_config<A: ToSocketAddrs>(&mut self, dst_addr: A, config: &Config) -> SSDPResult<()> {
+        self.unicast_with_context(SsdpContext::global(), dst_addr, config)
+    }
+
+    /// Send this search response to the destination on a socket owned by the caller.
+    ///
+    /// See `SSDPMessage::send_to`.
+    pub fn send_to(&self, socket: &UdpSocket, dst_addr: SocketAddr) -> SSDPResult<()> {
+        self.message.send_to(socket, dst_addr)
+    }
+
+    /// Send this search response from the socket that received the search request.
+    ///
+    /// Unlike `unicast` no new socket is bound, the response originates from the address and port
+    /// of the given socket. Some control points discard responses that do not come from port 1900.
+    pub fn unicast_from(&mut self, socket: &Arc<UdpSocket>, dst: SocketAddr) -> SSDPResult<()> {
+        self.unicast_from_with_context(SsdpContext::global(), socket, dst)
+    }
+
+    /// Send this search response from the socket, counted by the context.
+    pub(crate) fn unicast_from_with_context(
+        &mut self,
+        context: &SsdpContext,
+        socket: &Arc<UdpSocket>,
+        dst: SocketAddr,
+    ) -> SSDPResult<()> {
+        self.message.send_to(socket, dst)?;
+
+        context.stats().add_message_sent();
+        Ok(())
+    }
+
+    /// Send this search response to a single host through the interfaces of the context.
+    pub(crate) fn unicast_with_context<A: ToSocketAddrs>(
+        &mut self,
+        context: &SsdpContext,
+        dst_addr: A,
+        config: &Config,
+    ) -> SSDPResult<()> {
+        let mut connectors = unicast_connectors(context, config, &dst_addr)?;
+        send_unicast(context, config, &self.message, &mut connectors, &dst_addr)
+    }
+}
+
+/// Get the address that unicast searches for the device of the notify message go to.
+///
+/// That is the peer the notify message was received from, such that a link-local address keeps
+/// its scope, at the search port of the device.
+fn device_search_addr(notify: &NotifyMessage, peer: SocketAddr) -> SocketAddr {
+    let mut addr = peer;
+    addr.set_port(notify.search_port().map_or(message::UPNP_MULTICAST_PORT, |port| port.0));
+    addr
+}
+
+/// Create the connectors that send a unicast message to the destination.
+///
+/// With `UnicastPolicy::BestRoute` only the interface that the system routes the destination to
+/// is used, if it is one of the interfaces of the context.
+fn unicast_connectors<A: ToSocketAddrs>(
+    context: &SsdpContext,
+    config: &Config,
+    dst_addr: &A,
+) -> SSDPResult<Vec<UdpConnector>> {
+    config.validate()?;
+    let mode = net::IpVersionMode::from_addr(dst_addr)?;
+    let mut addrs = context.local_interfaces(config)?;
+
+    if config.unicast_policy == UnicastPolicy::BestRoute {
+        let dst_addr = net::addr_from_trait(dst_addr)?;
+        match net::route_source_addr(dst_addr) {
+            Ok(source) if addrs.iter().any(|addr| addr.sock == source) => {
+                addrs.retain(|addr| addr.sock == source);
             }
+            Ok(source) => debug!("Route source {} to {} is not a configured interface", source, dst_addr),
+            Err(err) => debug!("No route to {}, sending through all interfaces: {}", dst_addr, err),
         }
+    }
+
+    message::all_local_connectors(addrs, &LocalConnectorOptions::unicast(config, mode))
+}
+
+/// Create the connectors that broadcast, each with the broadcast address of its interface.
+///
+/// The prefixes are those of the system, interfaces of the context that are not found there have
+/// no broadcast address.
+fn broadcast_connectors(
+    context: &SsdpContext,
+    config: &Config,
+) -> SSDPResult<Vec<(UdpConnector, SocketAddr)>> {
+    config.validate()?;
+    let subnets = message::local_subnets();
+    let mut addrs = context.local_interfaces(config)?;
+    addrs.retain(|addr| addr.sock.is_ipv4());
+
+    let connectors = message::all_local_connectors(addrs, &LocalConnectorOptions::broadcast(config))?;
+    Ok(connectors
+        .into_iter()
+        .filter_map(|conn| {
+            let local = conn.local_addr().ok()?;
+            let subnet = subnets.iter().find(|subnet| subnet.addr == local.ip())?;
+            let Some(broadcast) = subnet.broadcast() else {
+                debug!("No broadcast address for {}/{}", subnet.addr, subnet.prefix_len);
+                return None;
+            };
+
+            Some((conn, SocketAddr::from((broadcast, config.port))))
+        })
+        .collect())
+}
+
+/// Send the message to a single host through every connector, dropping those that failed.
+///
+/// Some routing errors are expected, not all interfaces can reach the destination. This only
+/// fails if none of them could, with the error of every connector.
+/// Send the message to an address of a single IP version, returning the connectors that could.
+fn unicast_family(
+    context: &SsdpContext,
+    config: &Config,
+    message: &SSDPMessage,
+    dst_addr: SocketAddr,
+) -> SSDPResult<Vec<UdpConnector>> {
+    let mut connectors = unicast_connectors(context, config, &dst_addr)?;
+    send_unicast(context, config, message, &mut connectors, &dst_addr)?;
+    Ok(connectors)
+}
+
+/// Notes that a packet was received, before passing it on to the observer of the config.
+struct Answered {
+    answered: AtomicBool,
+    observer: Option<Arc<dyn SsdpObserver>>,
+}
 
-        if success_count == 0 && error_count > 0 {
-            Err(io::Error::last_os_error())?;
+impl SsdpObserver for Answered {
+    fn on_send(&self, iface: &SocketAddr, bytes: &[u8]) {
+        if let Some(observer) = &self.observer {
+            observer.on_send(iface, bytes);
         }
+    }
 
-        Ok(())
+    fn on_recv(&self, peer: &SocketAddr, bytes: &[u8]) {
+        self.answered.store(true, Ordering::Relaxed);
+        if let Some(observer) = &self.observer {
+            observer.on_recv(peer, bytes);
+        }
+    }
+
+    fn on_parse_error(&self, peer: &SocketAddr, err: &SSDPError) {
+        if let Some(observer) = &self.observer {
+            observer.on_parse_error(peer, err);
+        }
+    }
+}
+
+fn send_unicast<A: ToSocketAddrs>(
+    context: &SsdpContext,
+    config: &Config,
+    message: &SSDPMessage,
+    connectors: &mut Vec<UdpConnector>,
+    dst_addr: &A,
+) -> SSDPResult<()> {
+    let mut failures = Vec::new();
+
+    connectors.retain_mut(|conn| match message.send_styled(conn, dst_addr, config.header_style()) {
+        Ok(()) => {
+            context.stats().add_message_sent();
+            true
+        }
+        Err(err) => {
+            let local = conn
+                .local_addr()
+                .unwrap_or_else(|_| SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)));
+            debug!("Failed to send unicast through {}: {}", local, err);
+            failures.push((local, err));
+            false
+        }
+    });
+
+    if connectors.is_empty() && !failures.is_empty() {
+        return Err(AllSendsFailed(failures));
+    }
+
+    Ok(())
+}
+
+impl fmt::Display for SearchResponse {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.message, f)
     }
 }
 
@@ -165,56 +893,944 @@ impl Default for SearchResponse {
     }
 }
 
-/// Search listener that can listen for search messages sent within the network.
-pub struct SearchListener;
-
-impl Listen for SearchListener {
-    type Message = SearchResponse;
+/// Search listener that can listen for search requests sent within the network.
+#[derive(Clone, Default)]
+pub struct SearchListener {
+    config: Config,
 }
 
-impl FromRawSSDP for SearchResponse {
-    fn from_packet(bytes: &[u8]) -> SSDPResult<SearchResponse> {
-        let message = SSDPMessage::from_packet(bytes)?;
+impl SearchListener {
+    /// Construct a listener for the network described by the config.
+    pub fn new(config: Config) -> Self {
+        SearchListener { config }
+    }
 
-        if message.message_type() != MessageType::Response {
-            Err(InvalidMethod("SSDP Message Received Is Not A SearchResponse".into()))
-        } else {
-            Ok(SearchResponse { message: message })
-        }
+    /// Listen for messages on all local network interfaces.
+    ///
+    /// This will call `listen_with_config()` with _default_ values.
+    pub fn listen() -> SSDPResult<SSDPReceiver<SearchRequest>> {
+        listen::listen_with_config(&Default::default())
     }
-}
 
-impl HeaderMut for SearchResponse {
-    fn set<H>(&mut self, value: H)
-    where
-        H: Header,
-    {
-        self.message.set(value)
+    /// Listen for messages on all local network interfaces.
+    ///
+    /// See `listen::listen_with_config`.
+    pub fn listen_with_config(config: &Config) -> SSDPResult<SSDPReceiver<SearchRequest>> {
+        listen::listen_with_config(config)
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use crate::header::MX;
+    /// Listen on any interface.
+    ///
+    /// See `listen::listen_anyaddr_with_config`.
+    pub fn listen_anyaddr_with_config(config: &Config) -> SSDPResult<SSDPReceiver<SearchRequest>> {
+        listen::listen_anyaddr_with_config(config)
+    }
 
-    #[test]
-    fn positive_multicast_timeout() {
-        super::multicast_timeout(Some(MX(5))).unwrap();
+    /// Listen only for search requests whose search target matches the notification type.
+    ///
+    /// All other requests are dropped on the socket threads, see `ST::matches` and
+    /// `SSDPReceiver::set_filter`.
+    pub fn listen_for_target(&self, nt: NT) -> SSDPResult<SSDPReceiver<SearchRequest>> {
+        self.listen_for_target_with_context(SsdpContext::global(), nt)
     }
 
-    #[test]
-    fn positive_some_opt_multicast_timeout() {
-        super::opt_unicast_timeout(Some(MX(5))).unwrap();
+    /// Listen only for search requests for the notification type on the interfaces of the context.
+    pub(crate) fn listen_for_target_with_context(
+        &self,
+        context: &SsdpContext,
+        nt: NT,
+    ) -> SSDPResult<SSDPReceiver<SearchRequest>> {
+        let filter = move |request: &SearchRequest, _: &SocketAddr| {
+            request.headers().typed_get::<ST>().is_some_and(|st| st.matches(&nt))
+        };
+        listen::listen_filtered_with_context(context, &self.config, Some(Arc::new(filter)))
     }
+}
 
-    #[test]
-    fn positive_none_opt_multicast_timeout() {
-        super::opt_unicast_timeout(None).unwrap();
+impl Listen for SearchListener {
+    type Message = SearchRequest;
+
+    fn listen(&self) -> SSDPResult<SSDPReceiver<Self::Message>> {
+        listen::listen_with_config(&self.config)
     }
+}
 
-    #[test]
-    #[should_panic]
-    fn negative_multicast_timeout() {
-        super::multicast_timeout(None).unwrap();
+impl FromRawSSDP for SearchResponse {
+    fn from_packet(bytes: &[u8]) -> SSDPResult<SearchResponse> {
+        Self::from_packet_with_options(bytes, &Default::default())
+    }
+
+    fn from_packet_with_options(bytes: &[u8], options: &ParseOptions) -> SSDPResult<SearchResponse> {
+        SearchResponse::try_from(SSDPMessage::from_packet_with_options(bytes, options)?)
+    }
+
+    fn is_from_device(&self, uuid: &str) -> bool {
+        self.message.is_from_device(uuid)
+    }
+
+    fn usn(&self) -> Option<USN> {
+        self.headers().typed_get::<USN>()
+    }
+
+    fn verify_peer(&self, peer: &SocketAddr, options: &ParseOptions) -> bool {
+        self.location_matches(peer, options.location_policy)
+    }
+}
+
+/// Fails with `InvalidMethod` for the other types of messages.
+impl TryFrom<SSDPMessage> for SearchResponse {
+    type Error = SSDPError;
+
+    fn try_from(message: SSDPMessage) -> SSDPResult<SearchResponse> {
+        if message.message_type() != MessageType::Response {
+            Err(InvalidMethod("SSDP Message Received Is Not A SearchResponse".into()))
+        } else {
+            Ok(SearchResponse { message })
+        }
+    }
+}
+
+impl TryFrom<&[u8]> for SearchResponse {
+    type Error = SSDPError;
+
+    fn try_from(bytes: &[u8]) -> SSDPResult<SearchResponse> {
+        SearchResponse::from_packet(bytes)
+    }
+}
+
+impl FromStr for SearchResponse {
+    type Err = SSDPError;
+
+    fn from_str(message: &str) -> SSDPResult<SearchResponse> {
+        SearchResponse::try_from(message.as_bytes())
+    }
+}
+
+impl From<SearchResponse> for SSDPMessage {
+    fn from(response: SearchResponse) -> SSDPMessage {
+        response.into_message()
+    }
+}
+
+/// Fails only for a status code below 100, as accepted by `ParseOptions::set_lenient`.
+impl TryFrom<&SearchResponse> for http::Response<()> {
+    type Error = SSDPError;
+
+    fn try_from(response: &SearchResponse) -> SSDPResult<http::Response<()>> {
+        http::Response::try_from(&response.message)
+    }
+}
+
+impl TryFrom<http::Response<()>> for SearchResponse {
+    type Error = SSDPError;
+
+    fn try_from(response: http::Response<()>) -> SSDPResult<SearchResponse> {
+        let message = SSDPMessage::try_from(response)?;
+        Ok(SearchResponse { message })
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for SearchResponse {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.message.serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for SearchResponse {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let message = crate::serialize::deserialize_message(deserializer, MessageType::Response)?;
+        Ok(SearchResponse { message })
+    }
+}
+
+impl HeaderMut for SearchResponse {
+    fn set<H>(&mut self, value: H)
+    where
+        H: Header,
+    {
+        self.message.set(value)
+    }
+
+    fn set_raw(&mut self, name: HeaderName, value: HeaderValue) -> SSDPResult<()> {
+        self.message.set_raw(name, value)
+    }
+
+    fn set_raw_cased(&mut self, name: &str, value: HeaderValue) -> SSDPResult<()> {
+        self.message.set_raw_cased(name, value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6, UdpSocket};
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    use headers::{Header, HeaderMapExt as _, HeaderValue, Location, UserAgent};
+
+    use super::{device_search_addr, LocationPolicy, SearchListener, SearchRequest, SearchResponse};
+    use crate::header::{HeaderMut, Man, SearchPort, MX, NT, ST};
+    use crate::message::{
+        Config, Listen, MessageType, Multicast, NotifyMessage, SSDPMessage, SendKind, UnicastPolicy,
+    };
+    use crate::net::NetifAddr;
+    use crate::receiver::{FromRawSSDP, ParseOptions, SSDPReceiver};
+    use crate::testing::{loopback_context, search_response, MockConnector};
+    use crate::{FieldMap, SSDPError, SsdpContext};
+
+    fn assert_all_failed(err: SSDPError) {
+        match err {
+            SSDPError::AllSendsFailed(errors) => {
+                assert_eq!(errors.len(), 1);
+                assert_eq!(errors[0].0.ip(), IpAddr::V4(Ipv4Addr::LOCALHOST));
+                assert!(matches!(errors[0].1, SSDPError::IoAt { op: "send to", .. }));
+            }
+            other => panic!("unexpected error: {}", other),
+        }
+    }
+
+    fn response(location: &'static str) -> SearchResponse {
+        let mut response = SearchResponse::new();
+        response.set(Location::decode(&mut [HeaderValue::from_static(location)].iter()).unwrap());
+        response
+    }
+
+    fn peer(addr: &str) -> SocketAddr {
+        addr.parse().unwrap()
+    }
+
+    fn notify(location: &'static str, search_port: Option<u16>) -> NotifyMessage {
+        let mut notify = NotifyMessage::new();
+        notify.set(Location::decode(&mut [HeaderValue::from_static(location)].iter()).unwrap());
+        if let Some(port) = search_port {
+            notify.set(SearchPort(port));
+        }
+
+        notify
+    }
+
+    #[test]
+    fn positive_listen_search_request() {
+        let config = Config::new().set_port(19_003).set_mode(crate::IpVersionMode::V4Only);
+        let listener = SearchListener::new(config.clone()).listen().unwrap();
+
+        let st = ST::Target(FieldMap::urn("schemas-upnp-org:device:printer:1"));
+        let mut request = SearchRequest::new();
+        request.set(Man);
+        request.set(MX(1));
+        request.set(st.clone());
+        request.multicast_with_config(&config).unwrap();
+
+        let (received, _) = listener.recv().unwrap();
+        assert_eq!(received.headers().typed_get::<ST>(), Some(st));
+    }
+
+    #[test]
+    fn positive_multicast_clamped_mx() {
+        let config = Config::new()
+            .set_port(19_019)
+            .set_mode(crate::IpVersionMode::V4Only)
+            .set_clamp_mx(true);
+        let listener = SearchListener::new(config.clone()).listen().unwrap();
+
+        let mut request = SearchRequest::new();
+        request.set(Man);
+        request.set(MX(120));
+        request.set(ST::All);
+        request.multicast_with_config(&config).unwrap();
+
+        let (received, _) = listener.recv().unwrap();
+        assert_eq!(received.headers().typed_get::<MX>(), Some(MX(5)));
+        assert_eq!(request.headers().typed_get::<MX>(), Some(MX(120)));
+    }
+
+    #[test]
+    fn negative_multicast_without_st() {
+        let config = Config::new().set_port(19_020).set_mode(crate::IpVersionMode::V4Only);
+        let request = SearchRequest::new().with_mx(1).unwrap();
+
+        let result = request.multicast_with_context(&loopback_context(), &config);
+        assert!(matches!(result, Err(SSDPError::MissingHeader("ST"))));
+    }
+
+    #[test]
+    fn positive_multicast_source_port() {
+        let context = loopback_context();
+        let config = Config::new()
+            .set_port(19_032)
+            .set_mode(crate::IpVersionMode::V4Only)
+            .set_include_loopback(true)
+            .set_source_port(Some(19_033))
+            .set_response_window(Some(Duration::from_millis(100)));
+        let listener = context.listen::<SearchRequest>(&config).unwrap();
+
+        let request = SearchRequest::new().with_st(ST::All).with_mx(1).unwrap();
+        let receiver = request.multicast_with_context(&context, &config).unwrap();
+        assert_eq!(receiver.local_addrs(), vec![SocketAddr::from((Ipv4Addr::LOCALHOST, 19_033))]);
+
+        let (_, src) = listener.recv().unwrap();
+        assert_eq!(src, SocketAddr::from((Ipv4Addr::LOCALHOST, 19_033)));
+    }
+
+    #[test]
+    fn negative_source_port_taken() {
+        let taken = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let port = taken.local_addr().unwrap().port();
+        let config = Config::new()
+            .set_mode(crate::IpVersionMode::V4Only)
+            .set_include_loopback(true)
+            .set_source_port(Some(port));
+
+        let request = SearchRequest::new().with_st(ST::All).with_mx(1).unwrap();
+        match request.multicast_with_context(&loopback_context(), &config) {
+            Err(SSDPError::AllBindsFailed(errors)) => {
+                assert_eq!(errors.len(), 1);
+                let at = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+                let error = &errors[0].1;
+                assert!(matches!(error, SSDPError::IoAt { op: "bind", addr: Some(addr), .. } if *addr == at));
+            }
+            Err(other) => panic!("unexpected error: {}", other),
+            Ok(_) => panic!("bound a port that is taken"),
+        }
+    }
+
+    /// The loopback network is 127.0.0.0/8 on Linux, with a broadcast address like any other.
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn positive_broadcast_search() {
+        use crate::receiver::SSDPReceiver;
+
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 19_040)).unwrap();
+        let device = SSDPReceiver::<SearchRequest>::new(vec![Arc::new(socket)], None).unwrap();
+        let config = Config::new()
+            .set_port(19_040)
+            .set_mode(crate::IpVersionMode::V4Only)
+            .set_include_loopback(true);
+
+        let request = SearchRequest::new().with_st(ST::All).with_mx(1).unwrap();
+        let receiver = request.broadcast_with_context(&loopback_context(), &config).unwrap();
+
+        let search = device.recv_detailed_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(search.local_dst, Some(IpAddr::V4(Ipv4Addr::new(127, 255, 255, 255))));
+        assert_eq!(search.message.headers().typed_get::<ST>(), Some(ST::All));
+
+        let response = b"HTTP/1.1 200 OK\r\nCACHE-CONTROL: max-age=1800\r\nEXT:\r\n\
+            LOCATION: http://127.0.0.1/description.xml\r\nST: ssdp:all\r\nUSN: uuid:old-device\r\n\r\n";
+        let responder = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        responder.send_to(response, search.peer).unwrap();
+
+        let (_, peer) = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(peer, responder.local_addr().unwrap());
+    }
+
+    #[test]
+    fn positive_listen_for_target() {
+        let config = Config::new().set_port(19_026).set_mode(crate::IpVersionMode::V4Only);
+        let printer = NT(FieldMap::urn("schemas-upnp-org:device:printer:2"));
+        let listener = SearchListener::new(config.clone()).listen_for_target(printer).unwrap();
+
+        for st in [
+            ST::Target(FieldMap::urn("schemas-upnp-org:device:scanner:1")),
+            ST::Target(FieldMap::urn("schemas-upnp-org:device:printer:1")),
+            ST::Target(FieldMap::upnp("rootdevice")),
+        ] {
+            SearchRequest::new()
+                .with_st(st)
+                .with_mx(1)
+                .unwrap()
+                .multicast_with_config(&config)
+                .unwrap();
+        }
+
+        let (received, _) = listener.recv().unwrap();
+        let expected = ST::Target(FieldMap::urn("schemas-upnp-org:device:printer:1"));
+        assert_eq!(received.headers().typed_get::<ST>(), Some(expected));
+        assert!(listener.recv_timeout(Duration::from_millis(500)).is_err());
+        assert_eq!(listener.messages_filtered(), 2);
+    }
+
+    #[test]
+    fn positive_multicast_without_man() {
+        let config = Config::new().set_port(19_021).set_mode(crate::IpVersionMode::V4Only);
+        let listener = SearchListener::new(config.clone()).listen().unwrap();
+
+        let raw_message = "M-SEARCH * HTTP/1.1\r\nHOST: 239.255.255.250:1900\r\n\
+            MX: 1\r\nST: ssdp:all\r\n\r\n";
+        let request: SearchRequest = raw_message.parse().unwrap();
+        request.multicast_with_config(&config).unwrap();
+
+        let (received, _) = listener.recv().unwrap();
+        assert!(received.headers().typed_get::<Man>().is_some());
+        assert!(request.headers().typed_get::<Man>().is_none());
+    }
+
+    #[test]
+    fn positive_multicast_default_mx() {
+        let config = Config::new()
+            .set_port(19_022)
+            .set_mode(crate::IpVersionMode::V4Only)
+            .set_timeout_overhead(Duration::ZERO)
+            .set_default_mx(Some(1));
+        let listener = SearchListener::new(config.clone()).listen().unwrap();
+
+        let request = SearchRequest::new().with_st(ST::All);
+        assert_eq!(request.timeouts(&config, SendKind::Multicast).receive, Some(Duration::from_secs(1)));
+
+        let start = Instant::now();
+        let responses = request.multicast_with_config(&config).unwrap();
+        let (received, _) = listener.recv().unwrap();
+        assert_eq!(received.headers().typed_get::<MX>(), Some(MX(1)));
+        assert_eq!(request.headers().typed_get::<MX>(), None);
+
+        // The receiver ends once the MX passed without responses.
+        assert_eq!(responses.into_iter().count(), 0);
+        assert!(start.elapsed() >= Duration::from_secs(1));
+    }
+
+    #[test]
+    fn negative_multicast_without_default_mx() {
+        let config = Config::new().set_port(19_023).set_mode(crate::IpVersionMode::V4Only);
+        let request = SearchRequest::new().with_st(ST::All);
+
+        let result = request.multicast_with_context(&loopback_context(), &config);
+        assert!(matches!(result, Err(SSDPError::MissingHeader("Multicast Searches Require An MX Header"))));
+    }
+
+    #[test]
+    fn positive_multicast_with_timeout() {
+        let config = Config::new()
+            .set_port(19_024)
+            .set_mode(crate::IpVersionMode::V4Only)
+            .set_timeout_overhead(Duration::ZERO);
+        let listener = SearchListener::new(config.clone()).listen().unwrap();
+        let window = Duration::from_millis(1500);
+
+        let request = SearchRequest::new().with_st(ST::All).with_mx(1).unwrap();
+        let start = Instant::now();
+        let responses = request.multicast_with_timeout(&config, window).unwrap();
+        let (received, _) = listener.recv().unwrap();
+        assert_eq!(received.headers().typed_get::<MX>(), Some(MX(1)));
+
+        // Without the window the receiver would end after the MX of one second.
+        assert_eq!(responses.into_iter().count(), 0);
+        assert!(start.elapsed() >= window);
+    }
+
+    #[test]
+    fn positive_unicast_response_window() {
+        let window = Duration::from_millis(300);
+        let config = Config::new().set_response_window(Some(window));
+        let device = UdpSocket::bind("127.0.0.1:0").unwrap();
+
+        let mut request = SearchRequest::new().with_st(ST::All).with_mx(3).unwrap();
+        assert_eq!(request.timeouts(&config, SendKind::Unicast).receive, Some(window));
+
+        let start = Instant::now();
+        let responses = request
+            .unicast_with_context(&loopback_context(), device.local_addr().unwrap(), &config)
+            .unwrap();
+        assert_eq!(responses.into_iter().count(), 0);
+        assert!(start.elapsed() >= window);
+        assert!(start.elapsed() < Duration::from_secs(3));
+    }
+
+    /// A context with the loopback interfaces of the given IP versions.
+    fn loopback_context_of(ips: &'static [IpAddr]) -> SsdpContext {
+        SsdpContext::new().set_interfaces(|| -> io::Result<Vec<NetifAddr>> {
+            Ok(ips.iter().map(|&sock| NetifAddr { sock, index: 1 }).collect())
+        })
+    }
+
+    #[test]
+    fn positive_unicast_prefers_ipv4() {
+        let ips = &[IpAddr::V6(Ipv6Addr::LOCALHOST), IpAddr::V4(Ipv4Addr::LOCALHOST)];
+        let context = loopback_context_of(ips);
+        let device_v4 = UdpSocket::bind("127.0.0.1:0").unwrap();
+        device_v4.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let device_v6 = UdpSocket::bind("[::1]:0").unwrap();
+        let resolved = [device_v6.local_addr().unwrap(), device_v4.local_addr().unwrap()];
+
+        let mut request = SearchRequest::new().with_st(ST::All).with_mx(1).unwrap();
+        let config = Config::new().set_response_window(Some(Duration::from_millis(100)));
+        request.unicast_with_context(&context, &resolved[..], &config).unwrap();
+
+        let mut buffer = [0; 1500];
+        assert!(device_v4.recv_from(&mut buffer).is_ok());
+        device_v6.set_nonblocking(true).unwrap();
+        assert!(device_v6.recv_from(&mut buffer).is_err());
+    }
+
+    #[test]
+    fn positive_unicast_falls_back_to_ipv6() {
+        let context = loopback_context_of(&[IpAddr::V6(Ipv6Addr::LOCALHOST)]);
+        let device_v4 = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let device_v6 = UdpSocket::bind("[::1]:0").unwrap();
+        device_v6.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let resolved = [device_v4.local_addr().unwrap(), device_v6.local_addr().unwrap()];
+
+        let mut request = SearchRequest::new().with_st(ST::All).with_mx(1).unwrap();
+        let config = Config::new().set_response_window(Some(Duration::from_millis(100)));
+        request.unicast_with_context(&context, &resolved[..], &config).unwrap();
+
+        let mut buffer = [0; 1500];
+        let (len, _) = device_v6.recv_from(&mut buffer).unwrap();
+        assert!(buffer[..len].starts_with(b"M-SEARCH"));
+        device_v4.set_nonblocking(true).unwrap();
+        assert!(device_v4.recv_from(&mut buffer).is_err());
+    }
+
+    const DUAL_STACK: &[IpAddr] = &[IpAddr::V6(Ipv6Addr::LOCALHOST), IpAddr::V4(Ipv4Addr::LOCALHOST)];
+
+    /// Answer the first search received on the socket, as the device with the uuid.
+    fn answer_search(device: UdpSocket, uuid: &'static str) {
+        std::thread::spawn(move || {
+            device.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            if let Ok((_, peer)) = device.recv_from(&mut [0; 1500]) {
+                device.send_to(&search_response(uuid), peer).unwrap();
+            }
+        });
+    }
+
+    fn unicast_resolved(context: &SsdpContext, resolved: &[SocketAddr]) -> SSDPReceiver<SearchResponse> {
+        let mut request = SearchRequest::new().with_st(ST::All).with_mx(1).unwrap();
+        let config = Config::new().set_response_window(Some(Duration::from_secs(2)));
+        request.unicast_with_context(context, resolved, &config).unwrap()
+    }
+
+    #[test]
+    fn positive_unicast_resolved_v4_only() {
+        let device = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let resolved = [device.local_addr().unwrap()];
+        answer_search(device, "device-v4");
+
+        let receiver = unicast_resolved(&loopback_context_of(DUAL_STACK), &resolved);
+        let (_, peer) = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(peer, resolved[0]);
+        assert!(receiver.local_addrs().iter().all(SocketAddr::is_ipv4));
+    }
+
+    #[test]
+    fn positive_unicast_resolved_v6_only() {
+        let device = UdpSocket::bind("[::1]:0").unwrap();
+        let resolved = [device.local_addr().unwrap()];
+        answer_search(device, "device-v6");
+
+        let receiver = unicast_resolved(&loopback_context_of(DUAL_STACK), &resolved);
+        let (_, peer) = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(peer.ip(), IpAddr::V6(Ipv6Addr::LOCALHOST));
+        assert_eq!(peer.port(), resolved[0].port());
+        assert!(receiver.local_addrs().iter().all(SocketAddr::is_ipv6));
+    }
+
+    #[test]
+    fn positive_unicast_resolved_dual_stack() {
+        let device_v4 = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let device_v6 = UdpSocket::bind("[::1]:0").unwrap();
+        let resolved = [device_v6.local_addr().unwrap(), device_v4.local_addr().unwrap()];
+        answer_search(device_v4, "device-v4");
+
+        let receiver = unicast_resolved(&loopback_context_of(DUAL_STACK), &resolved);
+        let (_, peer) = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(peer, resolved[1]);
+
+        // The IPv4 address answered in time, the IPv6 one is never searched.
+        std::thread::sleep(super::FALLBACK_DELAY * 2);
+        device_v6.set_nonblocking(true).unwrap();
+        assert!(device_v6.recv_from(&mut [0; 1500]).is_err());
+        assert!(receiver.local_addrs().iter().all(SocketAddr::is_ipv4));
+    }
+
+    #[test]
+    fn positive_unicast_dual_stack_without_ipv4_response() {
+        let device_v4 = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let device_v6 = UdpSocket::bind("[::1]:0").unwrap();
+        let resolved = [device_v4.local_addr().unwrap(), device_v6.local_addr().unwrap()];
+        answer_search(device_v6, "device-v6");
+
+        let receiver = unicast_resolved(&loopback_context_of(DUAL_STACK), &resolved);
+        let (_, peer) = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(peer.ip(), IpAddr::V6(Ipv6Addr::LOCALHOST));
+
+        let mut buffer = [0; 1500];
+        let (len, _) = device_v4.recv_from(&mut buffer).unwrap();
+        assert!(buffer[..len].starts_with(b"M-SEARCH"));
+        assert!(receiver.local_addrs().iter().any(SocketAddr::is_ipv6));
+    }
+
+    #[test]
+    fn positive_unicast_ipv4_bind_failed() {
+        // Not an address of this host, so the IPv4 connector can not be bound.
+        const IPS: &[IpAddr] = &[IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)), IpAddr::V6(Ipv6Addr::LOCALHOST)];
+        let device_v4 = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let device_v6 = UdpSocket::bind("[::1]:0").unwrap();
+        let resolved = [device_v4.local_addr().unwrap(), device_v6.local_addr().unwrap()];
+        answer_search(device_v6, "device-v6");
+
+        let receiver = unicast_resolved(&loopback_context_of(IPS), &resolved);
+        let (_, peer) = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(peer.ip(), IpAddr::V6(Ipv6Addr::LOCALHOST));
+    }
+
+    #[test]
+    fn positive_new_sets_man() {
+        let request = SearchRequest::new();
+        let packet = String::from_utf8(request.to_bytes(None).unwrap()).unwrap();
+
+        assert!(packet.starts_with("M-SEARCH * HTTP/1.1\r\n"), "{packet}");
+        assert!(packet.to_ascii_lowercase().contains("\r\nman: \"ssdp:discover\"\r\n"), "{packet}");
+    }
+
+    #[test]
+    fn positive_builder_headers() {
+        let request = SearchRequest::new()
+            .with_st(ST::All)
+            .with_mx(3)
+            .unwrap()
+            .with_man()
+            .with_user_agent(UserAgent::from_static("Linux/6.1 UPnP/1.1 test/1.0"));
+        let packet = String::from_utf8(request.to_bytes(None).unwrap()).unwrap().to_ascii_lowercase();
+
+        assert!(packet.contains("\r\nman: \"ssdp:discover\"\r\n"), "{packet}");
+        assert!(packet.contains("\r\nst: ssdp:all\r\n"), "{packet}");
+        assert!(packet.contains("\r\nmx: 3\r\n"), "{packet}");
+        assert!(packet.contains("\r\nuser-agent: linux/6.1 upnp/1.1 test/1.0\r\n"), "{packet}");
+        assert_eq!(packet.matches("man:").count(), 1);
+    }
+
+    #[test]
+    fn negative_builder_mx_out_of_range() {
+        let result = SearchRequest::new().with_mx(0);
+
+        assert!(matches!(result, Err(SSDPError::InvalidHeader { .. })));
+    }
+
+    #[test]
+    fn positive_request_from_str() {
+        let raw_message = "M-SEARCH * HTTP/1.1\r\nHOST: 239.255.255.250:1900\r\nST: ssdp:all\r\n\r\n";
+
+        let request: SearchRequest = raw_message.parse().unwrap();
+        assert_eq!(request.headers().typed_get::<ST>(), Some(ST::All));
+        assert_eq!(SearchRequest::try_from(raw_message.as_bytes()).unwrap(), request);
+    }
+
+    #[test]
+    fn positive_remove_header() {
+        let mut request = SearchRequest::new().with_st(ST::All).with_mx(1).unwrap();
+        request.headers_mut().remove("MX");
+
+        assert_eq!(request.headers().typed_get::<MX>(), None);
+        assert_eq!(request.headers().typed_get::<ST>(), Some(ST::All));
+        let packet = String::from_utf8(request.to_bytes(None).unwrap()).unwrap();
+        assert!(!packet.to_ascii_lowercase().contains("\r\nmx:"), "{packet}");
+    }
+
+    #[test]
+    fn positive_into_message() {
+        let request = SearchRequest::new().with_st(ST::All);
+        let message = SSDPMessage::from(request);
+        assert_eq!(message.message_type(), MessageType::Search);
+        assert_eq!(message.headers().typed_get::<ST>(), Some(ST::All));
+
+        let raw_message = b"HTTP/1.1 200 OK\r\nST: ssdp:all\r\n\r\n";
+        let mut response = SearchResponse::try_from(&raw_message[..]).unwrap();
+        response.headers_mut().remove("ST");
+        let message = response.into_message();
+        assert_eq!(message.message_type(), MessageType::Response);
+        assert!(message.headers().is_empty());
+    }
+
+    #[test]
+    fn positive_try_from_message() {
+        let raw_message = b"M-SEARCH * HTTP/1.1\r\nHOST: 239.255.255.250:1900\r\nST: ssdp:all\r\n\r\n";
+        let message = SSDPMessage::from_packet(&raw_message[..]).unwrap();
+        let request = SearchRequest::try_from(message.clone()).unwrap();
+        assert_eq!(request.headers().typed_get::<ST>(), Some(ST::All));
+        assert_eq!(SSDPMessage::from(request), message);
+
+        let raw_message = b"HTTP/1.1 200 OK\r\nST: ssdp:all\r\n\r\n";
+        let message = SSDPMessage::from_packet(&raw_message[..]).unwrap();
+        let response = SearchResponse::try_from(message.clone()).unwrap();
+        assert_eq!(SSDPMessage::from(response), message);
+    }
+
+    #[test]
+    fn negative_try_from_message() {
+        let request = SSDPMessage::from(SearchRequest::new());
+        let response = SSDPMessage::from_packet(b"HTTP/1.1 200 OK\r\nST: ssdp:all\r\n\r\n").unwrap();
+
+        assert!(matches!(SearchResponse::try_from(request.clone()), Err(SSDPError::InvalidMethod(_))));
+        assert!(matches!(SearchRequest::try_from(response.clone()), Err(SSDPError::InvalidMethod(_))));
+        assert!(matches!(NotifyMessage::try_from(request), Err(SSDPError::InvalidMethod(_))));
+        assert!(matches!(NotifyMessage::try_from(response), Err(SSDPError::InvalidMethod(_))));
+    }
+
+    #[test]
+    fn positive_response_try_from() {
+        let raw_message = b"HTTP/1.1 200 OK\r\nST: ssdp:all\r\n\r\n";
+
+        let response = SearchResponse::try_from(&raw_message[..]).unwrap();
+        assert_eq!(response.headers().typed_get::<ST>(), Some(ST::All));
+        assert!(SearchRequest::try_from(&raw_message[..]).is_err());
+    }
+
+    #[test]
+    fn positive_max_age_quirks() {
+        let max_age = |cache_control: &str| {
+            let raw = format!("HTTP/1.1 200 OK\r\nCACHE-CONTROL: {cache_control}\r\nST: ssdp:all\r\n\r\n");
+            SearchResponse::from_packet(raw.as_bytes()).unwrap().max_age()
+        };
+
+        // Older Philips Hue bridges
+        assert_eq!(max_age("max-age = 66"), Some(Duration::from_secs(66)));
+        // Roku, as documented for its External Control Protocol
+        assert_eq!(max_age("max-age=3600"), Some(Duration::from_secs(3600)));
+        assert_eq!(max_age("max-age = 900,no-cache=\"Ext\""), Some(Duration::from_secs(900)));
+        assert_eq!(max_age("Max-Age=1800, must-revalidate"), Some(Duration::from_secs(1800)));
+        assert_eq!(max_age("no-cache"), None);
+    }
+
+    #[test]
+    fn positive_location_matches_peer() {
+        let response = response("http://192.168.1.20:49152/description.xml");
+
+        assert!(response.location_matches_peer(&peer("192.168.1.20:1900")));
+        assert!(response.location_matches_peer(&peer("[::ffff:192.168.1.20]:1900")));
+    }
+
+    #[test]
+    fn positive_location_matches_peer_v6() {
+        let response = response("http://[fd00::20]:49152/description.xml");
+
+        assert!(response.location_matches_peer(&peer("[fd00::20]:1900")));
+    }
+
+    #[test]
+    fn negative_location_other_host() {
+        let response = response("http://192.168.1.21:49152/description.xml");
+
+        assert!(!response.location_matches_peer(&peer("192.168.1.20:1900")));
+    }
+
+    #[test]
+    fn negative_location_host_name() {
+        let response = response("http://localhost:49152/description.xml");
+
+        assert!(!response.location_matches_peer(&peer("127.0.0.1:1900")));
+    }
+
+    #[test]
+    fn negative_location_missing() {
+        let response = SearchResponse::new();
+
+        assert!(!response.location_matches_peer(&peer("192.168.1.20:1900")));
+        assert!(response.location_matches(&peer("192.168.1.20:1900"), LocationPolicy::Off));
+    }
+
+    #[test]
+    fn positive_location_same_subnet() {
+        let response = response("http://192.168.1.21:49152/description.xml");
+        let peer = peer("192.168.1.20:1900");
+
+        assert!(response.location_matches(&peer, LocationPolicy::SameSubnet));
+        assert!(response.location_matches(&peer, LocationPolicy::Off));
+    }
+
+    #[test]
+    fn negative_location_same_subnet() {
+        let response = response("http://192.168.2.20:49152/description.xml");
+
+        assert!(!response.location_matches(&peer("192.168.1.20:1900"), LocationPolicy::SameSubnet));
+    }
+
+    #[test]
+    fn negative_verify_peer_with_policy() {
+        let response = response("http://192.168.1.21:49152/description.xml");
+        let options = ParseOptions::new().set_location_policy(LocationPolicy::Exact);
+
+        assert!(response.verify_peer(&peer("192.168.1.21:1900"), &options));
+        assert!(!response.verify_peer(&peer("192.168.1.20:1900"), &options));
+        assert!(response.verify_peer(&peer("192.168.1.20:1900"), &ParseOptions::new()));
+    }
+
+    #[test]
+    fn positive_unicast_response() {
+        let context = loopback_context();
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+
+        let mut response = SearchResponse::new();
+        response.unicast_with_context(&context, socket.local_addr().unwrap(), &Config::new()).unwrap();
+
+        assert_eq!(context.stats().messages_sent(), 1);
+    }
+
+    /// A context with two loopback addresses, of which the system routes `127.0.0.1` to itself.
+    fn multihomed_context() -> SsdpContext {
+        SsdpContext::new().set_interfaces(|| -> io::Result<Vec<NetifAddr>> {
+            Ok(vec![
+                NetifAddr {
+                    sock: IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2)),
+                    index: 1,
+                },
+                NetifAddr {
+                    sock: IpAddr::V4(Ipv4Addr::LOCALHOST),
+                    index: 1,
+                },
+            ])
+        })
+    }
+
+    #[test]
+    fn positive_unicast_best_route() {
+        let context = multihomed_context();
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let config = Config::new().set_unicast_policy(UnicastPolicy::BestRoute);
+
+        let mut response = SearchResponse::new();
+        response.unicast_with_context(&context, socket.local_addr().unwrap(), &config).unwrap();
+
+        assert_eq!(context.stats().messages_sent(), 1);
+        let (_, src) = socket.recv_from(&mut [0; 1500]).unwrap();
+        assert_eq!(src.ip(), IpAddr::V4(Ipv4Addr::LOCALHOST));
+    }
+
+    #[test]
+    fn positive_unicast_all_interfaces() {
+        let context = multihomed_context();
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+
+        let mut response = SearchResponse::new();
+        response.unicast_with_context(&context, socket.local_addr().unwrap(), &Config::new()).unwrap();
+
+        assert_eq!(context.stats().messages_sent(), 2);
+    }
+
+    #[test]
+    fn negative_unicast_best_route_fallback() {
+        let context = SsdpContext::new().set_interfaces(|| -> io::Result<Vec<NetifAddr>> {
+            Ok(vec![NetifAddr {
+                sock: IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2)),
+                index: 1,
+            }])
+        });
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let config = Config::new().set_unicast_policy(UnicastPolicy::BestRoute);
+
+        let mut response = SearchResponse::new();
+        response.unicast_with_context(&context, socket.local_addr().unwrap(), &config).unwrap();
+
+        assert_eq!(context.stats().messages_sent(), 1);
+    }
+
+    #[test]
+    fn positive_unicast_from_listener_socket() {
+        let context = loopback_context();
+        let listener = Arc::new(UdpSocket::bind("127.0.0.1:0").unwrap());
+        let control_point = UdpSocket::bind("127.0.0.1:0").unwrap();
+        control_point.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+
+        let mut response = SearchResponse::new();
+        response.set(ST::All);
+        response
+            .unicast_from_with_context(&context, &listener, control_point.local_addr().unwrap())
+            .unwrap();
+
+        let mut buffer = [0; 1500];
+        let (len, src) = control_point.recv_from(&mut buffer).unwrap();
+        assert_eq!(src.port(), listener.local_addr().unwrap().port());
+        let received = SearchResponse::from_packet(&buffer[..len]).unwrap();
+        assert_eq!(received.headers().typed_get::<ST>(), Some(ST::All));
+        assert_eq!(context.stats().messages_sent(), 1);
+    }
+
+    #[test]
+    fn negative_unicast_response_unreachable() {
+        let context = loopback_context();
+
+        let err = SearchResponse::new()
+            .unicast_with_context(&context, "192.0.2.1:1900", &Config::new())
+            .unwrap_err();
+
+        assert_all_failed(err);
+        assert_eq!(context.stats().messages_sent(), 0);
+    }
+
+    #[test]
+    fn negative_unicast_request_unreachable() {
+        let context = loopback_context();
+        let mut request = SearchRequest::new();
+        request.set(Man);
+        request.set(MX(1));
+
+        match request.unicast_with_context(&context, "192.0.2.1:1900", &Config::new()) {
+            Err(err) => assert_all_failed(err),
+            Ok(_) => panic!("sent through the loopback interface"),
+        }
+    }
+
+    #[test]
+    fn positive_unicast_to_device() {
+        let context = loopback_context();
+        let device = UdpSocket::bind("127.0.0.1:49219").unwrap();
+        device.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+
+        let mut request = SearchRequest::new();
+        request.set(Man);
+        request.set(MX(1));
+        request.set(ST::All);
+
+        // A host name in the LOCATION does not matter, the request goes to the peer.
+        let notify = notify("http://printer.local:8080/description.xml", Some(49219));
+        assert_eq!(notify.search_port(), Some(SearchPort(49219)));
+        let config = Config::new().set_unicast_policy(UnicastPolicy::BestRoute);
+        let receiver = request
+            .unicast_to_device_with_context(&context, &notify, peer("127.0.0.1:1900"), &config)
+            .unwrap();
+
+        let mut buffer = [0; 1500];
+        let (len, src) = device.recv_from(&mut buffer).unwrap();
+        let received = SearchRequest::from_packet(&buffer[..len]).unwrap();
+        assert_eq!(received.headers().typed_get::<ST>(), Some(ST::All));
+
+        device.send_to(b"HTTP/1.1 200 OK\r\nST: ssdp:all\r\n\r\n", src).unwrap();
+        let (response, from) = receiver.first().unwrap();
+        assert_eq!(response.headers().typed_get::<ST>(), Some(ST::All));
+        assert_eq!(from, device.local_addr().unwrap());
+    }
+
+    #[test]
+    fn positive_device_search_addr_default_port() {
+        let notify = notify("http://[fe80::1]:8080/description.xml", None);
+        let link_local = Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 1);
+        let scoped = |port| SocketAddr::V6(SocketAddrV6::new(link_local, port, 0, 3));
+
+        assert_eq!(notify.search_port(), None);
+        assert_eq!(device_search_addr(&notify, scoped(50_000)), scoped(1900));
+    }
+
+    #[test]
+    fn positive_device_search_addr_without_location() {
+        let mut notify = NotifyMessage::new();
+        notify.set(SearchPort(49_219));
+
+        assert_eq!(device_search_addr(&notify, peer("192.0.2.7:1900")), peer("192.0.2.7:49219"));
+    }
+
+    #[test]
+    fn positive_unicast_via() {
+        let mut connector = MockConnector::new();
+        let mut request = SearchRequest::new();
+        request.set(Man);
+        request.set(MX(1));
+
+        request.unicast_via(&mut connector, "192.0.2.1:1900").unwrap();
+
+        let sent = connector.sent();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].0, "192.0.2.1:1900".parse::<SocketAddr>().unwrap());
+        assert!(sent[0].1.starts_with(b"M-SEARCH * HTTP/1.1\r\n"));
     }
 }
@@ -0,0 +1,155 @@
+//! Repeated searches of a control point on sockets that are bound once.
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use crate::context::SsdpContext;
+use crate::error::SSDPResult;
+use crate::message::{Config, SearchRequest, SearchResponse, TransportSet};
+use crate::receiver::SSDPIter;
+
+/// A control point that searches in rounds, all through the same sockets.
+///
+/// Binding new sockets for every search churns ephemeral ports, and responses that arrive after
+/// a receiver timed out are lost with its sockets. A session binds one socket per interface once
+/// and sends every round from it. Packets that arrive between rounds are discarded before the
+/// next round is sent, so every round only yields responses that arrived during it.
+///
+/// ```no_run
+/// use std::time::Duration;
+///
+/// use ssdp::message::{Config, SearchRequest, SearchSession};
+///
+/// let mut session = SearchSession::bind(&Config::new()).unwrap();
+/// let request = SearchRequest::new();
+///
+/// loop {
+///     for (_, peer) in session.search(&request, Duration::from_secs(3)).unwrap() {
+///         println!("Response from {}", peer);
+///     }
+///     std::thread::sleep(Duration::from_secs(30));
+/// }
+/// ```
+pub struct SearchSession {
+    transports: TransportSet,
+}
+
+impl SearchSession {
+    /// Bind a socket to every interface the config selects.
+    pub fn bind(config: &Config) -> SSDPResult<SearchSession> {
+        SearchSession::bind_with_context(SsdpContext::global(), config)
+    }
+
+    /// Bind a socket to every interface of the context.
+    pub(crate) fn bind_with_context(context: &SsdpContext, config: &Config) -> SSDPResult<SearchSession> {
+        Ok(SearchSession {
+            transports: TransportSet::bind_with_context(context, config)?,
+        })
+    }
+
+    /// The local addresses the searches are sent from and answered at.
+    pub fn local_addrs(&self) -> Vec<SocketAddr> {
+        self.transports.local_addrs()
+    }
+
+    /// Send a round of the search to the multicast group and receive the responses.
+    ///
+    /// The iteration ends once no response arrived for the window, or when the next round is
+    /// sent. The window replaces the timeout derived from the MX, which is still sent as is.
+    pub fn search(
+        &mut self,
+        request: &SearchRequest,
+        window: Duration,
+    ) -> SSDPResult<SSDPIter<SearchResponse>> {
+        let receiver = request.multicast_on_transports_within(&mut self.transports, Some(window))?;
+        Ok(receiver.into_iter())
+    }
+
+    /// Send the search to a single host through the sockets of its IP version.
+    ///
+    /// This follows up on a device found by an earlier round, the response arrives at the same
+    /// local ports. The iteration ends like the one of `search`.
+    pub fn unicast(
+        &mut self,
+        request: &SearchRequest,
+        dst_addr: SocketAddr,
+        window: Duration,
+    ) -> SSDPResult<SSDPIter<SearchResponse>> {
+        let receiver = request.unicast_on_transports_within(&mut self.transports, dst_addr, Some(window))?;
+        Ok(receiver.into_iter())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{SocketAddr, UdpSocket};
+    use std::time::Duration;
+
+    use headers::HeaderMapExt as _;
+
+    use super::SearchSession;
+    use crate::header::{ST, USN};
+    use crate::message::{Config, SearchRequest, SearchResponse};
+    use crate::testing::{loopback_context, search_response};
+    use crate::FieldMap;
+
+    fn uuids(responses: impl Iterator<Item = (SearchResponse, SocketAddr)>) -> Vec<FieldMap> {
+        responses
+            .map(|(response, _)| response.headers().typed_get::<USN>().unwrap().0)
+            .collect()
+    }
+
+    #[test]
+    fn positive_search_rounds() {
+        let context = loopback_context();
+        let config = Config::new()
+            .set_port(19_031)
+            .set_mode(crate::IpVersionMode::V4Only)
+            .set_include_loopback(true);
+        let listener = context.listen::<SearchRequest>(&config).unwrap();
+
+        // Answer every round with a device of its own.
+        std::thread::spawn(move || {
+            let device = UdpSocket::bind("127.0.0.1:0").unwrap();
+            for (round, (_, src)) in listener.into_iter().enumerate() {
+                device.send_to(&search_response(&format!("round-{round}")), src).unwrap();
+            }
+        });
+
+        let mut session = SearchSession::bind_with_context(&context, &config).unwrap();
+        let local_addrs = session.local_addrs();
+        let request = SearchRequest::new().with_st(ST::All).with_mx(1).unwrap();
+        let stale = UdpSocket::bind("127.0.0.1:0").unwrap();
+
+        for round in 0..3 {
+            let responses = session.search(&request, Duration::from_millis(500)).unwrap();
+            assert_eq!(uuids(responses), vec![FieldMap::uuid(format!("round-{round}"))]);
+            assert_eq!(session.local_addrs(), local_addrs);
+
+            // A late response between rounds must not show up in the next one.
+            stale.send_to(&search_response("stale"), local_addrs[0]).unwrap();
+            std::thread::sleep(Duration::from_millis(50));
+        }
+    }
+
+    #[test]
+    fn positive_unicast_follow_up() {
+        let context = loopback_context();
+        let config = Config::new()
+            .set_mode(crate::IpVersionMode::V4Only)
+            .set_include_loopback(true);
+        let mut session = SearchSession::bind_with_context(&context, &config).unwrap();
+
+        let device = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let device_addr = device.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let mut buf = [0; 1024];
+            let (_, src) = device.recv_from(&mut buf).unwrap();
+            device.send_to(&search_response("device"), src).unwrap();
+        });
+
+        let request = SearchRequest::new().with_st(ST::All);
+        let responses = session.unicast(&request, device_addr, Duration::from_millis(500)).unwrap();
+        assert_eq!(uuids(responses), vec![FieldMap::uuid("device")]);
+    }
+}
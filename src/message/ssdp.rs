@@ -1,30 +1,61 @@
-use std::borrow::Cow;
-use std::fmt::Debug;
-use std::io::Write;
-use std::net::{SocketAddr, ToSocketAddrs};
+use std::fmt::{self, Debug};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::str::FromStr;
 
-use headers::{Header, HeaderMap, Host};
+use headers::{Header, HeaderMap, HeaderMapExt as _, Host, Server, UserAgent};
 
 use httparse::{Request, Response};
+use quick_error::quick_error;
 
-use crate::header::HeaderMut;
-use crate::message::MessageType;
-use crate::net::{self, NetworkConnector, NetworkStream};
-use crate::receiver::FromRawSSDP;
-use crate::{SSDPError, SSDPResult};
+use crate::header::{BootID, ConfigID, HeaderMut, Man, NextBootID, SearchPort, USN};
+use crate::message::{Config, MessageType, UpnpVersion, UPNP_MULTICAST_PORT};
+use crate::net::httpu::Style;
+use crate::net::packet::PacketBuffer;
+use crate::net::{self, IoAt, NetworkConnector, NetworkStream};
+use crate::receiver::{FromRawSSDP, ParseOptions};
+use crate::{FieldMap, SSDPError, SSDPResult};
 
 /// Only Valid `SearchResponse` Code
 const VALID_RESPONSE_CODE: u16 = 200;
 
 /// Case-Sensitive Method Names
-const NOTIFY_METHOD: &'static str = "NOTIFY";
-const SEARCH_METHOD: &'static str = "M-SEARCH";
+const NOTIFY_METHOD: &str = "NOTIFY";
+const SEARCH_METHOD: &str = "M-SEARCH";
+
+/// The destination of requests serialized without one, `UPNP_MULTICAST_IPV4_ADDR`.
+const MULTICAST_GROUP_V4: Ipv4Addr = Ipv4Addr::new(239, 255, 255, 250);
+
+quick_error! {
+    /// Enumerates why the HOST of a message does not name the multicast group.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    #[non_exhaustive]
+    pub enum HostMismatch {
+        /// The message has no HOST.
+        Missing {
+            display("missing HOST")
+        }
+        /// The HOST is not an IP address with an optional port, such as a host name.
+        Invalid(host: String) {
+            display("HOST is not an address: '{}'", host)
+        }
+        /// The HOST names another address or port, often that of the sender itself.
+        Other(addr: IpAddr, port: u16) {
+            display("HOST {} is not the multicast group", SocketAddr::new(*addr, *port))
+        }
+    }
+}
 
 /// Represents an SSDP method combined with both SSDP and HTTP headers.
+///
+/// `Display` renders the message as it would be sent with `to_bytes(None)`. Messages are equal if
+/// they have the same type, status code and headers, regardless of the order of the headers.
 #[derive(Debug, Clone)]
 pub struct SSDPMessage {
     method: MessageType,
     headers: HeaderMap,
+    status_code: u16,
+    /// The names of headers as spelled by `set_raw_cased`, written with `HeaderCase::AsSet`.
+    header_names: Vec<String>,
 }
 
 impl SSDPMessage {
@@ -33,6 +64,19 @@ impl SSDPMessage {
         SSDPMessage {
             method: message_type,
             headers: HeaderMap::new(),
+            status_code: VALID_RESPONSE_CODE,
+            header_names: Vec::new(),
+        }
+    }
+
+    /// Construct a message from headers that were validated elsewhere.
+    #[cfg(feature = "serde")]
+    pub(crate) fn from_parts(method: MessageType, headers: HeaderMap, status_code: u16) -> SSDPMessage {
+        SSDPMessage {
+            method,
+            headers,
+            status_code,
+            header_names: Vec::new(),
         }
     }
 
@@ -46,45 +90,278 @@ impl SSDPMessage {
         &self.headers
     }
 
+    /// Get the headers contained in this message for modification.
+    ///
+    /// Unlike `HeaderMut::set` this allows removing headers.
+    pub fn headers_mut(&mut self) -> &mut HeaderMap {
+        &mut self.headers
+    }
+
+    /// Get the status code of a response.
+    ///
+    /// This is 200 unless the response was parsed leniently, and for all other messages.
+    pub fn status_code(&self) -> u16 {
+        self.status_code
+    }
+
+    /// Check if the USN of the message carries the UUID, ignoring case.
+    pub(crate) fn is_from_device(&self, uuid: &str) -> bool {
+        match self.headers.typed_get::<USN>() {
+            Some(USN(FieldMap::UUID(value), _)) => value.eq_ignore_ascii_case(uuid),
+            _ => false,
+        }
+    }
+
+    /// Detect the version of UPnP that the sender of the message speaks.
+    ///
+    /// The version is taken from the `UPnP/x.y` token of the SERVER or, for searches, USER-AGENT
+    /// header. A message with a header that only exists since UPnP 1.1, such as BOOTID, is of at
+    /// least that version even if it names an older one.
+    pub fn upnp_version(&self) -> UpnpVersion {
+        let product = match self.method {
+            MessageType::Search => self.headers.typed_get::<UserAgent>().map(|agent| agent.to_string()),
+            MessageType::Notify | MessageType::Response => {
+                self.headers.typed_get::<Server>().map(|server| server.to_string())
+            }
+        };
+
+        let named = product.as_deref().and_then(upnp_token);
+        let since_v11 = [BootID::name(), ConfigID::name(), NextBootID::name(), SearchPort::name()]
+            .into_iter()
+            .any(|name| self.headers.contains_key(name));
+
+        match named {
+            Some(UpnpVersion::V10) | None if since_v11 => UpnpVersion::V11,
+            Some(version) => version,
+            None => UpnpVersion::Unknown,
+        }
+    }
+
+    /// Check that the HOST names the multicast group and port of the config.
+    ///
+    /// Notify messages and multicast searches must carry the group they are sent to. A HOST
+    /// without a port is that of the default port 1900, an IPv6 group is written in brackets and
+    /// compared with the group of the configured scope.
+    pub fn validate_host(&self, expected: &Config) -> Result<(), HostMismatch> {
+        let value = self.headers.get(Host::name()).ok_or(HostMismatch::Missing)?;
+        let invalid = || HostMismatch::Invalid(String::from_utf8_lossy(value.as_bytes()).into_owned());
+        let host = self.headers.typed_try_get::<Host>().ok().flatten().ok_or_else(invalid)?;
+
+        let hostname = host.hostname();
+        let hostname = hostname
+            .strip_prefix('[')
+            .and_then(|host| host.strip_suffix(']'))
+            .unwrap_or(hostname);
+        let addr = hostname.parse::<IpAddr>().map_err(|_| invalid())?;
+        let port = host.port().unwrap_or(UPNP_MULTICAST_PORT);
+
+        let group = match addr {
+            IpAddr::V4(_) => expected.ipv4_addr.parse::<IpAddr>(),
+            IpAddr::V6(_) => expected.ipv6_multicast_addr().parse::<IpAddr>(),
+        };
+
+        if group != Ok(addr) || port != expected.port {
+            return Err(HostMismatch::Other(addr, port));
+        }
+
+        Ok(())
+    }
+
+    /// Get the host of the LOCATION if it is an IP address.
+    pub(crate) fn location_host(&self) -> Option<IpAddr> {
+        let location = self.headers.get(headers::Location::name())?.to_str().ok()?;
+        let uri: http::Uri = location.parse().ok()?;
+        let host = uri.host()?;
+
+        // IPv6 addresses are enclosed in brackets
+        let host = host.strip_prefix('[').and_then(|host| host.strip_suffix(']')).unwrap_or(host);
+        host.parse::<IpAddr>().ok().map(|ip| ip.to_canonical())
+    }
+
     /// Send this request to the given destination address using the given connector.
     ///
     /// The host header field will be taken care of by the underlying library.
     pub fn send<A: ToSocketAddrs, C, S>(&self, connector: &mut C, dst_addr: A) -> SSDPResult<()>
+    where
+        C: NetworkConnector<Stream = S>,
+        S: Into<Box<dyn NetworkStream + Send>>,
+    {
+        self.send_styled(connector, dst_addr, Style::default())
+    }
+
+    /// Send this message like `send`, writing the headers in the given style.
+    pub(crate) fn send_styled<A: ToSocketAddrs, C, S>(
+        &self,
+        connector: &mut C,
+        dst_addr: A,
+        style: Style,
+    ) -> SSDPResult<()>
     where
         C: NetworkConnector<Stream = S>,
         S: Into<Box<dyn NetworkStream + Send>>,
     {
         let dst_sock_addr = net::addr_from_trait(dst_addr)?;
+        enter_span!("ssdp_send", message_type = ?self.method, peer = %dst_sock_addr);
+
         match self.method {
             MessageType::Notify => {
                 trace!("Notify to: {:?}", dst_sock_addr);
-                send_request(NOTIFY_METHOD, &self.headers, connector, dst_sock_addr)
+                send_request(self, connector, dst_sock_addr, style)
             }
             MessageType::Search => {
                 trace!("Sending search request...");
-                send_request(SEARCH_METHOD, &self.headers, connector, dst_sock_addr)
+                send_request(self, connector, dst_sock_addr, style)
             }
             MessageType::Response => {
                 trace!("Sending response to: {:?}", dst_sock_addr);
-                // This might need fixing for IPV6, passing down the IP loses the scope information
-                let dst_ip_string = dst_sock_addr.ip().to_string();
-                let dst_port = dst_sock_addr.port();
+                let mut net_stream: Box<dyn NetworkStream + Send> =
+                    connector.connect_addr(dst_sock_addr)?.into();
+                net_stream.send(&self.to_packet(dst_sock_addr, style)?)?;
 
-                let net_stream = connector.connect(&dst_ip_string[..], dst_port)?.into();
+                Ok(())
+            }
+        }
+    }
+
+    /// Send this message to the destination in a single datagram on the socket.
+    ///
+    /// Unlike `send` this involves no connector, the caller owns the socket. An IPv6 destination
+    /// without a scope id is scoped to the interface of the socket.
+    pub fn send_to(&self, socket: &UdpSocket, dst_addr: SocketAddr) -> SSDPResult<()> {
+        let packet = self.to_packet(dst_addr, Style::default())?;
+
+        let local = socket.local_addr().ok();
+        let scoped = local.map_or(dst_addr, |local| net::with_local_scope(dst_addr, local));
+        enter_span!(
+            "ssdp_send",
+            message_type = ?self.method,
+            interface = local.map(tracing::field::display),
+            peer = %scoped,
+        );
+        socket.send_to(packet.as_slice(), scoped).map_err(|err| {
+            let local = local.map(|addr| addr.ip().to_string());
+            IoAt::wrap(err, "send to", Some(scoped), local)
+        })?;
+
+        debug!("Sent HTTP Request:\n{}", String::from_utf8_lossy(packet.as_slice()));
+        Ok(())
+    }
+
+    /// Serialize this message as it is sent to the given destination address.
+    ///
+    /// Requests carry the destination in their host header, responses are sent with an empty
+    /// body.
+    pub(crate) fn to_packet(&self, dst_addr: SocketAddr, style: Style) -> SSDPResult<PacketBuffer> {
+        let mut buffer = PacketBuffer::default();
+        self.serialize(Some(dst_addr), style, &mut buffer)?;
+        Ok(buffer)
+    }
+
+    /// Serialize this message into the bytes that `send` puts on the wire.
+    ///
+    /// Requests carry the destination in their host header. Without a destination a request
+    /// keeps its own HOST header, or is addressed to the IPv4 multicast group if it has none.
+    /// Searches addressed to a multicast group get a MAN header if they lack one. Fails like
+    /// `send` if the message does not fit into a packet.
+    pub fn to_bytes(&self, dst_addr: Option<SocketAddr>) -> SSDPResult<Vec<u8>> {
+        let mut buffer = PacketBuffer::default();
+        self.serialize(dst_addr, Style::default(), &mut buffer)?;
+        Ok(buffer.as_slice().to_vec())
+    }
 
-                send_response(&self.headers, net_stream)
+    fn serialize(
+        &self,
+        dst_addr: Option<SocketAddr>,
+        style: Style,
+        buffer: &mut PacketBuffer,
+    ) -> SSDPResult<()> {
+        let method = match self.method {
+            MessageType::Notify => Some(NOTIFY_METHOD),
+            MessageType::Search => Some(SEARCH_METHOD),
+            MessageType::Response => None,
+        };
+
+        match method {
+            Some(method) => {
+                let host = match dst_addr {
+                    None if self.headers.contains_key(Host::name()) => None,
+                    None => Some(SocketAddr::from((MULTICAST_GROUP_V4, UPNP_MULTICAST_PORT))),
+                    Some(dst_addr) => Some(dst_addr),
+                };
+
+                let discover = self.method == MessageType::Search
+                    && host.is_some_and(|host| host.ip().is_multicast())
+                    && !self.headers.contains_key(Man::name());
+
+                let request = net::httpu::Request {
+                    method,
+                    host,
+                    discover,
+                    headers: &self.headers,
+                    names: &self.header_names,
+                    style,
+                };
+                request.serialize(buffer)?;
+            }
+            None => {
+                let response = net::httpu::Response::new(&self.headers, &self.header_names, style);
+                response.serialize(buffer)?;
             }
         }
+
+        Ok(())
+    }
+}
+
+impl PartialEq for SSDPMessage {
+    fn eq(&self, other: &SSDPMessage) -> bool {
+        self.method == other.method
+            && self.status_code == other.status_code
+            && same_headers(&self.headers, &other.headers)
+    }
+}
+
+impl Eq for SSDPMessage {}
+
+/// Compare two header maps as multisets, ignoring the order of names and of repeated values.
+fn same_headers(lhs: &HeaderMap, rhs: &HeaderMap) -> bool {
+    fn values<'a>(headers: &'a HeaderMap, name: &headers::HeaderName) -> Vec<&'a [u8]> {
+        let mut values: Vec<_> = headers.get_all(name).iter().map(|value| value.as_bytes()).collect();
+        values.sort_unstable();
+        values
+    }
+
+    lhs.len() == rhs.len() && lhs.keys().all(|name| values(lhs, name) == values(rhs, name))
+}
+
+impl fmt::Display for SSDPMessage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Not bound by the size of a packet, a message is displayed even if it can not be sent.
+        let mut buffer = PacketBuffer::with_capacity(usize::MAX);
+        self.serialize(None, Style::default(), &mut buffer).map_err(|_| fmt::Error)?;
+        f.write_str(&String::from_utf8_lossy(buffer.as_slice()))
     }
 }
 
-#[allow(unused)]
+/// Find the version in the `UPnP/x.y` token of a product list, such as `Linux UPnP/1.0 Sonos/70.3`.
+fn upnp_token(products: &str) -> Option<UpnpVersion> {
+    products.split_whitespace().find_map(|product| {
+        let (name, version) = product.split_once('/')?;
+        if !name.eq_ignore_ascii_case("upnp") {
+            return None;
+        }
+
+        let (major, minor) = version.split_once('.')?;
+        Some(UpnpVersion::from_token(major.parse().ok()?, minor.parse().ok()?))
+    })
+}
+
 /// Send a request using the connector with the supplied method and headers.
 fn send_request<C, S>(
-    method: &str,
-    headers: &HeaderMap,
+    message: &SSDPMessage,
     connector: &mut C,
     dst_addr: SocketAddr,
+    style: Style,
 ) -> SSDPResult<()>
 where
     C: NetworkConnector<Stream = S>,
@@ -100,47 +377,17 @@ where
         }
     }
 
-    trace!("Trying to parse url...");
     trace!("Url: {}", HttpmAddr { sock: dst_addr });
 
-    trace!("Setting up headers...");
-    let mut headers = headers.clone();
-
-    let mut request = net::httpu::Request::new(&headers);
-    request.method = method;
-
-    let mut buffer = net::packet::PacketBuffer::default();
-    request.serialize(&mut buffer)?;
+    let buffer = message.to_packet(dst_addr, style)?;
 
-    let sender = connector.connect(&dst_addr.ip().to_string(), dst_addr.port())?;
+    let sender = connector.connect_addr(dst_addr)?;
     let mut sender: Box<dyn net::NetworkStream + Send> = sender.into();
-    trace!("actual .send ...");
     sender.send(&buffer)?;
 
     Ok(())
 }
 
-/// Send an Ok response on the Writer with the supplied headers.
-fn send_response<W>(headers: &HeaderMap, mut dst_writer: W) -> SSDPResult<()>
-where
-    W: Write,
-{
-    let mut headers = headers.clone();
-    headers.set(headers::ContentLength(0));
-
-    let mut response = Response {
-        version: Some(1),
-        code: Some(200),
-        reason: Some("OK"),
-        headers: &mut [],
-    };
-
-    // Have to make sure response is destroyed here for lifetime issues with temp_headers
-    // response.start()?.end()?;
-
-    Ok(())
-}
-
 impl HeaderMut for SSDPMessage {
     fn set<H>(&mut self, value: H)
     where
@@ -148,12 +395,35 @@ impl HeaderMut for SSDPMessage {
     {
         HeaderMut::set(&mut self.headers, value)
     }
+
+    fn set_raw(&mut self, name: headers::HeaderName, value: headers::HeaderValue) -> SSDPResult<()> {
+        self.headers.set_raw(name, value)
+    }
+
+    fn set_raw_cased(&mut self, name: &str, value: headers::HeaderValue) -> SSDPResult<()> {
+        self.headers.set_raw_cased(name, value)?;
+        self.header_names.retain(|spelled| !spelled.eq_ignore_ascii_case(name));
+        self.header_names.push(name.to_owned());
+        Ok(())
+    }
 }
 
 impl FromRawSSDP for SSDPMessage {
     fn from_packet(bytes: &[u8]) -> SSDPResult<SSDPMessage> {
+        Self::from_packet_with_options(bytes, &Default::default())
+    }
+
+    fn from_packet_with_options(bytes: &[u8], options: &ParseOptions) -> SSDPResult<SSDPMessage> {
         let http1 = httparse::ParserConfig::default();
 
+        let normalized;
+        let bytes = if options.lenient {
+            normalized = normalize_head(bytes);
+            &normalized[..]
+        } else {
+            bytes
+        };
+
         fn is_complete(status: httparse::Status<usize>) -> SSDPResult<usize> {
             match status {
                 httparse::Status::Complete(n) => Ok(n),
@@ -165,16 +435,20 @@ impl FromRawSSDP for SSDPMessage {
         // method so we can not confuse these two cases.
         //
         // On header parsing note that most requests should not have more than this count of
-        // headers. Each here is two bytes making the stack usage still quite small.
+        // headers. Each here is two bytes making the stack usage still quite small. Only messages
+        // with more headers are parsed again, into a buffer bounded by the options.
         let mut initial_buffer = [httparse::EMPTY_HEADER; 32];
+        let initial_len = options.max_headers.min(initial_buffer.len());
+        let needs_fallback = options.max_headers > initial_len;
+
         if bytes.starts_with(b"HTTP/1") {
             let mut fallback_buffer: Box<[httparse::Header]>;
-            let mut response = httparse::Response::new(&mut initial_buffer);
+            let mut response = httparse::Response::new(&mut initial_buffer[..initial_len]);
 
             let after_header_count = match http1.parse_response(&mut response, bytes) {
                 Ok(count) => is_complete(count)?,
-                Err(httparse::Error::TooManyHeaders) => {
-                    fallback_buffer = vec![httparse::EMPTY_HEADER; 1 << 12].into();
+                Err(httparse::Error::TooManyHeaders) if needs_fallback => {
+                    fallback_buffer = vec![httparse::EMPTY_HEADER; options.max_headers].into();
                     response = httparse::Response::new(&mut fallback_buffer[..]);
                     is_complete(http1.parse_response(&mut response, bytes)?)?
                 }
@@ -191,18 +465,18 @@ impl FromRawSSDP for SSDPMessage {
                 return Err(SSDPError::InvalidBodyForMethod("M-SEARCH".into()));
             }
 
-            let message_result = message_from_response(response);
+            let message_result = message_from_response(response, options);
             log_message_result(&message_result, bytes);
 
             message_result
         } else {
             let mut fallback_buffer: Box<[httparse::Header]>;
-            let mut request = httparse::Request::new(&mut initial_buffer);
+            let mut request = httparse::Request::new(&mut initial_buffer[..initial_len]);
 
             let after_header_count = match http1.parse_request(&mut request, bytes) {
                 Ok(count) => is_complete(count)?,
-                Err(httparse::Error::TooManyHeaders) => {
-                    fallback_buffer = vec![httparse::EMPTY_HEADER; 1 << 12].into();
+                Err(httparse::Error::TooManyHeaders) if needs_fallback => {
+                    fallback_buffer = vec![httparse::EMPTY_HEADER; options.max_headers].into();
                     request = httparse::Request::new(&mut fallback_buffer[..]);
                     is_complete(http1.parse_request(&mut request, bytes)?)?
                 }
@@ -229,6 +503,120 @@ impl FromRawSSDP for SSDPMessage {
     }
 }
 
+impl TryFrom<&[u8]> for SSDPMessage {
+    type Error = SSDPError;
+
+    fn try_from(bytes: &[u8]) -> SSDPResult<SSDPMessage> {
+        SSDPMessage::from_packet(bytes)
+    }
+}
+
+impl FromStr for SSDPMessage {
+    type Err = SSDPError;
+
+    fn from_str(message: &str) -> SSDPResult<SSDPMessage> {
+        SSDPMessage::try_from(message.as_bytes())
+    }
+}
+
+/// Convert a notify or search message into a request with the URI `*`.
+impl TryFrom<&SSDPMessage> for http::Request<()> {
+    type Error = SSDPError;
+
+    fn try_from(message: &SSDPMessage) -> SSDPResult<http::Request<()>> {
+        let method = match message.method {
+            MessageType::Notify => NOTIFY_METHOD,
+            MessageType::Search => SEARCH_METHOD,
+            MessageType::Response => {
+                return Err(SSDPError::InvalidMethod("a response is not a request".into()));
+            }
+        };
+
+        let mut request = http::Request::new(());
+        *request.method_mut() = http::Method::from_bytes(method.as_bytes()).expect("valid method token");
+        *request.uri_mut() = http::Uri::from_static("*");
+        *request.version_mut() = http::Version::HTTP_11;
+        *request.headers_mut() = message.headers.clone();
+        Ok(request)
+    }
+}
+
+/// Convert a response message into a response.
+impl TryFrom<&SSDPMessage> for http::Response<()> {
+    type Error = SSDPError;
+
+    fn try_from(message: &SSDPMessage) -> SSDPResult<http::Response<()>> {
+        if message.method != MessageType::Response {
+            return Err(SSDPError::InvalidMethod("a request is not a response".into()));
+        }
+
+        let status = http::StatusCode::from_u16(message.status_code)
+            .map_err(|_| SSDPError::ResponseCode(message.status_code))?;
+
+        let mut response = http::Response::new(());
+        *response.status_mut() = status;
+        *response.version_mut() = http::Version::HTTP_11;
+        *response.headers_mut() = message.headers.clone();
+        Ok(response)
+    }
+}
+
+/// Validate a request as it would be when parsed from a packet.
+impl TryFrom<http::Request<()>> for SSDPMessage {
+    type Error = SSDPError;
+
+    fn try_from(request: http::Request<()>) -> SSDPResult<SSDPMessage> {
+        let (parts, ()) = request.into_parts();
+
+        if parts.version != http::Version::HTTP_11 {
+            return Err(SSDPError::InvalidHttpVersion);
+        }
+
+        if parts.uri != "*" {
+            return Err(SSDPError::InvalidUri(parts.uri.to_string()));
+        }
+
+        let method = match parts.method.as_str() {
+            NOTIFY_METHOD => MessageType::Notify,
+            SEARCH_METHOD => MessageType::Search,
+            n => return Err(SSDPError::InvalidMethod(n.to_string())),
+        };
+
+        if parts.headers.get(Host::name()).is_none() {
+            return Err(SSDPError::MissingHeader(Host::name().as_str()));
+        }
+
+        Ok(SSDPMessage {
+            method,
+            headers: parts.headers,
+            status_code: VALID_RESPONSE_CODE,
+            header_names: Vec::new(),
+        })
+    }
+}
+
+/// Validate a response as it would be when parsed from a packet with the default options.
+impl TryFrom<http::Response<()>> for SSDPMessage {
+    type Error = SSDPError;
+
+    fn try_from(response: http::Response<()>) -> SSDPResult<SSDPMessage> {
+        let (parts, ()) = response.into_parts();
+
+        if parts.version != http::Version::HTTP_11 {
+            return Err(SSDPError::InvalidHttpVersion);
+        }
+
+        validate_response_code(parts.status.as_u16())?;
+
+        Ok(SSDPMessage {
+            method: MessageType::Response,
+            headers: parts.headers,
+            status_code: parts.status.as_u16(),
+            header_names: Vec::new(),
+        })
+    }
+}
+
 /// Logs a debug! message based on the value of the `SSDPResult`.
 fn log_message_result(result: &SSDPResult<SSDPMessage>, message: &[u8]) {
     match *result {
@@ -237,48 +625,118 @@ fn log_message_result(result: &SSDPResult<SSDPMessage>, message: &[u8]) {
     }
 }
 
+/// Normalize the head of a message for devices that deviate from HTTP.
+///
+/// Line endings become CRLF and whitespace between a header name and its colon is removed, as is
+/// whitespace around the `=` of CACHE-CONTROL directives. Other header values, continuation lines
+/// and the body are kept as they are.
+fn normalize_head(bytes: &[u8]) -> Vec<u8> {
+    let mut normalized = Vec::with_capacity(bytes.len() + 32);
+    let mut offset = 0;
+
+    for (number, line) in bytes.split_inclusive(|&byte| byte == b'\n').enumerate() {
+        offset += line.len();
+
+        let Some(content) = line.strip_suffix(b"\n") else {
+            // An unterminated last line, which httparse reports as partial.
+            normalized.extend_from_slice(line);
+            break;
+        };
+        let content = content.strip_suffix(b"\r").unwrap_or(content);
+
+        let colon = content.iter().position(|&byte| byte == b':');
+        match colon {
+            Some(colon) if number > 0 && !content[0].is_ascii_whitespace() => {
+                let name = content[..colon].trim_ascii_end();
+                normalized.extend_from_slice(name);
+
+                if name.eq_ignore_ascii_case(b"cache-control") {
+                    normalized.push(b':');
+                    normalize_directives(&content[colon + 1..], &mut normalized);
+                } else {
+                    normalized.extend_from_slice(&content[colon..]);
+                }
+            }
+            _ => normalized.extend_from_slice(content),
+        }
+
+        normalized.extend_from_slice(b"\r\n");
+        if content.is_empty() {
+            break;
+        }
+    }
+
+    normalized.extend_from_slice(&bytes[offset..]);
+    normalized
+}
+
+/// Remove the whitespace around the `=` of each directive, as in `max-age = 1800`.
+fn normalize_directives(value: &[u8], normalized: &mut Vec<u8>) {
+    let mut parts = value.split(|&byte| byte == b'=');
+
+    if let Some(first) = parts.next() {
+        normalized.extend_from_slice(first);
+    }
+
+    for part in parts {
+        normalized.truncate(normalized.trim_ascii_end().len());
+        normalized.push(b'=');
+        normalized.extend_from_slice(part.trim_ascii_start());
+    }
+}
+
 /// Attempts to construct an `SSDPMessage` from the given request pieces.
 fn message_from_request(parts: Request<'_, '_>) -> SSDPResult<SSDPMessage> {
     validate_http_version(parts.version)?;
-    let headers = validate_http_headers(&parts.headers)?;
+    let headers = validate_http_headers(parts.headers)?;
 
     // Shouldn't have to do this but hyper doesn't make sure that HTTP/1.1
     // messages contain Host headers so we will assure conformance ourselves.
     if headers.get(Host::name()).is_none() {
-        return Err(SSDPError::MissingHeader(Host::name().as_str()).into());
+        return Err(SSDPError::MissingHeader(Host::name().as_str()));
     }
 
     match parts.path.expect("filled by httparse") {
         "*" => {}
         n => {
-            return Err(SSDPError::InvalidUri(n.to_string()))?;
+            Err(SSDPError::InvalidUri(n.to_string()))?;
         }
     };
 
     match parts.method.expect("filled by httparse") {
         NOTIFY_METHOD => Ok(SSDPMessage {
             method: MessageType::Notify,
-            headers: headers,
+            headers,
+            status_code: VALID_RESPONSE_CODE,
+            header_names: Vec::new(),
         }),
         SEARCH_METHOD => Ok(SSDPMessage {
             method: MessageType::Search,
-            headers: headers,
+            headers,
+            status_code: VALID_RESPONSE_CODE,
+            header_names: Vec::new(),
         }),
-        n => Err(SSDPError::InvalidMethod(n.to_string()).into()),
+        n => Err(SSDPError::InvalidMethod(n.to_string())),
     }
 }
 
 /// Attempts to construct an `SSDPMessage` from the given response pieces.
-fn message_from_response(parts: Response<'_, '_>) -> SSDPResult<SSDPMessage> {
+///
+/// Lenient options accept any status code, which is kept in the message.
+fn message_from_response(parts: Response<'_, '_>, options: &ParseOptions) -> SSDPResult<SSDPMessage> {
     let status_code = parts.code.expect("filled by httparse");
 
     validate_http_version(parts.version)?;
-    validate_response_code(status_code)?;
-    let headers = validate_http_headers(&parts.headers)?;
+    if !options.lenient {
+        validate_response_code(status_code)?;
+    }
+    let headers = validate_http_headers(parts.headers)?;
 
     Ok(SSDPMessage {
         method: MessageType::Response,
-        headers: headers,
+        headers,
+        status_code,
+        header_names: Vec::new(),
     })
 }
 
@@ -289,7 +747,7 @@ fn message_from_response(parts: Response<'_, '_>) -> SSDPResult<SSDPMessage> {
 /// Assumes that we parsed the request as HTTP1 in the first place.
 fn validate_http_version(minor: Option<u8>) -> SSDPResult<()> {
     if minor != Some(1) {
-        Err(SSDPError::InvalidHttpVersion.into())
+        Err(SSDPError::InvalidHttpVersion)
     } else {
         Ok(())
     }
@@ -300,10 +758,13 @@ fn validate_http_headers(headers: &[httparse::Header<'_>]) -> SSDPResult<HeaderM
     let mut map = HeaderMap::new();
 
     for hdr in headers {
-        let key = headers::HeaderName::from_bytes(hdr.name.as_bytes())
-            .map_err(|_| SSDPError::InvalidHeader(Cow::Owned(hdr.name.to_string())))?;
-        let value = headers::HeaderValue::from_bytes(hdr.value)
-            .map_err(|_| SSDPError::InvalidHeader(Cow::Owned(hdr.name.to_string())))?;
+        let invalid = || SSDPError::InvalidHeader {
+            name: hdr.name.to_string(),
+            value: hdr.value.to_vec(),
+        };
+
+        let key = headers::HeaderName::from_bytes(hdr.name.as_bytes()).map_err(|_| invalid())?;
+        let value = headers::HeaderValue::from_bytes(hdr.value).map_err(|_| invalid())?;
         map.insert(key, value);
     }
 
@@ -313,106 +774,94 @@ fn validate_http_headers(headers: &[httparse::Header<'_>]) -> SSDPResult<HeaderM
 /// Validate the response code for an SSDP message.
 fn validate_response_code(code: u16) -> SSDPResult<()> {
     if code != VALID_RESPONSE_CODE {
-        Err(SSDPError::ResponseCode(code).into())
+        Err(SSDPError::ResponseCode(code))
     } else {
         Ok(())
     }
 }
 
 #[cfg(test)]
-mod mocks {
-    use std::cell::RefCell;
-    use std::io::{self, ErrorKind, Read, Write};
-    use std::net::SocketAddr;
-    use std::sync::mpsc::{self, Receiver, Sender};
+mod tests {
+    mod send {
+        use std::net::UdpSocket;
+        use std::time::Duration;
 
-    use crate::net::{NetworkConnector, NetworkStream};
+        use headers::{HeaderName, HeaderValue};
 
-    pub struct MockConnector {
-        pub receivers: RefCell<Vec<Receiver<Vec<u8>>>>,
-    }
+        use super::super::SSDPMessage;
+        use crate::message::MessageType;
+        use crate::testing::MockConnector;
+        use crate::SSDPError;
 
-    impl MockConnector {
-        pub fn new() -> MockConnector {
-            MockConnector {
-                receivers: RefCell::new(Vec::new()),
+        fn join_buffers(connector: &MockConnector) -> Vec<u8> {
+            let mut buffer = Vec::new();
+
+            for (_, datagram) in connector.sent() {
+                buffer.extend(&datagram[..])
             }
-        }
-    }
 
-    impl NetworkConnector for MockConnector {
-        type Stream = MockStream;
+            buffer
+        }
 
-        fn connect(&self, _: &str, _: u16) -> io::Result<Self::Stream> {
-            let (send, recv) = mpsc::channel();
+        #[test]
+        fn positive_send_to_socket() {
+            let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+            receiver.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
 
-            self.receivers.borrow_mut().push(recv);
+            let message = SSDPMessage::new(MessageType::Notify);
+            message.send_to(&sender, receiver.local_addr().unwrap()).unwrap();
 
-            Ok(MockStream { sender: send })
+            let mut buffer = [0; 1500];
+            let (len, src) = receiver.recv_from(&mut buffer).unwrap();
+            assert_eq!(src, sender.local_addr().unwrap());
+            assert_eq!(&buffer[..len], message.to_bytes(receiver.local_addr().ok()).unwrap());
         }
-    }
 
-    pub struct MockStream {
-        sender: Sender<Vec<u8>>,
-    }
+        #[test]
+        fn negative_send_to_other_family() {
+            let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
 
-    impl NetworkStream for MockStream {
-        fn peer_addr(&mut self) -> io::Result<SocketAddr> {
-            Err(io::Error::new(ErrorKind::AddrNotAvailable, ""))
-        }
-    }
+            let message = SSDPMessage::new(MessageType::Notify);
+            let result = message.send_to(&sender, "[::1]:1900".parse().unwrap());
 
-    impl Read for MockStream {
-        fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
-            Err(io::Error::new(ErrorKind::ConnectionAborted, ""))
+            assert!(matches!(result, Err(SSDPError::IoAt { op: "send to", .. })));
         }
-    }
-
-    impl Write for MockStream {
-        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-            // Hyper will generate a request with a /, we need to intercept that.
-            let mut buffer = vec![0u8; buf.len()];
 
-            let mut found = false;
-            for (src, dst) in buf.iter().zip(buffer.iter_mut()) {
-                if *src == b'/' && !found && buf[0] != b'H' {
-                    *dst = b'*';
-                    found = true;
-                } else {
-                    *dst = *src;
-                }
-            }
+        #[test]
+        #[cfg(feature = "tracing")]
+        fn positive_send_span() {
+            let message = SSDPMessage::new(MessageType::Search);
+            let mut connector = MockConnector::new();
 
-            self.sender.send(buffer).unwrap();
+            let spans = crate::testing::capture_spans(|| {
+                message.send(&mut connector, ("127.0.0.1", 1900)).unwrap();
+            });
 
-            Ok(buf.len())
+            assert!(spans.contains("ssdp_send{message_type=Search peer=127.0.0.1:1900}"), "{spans}");
         }
 
-        fn flush(&mut self) -> io::Result<()> {
-            Ok(())
-        }
-    }
-}
+        #[test]
+        fn positive_multicast_search_man() {
+            let message = SSDPMessage::new(MessageType::Search);
+            let mut connector = MockConnector::new();
 
-#[cfg(test)]
-mod tests {
-    mod send {
-        use std::sync::mpsc::Receiver;
+            message.send(&mut connector, ("239.255.255.250", 1900)).unwrap();
 
-        use super::super::mocks::MockConnector;
-        use super::super::SSDPMessage;
-        use crate::message::MessageType;
+            let sent_message = String::from_utf8(join_buffers(&connector)).unwrap();
+            let head = "M-SEARCH * HTTP/1.1\r\nHOST: 239.255.255.250:1900\r\nMAN: \"ssdp:discover\"\r\n";
+            assert_eq!(sent_message, format!("{head}\r\n"));
+        }
 
-        fn join_buffers(recv_list: &[Receiver<Vec<u8>>]) -> Vec<u8> {
-            let mut buffer = Vec::new();
+        #[test]
+        fn negative_unicast_search_man() {
+            let message = SSDPMessage::new(MessageType::Search);
+            let mut connector = MockConnector::new();
 
-            for recv in recv_list {
-                for recv_buf in recv {
-                    buffer.extend(&recv_buf[..])
-                }
-            }
+            message.send(&mut connector, ("127.0.0.1", 1900)).unwrap();
 
-            buffer
+            let sent_message = String::from_utf8(join_buffers(&connector)).unwrap();
+            assert!(!sent_message.contains("MAN:"), "{sent_message}");
         }
 
         #[test]
@@ -422,7 +871,7 @@ mod tests {
 
             message.send(&mut connector, ("127.0.0.1", 0)).unwrap();
 
-            let sent_message = String::from_utf8(join_buffers(&*connector.receivers.borrow())).unwrap();
+            let sent_message = String::from_utf8(join_buffers(&connector)).unwrap();
 
             assert_eq!(&sent_message[..19], "M-SEARCH * HTTP/1.1");
         }
@@ -434,7 +883,7 @@ mod tests {
 
             message.send(&mut connector, ("127.0.0.1", 0)).unwrap();
 
-            let sent_message = String::from_utf8(join_buffers(&*connector.receivers.borrow())).unwrap();
+            let sent_message = String::from_utf8(join_buffers(&connector)).unwrap();
 
             assert_eq!(&sent_message[..17], "NOTIFY * HTTP/1.1");
         }
@@ -446,7 +895,7 @@ mod tests {
 
             message.send(&mut connector, ("127.0.0.1", 0)).unwrap();
 
-            let sent_message = String::from_utf8(join_buffers(&*connector.receivers.borrow())).unwrap();
+            let sent_message = String::from_utf8(join_buffers(&connector)).unwrap();
 
             assert_eq!(&sent_message[..15], "HTTP/1.1 200 OK");
         }
@@ -458,15 +907,130 @@ mod tests {
 
             message.send(&mut connector, ("127.0.0.1", 0)).unwrap();
 
-            let sent_message = String::from_utf8(join_buffers(&*connector.receivers.borrow())).unwrap();
+            let sent_message = String::from_utf8(join_buffers(&connector)).unwrap();
 
-            assert!(sent_message.contains("Host: 127.0.0.1:0"));
+            assert!(sent_message.contains("HOST: 127.0.0.1:0"));
+        }
+
+        #[test]
+        fn negative_packet_too_large() {
+            let mut message = SSDPMessage::new(MessageType::Notify);
+            let value = HeaderValue::from_str(&"a".repeat(2000)).unwrap();
+            message.headers.insert(HeaderName::from_static("x-vendor"), value);
+            let mut connector = MockConnector::new();
+
+            let result = message.send(&mut connector, ("127.0.0.1", 0));
+
+            assert!(matches!(result, Err(SSDPError::PacketTooLarge { limit: Some(1500), .. })));
+            assert!(join_buffers(&connector).is_empty());
         }
     }
 
     mod parse {
+        use std::time::Duration;
+
+        use headers::{CacheControl, HeaderMapExt as _};
+
         use super::super::SSDPMessage;
-        use crate::receiver::FromRawSSDP;
+        use crate::header::{MX, NTS};
+        use crate::receiver::{FromRawSSDP, ParseOptions};
+        use crate::SSDPError;
+
+        fn with_headers(count: usize) -> String {
+            let mut raw_message = String::from("NOTIFY * HTTP/1.1\r\nHOST: 192.168.1.1\r\n");
+            for header in 1..count {
+                raw_message.push_str(&format!("X-{header}: {header}\r\n"));
+            }
+
+            raw_message + "\r\n"
+        }
+
+        #[test]
+        fn positive_fallback_headers() {
+            SSDPMessage::from_packet(with_headers(33).as_bytes()).unwrap();
+            SSDPMessage::from_packet(with_headers(128).as_bytes()).unwrap();
+        }
+
+        #[test]
+        fn negative_too_many_headers() {
+            let result = SSDPMessage::from_packet(with_headers(200).as_bytes());
+
+            assert!(matches!(result, Err(SSDPError::InvalidHttp(httparse::Error::TooManyHeaders))));
+        }
+
+        #[test]
+        fn positive_normalize_head() {
+            let raw_message = b"NOTIFY * HTTP/1.1\nHOST : 192.168.1.1\nSERVER: a : b\n\nbody\n";
+            let normalized = super::super::normalize_head(raw_message);
+
+            assert_eq!(normalized, b"NOTIFY * HTTP/1.1\r\nHOST: 192.168.1.1\r\nSERVER: a : b\r\n\r\nbody\n");
+        }
+
+        #[test]
+        fn negative_error_response() {
+            let raw_message = "HTTP/1.1 404 Not Found\r\nST: upnp:rootdevice\r\nEXT:\r\n\r\n";
+            let result = SSDPMessage::from_packet(raw_message.as_bytes());
+
+            assert!(matches!(result, Err(SSDPError::ResponseCode(404))));
+        }
+
+        #[test]
+        fn positive_lenient_error_response() {
+            let raw_message = "HTTP/1.1 404 Not Found\r\nST: upnp:rootdevice\r\nEXT:\r\n\r\n";
+            let options = ParseOptions::new().set_lenient(true);
+            let message = SSDPMessage::from_packet_with_options(raw_message.as_bytes(), &options).unwrap();
+
+            assert_eq!(message.status_code(), 404);
+            assert_eq!(message.headers().get("st").unwrap(), "upnp:rootdevice");
+        }
+
+        #[test]
+        fn positive_padded_values() {
+            let raw_message = b"M-SEARCH * HTTP/1.1\r\nHOST: 239.255.255.250:1900\r\n\
+                                MAN: \"ssdp:discover\"\r\nMX: 3 \r\nST: ssdp:all\r\n\r\n";
+            let message = SSDPMessage::from_packet(raw_message).unwrap();
+
+            assert_eq!(message.headers().typed_get::<MX>(), Some(MX(3)));
+        }
+
+        #[test]
+        fn positive_quoted_nts() {
+            let raw_message = b"NOTIFY * HTTP/1.1\r\nHOST: 239.255.255.250:1900\r\n\
+                                NT: upnp:rootdevice\r\nNTS: \"ssdp:alive\"\r\n\r\n";
+            let message = SSDPMessage::from_packet(raw_message).unwrap();
+
+            assert_eq!(message.headers().typed_get::<NTS>(), Some(NTS::Alive));
+        }
+
+        #[test]
+        fn positive_lenient_cache_control() {
+            let raw_message = b"HTTP/1.1 200 OK\r\nCACHE-CONTROL: max-age = 1800\r\nEXT:\r\n\
+                                ST: upnp:rootdevice\r\n\r\n";
+            let options = ParseOptions::new().set_lenient(true);
+            let message = SSDPMessage::from_packet_with_options(raw_message, &options).unwrap();
+
+            let cache_control = message.headers().typed_get::<CacheControl>().unwrap();
+            assert_eq!(cache_control.max_age(), Some(Duration::from_secs(1800)));
+        }
+
+        #[test]
+        fn negative_strict_cache_control() {
+            let raw_message = b"HTTP/1.1 200 OK\r\nCACHE-CONTROL: max-age = 1800\r\nEXT:\r\n\
+                                ST: upnp:rootdevice\r\n\r\n";
+            let message = SSDPMessage::from_packet(raw_message).unwrap();
+
+            let cache_control = message.headers().typed_get::<CacheControl>().unwrap();
+            assert_eq!(cache_control.max_age(), None);
+        }
+
+        #[test]
+        fn positive_max_headers_option() {
+            let options = ParseOptions::new().set_max_headers(200);
+            SSDPMessage::from_packet_with_options(with_headers(200).as_bytes(), &options).unwrap();
+
+            let options = ParseOptions::new().set_max_headers(8);
+            assert!(SSDPMessage::from_packet_with_options(with_headers(9).as_bytes(), &options).is_err());
+        }
 
         #[test]
         fn positive_valid_http() {
@@ -508,4 +1072,513 @@ mod tests {
             SSDPMessage::from_packet(raw_message.as_bytes()).unwrap();
         }
     }
+
+    mod host {
+        use std::net::{IpAddr, Ipv4Addr};
+
+        use super::super::{HostMismatch, SSDPMessage};
+        use crate::message::{Config, Ipv6Scope};
+        use crate::receiver::FromRawSSDP;
+
+        fn notify(host: &str) -> SSDPMessage {
+            let packet = format!("NOTIFY * HTTP/1.1\r\nHOST: {}\r\nNTS: ssdp:alive\r\n\r\n", host);
+            SSDPMessage::from_packet(packet.as_bytes()).unwrap()
+        }
+
+        #[test]
+        fn positive_group() {
+            let config = Config::new();
+
+            assert_eq!(notify("239.255.255.250:1900").validate_host(&config), Ok(()));
+            // The default port of the HOST
+            assert_eq!(notify("239.255.255.250").validate_host(&config), Ok(()));
+            assert_eq!(notify("[FF02::C]:1900").validate_host(&config), Ok(()));
+            assert_eq!(notify("[ff02::c]").validate_host(&config), Ok(()));
+
+            let config = config.set_ipv6_scope(Ipv6Scope::SiteLocal).set_port(19_000);
+            assert_eq!(notify("[ff05::c]:19000").validate_host(&config), Ok(()));
+        }
+
+        #[test]
+        fn negative_other_host() {
+            let config = Config::new();
+            let sender = IpAddr::V4(Ipv4Addr::new(192, 168, 1, 20));
+
+            assert_eq!(
+                notify("192.168.1.20:1900").validate_host(&config),
+                Err(HostMismatch::Other(sender, 1900))
+            );
+            assert_eq!(
+                notify("239.255.255.250:1901").validate_host(&config),
+                Err(HostMismatch::Other(IpAddr::V4(Ipv4Addr::new(239, 255, 255, 250)), 1901))
+            );
+            assert_eq!(
+                notify("[ff05::c]:1900").validate_host(&config),
+                Err(HostMismatch::Other("ff05::c".parse().unwrap(), 1900))
+            );
+            assert_eq!(
+                notify("239.255.255.250").validate_host(&config.set_port(19_000)),
+                Err(HostMismatch::Other(IpAddr::V4(Ipv4Addr::new(239, 255, 255, 250)), 1900))
+            );
+        }
+
+        #[test]
+        fn negative_invalid_host() {
+            let config = Config::new();
+
+            assert_eq!(
+                notify("ssdp.local:1900").validate_host(&config),
+                Err(HostMismatch::Invalid("ssdp.local:1900".into()))
+            );
+            assert_eq!(
+                SSDPMessage::new(crate::message::MessageType::Response).validate_host(&config),
+                Err(HostMismatch::Missing)
+            );
+        }
+    }
+
+    mod version {
+        use super::super::SSDPMessage;
+        use crate::message::{NotifyMessage, SearchResponse, UpnpVersion};
+        use crate::receiver::FromRawSSDP;
+
+        fn response(head: &str) -> SearchResponse {
+            let packet = format!("HTTP/1.1 200 OK\r\nST: upnp:rootdevice\r\n{}\r\n", head);
+            SearchResponse::from_packet(packet.as_bytes()).unwrap()
+        }
+
+        #[test]
+        fn positive_sonos_v10() {
+            let response = response("SERVER: Linux UPnP/1.0 Sonos/70.3-35220 (ZPS9)\r\n");
+
+            assert_eq!(response.upnp_version(), UpnpVersion::V10);
+        }
+
+        #[test]
+        fn positive_bootid_v11() {
+            let raw_message = "NOTIFY * HTTP/1.1\r\nHOST: 239.255.255.250:1900\r\nNTS: ssdp:byebye\r\n\
+                               BOOTID.UPNP.ORG: 7\r\n\r\n";
+            let notify = NotifyMessage::from_packet(raw_message.as_bytes()).unwrap();
+
+            assert_eq!(notify.upnp_version(), UpnpVersion::V11);
+        }
+
+        #[test]
+        fn positive_bootid_overrides_v10() {
+            let response = response("SERVER: Linux UPnP/1.0 product/1\r\nCONFIGID.UPNP.ORG: 1\r\n");
+
+            assert_eq!(response.upnp_version(), UpnpVersion::V11);
+        }
+
+        #[test]
+        fn positive_v20_and_later_minor() {
+            assert_eq!(response("SERVER: OS/1 upnp/2.0 product/1\r\n").upnp_version(), UpnpVersion::V20);
+            assert_eq!(response("SERVER: OS/1 UPnP/1.2 product/1\r\n").upnp_version(), UpnpVersion::V11);
+        }
+
+        #[test]
+        fn positive_search_user_agent() {
+            let raw_message = "M-SEARCH * HTTP/1.1\r\nHOST: 239.255.255.250:1900\r\n\
+                               USER-AGENT: Windows/10 UPnP/1.1 client/1\r\n\r\n";
+            let request = SSDPMessage::from_packet(raw_message.as_bytes()).unwrap();
+
+            assert_eq!(request.upnp_version(), UpnpVersion::V11);
+        }
+
+        #[test]
+        fn negative_no_server() {
+            assert_eq!(response("").upnp_version(), UpnpVersion::Unknown);
+            assert_eq!(response("SERVER: Linux/5.10 product/1\r\n").upnp_version(), UpnpVersion::Unknown);
+            assert_eq!(response("SERVER: Linux UPnP/x.y\r\n").upnp_version(), UpnpVersion::Unknown);
+        }
+    }
+
+    mod serialize {
+        use std::net::SocketAddr;
+        use std::time::Duration;
+
+        use headers::{CacheControl, ContentLength, Header as _, HeaderValue, Host, Location, UserAgent};
+        use http::uri::Authority;
+
+        use super::super::SSDPMessage;
+        use crate::header::{BootID, ConfigID, HeaderMut, Man, SearchPort, MX, NT, NTS, ST, USN};
+        use crate::message::{HeaderCase, MessageType, NotifyMessage, SearchRequest, SearchResponse};
+        use crate::net::httpu::{HeaderOrder, Style};
+        use crate::receiver::FromRawSSDP;
+        use crate::FieldMap;
+
+        /// The expected canonical serialization of `scrambled_alive`, escaped on a single line.
+        const CANONICAL_ALIVE: &str = include_str!("fixtures/canonical_alive.txt");
+        /// The expected canonical serialization of `scrambled_search`.
+        const CANONICAL_SEARCH: &str = include_str!("fixtures/canonical_search.txt");
+
+        fn multicast_host() -> Host {
+            Host::from(Authority::from_static("239.255.255.250:1900"))
+        }
+
+        fn usn() -> USN {
+            USN(FieldMap::UUID("2fac1234-31f8-11b4-a222-08002b34c003".into()), None)
+        }
+
+        fn search_request() -> SearchRequest {
+            let mut request = SearchRequest::new();
+            request.set(Man);
+            request.set(MX(2));
+            request.set(ST::All);
+            request
+        }
+
+        fn multicast_group() -> SocketAddr {
+            SocketAddr::from(([239, 255, 255, 250], 1900))
+        }
+
+        fn canonical(message: &SSDPMessage) -> String {
+            let packet = message.to_packet(multicast_group(), Style {
+                order: HeaderOrder::Canonical,
+                ..Style::default()
+            }).unwrap();
+            packet.as_slice().escape_ascii().to_string()
+        }
+
+        /// An alive message with its headers set in an order unlike the canonical one.
+        fn scrambled_alive() -> SSDPMessage {
+            let mut message = SSDPMessage::new(MessageType::Notify);
+            message.headers.append("x-vendor", HeaderValue::from_static("a"));
+            message.set(SearchPort(49152));
+            let uuid = FieldMap::uuid("2fac1234-31f8-11b4-a222-08002b34c003");
+            message.set(USN(uuid, Some(FieldMap::upnp("rootdevice"))));
+            let opt = HeaderValue::from_static("\"http://schemas.upnp.org/upnp/1/0/\"; ns=01");
+            message.headers.insert("opt", opt);
+            message.set(ConfigID(1));
+            message.set(NTS::Alive);
+            message.set("Linux/5.10 UPnP/1.1 ssdp/0.7".parse::<headers::Server>().unwrap());
+            message.headers.insert("01-nls", HeaderValue::from_static("1"));
+            message.set(BootID(1));
+            message.set(NT(FieldMap::upnp("rootdevice")));
+            let location = HeaderValue::from_static("http://192.168.1.2:8080/description.xml");
+            message.set(Location::decode(&mut [location].iter()).unwrap());
+            message.set(CacheControl::new().with_max_age(Duration::from_secs(1800)));
+            message.headers.append("x-vendor", HeaderValue::from_static("b"));
+            message
+        }
+
+        /// A search without a MAN header, with its headers set in reverse order.
+        fn scrambled_search() -> SSDPMessage {
+            let mut message = SSDPMessage::new(MessageType::Search);
+            message.set("Linux/5.10 UPnP/1.1 ssdp/0.7".parse::<UserAgent>().unwrap());
+            message.set(ST::All);
+            message.set(MX(2));
+            message.headers.insert("cpfn.upnp.org", HeaderValue::from_static("Living Room"));
+            message
+        }
+
+        #[test]
+        fn positive_canonical_alive() {
+            assert_eq!(canonical(&scrambled_alive()), CANONICAL_ALIVE.trim_end());
+        }
+
+        #[test]
+        fn positive_canonical_search() {
+            assert_eq!(canonical(&scrambled_search()), CANONICAL_SEARCH.trim_end());
+        }
+
+        fn cased(message: &SSDPMessage, case: HeaderCase) -> String {
+            let style = Style {
+                case,
+                ..Style::default()
+            };
+            let packet = message.to_packet(multicast_group(), style).unwrap();
+            String::from_utf8(packet.as_slice().to_vec()).unwrap()
+        }
+
+        fn cache_control() -> CacheControl {
+            CacheControl::new().with_max_age(Duration::from_secs(1800))
+        }
+
+        #[test]
+        fn positive_request_case() {
+            let mut message = SSDPMessage::new(MessageType::Search);
+            message.set(cache_control());
+            message.set(ST::All);
+
+            let expected = [
+                (HeaderCase::Upper, "HOST", "MAN", "CACHE-CONTROL", "ST"),
+                (HeaderCase::Canonical, "Host", "Man", "Cache-Control", "St"),
+            ];
+
+            for (case, host, man, cache_control, st) in expected {
+                assert_eq!(
+                    cased(&message, case),
+                    format!(
+                        "M-SEARCH * HTTP/1.1\r\n{}: 239.255.255.250:1900\r\n{}: \"ssdp:discover\"\r\n\
+                         {}: max-age=1800\r\n{}: ssdp:all\r\n\r\n",
+                        host, man, cache_control, st
+                    ),
+                );
+            }
+        }
+
+        #[test]
+        fn positive_response_case() {
+            let mut message = SSDPMessage::new(MessageType::Response);
+            message.set(cache_control());
+            message.headers.insert("bootid.upnp.org", HeaderValue::from_static("1"));
+            message.headers.insert("01-nls", HeaderValue::from_static("1"));
+
+            let expected = [
+                (HeaderCase::Upper, "CACHE-CONTROL", "BOOTID.UPNP.ORG", "01-NLS", "CONTENT-LENGTH"),
+                (HeaderCase::Canonical, "Cache-Control", "BOOTID.UPNP.ORG", "01-NLS", "Content-Length"),
+            ];
+
+            for (case, cache_control, bootid, nls, content_length) in expected {
+                assert_eq!(
+                    cased(&message, case),
+                    format!(
+                        "HTTP/1.1 200 OK\r\n{}: max-age=1800\r\n{}: 1\r\n{}: 1\r\n{}: 0\r\n\r\n",
+                        cache_control, bootid, nls, content_length
+                    ),
+                );
+            }
+        }
+
+        #[test]
+        fn positive_request_as_set_case() {
+            let mut message = SSDPMessage::new(MessageType::Notify);
+            message.set_raw_cased("Cache-Control", HeaderValue::from_static("max-age=1800")).unwrap();
+            message.set_raw_cased("nT", HeaderValue::from_static("upnp:rootdevice")).unwrap();
+            message.set(NTS::Alive);
+
+            assert_eq!(
+                cased(&message, HeaderCase::AsSet),
+                "NOTIFY * HTTP/1.1\r\nHOST: 239.255.255.250:1900\r\nCache-Control: max-age=1800\r\n\
+                 nT: upnp:rootdevice\r\nNTS: ssdp:alive\r\n\r\n",
+            );
+            // Other cases ignore the spelling.
+            assert!(cased(&message, HeaderCase::Upper).contains("CACHE-CONTROL: max-age=1800"));
+        }
+
+        #[test]
+        fn positive_response_as_set_case() {
+            let mut message = SSDPMessage::new(MessageType::Response);
+            message.set_raw_cased("cache-control", HeaderValue::from_static("max-age=60")).unwrap();
+            // The latest spelling of a name is kept.
+            message.set_raw_cased("Cache-Control", HeaderValue::from_static("max-age=1800")).unwrap();
+            message.set_raw_cased("Content-length", HeaderValue::from_static("5")).unwrap();
+            message.headers.insert("bootid.upnp.org", HeaderValue::from_static("1"));
+
+            assert_eq!(
+                cased(&message, HeaderCase::AsSet),
+                "HTTP/1.1 200 OK\r\nCache-Control: max-age=1800\r\nBOOTID.UPNP.ORG: 1\r\n\
+                 Content-length: 0\r\n\r\n",
+            );
+        }
+
+        #[test]
+        fn negative_set_raw_cased_invalid_name() {
+            let mut message = SSDPMessage::new(MessageType::Notify);
+
+            let result = message.set_raw_cased("Cache Control", HeaderValue::from_static("max-age=1800"));
+            assert!(matches!(result, Err(crate::SSDPError::InvalidHeader { .. })));
+            assert!(message.headers().is_empty());
+        }
+
+        #[test]
+        fn positive_map_order_by_default() {
+            let bytes = scrambled_search().to_bytes(None).unwrap();
+            let text = String::from_utf8(bytes).unwrap();
+
+            assert!(text.contains("\r\nUSER-AGENT: Linux/5.10 UPnP/1.1 ssdp/0.7\r\nST: ssdp:all\r\n"));
+        }
+
+        #[test]
+        fn positive_search_request_roundtrip() {
+            let mut request = search_request();
+            request.set(multicast_host());
+
+            let bytes = request.to_bytes(None).unwrap();
+
+            assert_eq!(SearchRequest::from_packet(&bytes).unwrap(), request);
+        }
+
+        #[test]
+        fn positive_notify_roundtrip() {
+            let mut notify = NotifyMessage::new();
+            notify.set(multicast_host());
+            notify.set(NT(FieldMap::UPnP("rootdevice".into())));
+            notify.set(NTS::Alive);
+            notify.set(usn());
+
+            let bytes = notify.to_bytes(None).unwrap();
+
+            assert_eq!(NotifyMessage::from_packet(&bytes).unwrap(), notify);
+        }
+
+        #[test]
+        fn positive_search_response_roundtrip() {
+            let mut response = SearchResponse::new();
+            response.set(ContentLength(0));
+            response.set(ST::All);
+            response.set(usn());
+            let location = HeaderValue::from_static("http://192.168.1.2/");
+            response.set(Location::decode(&mut [location].iter()).unwrap());
+
+            let bytes = response.to_bytes(None).unwrap();
+
+            assert_eq!(SearchResponse::from_packet(&bytes).unwrap(), response);
+        }
+
+        #[test]
+        fn positive_destination_host() {
+            let mut request = search_request();
+            request.set(multicast_host());
+
+            let dst: SocketAddr = "[fe80::1%2]:1900".parse().unwrap();
+            let bytes = request.to_bytes(Some(dst)).unwrap();
+            let text = String::from_utf8(bytes).unwrap();
+
+            assert!(text.starts_with("M-SEARCH * HTTP/1.1\r\nHOST: [fe80::1]:1900\r\n"));
+            assert!(!text.contains("239.255.255.250"));
+        }
+
+        #[test]
+        fn positive_default_host() {
+            let bytes = search_request().to_bytes(None).unwrap();
+            let text = String::from_utf8(bytes).unwrap();
+
+            assert!(text.starts_with("M-SEARCH * HTTP/1.1\r\nHOST: 239.255.255.250:1900\r\n"));
+        }
+
+        #[test]
+        fn positive_eq_ignores_order() {
+            let mut first = SSDPMessage::new(MessageType::Notify);
+            let mut second = SSDPMessage::new(MessageType::Notify);
+
+            for (message, values) in [(&mut first, ["a", "b"]), (&mut second, ["b", "a"])] {
+                message.headers.insert("nt", HeaderValue::from_static("upnp:rootdevice"));
+                for value in values {
+                    message.headers.append("x-value", HeaderValue::from_static(value));
+                }
+            }
+            assert_eq!(first, second);
+
+            second.headers.append("x-value", HeaderValue::from_static("a"));
+            assert_ne!(first, second);
+            assert_ne!(first, SSDPMessage::new(MessageType::Search));
+        }
+
+        #[test]
+        fn positive_display() {
+            let request = search_request();
+
+            let bytes = request.to_bytes(None).unwrap();
+            assert_eq!(request.to_string().as_bytes(), &bytes[..]);
+        }
+
+        #[test]
+        fn negative_too_large() {
+            let mut notify = NotifyMessage::new();
+            notify.set(NT(FieldMap::UPnP("x".repeat(2000))));
+
+            assert!(notify.to_bytes(None).is_err());
+            assert!(notify.to_string().contains(&"x".repeat(2000)));
+        }
+    }
+
+    mod convert {
+        use headers::{Header as _, HeaderValue, Host};
+
+        use super::super::SSDPMessage;
+        use crate::message::{MessageType, NotifyMessage, SearchRequest, SearchResponse};
+        use crate::receiver::FromRawSSDP;
+        use crate::SSDPError;
+
+        fn request(method: &str, uri: &str) -> http::Request<()> {
+            let mut request = http::Request::builder().method(method).uri(uri).body(()).unwrap();
+            request.headers_mut().insert(Host::name(), HeaderValue::from_static("239.255.255.250:1900"));
+            request
+        }
+
+        #[test]
+        fn positive_request_roundtrip() {
+            let raw_message = "M-SEARCH * HTTP/1.1\r\nHOST: 239.255.255.250:1900\r\nST: ssdp:all\r\n\r\n";
+            let search = SearchRequest::from_packet(raw_message.as_bytes()).unwrap();
+
+            let request = http::Request::from(&search);
+            assert_eq!(request.method(), "M-SEARCH");
+            assert_eq!(request.uri(), "*");
+            assert_eq!(request.version(), http::Version::HTTP_11);
+            assert_eq!(request.headers()["st"], "ssdp:all");
+
+            assert_eq!(SearchRequest::try_from(request).unwrap(), search);
+        }
+
+        #[test]
+        fn positive_notify_request() {
+            let notify = NotifyMessage::try_from(request("NOTIFY", "*")).unwrap();
+
+            assert_eq!(http::Request::from(&notify).method(), "NOTIFY");
+        }
+
+        #[test]
+        fn positive_response_roundtrip() {
+            let raw_message = "HTTP/1.1 200 OK\r\nST: ssdp:all\r\n\r\n";
+            let search = SearchResponse::from_packet(raw_message.as_bytes()).unwrap();
+
+            let response = http::Response::try_from(&search).unwrap();
+            assert_eq!(response.status(), http::StatusCode::OK);
+
+            assert_eq!(SearchResponse::try_from(response).unwrap(), search);
+        }
+
+        #[test]
+        fn negative_message_kind() {
+            let response = SSDPMessage::new(MessageType::Response);
+            let notify = SSDPMessage::new(MessageType::Notify);
+
+            assert!(http::Request::try_from(&response).is_err());
+            assert!(http::Response::try_from(&notify).is_err());
+            assert!(SearchRequest::try_from(request("NOTIFY", "*")).is_err());
+        }
+
+        #[test]
+        fn negative_request_version() {
+            let mut request = request("NOTIFY", "*");
+            *request.version_mut() = http::Version::HTTP_10;
+
+            let result = SSDPMessage::try_from(request);
+            assert!(matches!(result, Err(SSDPError::InvalidHttpVersion)));
+        }
+
+        #[test]
+        fn negative_request_uri() {
+            let result = SSDPMessage::try_from(request("NOTIFY", "/"));
+
+            assert!(matches!(result, Err(SSDPError::InvalidUri(_))));
+        }
+
+        #[test]
+        fn negative_request_method() {
+            let result = SSDPMessage::try_from(request("GET", "*"));
+
+            assert!(matches!(result, Err(SSDPError::InvalidMethod(_))));
+        }
+
+        #[test]
+        fn negative_request_missing_host() {
+            let mut request = request("NOTIFY", "*");
+            request.headers_mut().clear();
+
+            let result = SSDPMessage::try_from(request);
+            assert!(matches!(result, Err(SSDPError::MissingHeader("host"))));
+        }
+
+        #[test]
+        fn negative_response_status() {
+            let mut response = http::Response::new(());
+            *response.status_mut() = http::StatusCode::NOT_FOUND;
+
+            let result = SSDPMessage::try_from(response);
+            assert!(matches!(result, Err(SSDPError::ResponseCode(404))));
+        }
+    }
 }
@@ -0,0 +1,280 @@
+//! Decides how long to wait for responses to a search.
+//!
+//! Every send path that waits for responses consults `effective_timeouts`, such that there is a
+//! single place that describes which rule applies.
+
+use std::fmt::Write as _;
+use std::time::Duration;
+
+use headers::{Header, HeaderMapExt as _};
+
+use crate::header::MX;
+use crate::message::ssdp::SSDPMessage;
+use crate::message::Config;
+
+/// Devices are required to respond within 1 second of receiving unicast message.
+const DEFAULT_UNICAST_WAIT: Duration = Duration::from_secs(1);
+
+/// Enumerates the ways a message can be sent.
+#[derive(Copy, Clone, Hash, Eq, PartialEq, Debug)]
+pub enum SendKind {
+    /// The message is sent to the multicast group.
+    Multicast,
+    /// The message is sent to a single host.
+    Unicast,
+}
+
+/// The timeouts in effect for a message.
+#[derive(Copy, Clone, Hash, Eq, PartialEq, Debug)]
+pub struct Timeouts {
+    /// The MX value that was taken into account, if any.
+    pub mx: Option<MX>,
+    /// How long to wait for responses.
+    ///
+    /// This is `None` if no valid timeout could be determined, which is an error for multicast
+    /// searches as devices would not know how long to delay their response.
+    pub receive: Option<Duration>,
+}
+
+/// Determine the timeouts for sending the message.
+///
+/// The rules, in order:
+/// * A multicast search without a valid MX header takes the `Config::default_mx`, if any.
+/// * A valid MX header of a multicast search is clamped to 5 seconds if `Config::clamp_mx` is set.
+/// * A valid MX header bounds the device response delay, responses are awaited for MX plus the
+///   configured overhead.
+/// * A missing or invalid MX header is an error for multicast.
+/// * A missing or invalid MX header on unicast waits for one second plus the configured
+///   overhead, as devices must respond to unicast searches within a second.
+/// * The `Config::response_window` replaces the timeout derived by the rules above, if any.
+pub(crate) fn effective_timeouts(message: &SSDPMessage, config: &Config, kind: SendKind) -> Timeouts {
+    decide(message, config, kind, &mut |_| {})
+}
+
+/// Like `effective_timeouts` but describe which rules fired.
+pub(crate) fn explain(message: &SSDPMessage, config: &Config, kind: SendKind) -> String {
+    let mut trace = String::new();
+
+    let timeouts = decide(message, config, kind, &mut |rule| {
+        let _ = writeln!(trace, "{}", rule);
+    });
+
+    let _ = write!(trace, "result: {:?}", timeouts);
+    trace
+}
+
+fn decide(message: &SSDPMessage, config: &Config, kind: SendKind, trace: &mut dyn FnMut(&str)) -> Timeouts {
+    let headers = message.headers();
+    let mut mx = headers.typed_get::<MX>();
+
+    if let (SendKind::Multicast, None, Some(default)) = (kind, mx, config.default_mx) {
+        if let Ok(default) = MX::new(default) {
+            trace("multicast without a valid MX uses the default MX");
+            mx = Some(default);
+        }
+    }
+
+    if let (SendKind::Multicast, Some(value), true) = (kind, mx, config.clamp_mx) {
+        if value != value.clamped_upnp11() {
+            trace("multicast clamps MX to the UPnP 1.1 maximum");
+            mx = Some(value.clamped_upnp11());
+        }
+    }
+
+    let mut timeouts = match (kind, mx) {
+        (_, Some(MX(n))) => {
+            trace("valid MX bounds the response delay");
+            Timeouts {
+                mx,
+                receive: Some(Duration::from_secs(n.into()) + config.timeout_overhead),
+            }
+        }
+        (SendKind::Multicast, None) => {
+            if headers.contains_key(MX::name()) {
+                trace("multicast with an invalid MX has no timeout");
+            } else {
+                trace("multicast without MX has no timeout");
+            }
+
+            Timeouts { mx, receive: None }
+        }
+        (SendKind::Unicast, None) => {
+            if headers.contains_key(MX::name()) {
+                trace("unicast ignores an invalid MX");
+            }
+
+            trace("unicast without MX uses the default response delay");
+            Timeouts {
+                mx,
+                receive: Some(DEFAULT_UNICAST_WAIT + config.timeout_overhead),
+            }
+        }
+    };
+
+    if let (Some(_), Some(window)) = (timeouts.receive, config.response_window) {
+        trace("the response window replaces the derived timeout");
+        timeouts.receive = Some(window);
+    }
+
+    timeouts
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{effective_timeouts, explain, SendKind, Timeouts};
+    use crate::header::MX;
+    use crate::message::ssdp::SSDPMessage;
+    use crate::message::Config;
+    use crate::receiver::FromRawSSDP;
+
+    enum Mx {
+        Present,
+        Absent,
+        OutOfRange,
+    }
+
+    fn message(mx: Mx) -> SSDPMessage {
+        let mx = match mx {
+            Mx::Present => "MX: 3\r\n",
+            Mx::Absent => "",
+            Mx::OutOfRange => "MX: 121\r\n",
+        };
+
+        let raw = format!("M-SEARCH * HTTP/1.1\r\nHOST: 239.255.255.250:1900\r\n{}\r\n", mx);
+        SSDPMessage::from_packet(raw.as_bytes()).unwrap()
+    }
+
+    fn timeouts(mx: Mx, kind: SendKind, overhead: Option<u64>) -> Timeouts {
+        let mut config = Config::new();
+        if let Some(overhead) = overhead {
+            config = config.set_timeout_overhead(Duration::from_secs(overhead));
+        }
+
+        effective_timeouts(&message(mx), &config, kind)
+    }
+
+    fn receive(secs: u64) -> Option<Duration> {
+        Some(Duration::from_secs(secs))
+    }
+
+    #[test]
+    fn positive_multicast_mx_present() {
+        assert_eq!(timeouts(Mx::Present, SendKind::Multicast, None).receive, receive(4));
+        assert_eq!(timeouts(Mx::Present, SendKind::Multicast, None).mx, Some(MX(3)));
+        assert_eq!(timeouts(Mx::Present, SendKind::Multicast, Some(5)).receive, receive(8));
+    }
+
+    #[test]
+    fn negative_multicast_mx_absent() {
+        assert_eq!(timeouts(Mx::Absent, SendKind::Multicast, None).receive, None);
+        assert_eq!(timeouts(Mx::Absent, SendKind::Multicast, Some(5)).receive, None);
+    }
+
+    #[test]
+    fn negative_multicast_mx_out_of_range() {
+        assert_eq!(timeouts(Mx::OutOfRange, SendKind::Multicast, None).receive, None);
+        assert_eq!(timeouts(Mx::OutOfRange, SendKind::Multicast, Some(5)).receive, None);
+    }
+
+    #[test]
+    fn positive_multicast_default_mx() {
+        let config = Config::new().set_default_mx(Some(3));
+
+        let absent = effective_timeouts(&message(Mx::Absent), &config, SendKind::Multicast);
+        assert_eq!(absent.mx, Some(MX(3)));
+        assert_eq!(absent.receive, receive(4));
+
+        let out_of_range = effective_timeouts(&message(Mx::OutOfRange), &config, SendKind::Multicast);
+        assert_eq!(out_of_range.receive, receive(4));
+
+        let config = config.set_default_mx(Some(1));
+        let present = effective_timeouts(&message(Mx::Present), &config, SendKind::Multicast);
+        assert_eq!(present.receive, receive(4));
+    }
+
+    #[test]
+    fn negative_default_mx_unicast_or_invalid() {
+        let config = Config::new().set_default_mx(Some(3));
+        let unicast = effective_timeouts(&message(Mx::Absent), &config, SendKind::Unicast);
+        assert_eq!(unicast.mx, None);
+        assert_eq!(unicast.receive, receive(2));
+
+        let config = Config::new().set_default_mx(Some(0));
+        assert_eq!(effective_timeouts(&message(Mx::Absent), &config, SendKind::Multicast).receive, None);
+    }
+
+    #[test]
+    fn positive_response_window() {
+        let config = Config::new().set_response_window(Some(Duration::from_secs(10)));
+
+        let multicast = effective_timeouts(&message(Mx::Present), &config, SendKind::Multicast);
+        assert_eq!(multicast.mx, Some(MX(3)));
+        assert_eq!(multicast.receive, receive(10));
+
+        let unicast = effective_timeouts(&message(Mx::Absent), &config, SendKind::Unicast);
+        assert_eq!(unicast.receive, receive(10));
+    }
+
+    #[test]
+    fn negative_response_window_without_mx() {
+        let config = Config::new().set_response_window(Some(Duration::from_secs(10)));
+
+        assert_eq!(effective_timeouts(&message(Mx::Absent), &config, SendKind::Multicast).receive, None);
+    }
+
+    #[test]
+    fn positive_unicast_mx_present() {
+        assert_eq!(timeouts(Mx::Present, SendKind::Unicast, None).receive, receive(4));
+        assert_eq!(timeouts(Mx::Present, SendKind::Unicast, Some(5)).receive, receive(8));
+    }
+
+    #[test]
+    fn positive_unicast_mx_absent() {
+        assert_eq!(timeouts(Mx::Absent, SendKind::Unicast, None).receive, receive(2));
+        assert_eq!(timeouts(Mx::Absent, SendKind::Unicast, Some(5)).receive, receive(6));
+    }
+
+    #[test]
+    fn positive_unicast_mx_out_of_range() {
+        assert_eq!(timeouts(Mx::OutOfRange, SendKind::Unicast, None).receive, receive(2));
+        assert_eq!(timeouts(Mx::OutOfRange, SendKind::Unicast, None).mx, None);
+        assert_eq!(timeouts(Mx::OutOfRange, SendKind::Unicast, Some(5)).receive, receive(6));
+    }
+
+    #[test]
+    fn positive_clamped_mx() {
+        let config = Config::new().set_clamp_mx(true);
+        let raw = b"M-SEARCH * HTTP/1.1\r\nHOST: 239.255.255.250:1900\r\nMX: 120\r\n\r\n";
+        let search = SSDPMessage::from_packet(raw).unwrap();
+
+        let multicast = effective_timeouts(&search, &config, SendKind::Multicast);
+        assert_eq!(multicast.mx, Some(MX(5)));
+        assert_eq!(multicast.receive, receive(6));
+
+        let present = effective_timeouts(&message(Mx::Present), &config, SendKind::Multicast);
+        assert_eq!(present.receive, receive(4));
+    }
+
+    #[test]
+    fn negative_clamped_mx_unicast_or_disabled() {
+        let config = Config::new().set_clamp_mx(true);
+        let raw = b"M-SEARCH * HTTP/1.1\r\nHOST: 239.255.255.250:1900\r\nMX: 120\r\n\r\n";
+        let search = SSDPMessage::from_packet(raw).unwrap();
+
+        let unicast = effective_timeouts(&search, &config, SendKind::Unicast);
+        assert_eq!(unicast.receive, receive(121));
+
+        let disabled = effective_timeouts(&search, &Config::new(), SendKind::Multicast);
+        assert_eq!(disabled.receive, receive(121));
+    }
+
+    #[test]
+    fn positive_explain_names_rule() {
+        let trace = explain(&message(Mx::OutOfRange), &Config::new(), SendKind::Unicast);
+
+        assert!(trace.contains("unicast ignores an invalid MX"));
+        assert!(trace.contains("result: "));
+    }
+}
@@ -0,0 +1,352 @@
+//! Sockets that are shared by the sends and receives of successive searches.
+
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::context::SsdpContext;
+use crate::error::{SSDPError, SSDPResult};
+use crate::message::multicast;
+use crate::message::ssdp::SSDPMessage;
+use crate::message::{self, Config, LocalConnectorOptions};
+use crate::net::connector::UdpConnector;
+use crate::receiver::{FromRawSSDP, ReceiverOptions, SSDPReceiver};
+
+/// The longest wait for the receiver of a previous search to release the sockets.
+///
+/// A custom spawner might never run the socket threads, so this is bounded like a dropped receiver.
+const RELEASE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The discarded stale packets are only counted, so a truncated read does not matter.
+const DRAIN_BUFFER_SIZE: usize = 512;
+
+/// One socket per interface, used both to send searches and to receive their responses.
+///
+/// The sockets are bound once and reused by every search sent through the set, so responses
+/// always arrive at the local port the search was sent from, and repeated searches neither rebind
+/// sockets nor change their ports. Only the receiver of the latest search reads from the sockets,
+/// the receiver of the previous one stops once the next search is sent.
+///
+/// ```no_run
+/// use ssdp::message::{Config, SearchRequest, TransportSet};
+///
+/// let config = Config::new();
+/// let mut transports = TransportSet::bind(&config).unwrap();
+///
+/// let request = SearchRequest::new();
+///
+/// for _ in 0..3 {
+///     for (_, peer) in request.multicast_on_transports(&mut transports).unwrap() {
+///         println!("{} answered on {:?}", peer, transports.local_addrs());
+///     }
+/// }
+/// ```
+pub struct TransportSet {
+    context: SsdpContext,
+    config: Config,
+    connectors: Vec<UdpConnector>,
+    previous: Option<PreviousReceiver>,
+}
+
+/// The receiver of the previous search, which still reads from the sockets until it is stopped.
+struct PreviousReceiver {
+    stopped: Arc<AtomicBool>,
+    /// Disconnects once all its socket threads exited.
+    released: mpsc::Receiver<()>,
+}
+
+impl TransportSet {
+    /// Bind a socket to every interface the config selects.
+    pub fn bind(config: &Config) -> SSDPResult<TransportSet> {
+        TransportSet::bind_with_context(SsdpContext::global(), config)
+    }
+
+    /// Bind a socket to every interface of the context.
+    pub(crate) fn bind_with_context(context: &SsdpContext, config: &Config) -> SSDPResult<TransportSet> {
+        config.validate()?;
+        let addrs = context.local_interfaces(config)?;
+        let connectors = message::all_local_connectors(addrs, &LocalConnectorOptions::multicast(config))?;
+
+        Ok(TransportSet {
+            context: context.clone(),
+            config: config.clone(),
+            connectors,
+            previous: None,
+        })
+    }
+
+    /// The config the sockets were bound for, which also applies to every search.
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// The local addresses of the sockets, one per interface.
+    pub fn local_addrs(&self) -> Vec<SocketAddr> {
+        self.connectors.iter().filter_map(|conn| conn.local_addr().ok()).collect()
+    }
+
+    /// Destroy the set and return its connectors.
+    pub(crate) fn into_connectors(self) -> Vec<UdpConnector> {
+        self.connectors
+    }
+
+    /// Send the message to the multicast group through every socket.
+    ///
+    /// The receiver of the previous search is stopped first. Returns the sockets the message was
+    /// sent through, on which the responses arrive.
+    pub(crate) fn send(&mut self, message: &SSDPMessage) -> Vec<Arc<UdpSocket>> {
+        self.release_previous();
+
+        let sent = multicast::send_each(&self.context, message, &self.config, &self.connectors);
+        self.connectors
+            .iter()
+            .zip(sent)
+            .filter(|&(_, sent)| sent)
+            .map(|(conn, _)| conn.socket())
+            .collect()
+    }
+
+    /// Send the message to a single host through every socket of its IP version.
+    ///
+    /// Like `send` this stops the receiver of the previous search first. Only fails if none of the
+    /// sockets could send the message, with the error of every socket.
+    pub(crate) fn send_unicast(
+        &mut self,
+        message: &SSDPMessage,
+        dst_addr: SocketAddr,
+    ) -> SSDPResult<Vec<Arc<UdpSocket>>> {
+        self.release_previous();
+
+        let style = self.config.header_style();
+        let mut socks = Vec::new();
+        let mut failures = Vec::new();
+
+        for conn in &mut self.connectors {
+            let Ok(local) = conn.local_addr() else {
+                continue;
+            };
+            if local.is_ipv4() != dst_addr.is_ipv4() {
+                continue;
+            }
+
+            match message.send_styled(conn, dst_addr, style) {
+                Ok(()) => {
+                    self.context.stats().add_message_sent();
+                    socks.push(conn.socket());
+                }
+                Err(err) => {
+                    debug!("Failed to send unicast through {}: {}", local, err);
+                    failures.push((local, err));
+                }
+            }
+        }
+
+        if socks.is_empty() && !failures.is_empty() {
+            return Err(SSDPError::AllSendsFailed(failures));
+        }
+
+        Ok(socks)
+    }
+
+    /// Receive on the sockets until the timeout, until the next search is sent through the set.
+    pub(crate) fn receive<T>(
+        &mut self,
+        socks: Vec<Arc<UdpSocket>>,
+        timeout: Option<Duration>,
+    ) -> SSDPResult<SSDPReceiver<T>>
+    where
+        T: FromRawSSDP + Send + 'static,
+    {
+        let (released_send, released) = mpsc::channel();
+        let options = ReceiverOptions {
+            released: Some(released_send),
+            ..ReceiverOptions::from_config(&self.config)
+        };
+
+        let receiver = SSDPReceiver::with_context(&self.context, socks, timeout, &options)?;
+        self.previous = Some(PreviousReceiver {
+            stopped: receiver.stop_flag(),
+            released,
+        });
+
+        Ok(receiver)
+    }
+
+    /// Stop the receiver of the previous search and make sure no thread reads the sockets.
+    ///
+    /// Once its threads exited the sockets are drained. Threads that did not exit in time keep the
+    /// sockets to themselves, the set binds new ones instead of sharing them, at new ports.
+    fn release_previous(&mut self) {
+        let Some(previous) = self.previous.take() else {
+            return;
+        };
+
+        previous.stopped.store(true, Ordering::Relaxed);
+        let deadline = Instant::now() + RELEASE_TIMEOUT;
+        loop {
+            match previous.released.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                Err(RecvTimeoutError::Disconnected) => break,
+                Ok(()) => {}
+                Err(RecvTimeoutError::Timeout) => {
+                    debug!("Socket threads of the previous search did not exit in time, rebinding");
+                    if let Err(err) = self.rebind() {
+                        debug!("Failed to rebind the sockets of the previous search: {}", err);
+                    }
+                    return;
+                }
+            }
+        }
+
+        self.drain();
+    }
+
+    /// Replace the sockets by newly bound ones.
+    fn rebind(&mut self) -> SSDPResult<()> {
+        let addrs = self.context.local_interfaces(&self.config)?;
+        let options = LocalConnectorOptions::multicast(&self.config);
+        self.connectors = message::all_local_connectors(addrs, &options)?;
+        Ok(())
+    }
+
+    /// Discard the packets that arrived since the previous search stopped receiving.
+    ///
+    /// These are late responses to earlier searches, which would otherwise be mistaken for
+    /// responses to the next one. Toggles the sockets to nonblocking, so no other thread may read
+    /// them meanwhile.
+    fn drain(&self) {
+        let mut buf = [0; DRAIN_BUFFER_SIZE];
+
+        for conn in &self.connectors {
+            let sock = conn.socket();
+            if let Err(err) = sock.set_nonblocking(true) {
+                debug!("Can not drain the socket {:?}: {}", sock.local_addr(), err);
+                continue;
+            }
+
+            let mut stale = 0;
+            while sock.recv_from(&mut buf).is_ok() {
+                stale += 1;
+            }
+
+            if let Err(err) = sock.set_nonblocking(false) {
+                debug!("Can not make the socket {:?} blocking again: {}", sock.local_addr(), err);
+            }
+            if stale > 0 {
+                debug!("Discarded {} stale packets at {:?}", stale, sock.local_addr());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{SocketAddr, UdpSocket};
+    use std::sync::{mpsc, Arc, Mutex};
+    use std::time::Duration;
+
+    use headers::HeaderMapExt as _;
+
+    use super::TransportSet;
+    use crate::header::{ST, USN};
+    use crate::message::{Config, SearchRequest};
+    use crate::testing::{loopback_context, search_response};
+    use crate::{FieldMap, Task};
+
+    #[test]
+    fn positive_searches_share_sockets() {
+        let context = loopback_context();
+        let config = Config::new()
+            .set_port(19_029)
+            .set_mode(crate::IpVersionMode::V4Only)
+            .set_include_loopback(true)
+            .set_response_window(Some(Duration::from_millis(600)));
+        let listener = context.listen::<SearchRequest>(&config).unwrap();
+
+        // Answer every search from a socket of its own, reporting where the search came from.
+        let (searches, sources) = mpsc::channel::<SocketAddr>();
+        std::thread::spawn(move || {
+            let device = UdpSocket::bind("127.0.0.1:0").unwrap();
+            for (index, (_, src)) in listener.into_iter().enumerate() {
+                device.send_to(&search_response(&format!("device-{index}")), src).unwrap();
+                if searches.send(src).is_err() {
+                    return;
+                }
+            }
+        });
+
+        let mut transports = TransportSet::bind_with_context(&context, &config).unwrap();
+        let local_addrs = transports.local_addrs();
+        assert_eq!(local_addrs.len(), 1);
+
+        let request = SearchRequest::new().with_st(ST::All).with_mx(1).unwrap();
+
+        let first = request.multicast_on_transports(&mut transports).unwrap();
+        let from_first = sources.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(first.local_addrs(), vec![from_first]);
+        let (message, _) = first.into_iter().next().unwrap();
+        let usn = message.headers().typed_get::<USN>().unwrap();
+        assert_eq!(usn.0, FieldMap::uuid("device-0"));
+
+        let second = request.multicast_on_transports(&mut transports).unwrap();
+        let from_second = sources.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(from_first, from_second);
+        assert_eq!(vec![from_first], local_addrs);
+        assert_eq!(transports.local_addrs(), local_addrs);
+
+        // The response to the second search reaches its receiver, not the stopped first one.
+        let (message, _) = second.into_iter().next().unwrap();
+        let usn = message.headers().typed_get::<USN>().unwrap();
+        assert_eq!(usn.0, FieldMap::uuid("device-1"));
+    }
+
+    #[test]
+    fn positive_previous_receiver_stops() {
+        let context = loopback_context();
+        let config = Config::new()
+            .set_port(19_030)
+            .set_mode(crate::IpVersionMode::V4Only)
+            .set_include_loopback(true)
+            .set_response_window(Some(Duration::from_secs(30)));
+
+        let mut transports = TransportSet::bind_with_context(&context, &config).unwrap();
+        let request = SearchRequest::new().with_st(ST::All).with_mx(1).unwrap();
+
+        let first = request.multicast_on_transports(&mut transports).unwrap();
+        let _second = request.multicast_on_transports(&mut transports).unwrap();
+
+        // Without the next search, the first receiver would wait for the whole window.
+        let (done, ended) = mpsc::channel();
+        std::thread::spawn(move || {
+            assert_eq!(first.into_iter().count(), 0);
+            done.send(()).unwrap();
+        });
+        ended.recv_timeout(Duration::from_secs(5)).unwrap();
+    }
+
+    #[test]
+    fn negative_previous_receiver_stuck() {
+        // Tasks are never run, so the receiver of a search never releases its sockets.
+        let parked = Arc::new(Mutex::new(Vec::<Task>::new()));
+        let spawned = Arc::clone(&parked);
+        let context = loopback_context().set_spawner(move |task| spawned.lock().unwrap().push(task));
+        let config = Config::new()
+            .set_port(19_043)
+            .set_mode(crate::IpVersionMode::V4Only)
+            .set_include_loopback(true)
+            .set_response_window(Some(Duration::from_secs(30)));
+
+        let mut transports = TransportSet::bind_with_context(&context, &config).unwrap();
+        let local_addrs = transports.local_addrs();
+        let request = SearchRequest::new().with_st(ST::All).with_mx(1).unwrap();
+
+        let _first = request.multicast_on_transports(&mut transports).unwrap();
+        let _second = request.multicast_on_transports(&mut transports).unwrap();
+
+        // The sockets still owned by the first receiver are neither drained nor shared.
+        assert_eq!(transports.local_addrs().len(), 1);
+        assert_ne!(transports.local_addrs(), local_addrs);
+        assert!(!parked.lock().unwrap().is_empty());
+    }
+}
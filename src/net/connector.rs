@@ -5,53 +5,161 @@ use std::sync::Arc;
 
 use net2::UdpSocketExt as _;
 
-use crate::net::NetworkConnector;
+use crate::context::SsdpObserver;
+use crate::net::{IoAt, NetworkConnector};
 
 use crate::net;
-use crate::net::sender::UdpSender;
+use crate::net::sender::{RetryPolicy, UdpSender};
+
+/// Options applied to the socket of a `UdpConnector` when it is created.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ConnectorOptions {
+    /// Allow sending to broadcast addresses.
+    ///
+    /// SSDP only sends to multicast groups and single hosts, and some platforms require
+    /// privileges to enable broadcast, so it is off by default.
+    pub broadcast: bool,
+    /// The TTL of multicast packets, or their hop limit on IPv6.
+    pub ttl: Option<u32>,
+    /// Whether multicast packets are looped back to the local host.
+    pub loop_: Option<bool>,
+    /// How sends through the connector are retried after transient errors.
+    pub retry: RetryPolicy,
+    /// Bind with `SO_REUSEADDR`, and with `SO_REUSEPORT` if this is true, so that a fixed port
+    /// can be shared with a listener.
+    pub reuse_port: Option<bool>,
+    /// The DSCP of sent packets, see `net::set_dscp`.
+    pub dscp: Option<u8>,
+}
+
+/// The broadcast switch of a socket, so that tests can mock one that may not enable it.
+pub(crate) trait SetBroadcast {
+    fn set_broadcast(&self, broadcast: bool) -> io::Result<()>;
+}
+
+impl SetBroadcast for UdpSocket {
+    fn set_broadcast(&self, broadcast: bool) -> io::Result<()> {
+        UdpSocket::set_broadcast(self, broadcast)
+    }
+}
+
+/// Enable broadcast on the socket only if the options ask for it.
+fn enable_broadcast<S: SetBroadcast + ?Sized>(socket: &S, options: &ConnectorOptions) -> io::Result<()> {
+    if options.broadcast {
+        socket.set_broadcast(true)?;
+    }
+    Ok(())
+}
 
 /// A `UdpConnector` allows Hyper to obtain `NetworkStream` objects over `UdpSockets`
 /// so that Http messages created by Hyper can be sent over UDP instead of TCP.
-pub struct UdpConnector(Arc<UdpSocket>);
+pub struct UdpConnector(Arc<UdpSocket>, Option<Arc<dyn SsdpObserver>>, RetryPolicy);
 
 impl UdpConnector {
     /// Create a new UdpConnector that will be bound to the given local address.
+    ///
+    /// Broadcast is only enabled if the options ask for it, sending never changes the socket.
     pub fn new(
         local_addr: impl ToSocketAddrs,
         index: u32,
-        _multicast_ttl: Option<u32>,
+        options: &ConnectorOptions,
     ) -> io::Result<UdpConnector> {
-        let addr = net::addr_from_trait(local_addr)?;
+        let mut addr = net::addr_from_trait(local_addr)?;
+
+        // Link-local addresses can only be bound on the interface they belong to.
+        if let SocketAddr::V6(ref mut v6) = addr {
+            if v6.ip().is_unicast_link_local() && v6.scope_id() == 0 {
+                v6.set_scope_id(index);
+            }
+        }
+
         debug!("Attempting to bind to {}", addr);
 
-        let udp = UdpSocket::bind(addr)?;
+        let udp = match options.reuse_port {
+            Some(reuse_port) => net::bind_reuse(addr, reuse_port),
+            None => UdpSocket::bind(addr),
+        };
+        let udp = udp.map_err(|err| {
+            let label = (index != 0).then(|| net::iface_label(index));
+            IoAt::wrap(err, "bind", Some(addr), label)
+        })?;
 
         // The bind address indicates where to receive messages. This is independent from where to
         // send messages (<https://stackoverflow.com/a/26988214>) which is a problem in particular
         // for IPv6 that shares the same multicast addresses across links, i.e. the common ones are
         // not bound from the network prefix.
         match addr {
-            SocketAddr::V4(v4) => {
-                udp.set_multicast_if_v4(v4.ip())?
-            },
+            SocketAddr::V4(v4) => udp.set_multicast_if_v4(v4.ip())?,
             SocketAddr::V6(_) => {
                 udp.set_multicast_if_v6(index)?;
             }
         };
 
-        // TODO: This throws an invalid argument error
-        // if let Some(n) = multicast_ttl {
-        //     trace!("Setting ttl to {}", n);
-        //     try!(udp.set_multicast_ttl_v4(n));
-        // }
+        if let Some(multicast_loop) = options.loop_ {
+            net::set_multicast_loop(&udp, &addr, multicast_loop)?;
+        }
+
+        enable_broadcast(&udp, options)?;
+
+        if let Some(dscp) = options.dscp {
+            net::set_dscp(&udp, &addr, dscp)?;
+        }
+
+        if let Some(ttl) = options.ttl {
+            trace!("Setting multicast ttl to {}", ttl);
+            net::set_multicast_ttl(&udp, &addr, ttl)?;
+        }
+
+        Ok(UdpConnector(Arc::new(udp), None, options.retry))
+    }
+
+    /// Create a new UdpConnector bound to the given local address and restricted to the device.
+    ///
+    /// This is required on hosts where several interfaces share a subnet, see
+    /// `net::bind_to_device` for the platform support.
+    pub fn new_bound_to_device(
+        local_addr: impl ToSocketAddrs,
+        iface_name: &str,
+        index: u32,
+        options: &ConnectorOptions,
+    ) -> io::Result<UdpConnector> {
+        let connector = UdpConnector::new(local_addr, index, options)?;
+        net::bind_to_device(&connector.0, iface_name, index).map_err(|err| {
+            let addr = connector.local_addr().ok();
+            IoAt::wrap(err, "bind to device", addr, Some(iface_name.to_string()))
+        })?;
+        Ok(connector)
+    }
+
+    /// Create a UdpConnector from a socket that was already bound by the caller.
+    ///
+    /// Multicast packets are sent through the interface of the bound address, for IPv6 through
+    /// the interface with the given index unless it is zero.
+    pub fn from_socket(udp: UdpSocket, index: u32) -> io::Result<UdpConnector> {
+        match udp.local_addr()? {
+            SocketAddr::V4(v4) if !v4.ip().is_unspecified() => udp.set_multicast_if_v4(v4.ip())?,
+            SocketAddr::V6(_) if index != 0 => udp.set_multicast_if_v6(index)?,
+            _ => {}
+        }
 
-        Ok(UdpConnector(Arc::new(udp)))
+        Ok(UdpConnector(Arc::new(udp), None, RetryPolicy::default()))
+    }
+
+    /// Report the packets sent through the streams of this connector to the observer.
+    pub(crate) fn with_observer(mut self, observer: Option<Arc<dyn SsdpObserver>>) -> Self {
+        self.1 = observer;
+        self
     }
 
     pub fn local_addr(&self) -> io::Result<SocketAddr> {
         self.0.local_addr()
     }
 
+    /// Get a handle to the underlying UdpSocket.
+    pub(crate) fn socket(&self) -> Arc<UdpSocket> {
+        Arc::clone(&self.0)
+    }
+
     /// Destroy the UdpConnector and return the underlying UdpSocket.
     pub fn deconstruct(self) -> Arc<UdpSocket> {
         self.0
@@ -63,7 +171,6 @@ impl NetworkConnector for UdpConnector {
 
     fn connect(&self, host: &str, port: u16) -> io::Result<Self::Stream> {
         let udp_sock = Arc::clone(&self.0);
-        udp_sock.set_broadcast(true)?;
         let local = self.local_addr()?;
 
         trace!("Connecting through {local}");
@@ -87,6 +194,212 @@ impl NetworkConnector for UdpConnector {
             }
         };
 
-        Ok(UdpSender::new(udp_sock, sock_addr))
+        Ok(UdpSender::new(udp_sock, sock_addr)
+            .with_observer(self.1.clone())
+            .with_retry(self.2))
+    }
+
+    fn connect_addr(&self, addr: SocketAddr) -> io::Result<Self::Stream> {
+        let udp_sock = Arc::clone(&self.0);
+        let local = self.local_addr()?;
+
+        trace!("Connecting through {local}");
+        let dst = net::with_local_scope(addr, local);
+        Ok(UdpSender::new(udp_sock, dst).with_observer(self.1.clone()).with_retry(self.2))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Write as _};
+    use std::net::{SocketAddr, SocketAddrV6, UdpSocket};
+    use std::time::Duration;
+
+    use super::{enable_broadcast, ConnectorOptions, SetBroadcast, UdpConnector};
+    use crate::net::sender::UdpSender;
+    use crate::net::{NetworkConnector, NetworkStream};
+    use crate::SSDPError;
+
+    /// A connector over a socket that may not enable broadcast, as without privileges.
+    struct BroadcastDenied(UdpConnector);
+
+    impl SetBroadcast for BroadcastDenied {
+        fn set_broadcast(&self, _: bool) -> io::Result<()> {
+            Err(io::ErrorKind::PermissionDenied.into())
+        }
+    }
+
+    impl NetworkConnector for BroadcastDenied {
+        type Stream = UdpSender;
+
+        fn connect(&self, host: &str, port: u16) -> io::Result<UdpSender> {
+            self.0.connect(host, port)
+        }
+    }
+
+    #[test]
+    fn positive_connect_keeps_scope() {
+        let connector = UdpConnector::new(("::1", 0), 1, &ConnectorOptions::default()).unwrap();
+        let dst = SocketAddrV6::new("fe80::1".parse().unwrap(), 1900, 0, 7);
+
+        let mut stream = connector.connect_addr(dst.into()).unwrap();
+        assert_eq!(stream.peer_addr().unwrap(), SocketAddr::V6(dst));
+    }
+
+    #[test]
+    fn positive_connect_without_broadcast() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let connector = UdpConnector::from_socket(socket, 0).unwrap();
+
+        connector.connect("239.255.255.250", 1900).unwrap();
+        connector.connect_addr("127.0.0.1:1900".parse().unwrap()).unwrap();
+        assert!(!connector.deconstruct().broadcast().unwrap());
+    }
+
+    #[test]
+    fn positive_connect_broadcast_denied() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let port = receiver.local_addr().unwrap().port();
+
+        let options = ConnectorOptions::default();
+        let connector = BroadcastDenied(UdpConnector::new(("127.0.0.1", 0), 0, &options).unwrap());
+
+        let broadcast = ConnectorOptions {
+            broadcast: true,
+            ..options
+        };
+        let err = enable_broadcast(&connector, &broadcast).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+        enable_broadcast(&connector, &options).unwrap();
+
+        let mut stream = connector.connect("127.0.0.1", port).unwrap();
+        stream.write_all(b"M-SEARCH").unwrap();
+        stream.flush().unwrap();
+
+        let mut buf = [0; 16];
+        let (len, _) = receiver.recv_from(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"M-SEARCH");
+    }
+
+    #[test]
+    fn positive_broadcast_option() {
+        let options = ConnectorOptions {
+            broadcast: true,
+            ..ConnectorOptions::default()
+        };
+
+        let connector = UdpConnector::new(("127.0.0.1", 0), 0, &options).unwrap();
+        assert!(connector.deconstruct().broadcast().unwrap());
+
+        let connector = UdpConnector::new(("127.0.0.1", 0), 0, &ConnectorOptions::default()).unwrap();
+        assert!(!connector.deconstruct().broadcast().unwrap());
+    }
+
+    #[test]
+    fn negative_bind_in_use() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+
+        let err = UdpConnector::new(addr, 0, &ConnectorOptions::default()).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+
+        match SSDPError::from(err) {
+            SSDPError::IoAt {
+                op: "bind",
+                addr: Some(at),
+                iface: None,
+                ..
+            } => assert_eq!(at, addr),
+            other => panic!("unexpected error: {}", other),
+        }
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn positive_dscp_option() {
+        use std::os::fd::AsRawFd as _;
+
+        let options = ConnectorOptions {
+            dscp: Some(8),
+            ..ConnectorOptions::default()
+        };
+
+        for (local, level, option) in [
+            ("127.0.0.1", libc::IPPROTO_IP, libc::IP_TOS),
+            ("::1", libc::IPPROTO_IPV6, libc::IPV6_TCLASS),
+        ] {
+            let socket = UdpConnector::new((local, 0), 1, &options).unwrap().deconstruct();
+
+            let mut value: libc::c_int = 0;
+            let mut len = std::mem::size_of_val(&value) as libc::socklen_t;
+            // SAFETY: The value and its length are valid for writes.
+            let result = unsafe {
+                libc::getsockopt(
+                    socket.as_raw_fd(),
+                    level,
+                    option,
+                    (&mut value as *mut libc::c_int).cast(),
+                    &mut len,
+                )
+            };
+
+            assert_eq!(result, 0);
+            // CS1 in the upper six bits.
+            assert_eq!(value, 0x20, "{}", local);
+        }
+    }
+
+    #[test]
+    fn positive_ttl_option() {
+        let options = ConnectorOptions {
+            ttl: Some(4),
+            ..ConnectorOptions::default()
+        };
+
+        let v4 = UdpConnector::new(("127.0.0.1", 0), 1, &options).unwrap().deconstruct();
+        assert_eq!(v4.multicast_ttl_v4().unwrap(), 4);
+
+        let v6 = UdpConnector::new(("::1", 0), 1, &options).unwrap().deconstruct();
+        assert_eq!(net2::UdpSocketExt::multicast_hops_v6(&*v6).unwrap(), 4);
+    }
+
+    #[test]
+    fn negative_dscp_out_of_range() {
+        let options = ConnectorOptions {
+            dscp: Some(64),
+            ..ConnectorOptions::default()
+        };
+
+        let result = UdpConnector::new(("127.0.0.1", 0), 0, &options);
+        if cfg!(unix) {
+            assert_eq!(result.err().unwrap().kind(), io::ErrorKind::InvalidInput);
+        }
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn positive_bound_to_device() {
+        use std::os::fd::AsRawFd as _;
+
+        let options = ConnectorOptions::default();
+        let connector = UdpConnector::new_bound_to_device(("127.0.0.1", 0), "lo", 1, &options).unwrap();
+        let socket = connector.deconstruct();
+
+        let mut name = [0u8; libc::IF_NAMESIZE];
+        let mut len = name.len() as libc::socklen_t;
+        // SAFETY: The buffer and its length are valid for writes.
+        let result = unsafe {
+            libc::getsockopt(
+                socket.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_BINDTODEVICE,
+                name.as_mut_ptr().cast(),
+                &mut len,
+            )
+        };
+
+        assert_eq!(result, 0);
+        assert_eq!(&name[..len as usize].split(|&b| b == 0).next().unwrap(), b"lo");
     }
 }
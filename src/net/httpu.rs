@@ -1,41 +1,170 @@
 //! Format http into an HTTPU packet.
 use std::io::Write as _;
+use std::net::SocketAddr;
 
 use crate::error::SSDPError;
+use crate::header::{BootID, ConfigID, Man, SearchPort, NT, NTS, USN};
+use crate::message::HeaderCase;
 use crate::net::packet::PacketBuffer;
 
-use headers::HeaderMap;
+use headers::{CacheControl, ContentLength, Header as _, HeaderMap, HeaderName, Host, Location, Server};
+
+/// The order in which the headers of a message are written.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum HeaderOrder {
+    /// The iteration order of the header map.
+    #[default]
+    Map,
+    /// The order of the examples in the UPnP device architecture, HOST, CACHE-CONTROL, LOCATION, NT,
+    /// NTS, SERVER, USN, BOOTID, CONFIGID and SEARCHPORT, followed by all others sorted by name.
+    Canonical,
+}
+
+/// How the headers of a message are written.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Style {
+    pub order: HeaderOrder,
+    pub case: HeaderCase,
+}
 
 pub struct Request<'map> {
     pub method: &'map str,
+    /// The destination written as HOST, or `None` to keep the HOST of the headers.
+    pub host: Option<SocketAddr>,
+    /// Write the MAN header of a multicast search, for headers that lack it.
+    pub discover: bool,
     pub headers: &'map HeaderMap,
-    body: Option<&'map [u8]>,
+    /// The spelling of header names for `HeaderCase::AsSet`.
+    pub names: &'map [String],
+    pub style: Style,
 }
 
 pub struct Response<'map> {
     pub headers: &'map HeaderMap,
-    pub body: Option<&'map [u8]>,
+    pub names: &'map [String],
+    pub style: Style,
 }
 
 impl<'map> Request<'map> {
-    pub fn new(headers: &'map HeaderMap) -> Self {
-        Request {
-            method: "NOTIFY",
-            headers,
-            body: None,
+    pub fn serialize(&self, packet: &mut PacketBuffer) -> Result<(), SSDPError> {
+        packet.clear();
+        write!(packet, "{} * HTTP/1.1\r\n", self.method)?;
+        // The scope identifier is only meaningful locally, leave it out of the header.
+        if let Some(host) = self.host {
+            write_name(packet, Host::name(), self.style.case, self.names)?;
+            match host {
+                SocketAddr::V4(host) => write!(packet, "{}\r\n", host)?,
+                SocketAddr::V6(host) => write!(packet, "[{}]:{}\r\n", host.ip(), host.port())?,
+            }
+        }
+        if self.discover {
+            write_name(packet, Man::name(), self.style.case, self.names)?;
+            write!(packet, "\"ssdp:discover\"\r\n")?;
         }
+        let skip = self.host.map(|_| Host::name());
+        serialize_headers(self.headers, self.names, self.style, packet, skip)?;
+        write!(packet, "\r\n")?;
+        Ok(())
+    }
+}
+
+impl<'map> Response<'map> {
+    pub fn new(headers: &'map HeaderMap, names: &'map [String], style: Style) -> Self {
+        Response { headers, names, style }
     }
 
+    /// Serialize the response with an empty body, overriding any content length of the headers.
     pub fn serialize(&self, packet: &mut PacketBuffer) -> Result<(), SSDPError> {
-        packet.buffer.truncate(0);
-        write!(packet, "{} * HTTP/1.1\r\n", self.method)?;
-        write!(packet, "HOST: 239.255.255.250:1900\r\n")?;
-        for (name, value) in self.headers {
-            write!(packet, "{}: ", name.as_str().to_uppercase())?;
-            packet.write_all(value.as_bytes())?;
-            write!(packet, "\r\n")?;
-        }
+        packet.clear();
+        write!(packet, "HTTP/1.1 200 OK\r\n")?;
+        serialize_headers(self.headers, self.names, self.style, packet, Some(ContentLength::name()))?;
+        write_name(packet, ContentLength::name(), self.style.case, self.names)?;
+        write!(packet, "0\r\n")?;
         write!(packet, "\r\n")?;
         Ok(())
     }
 }
+
+/// Write all headers, except for one that is already covered by the start of the message.
+fn serialize_headers(
+    headers: &HeaderMap,
+    names: &[String],
+    style: Style,
+    packet: &mut PacketBuffer,
+    skip: Option<&HeaderName>,
+) -> Result<(), SSDPError> {
+    let mut fields: Vec<_> = headers.iter().collect();
+
+    if style.order == HeaderOrder::Canonical {
+        let canonical = [
+            Host::name(),
+            CacheControl::name(),
+            Location::name(),
+            NT::name(),
+            NTS::name(),
+            Server::name(),
+            USN::name(),
+            BootID::name(),
+            ConfigID::name(),
+            SearchPort::name(),
+        ];
+
+        // The sort is stable, several values of a header keep their order.
+        fields.sort_by_key(|&(name, _)| {
+            let rank = canonical.iter().position(|&known| known == name);
+            (rank.unwrap_or(canonical.len()), name.as_str())
+        });
+    }
+
+    for (name, value) in fields {
+        if Some(name) == skip {
+            continue;
+        }
+
+        write_name(packet, name, style.case, names)?;
+        packet.write_all(value.as_bytes())?;
+        write!(packet, "\r\n")?;
+    }
+
+    Ok(())
+}
+
+/// Write the name of a header with its colon, in the given case.
+///
+/// The names are the spellings that `HeaderCase::AsSet` writes.
+fn write_name(
+    packet: &mut PacketBuffer,
+    name: &HeaderName,
+    case: HeaderCase,
+    names: &[String],
+) -> Result<(), SSDPError> {
+    let spelled = names.iter().find(|spelled| spelled.eq_ignore_ascii_case(name.as_str()));
+
+    match (case, spelled) {
+        (HeaderCase::AsSet, Some(spelled)) => write!(packet, "{}: ", spelled)?,
+        (HeaderCase::Upper | HeaderCase::AsSet, _) => write!(packet, "{}: ", name.as_str().to_uppercase())?,
+        (HeaderCase::Canonical, _) if is_upnp_extension(name) => {
+            write!(packet, "{}: ", name.as_str().to_uppercase())?
+        }
+        (HeaderCase::Canonical, _) => {
+            for (index, word) in name.as_str().split('-').enumerate() {
+                if index > 0 {
+                    write!(packet, "-")?;
+                }
+
+                let mut chars = word.chars();
+                if let Some(first) = chars.next() {
+                    write!(packet, "{}{}", first.to_ascii_uppercase(), chars.as_str())?;
+                }
+            }
+            write!(packet, ": ")?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Check if the header is one defined by `UPnP` rather than HTTP, whose name is uppercase.
+fn is_upnp_extension(name: &HeaderName) -> bool {
+    name.as_str().ends_with(".upnp.org") || name.as_str() == "01-nls"
+}
@@ -3,43 +3,197 @@
 //! This module deals with primitives for working with external libraries to write
 //! data to UDP sockets as a stream, and read data from UDP sockets as packets.
 
+use std::error;
+use std::fmt;
 use std::io::{self, ErrorKind};
-use std::net::{IpAddr, SocketAddr};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4};
 use std::net::{ToSocketAddrs, UdpSocket};
+use std::str::FromStr;
 
 #[cfg(not(windows))]
 use net2::unix::UnixUdpBuilderExt;
 use net2::UdpBuilder;
 
-pub mod connector;
-pub mod httpu;
-pub mod packet;
-pub mod sender;
+use crate::message::{self, Config};
+use crate::net::packet::PacketInfo;
 
-#[derive(Copy, Clone)]
+pub(crate) mod connector;
+pub(crate) mod httpu;
+pub(crate) mod packet;
+#[cfg(any(target_os = "linux", target_os = "android", target_vendor = "apple"))]
+mod pktinfo;
+pub(crate) mod sender;
+#[cfg(windows)]
+mod windows;
+
+/// The IP versions used for sending and listening.
+///
+/// Written as `v4`, `v6` or `any`, which is also the form used with `serde`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum IpVersionMode {
+    #[cfg_attr(feature = "serde", serde(rename = "v4"))]
     V4Only,
+    #[cfg_attr(feature = "serde", serde(rename = "v6"))]
     V6Only,
+    #[cfg_attr(feature = "serde", serde(rename = "any"))]
     Any,
 }
 
-#[derive(Clone, Copy)]
+/// The error of parsing an `IpVersionMode` from a string other than `v4`, `v6` or `any`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseIpVersionModeError(String);
+
+/// An address of a local network interface.
+#[derive(Clone, Copy, Debug)]
 pub struct NetifAddr {
     pub sock: IpAddr,
     pub index: u32,
 }
 
+impl NetifAddr {
+    /// Get the address.
+    pub fn addr(&self) -> IpAddr {
+        self.sock
+    }
+
+    /// Get the index of the interface the address belongs to.
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+}
+
+/// A local network interface with its addresses that are usable for SSDP.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Interface {
+    pub name: String,
+    pub index: u32,
+    pub addrs: Vec<IpAddr>,
+}
+
+/// Get the interfaces of the system that are used by default.
+///
+/// Loopback and global IPv6 addresses are skipped, as with `Config::default()`, and interfaces
+/// without any other address are left out. The addresses can restrict a config to an interface:
+///
+/// ```
+/// use ssdp::message::Config;
+///
+/// for iface in ssdp::net::interfaces().unwrap() {
+///     println!("{} ({}): {:?}", iface.name, iface.index, iface.addrs);
+///     let config = Config::new().set_interfaces(iface.addrs.clone());
+/// }
+/// ```
+pub fn interfaces() -> io::Result<Vec<Interface>> {
+    interfaces_for(&Config::default())
+}
+
+/// Get the interfaces of the system that are used with the config.
+///
+/// Addresses are filtered by options such as `Config::set_include_loopback`. A restriction set
+/// with `Config::set_interfaces` is not applied.
+pub fn interfaces_for(config: &Config) -> io::Result<Vec<Interface>> {
+    Ok(message::system_interfaces(config))
+}
+
 impl IpVersionMode {
+    /// Get the IP versions of all addresses that the address resolves to.
+    ///
+    /// A host name that resolves to addresses of both versions is `Any`.
     pub fn from_addr<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
-        match addr_from_trait(addr)? {
-            SocketAddr::V4(_) => Ok(IpVersionMode::V4Only),
-            SocketAddr::V6(_) => Ok(IpVersionMode::V6Only),
+        match resolve_families(addr)? {
+            (_, Some(_)) => Ok(IpVersionMode::Any),
+            (SocketAddr::V4(_), None) => Ok(IpVersionMode::V4Only),
+            (SocketAddr::V6(_), None) => Ok(IpVersionMode::V6Only),
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            IpVersionMode::V4Only => "v4",
+            IpVersionMode::V6Only => "v6",
+            IpVersionMode::Any => "any",
         }
     }
 }
 
+impl fmt::Display for IpVersionMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for IpVersionMode {
+    type Err = ParseIpVersionModeError;
+
+    /// Parse the mode, ignoring case.
+    fn from_str(mode: &str) -> Result<IpVersionMode, ParseIpVersionModeError> {
+        [IpVersionMode::V4Only, IpVersionMode::V6Only, IpVersionMode::Any]
+            .into_iter()
+            .find(|known| known.as_str().eq_ignore_ascii_case(mode))
+            .ok_or_else(|| ParseIpVersionModeError(mode.to_string()))
+    }
+}
+
+impl fmt::Display for ParseIpVersionModeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid IP version mode '{}', expected v4, v6 or any", self.0)
+    }
+}
+
+impl error::Error for ParseIpVersionModeError {}
+
+/// The address of a local network together with its prefix length.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Subnet {
+    pub(crate) addr: IpAddr,
+    pub(crate) prefix_len: u8,
+}
+
+impl Subnet {
+    /// Check if the address is part of this network.
+    pub(crate) fn contains(&self, ip: IpAddr) -> bool {
+        fn same_prefix(net: u128, ip: u128, prefix_len: u8, bits: u8) -> bool {
+            let shift = bits.saturating_sub(prefix_len);
+            shift >= bits || net >> shift == ip >> shift
+        }
+
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                same_prefix(u32::from(net).into(), u32::from(ip).into(), self.prefix_len, 32)
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => same_prefix(net.into(), ip.into(), self.prefix_len, 128),
+            _ => false,
+        }
+    }
+
+    /// The broadcast address of an IPv4 network.
+    ///
+    /// IPv6 has no broadcast, and networks with a prefix of 31 or 32 bits have no room for one.
+    pub(crate) fn broadcast(&self) -> Option<Ipv4Addr> {
+        match self.addr {
+            IpAddr::V4(addr) if self.prefix_len < 31 => {
+                let host_bits = u32::MAX >> self.prefix_len;
+                Some(Ipv4Addr::from(u32::from(addr) | host_bits))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Check if a peer is on one of the local networks.
+///
+/// Link-local IPv6 peers are always local, their network is only defined by the interface.
+pub(crate) fn is_local_source(peer: IpAddr, subnets: &[Subnet]) -> bool {
+    match peer.to_canonical() {
+        IpAddr::V6(ip) if ip.is_unicast_link_local() => true,
+        ip => subnets.iter().any(|subnet| subnet.contains(ip)),
+    }
+}
+
 /// Accept a type implementing `ToSocketAddrs` and tries to extract the first address.
-pub fn addr_from_trait<A: ToSocketAddrs>(addr: A) -> io::Result<SocketAddr> {
+pub(crate) fn addr_from_trait<A: ToSocketAddrs>(addr: A) -> io::Result<SocketAddr> {
     let mut sock_iter = addr.to_socket_addrs()?;
 
     match sock_iter.next() {
@@ -48,53 +202,423 @@ pub fn addr_from_trait<A: ToSocketAddrs>(addr: A) -> io::Result<SocketAddr> {
     }
 }
 
+/// Resolve the address to try first, preferring IPv4, and a fallback of the other IP version.
+pub(crate) fn resolve_families<A: ToSocketAddrs>(addr: A) -> io::Result<(SocketAddr, Option<SocketAddr>)> {
+    let addrs: Vec<SocketAddr> = addr.to_socket_addrs()?.collect();
+    let v4 = addrs.iter().copied().find(SocketAddr::is_ipv4);
+    let v6 = addrs.iter().copied().find(SocketAddr::is_ipv6);
+
+    match (v4, v6) {
+        (Some(v4), v6) => Ok((v4, v6)),
+        (None, Some(v6)) => Ok((v6, None)),
+        (None, None) => Err(io::Error::new(ErrorKind::InvalidInput, "Failed To Parse SocketAddr")),
+    }
+}
+
+/// Scope an IPv6 destination to the interface of a local address if it has no scope of its own.
+///
+/// Link-local addresses are only unique per interface, so sending to one requires the scope id of
+/// the interface it is reached through.
+pub(crate) fn with_local_scope(dst_addr: SocketAddr, local_addr: SocketAddr) -> SocketAddr {
+    match (dst_addr, local_addr) {
+        (SocketAddr::V6(mut dst), SocketAddr::V6(local)) if dst.scope_id() == 0 => {
+            dst.set_scope_id(local.scope_id());
+            SocketAddr::V6(dst)
+        }
+        (dst, _) => dst,
+    }
+}
+
+/// Turn an IPv4-mapped IPv6 peer address into the IPv4 address it stands for.
+///
+/// A dual-stack socket reports IPv4 peers as `::ffff:a.b.c.d`, which would not match the IPv4
+/// addresses of interfaces, subnets and devices.
+pub(crate) fn unmap_v4(addr: SocketAddr) -> SocketAddr {
+    match addr {
+        SocketAddr::V6(v6) => match v6.ip().to_ipv4_mapped() {
+            Some(v4) => SocketAddr::V4(SocketAddrV4::new(v4, v6.port())),
+            None => addr,
+        },
+        addr => addr,
+    }
+}
+
+/// An IO error together with the operation, address and interface it failed on.
+///
+/// Wrapped in an `io::Error` of the same kind, which converts to `SSDPError::IoAt`.
+#[derive(Debug)]
+pub(crate) struct IoAt {
+    pub(crate) op: &'static str,
+    pub(crate) addr: Option<SocketAddr>,
+    pub(crate) iface: Option<String>,
+    pub(crate) source: io::Error,
+}
+
+impl IoAt {
+    /// Attach the context to the error, unless it already carries one.
+    pub(crate) fn wrap(
+        source: io::Error,
+        op: &'static str,
+        addr: Option<SocketAddr>,
+        iface: Option<String>,
+    ) -> io::Error {
+        if source.get_ref().is_some_and(|inner| inner.is::<IoAt>()) {
+            return source;
+        }
+
+        let kind = source.kind();
+        io::Error::new(
+            kind,
+            IoAt {
+                op,
+                addr,
+                iface,
+                source,
+            },
+        )
+    }
+}
+
+impl fmt::Display for IoAt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let target = IoTarget {
+            op: self.op,
+            addr: &self.addr,
+            iface: &self.iface,
+        };
+
+        write!(f, "{} failed: {}", target, self.source)
+    }
+}
+
+impl error::Error for IoAt {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+/// Formats an operation with its address and interface, such as `bind 0.0.0.0:1900 on eth1`.
+pub(crate) struct IoTarget<'a> {
+    pub(crate) op: &'a str,
+    pub(crate) addr: &'a Option<SocketAddr>,
+    pub(crate) iface: &'a Option<String>,
+}
+
+impl fmt::Display for IoTarget<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.op)?;
+
+        match self.addr {
+            // Ports are only relevant where they were chosen
+            Some(addr) if addr.port() == 0 => write!(f, " {}", addr.ip())?,
+            Some(addr) => write!(f, " {}", addr)?,
+            None => {}
+        }
+
+        if let Some(iface) = self.iface {
+            write!(f, " on {}", iface)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Describe the interface with the given index for error messages, by name if possible.
+pub(crate) fn iface_label(index: u32) -> String {
+    interface_name(index).unwrap_or_else(|_| format!("interface {}", index))
+}
+
+/// Find the local address the system would use to send packets to the destination.
+///
+/// No packets are sent, this only consults the routing table by connecting a socket.
+pub(crate) fn route_source_addr(dst_addr: SocketAddr) -> io::Result<IpAddr> {
+    let local_addr: SocketAddr = match dst_addr {
+        SocketAddr::V4(_) => ([0, 0, 0, 0], 0).into(),
+        SocketAddr::V6(_) => ([0u16; 8], 0).into(),
+    };
+
+    let sock = UdpSocket::bind(local_addr).map_err(|err| IoAt::wrap(err, "bind", Some(local_addr), None))?;
+    sock.connect(dst_addr)
+        .map_err(|err| IoAt::wrap(err, "connect to", Some(dst_addr), None))?;
+    Ok(sock.local_addr()?.ip())
+}
+
 /// Bind to a `UdpSocket`, setting `SO_REUSEADDR` on the underlying socket before binding.
-pub fn bind_reuse<A: ToSocketAddrs>(local_addr: A) -> io::Result<UdpSocket> {
+///
+/// With `reuse_port` this also sets `SO_REUSEPORT` where available, which lets other SSDP stacks
+/// on the same host receive packets on the port as well.
+pub(crate) fn bind_reuse<A: ToSocketAddrs>(local_addr: A, reuse_port: bool) -> io::Result<UdpSocket> {
     let local_addr = addr_from_trait(local_addr)?;
 
     let builder = match local_addr {
         SocketAddr::V4(_) => UdpBuilder::new_v4()?,
-        SocketAddr::V6(_) => UdpBuilder::new_v6()?,
+        SocketAddr::V6(_) => {
+            // A dual-stack socket on the wildcard address would also receive the IPv4 packets
+            // of the separate IPv4 socket, which can only be prevented before binding.
+            let builder = UdpBuilder::new_v6()?;
+            builder.only_v6(true)?;
+            builder
+        }
     };
 
-    reuse_port(&builder)?;
-    builder.bind(local_addr)
+    // Allow wildcards + specific to not overlap
+    builder.reuse_address(true)?;
+
+    if reuse_port {
+        set_reuse_port(&builder)?;
+    }
+
+    builder
+        .bind(local_addr)
+        .map_err(|err| IoAt::wrap(err, "bind", Some(local_addr), None))
 }
 
 #[cfg(target_os = "windows")]
-fn reuse_port(builder: &UdpBuilder) -> io::Result<()> {
-    // Allow wildcards + specific to not overlap
-    builder.reuse_address(true)?;
+fn set_reuse_port(_: &UdpBuilder) -> io::Result<()> {
+    // There is no such option, `SO_REUSEADDR` already allows multiple listeners.
     Ok(())
 }
 
 #[cfg(not(windows))]
-fn reuse_port(builder: &UdpBuilder) -> io::Result<()> {
-    // Allow wildcards + specific to not overlap
-    builder.reuse_address(true)?;
+fn set_reuse_port(builder: &UdpBuilder) -> io::Result<()> {
     // Allow multiple listeners on the same port
     builder.reuse_port(true)?;
     Ok(())
 }
 
+/// Get the name of the network interface with the given index.
+#[cfg(unix)]
+pub(crate) fn interface_name(index: u32) -> io::Result<String> {
+    let mut buffer = [0 as libc::c_char; libc::IF_NAMESIZE];
+
+    // SAFETY: The buffer has the size required by `if_indextoname`.
+    let name = unsafe { libc::if_indextoname(index, buffer.as_mut_ptr()) };
+    if name.is_null() {
+        return Err(io::Error::last_os_error());
+    }
+
+    // SAFETY: On success the buffer holds a nul-terminated name.
+    let name = unsafe { std::ffi::CStr::from_ptr(name) };
+    Ok(name.to_string_lossy().into_owned())
+}
+
+#[cfg(not(unix))]
+pub(crate) fn interface_name(_: u32) -> io::Result<String> {
+    Err(io::Error::new(ErrorKind::Unsupported, "Interface Names Are Not Supported"))
+}
+
+/// Restrict the `UdpSocket` to send and receive only through the given interface.
+///
+/// Uses `SO_BINDTODEVICE` on Linux, which is identified by name, and `IP_BOUND_IF` on Apple
+/// platforms, which is identified by index.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub(crate) fn bind_to_device(sock: &UdpSocket, name: &str, _index: u32) -> io::Result<()> {
+    use std::os::fd::AsRawFd as _;
+
+    // SAFETY: The option value points to `name.len()` readable bytes.
+    let result = unsafe {
+        libc::setsockopt(
+            sock.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_BINDTODEVICE,
+            name.as_ptr().cast(),
+            name.len() as libc::socklen_t,
+        )
+    };
+
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+#[cfg(target_vendor = "apple")]
+pub(crate) fn bind_to_device(sock: &UdpSocket, _name: &str, index: u32) -> io::Result<()> {
+    use std::os::fd::AsRawFd as _;
+
+    let (level, option) = match sock.local_addr()? {
+        SocketAddr::V4(_) => (libc::IPPROTO_IP, libc::IP_BOUND_IF),
+        SocketAddr::V6(_) => (libc::IPPROTO_IPV6, libc::IPV6_BOUND_IF),
+    };
+    let index = index as libc::c_uint;
+
+    // SAFETY: The option value points to an initialized `c_uint` of the given size.
+    let result = unsafe {
+        libc::setsockopt(
+            sock.as_raw_fd(),
+            level,
+            option,
+            (&index as *const libc::c_uint).cast(),
+            std::mem::size_of_val(&index) as libc::socklen_t,
+        )
+    };
+
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android", target_vendor = "apple")))]
+pub(crate) fn bind_to_device(_: &UdpSocket, name: &str, _: u32) -> io::Result<()> {
+    // The socket stays bound to the address of the interface only.
+    debug!("Binding to device {} is not supported on this platform", name);
+    Ok(())
+}
+
+/// Report the destination and interface of the packets received on the `UdpSocket`.
+///
+/// Uses `IP_PKTINFO` and `IPV6_RECVPKTINFO` on Linux and Apple platforms, see `pktinfo`.
+#[cfg(any(target_os = "linux", target_os = "android", target_vendor = "apple"))]
+pub(crate) fn enable_packet_info(sock: &UdpSocket) -> io::Result<()> {
+    pktinfo::enable(sock)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android", target_vendor = "apple")))]
+pub(crate) fn enable_packet_info(_: &UdpSocket) -> io::Result<()> {
+    // Packets are received without their destination.
+    Ok(())
+}
+
+/// Receive a packet from the `UdpSocket` with the info enabled by `enable_packet_info`.
+#[cfg(any(target_os = "linux", target_os = "android", target_vendor = "apple"))]
+pub(crate) fn recv_with_info(
+    sock: &UdpSocket,
+    buf: &mut [u8],
+) -> io::Result<(usize, SocketAddr, PacketInfo)> {
+    pktinfo::recv_from(sock, buf)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android", target_vendor = "apple")))]
+pub(crate) fn recv_with_info(
+    sock: &UdpSocket,
+    buf: &mut [u8],
+) -> io::Result<(usize, SocketAddr, PacketInfo)> {
+    let (size, addr) = sock.recv_from(buf)?;
+    Ok((size, addr, PacketInfo::default()))
+}
+
+/// Replace the interface indices of IPv6 addresses with the indices Winsock expects.
+///
+/// The index of an interface as reported by `netdev` is its IPv4 index, see `windows`. Addresses
+/// that are not found keep their index.
+#[cfg(windows)]
+pub(crate) fn with_ipv6_indices(mut addrs: Vec<NetifAddr>) -> Vec<NetifAddr> {
+    let indices = match windows::ipv6_indices() {
+        Ok(indices) => indices,
+        Err(err) => {
+            debug!("Failed to get the IPv6 interface indices: {}", err);
+            return addrs;
+        }
+    };
+
+    for addr in &mut addrs {
+        if let IpAddr::V6(v6) = addr.sock {
+            if let Some(&(_, index)) = indices.iter().find(|(other, _)| *other == v6) {
+                addr.index = index;
+            }
+        }
+    }
+
+    addrs
+}
+
+#[cfg(not(windows))]
+pub(crate) fn with_ipv6_indices(addrs: Vec<NetifAddr>) -> Vec<NetifAddr> {
+    // The interface index is the IPv6 scope id on other platforms.
+    addrs
+}
+
 /// Join a multicast address on the current `UdpSocket`.
 pub fn join_multicast(sock: &UdpSocket, iface: &SocketAddr, mcast_addr: &IpAddr) -> io::Result<()> {
-    match (iface, mcast_addr) {
-        (&SocketAddr::V4(ref i), &IpAddr::V4(ref m)) => sock.join_multicast_v4(m, i.ip()),
-        (&SocketAddr::V6(ref i), &IpAddr::V6(ref m)) => sock.join_multicast_v6(m, i.scope_id()),
+    let result = match (iface, mcast_addr) {
+        (SocketAddr::V4(i), IpAddr::V4(m)) => sock.join_multicast_v4(m, i.ip()),
+        (SocketAddr::V6(i), IpAddr::V6(m)) => sock.join_multicast_v6(m, i.scope_id()),
         _ => Err(io::Error::new(
             ErrorKind::InvalidInput,
             "Multicast And Interface Addresses Are Not The Same Version",
         )),
+    };
+
+    result.map_err(|err| {
+        let label = match iface {
+            SocketAddr::V4(v4) => v4.ip().to_string(),
+            SocketAddr::V6(v6) => iface_label(v6.scope_id()),
+        };
+
+        IoAt::wrap(err, "join", Some(SocketAddr::new(*mcast_addr, 0)), Some(label))
+    })
+}
+
+/// The largest differentiated services code point, which has six bits.
+pub(crate) const MAX_DSCP: u8 = 63;
+
+/// Mark the packets sent from the `UdpSocket` with the differentiated services code point.
+///
+/// The code point is the upper six bits of `IP_TOS` or `IPV6_TCLASS`, the lower two bits of ECN
+/// are left to the operating system.
+#[cfg(unix)]
+pub(crate) fn set_dscp(sock: &UdpSocket, local_addr: &SocketAddr, dscp: u8) -> io::Result<()> {
+    use std::os::fd::AsRawFd as _;
+
+    if dscp > MAX_DSCP {
+        return Err(io::Error::new(ErrorKind::InvalidInput, "DSCP Is Larger Than 63"));
+    }
+
+    let (level, option) = match local_addr {
+        SocketAddr::V4(_) => (libc::IPPROTO_IP, libc::IP_TOS),
+        SocketAddr::V6(_) => (libc::IPPROTO_IPV6, libc::IPV6_TCLASS),
+    };
+    let value = libc::c_int::from(dscp) << 2;
+
+    // SAFETY: The option value points to an initialized `c_int` of the given size.
+    let result = unsafe {
+        libc::setsockopt(
+            sock.as_raw_fd(),
+            level,
+            option,
+            (&value as *const libc::c_int).cast(),
+            std::mem::size_of_val(&value) as libc::socklen_t,
+        )
+    };
+
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub(crate) fn set_dscp(_: &UdpSocket, _: &SocketAddr, dscp: u8) -> io::Result<()> {
+    // Windows ignores `IP_TOS`, marking requires a QoS policy of the system.
+    debug!("Marking packets with DSCP {} is not supported on this platform", dscp);
+    Ok(())
+}
+
+/// Set whether multicast packets sent from the `UdpSocket` are looped back to the local host.
+pub(crate) fn set_multicast_loop(sock: &UdpSocket, local_addr: &SocketAddr, value: bool) -> io::Result<()> {
+    match local_addr {
+        SocketAddr::V4(_) => sock.set_multicast_loop_v4(value),
+        SocketAddr::V6(_) => sock.set_multicast_loop_v6(value),
+    }
+}
+
+/// Set the TTL of multicast packets sent from the `UdpSocket`, the hop limit for IPv6.
+pub(crate) fn set_multicast_ttl(sock: &UdpSocket, local_addr: &SocketAddr, ttl: u32) -> io::Result<()> {
+    match local_addr {
+        SocketAddr::V4(_) => sock.set_multicast_ttl_v4(ttl),
+        SocketAddr::V6(_) => net2::UdpSocketExt::set_multicast_hops_v6(sock, ttl),
     }
 }
 
 /// Leave a multicast address on the current `UdpSocket`.
-#[allow(dead_code)] // TODO: call this from somewhere?
-pub fn leave_multicast(sock: &UdpSocket, iface_addr: &SocketAddr, mcast_addr: &SocketAddr) -> io::Result<()> {
-    match (iface_addr, mcast_addr) {
-        (&SocketAddr::V4(ref i), &SocketAddr::V4(ref m)) => sock.leave_multicast_v4(m.ip(), i.ip()),
-        (&SocketAddr::V6(ref i), &SocketAddr::V6(ref m)) => sock.leave_multicast_v6(m.ip(), i.scope_id()),
+pub(crate) fn leave_multicast(sock: &UdpSocket, iface: &SocketAddr, mcast_addr: &IpAddr) -> io::Result<()> {
+    match (iface, mcast_addr) {
+        (SocketAddr::V4(i), IpAddr::V4(m)) => sock.leave_multicast_v4(m, i.ip()),
+        (SocketAddr::V6(i), IpAddr::V6(m)) => sock.leave_multicast_v6(m, i.scope_id()),
         _ => Err(io::Error::new(
             ErrorKind::InvalidInput,
             "Multicast And Interface Addresses Are Not The Same Version",
@@ -104,10 +628,14 @@ pub fn leave_multicast(sock: &UdpSocket, iface_addr: &SocketAddr, mcast_addr: &S
 
 /// A synchronous stream abstraction.
 ///
-/// Interface taken from: `hyper:0.10`.
+/// Interface taken from: `hyper:0.10`. Messages are written in full and then flushed once, and
+/// every flush must send the bytes written since the previous flush as a single datagram. Reading
+/// is not used, responses are received on sockets of their own.
 pub trait NetworkStream: io::Read + io::Write + Send {
+    /// Get the address the datagrams are sent to.
     fn peer_addr(&mut self) -> Result<SocketAddr, io::Error>;
 
+    /// Send the packet as a single datagram.
     fn send(&mut self, packet: &packet::PacketBuffer) -> Result<(), io::Error> {
         self.write_all(&packet.buffer)?;
         self.flush()?;
@@ -117,13 +645,25 @@ pub trait NetworkStream: io::Read + io::Write + Send {
 
 /// A connector creates a NetworkStream.
 ///
-/// Interface taken from: `hyper:0.10`.
+/// Interface taken from: `hyper:0.10`. Connecting need not perform any IO, there is no handshake
+/// for datagrams, but it should fail for destinations the connector can not reach. A stream is
+/// created for every message sent, so this should be cheap.
 pub trait NetworkConnector {
     /// Type of `Stream` to create
     type Stream: Into<Box<dyn NetworkStream + Send>>;
 
     /// Connect to a remote address.
+    ///
+    /// The host is an IP address, IPv6 addresses may be enclosed in brackets.
     fn connect(&self, host: &str, port: u16) -> io::Result<Self::Stream>;
+
+    /// Connect to a remote socket address.
+    ///
+    /// Unlike `connect` this can keep the scope id of an IPv6 address, which is required to reach
+    /// link-local peers. The default implementation discards it.
+    fn connect_addr(&self, addr: SocketAddr) -> io::Result<Self::Stream> {
+        self.connect(&addr.ip().to_string(), addr.port())
+    }
 }
 
 impl<T: NetworkStream + 'static> From<T> for Box<dyn NetworkStream + Send> {
@@ -134,6 +674,75 @@ impl<T: NetworkStream + 'static> From<T> for Box<dyn NetworkStream + Send> {
 
 #[cfg(test)]
 mod tests {
+    use std::io;
+    use std::net::{Ipv4Addr, SocketAddr, SocketAddrV6, ToSocketAddrs};
+    use std::vec;
+
+    use super::IpVersionMode;
+
+    /// Resolves to the given addresses, as a host name with several records would.
+    struct Resolved(&'static [&'static str]);
+
+    impl ToSocketAddrs for Resolved {
+        type Iter = vec::IntoIter<SocketAddr>;
+
+        fn to_socket_addrs(&self) -> io::Result<Self::Iter> {
+            Ok(self.0.iter().map(|addr| addr.parse().unwrap()).collect::<Vec<_>>().into_iter())
+        }
+    }
+
+    #[test]
+    fn positive_ip_version_mode_from_addr() {
+        let v4_only = Resolved(&["192.0.2.1:1900", "192.0.2.2:1900"]);
+        let v6_only = Resolved(&["[2001:db8::1]:1900"]);
+        let dual = Resolved(&["[2001:db8::1]:1900", "192.0.2.1:1900"]);
+
+        assert_eq!(IpVersionMode::from_addr(v4_only).unwrap(), IpVersionMode::V4Only);
+        assert_eq!(IpVersionMode::from_addr(v6_only).unwrap(), IpVersionMode::V6Only);
+        assert_eq!(IpVersionMode::from_addr(dual).unwrap(), IpVersionMode::Any);
+    }
+
+    #[test]
+    fn negative_ip_version_mode_from_addr() {
+        assert!(IpVersionMode::from_addr(Resolved(&[])).is_err());
+    }
+
+    #[test]
+    fn positive_resolve_families() {
+        let v4: SocketAddr = "192.0.2.1:1900".parse().unwrap();
+        let v6: SocketAddr = "[2001:db8::1]:1900".parse().unwrap();
+
+        let dual = Resolved(&["[2001:db8::1]:1900", "192.0.2.1:1900", "192.0.2.2:1900"]);
+        assert_eq!(super::resolve_families(dual).unwrap(), (v4, Some(v6)));
+        assert_eq!(super::resolve_families(Resolved(&["[2001:db8::1]:1900"])).unwrap(), (v6, None));
+        assert_eq!(super::resolve_families(Resolved(&["192.0.2.1:1900"])).unwrap(), (v4, None));
+    }
+
+    #[test]
+    fn positive_ip_version_mode_from_str() {
+        use super::IpVersionMode;
+
+        assert_eq!("v4".parse(), Ok(IpVersionMode::V4Only));
+        assert_eq!("V6".parse(), Ok(IpVersionMode::V6Only));
+        assert_eq!("any".parse(), Ok(IpVersionMode::Any));
+        assert_eq!(IpVersionMode::V6Only.to_string(), "v6");
+    }
+
+    #[test]
+    fn negative_ip_version_mode_from_str() {
+        assert!("V4Only".parse::<super::IpVersionMode>().is_err());
+    }
+
+    #[test]
+    fn positive_interfaces() {
+        let interfaces = super::interfaces().unwrap();
+
+        assert!(interfaces.iter().any(|iface| !iface.addrs.is_empty()));
+        for iface in &interfaces {
+            assert!(!iface.name.is_empty());
+            assert!(iface.addrs.iter().all(|addr| !addr.is_loopback()));
+        }
+    }
 
     #[test]
     fn positive_addr_from_trait() {
@@ -145,4 +754,74 @@ mod tests {
     fn negative_addr_from_trait() {
         super::addr_from_trait("192.168.0.1").unwrap();
     }
+
+    fn subnet(addr: &str, prefix_len: u8) -> super::Subnet {
+        super::Subnet {
+            addr: addr.parse().unwrap(),
+            prefix_len,
+        }
+    }
+
+    #[test]
+    fn positive_broadcast() {
+        assert_eq!(subnet("192.168.1.7", 24).broadcast(), Some(Ipv4Addr::new(192, 168, 1, 255)));
+        assert_eq!(subnet("127.0.0.1", 8).broadcast(), Some(Ipv4Addr::new(127, 255, 255, 255)));
+        assert_eq!(subnet("10.1.2.3", 30).broadcast(), Some(Ipv4Addr::new(10, 1, 2, 3)));
+        assert_eq!(subnet("0.0.0.0", 0).broadcast(), Some(Ipv4Addr::BROADCAST));
+    }
+
+    #[test]
+    fn negative_broadcast() {
+        assert_eq!(subnet("10.1.2.3", 31).broadcast(), None);
+        assert_eq!(subnet("10.1.2.3", 32).broadcast(), None);
+        assert_eq!(subnet("fd00::2", 64).broadcast(), None);
+    }
+
+    #[test]
+    fn positive_is_local_source() {
+        let subnets = [subnet("192.168.1.7", 24), subnet("fd00::2", 64)];
+
+        assert!(super::is_local_source("192.168.1.200".parse().unwrap(), &subnets));
+        assert!(super::is_local_source("::ffff:192.168.1.200".parse().unwrap(), &subnets));
+        assert!(super::is_local_source("fd00::1234".parse().unwrap(), &subnets));
+        assert!(super::is_local_source("fe80::1".parse().unwrap(), &subnets));
+    }
+
+    #[test]
+    fn negative_is_local_source() {
+        let subnets = [subnet("192.168.1.7", 24), subnet("fd00::2", 64)];
+
+        assert!(!super::is_local_source("192.168.2.1".parse().unwrap(), &subnets));
+        assert!(!super::is_local_source("203.0.113.9".parse().unwrap(), &subnets));
+        assert!(!super::is_local_source("fd00:0:0:1::1".parse().unwrap(), &subnets));
+        assert!(!super::is_local_source("2001:db8::1".parse().unwrap(), &[]));
+    }
+
+    #[test]
+    fn positive_with_local_scope() {
+        let dst = SocketAddrV6::new("fe80::1".parse().unwrap(), 1900, 0, 0);
+        let local = SocketAddrV6::new("fe80::2".parse().unwrap(), 0, 0, 4);
+
+        let scoped = super::with_local_scope(dst.into(), local.into());
+        assert_eq!(scoped, SocketAddrV6::new("fe80::1".parse().unwrap(), 1900, 0, 4).into());
+    }
+
+    #[test]
+    fn positive_with_local_scope_keeps_scope() {
+        let dst = SocketAddrV6::new("fe80::1".parse().unwrap(), 1900, 0, 7);
+        let local = SocketAddrV6::new("fe80::2".parse().unwrap(), 0, 0, 4);
+
+        assert_eq!(super::with_local_scope(dst.into(), local.into()), dst.into());
+    }
+
+    #[test]
+    fn positive_unmap_v4() {
+        let mapped: SocketAddr = "[::ffff:192.168.1.2]:1900".parse().unwrap();
+        assert_eq!(super::unmap_v4(mapped), "192.168.1.2:1900".parse().unwrap());
+
+        let v6: SocketAddr = "[fe80::1%4]:1900".parse().unwrap();
+        assert_eq!(super::unmap_v4(v6), v6);
+        let v4: SocketAddr = "192.168.1.2:1900".parse().unwrap();
+        assert_eq!(super::unmap_v4(v4), v4);
+    }
 }
@@ -1,8 +1,11 @@
+use std::error;
 use std::fmt;
 use std::io::{self, Error, ErrorKind};
-use std::net::{SocketAddr, UdpSocket};
+use std::net::{IpAddr, SocketAddr, UdpSocket};
 use std::sync::Arc;
 
+use crate::net;
+
 /// Maximum length for packets received on a `PacketReceiver`.
 pub const MAX_PCKT_LEN: usize = 1500;
 
@@ -13,6 +16,15 @@ pub const MAX_PCKT_LEN: usize = 1500;
 /// See `net::packet::MAX_PCKT_LEN`.
 pub struct PacketReceiver(Arc<UdpSocket>);
 
+/// Where a packet arrived, as far as the platform reports it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct PacketInfo {
+    /// The destination of the packet, one of our addresses or the multicast group.
+    pub(crate) local_dst: Option<IpAddr>,
+    /// The index of the interface the packet arrived on.
+    pub(crate) iface_index: Option<u32>,
+}
+
 /// An owned buffer suitable for packet.
 #[derive(Clone)]
 pub struct PacketBuffer {
@@ -20,35 +32,57 @@ pub struct PacketBuffer {
     pub(crate) mmu: usize,
 }
 
+/// The error of writing more than the limit of a `PacketBuffer`.
+///
+/// Wrapped in an `io::Error` of kind `WriteZero`, which converts to `SSDPError::PacketTooLarge`.
+#[derive(Debug)]
+pub(crate) struct PacketOverflow {
+    /// The size the packet would have had, at least.
+    pub(crate) size: usize,
+    /// Unknown for a packet the OS refused to send.
+    pub(crate) limit: Option<usize>,
+}
+
 impl Default for PacketBuffer {
     fn default() -> Self {
-        PacketBuffer {
-            buffer: vec![],
-            mmu: MAX_PCKT_LEN,
-        }
+        PacketBuffer::with_capacity(MAX_PCKT_LEN)
     }
 }
 
 impl PacketReceiver {
     /// Create a new PacketReceiver from the given UdpSocket.
+    ///
+    /// Enables the packet info of the socket where the platform supports it, see
+    /// `recv_pckt_info`.
     pub fn new(udp: Arc<UdpSocket>) -> PacketReceiver {
+        if let Err(err) = net::enable_packet_info(&udp) {
+            debug!("Packets at {:?} are received without their destination: {}", udp.local_addr(), err);
+        }
+
         PacketReceiver(udp)
     }
 
     /// Receive a packet from the underlying connection.
     pub fn recv_pckt(&self) -> io::Result<(Vec<u8>, SocketAddr)> {
+        self.recv_pckt_info().map(|(bytes, addr, _)| (bytes, addr))
+    }
+
+    /// Receive a packet together with the address it was sent to and the interface it arrived on.
+    ///
+    /// The info is empty where the platform does not report it.
+    pub(crate) fn recv_pckt_info(&self) -> io::Result<(Vec<u8>, SocketAddr, PacketInfo)> {
         let mut pckt_buf = vec![0u8; MAX_PCKT_LEN];
 
-        let (size, addr) = self.0.recv_from(&mut pckt_buf)?;
+        let (size, addr, info) = net::recv_with_info(&self.0, &mut pckt_buf)?;
 
         // Check For Something That SHOULD NEVER Occur.
         if size > pckt_buf.len() {
-            Err(Error::new(ErrorKind::Other, "UdpSocket Reported Receive Length Greater Than Buffer"))
+            Err(Error::other("UdpSocket Reported Receive Length Greater Than Buffer"))
         } else {
             // `truncate` does not reallocate the vec's backing storage
             pckt_buf.truncate(size);
 
-            Ok((pckt_buf, addr))
+            Ok((pckt_buf, net::unmap_v4(addr), info))
         }
     }
 }
@@ -63,6 +97,14 @@ impl fmt::Display for PacketReceiver {
 }
 
 impl PacketBuffer {
+    /// Create an empty buffer for packets of at most `limit` bytes.
+    pub fn with_capacity(limit: usize) -> PacketBuffer {
+        PacketBuffer {
+            buffer: vec![],
+            mmu: limit,
+        }
+    }
+
     pub fn as_slice(&self) -> &[u8] {
         self.buffer.as_slice()
     }
@@ -70,37 +112,122 @@ impl PacketBuffer {
     pub fn clear(&mut self) {
         self.buffer.clear();
     }
+
+    /// Check that `len` more bytes fit into the buffer.
+    fn reserve(&self, len: usize) -> io::Result<()> {
+        let size = self.buffer.len() + len;
+
+        if size > self.mmu {
+            let overflow = PacketOverflow {
+                size,
+                limit: Some(self.mmu),
+            };
+            return Err(Error::new(ErrorKind::WriteZero, overflow));
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for PacketOverflow {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.limit {
+            Some(limit) => write!(f, "packet of {} bytes exceeds the limit of {} bytes", self.size, limit),
+            None => write!(f, "packet of {} bytes exceeds the limit of the OS", self.size),
+        }
+    }
 }
 
+impl error::Error for PacketOverflow {}
+
+/// Writes either succeed completely or fail, a packet is never truncated.
 impl io::Write for PacketBuffer {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        let space = self.mmu.saturating_sub(self.buffer.len());
-        let take = buf.len().min(space);
-        self.buffer.extend_from_slice(&buf[..take]);
-        Ok(take)
+        self.reserve(buf.len())?;
+        self.buffer.extend_from_slice(buf);
+        Ok(buf.len())
     }
 
     fn write_vectored(&mut self, bufs: &[io::IoSlice<'_>]) -> io::Result<usize> {
-        let mut space = self.mmu.saturating_sub(self.buffer.len());
-        let mut written = 0;
+        let len = bufs.iter().map(|slice| slice.len()).sum();
+        self.reserve(len)?;
 
         for slice in bufs {
-            let take = slice.len().min(space);
-            self.buffer.extend_from_slice(&slice[..take]);
-            let done = take == space;
-
-            written += take;
-            space -= take;
-
-            if done {
-                break;
-            }
+            self.buffer.extend_from_slice(slice);
         }
 
-        Ok(written)
+        Ok(len)
     }
 
     fn flush(&mut self) -> io::Result<()> {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io::{ErrorKind, Write as _};
+    #[cfg(target_os = "linux")]
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, UdpSocket};
+    #[cfg(target_os = "linux")]
+    use std::sync::Arc;
+
+    use super::PacketBuffer;
+    #[cfg(target_os = "linux")]
+    use super::PacketReceiver;
+
+    #[test]
+    fn positive_write_within_limit() {
+        let mut buffer = PacketBuffer::with_capacity(8);
+
+        buffer.write_all(b"NOTIFY").unwrap();
+        buffer.write_all(b" *").unwrap();
+
+        assert_eq!(buffer.as_slice(), b"NOTIFY *");
+    }
+
+    #[test]
+    fn negative_write_over_limit() {
+        let mut buffer = PacketBuffer::with_capacity(8);
+        buffer.write_all(b"NOTIFY").unwrap();
+
+        let err = buffer.write_all(b" * HTTP/1.1").unwrap_err();
+
+        assert_eq!(err.kind(), ErrorKind::WriteZero);
+        assert_eq!(buffer.as_slice(), b"NOTIFY");
+    }
+
+    /// All of 127.0.0.0/8 is local on Linux, each address stands for one of several of ours.
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn positive_packet_info_loopback_aliases() {
+        let socket = Arc::new(UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).unwrap());
+        let port = socket.local_addr().unwrap().port();
+        let receiver = PacketReceiver::new(socket);
+        let sender = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+
+        for alias in [Ipv4Addr::new(127, 0, 0, 2), Ipv4Addr::new(127, 0, 0, 3)] {
+            sender.send_to(b"M-SEARCH", (alias, port)).unwrap();
+
+            let (bytes, peer, info) = receiver.recv_pckt_info().unwrap();
+            assert_eq!(bytes, b"M-SEARCH");
+            assert_eq!(peer, sender.local_addr().unwrap());
+            assert_eq!(info.local_dst, Some(IpAddr::V4(alias)));
+            assert!(info.iface_index.is_some_and(|index| index != 0));
+        }
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn positive_packet_info_v6() {
+        let socket = Arc::new(UdpSocket::bind((Ipv6Addr::UNSPECIFIED, 0)).unwrap());
+        let port = socket.local_addr().unwrap().port();
+        let receiver = PacketReceiver::new(socket);
+        let sender = UdpSocket::bind((Ipv6Addr::LOCALHOST, 0)).unwrap();
+
+        sender.send_to(b"M-SEARCH", (Ipv6Addr::LOCALHOST, port)).unwrap();
+
+        let (_, _, info) = receiver.recv_pckt_info().unwrap();
+        assert_eq!(info.local_dst, Some(IpAddr::V6(Ipv6Addr::LOCALHOST)));
+    }
+}
@@ -0,0 +1,134 @@
+//! Receiving packets together with their destination address and interface.
+//!
+//! `recv_from` only reports the sender of a packet. A socket bound to the wildcard address
+//! receives for all local addresses and interfaces, which of them a packet was sent to is only
+//! reported as a control message of `recvmsg` once `IP_PKTINFO` or `IPV6_RECVPKTINFO` is enabled.
+
+use std::io;
+use std::mem;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6, UdpSocket};
+use std::os::fd::AsRawFd as _;
+use std::ptr;
+
+use crate::net::packet::PacketInfo;
+
+/// Room for the control messages, more than `CMSG_SPACE` of either packet info.
+///
+/// Backed by `u64` for the alignment of the headers written into it.
+const CONTROL_WORDS: usize = 16;
+
+/// Ask the OS to report the destination and interface of every packet received on the socket.
+pub(crate) fn enable(sock: &UdpSocket) -> io::Result<()> {
+    let (level, option) = match sock.local_addr()? {
+        SocketAddr::V4(_) => (libc::IPPROTO_IP, libc::IP_PKTINFO),
+        SocketAddr::V6(_) => (libc::IPPROTO_IPV6, libc::IPV6_RECVPKTINFO),
+    };
+    let value: libc::c_int = 1;
+
+    // SAFETY: The option value points to an initialized `c_int` of the given size.
+    let result = unsafe {
+        libc::setsockopt(
+            sock.as_raw_fd(),
+            level,
+            option,
+            (&value as *const libc::c_int).cast(),
+            mem::size_of_val(&value) as libc::socklen_t,
+        )
+    };
+
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+/// Receive a packet like `recv_from`, with the packet info the socket was enabled for.
+pub(crate) fn recv_from(sock: &UdpSocket, buf: &mut [u8]) -> io::Result<(usize, SocketAddr, PacketInfo)> {
+    // SAFETY: All-zero is a valid value of these plain C structures.
+    let mut name: libc::sockaddr_storage = unsafe { mem::zeroed() };
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    let mut control = [0u64; CONTROL_WORDS];
+    let mut iov = libc::iovec {
+        iov_base: buf.as_mut_ptr().cast(),
+        iov_len: buf.len(),
+    };
+
+    msg.msg_name = (&mut name as *mut libc::sockaddr_storage).cast();
+    msg.msg_namelen = mem::size_of_val(&name) as libc::socklen_t;
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr().cast();
+    msg.msg_controllen = mem::size_of_val(&control) as _;
+
+    // SAFETY: The header points to the buffers above, which outlive the call.
+    let size = unsafe { libc::recvmsg(sock.as_raw_fd(), &mut msg, 0) };
+    if size < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let peer = socket_addr(&name)?;
+    // SAFETY: The control buffer was filled by `recvmsg` as the header describes.
+    let info = unsafe { packet_info(&msg) };
+
+    Ok((size as usize, peer, info))
+}
+
+/// Convert the address written by `recvmsg`.
+fn socket_addr(name: &libc::sockaddr_storage) -> io::Result<SocketAddr> {
+    match libc::c_int::from(name.ss_family) {
+        libc::AF_INET => {
+            // SAFETY: The storage holds a `sockaddr_in` for this family and is suitably aligned.
+            let addr = unsafe { &*(name as *const libc::sockaddr_storage).cast::<libc::sockaddr_in>() };
+            let ip = Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr));
+            Ok(SocketAddr::V4(SocketAddrV4::new(ip, u16::from_be(addr.sin_port))))
+        }
+        libc::AF_INET6 => {
+            // SAFETY: The storage holds a `sockaddr_in6` for this family and is suitably aligned.
+            let addr = unsafe { &*(name as *const libc::sockaddr_storage).cast::<libc::sockaddr_in6>() };
+            Ok(SocketAddr::V6(SocketAddrV6::new(
+                Ipv6Addr::from(addr.sin6_addr.s6_addr),
+                u16::from_be(addr.sin6_port),
+                addr.sin6_flowinfo,
+                addr.sin6_scope_id,
+            )))
+        }
+        family => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Unexpected Address Family {}", family),
+        )),
+    }
+}
+
+/// Read the packet info from the control messages.
+///
+/// # Safety
+///
+/// The header must describe control messages written by `recvmsg`.
+unsafe fn packet_info(msg: &libc::msghdr) -> PacketInfo {
+    let mut info = PacketInfo::default();
+    let mut cmsg = libc::CMSG_FIRSTHDR(msg);
+
+    while let Some(header) = cmsg.as_ref() {
+        let data = libc::CMSG_DATA(cmsg);
+
+        match (header.cmsg_level, header.cmsg_type) {
+            (libc::IPPROTO_IP, libc::IP_PKTINFO) => {
+                let pktinfo = ptr::read_unaligned(data.cast::<libc::in_pktinfo>());
+                info.local_dst = Some(IpAddr::V4(Ipv4Addr::from(u32::from_be(pktinfo.ipi_addr.s_addr))));
+                info.iface_index = Some(pktinfo.ipi_ifindex as u32);
+            }
+            (libc::IPPROTO_IPV6, libc::IPV6_PKTINFO) => {
+                let pktinfo = ptr::read_unaligned(data.cast::<libc::in6_pktinfo>());
+                // A dual-stack socket reports IPv4 destinations as mapped addresses.
+                info.local_dst = Some(IpAddr::V6(Ipv6Addr::from(pktinfo.ipi6_addr.s6_addr)).to_canonical());
+                info.iface_index = Some(pktinfo.ipi6_ifindex as u32);
+            }
+            _ => {}
+        }
+
+        cmsg = libc::CMSG_NXTHDR(msg, cmsg);
+    }
+
+    info
+}
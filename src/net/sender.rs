@@ -1,7 +1,46 @@
-use crate::net::{self, NetworkStream};
+use crate::context::SsdpObserver;
+use crate::net::packet::PacketOverflow;
+use crate::net::{self, IoAt, NetworkStream};
 use std::io::{self, ErrorKind, Read, Write};
 use std::net::{SocketAddr, UdpSocket};
 use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// The OS error of a send while the buffers of the network interface are full.
+#[cfg(unix)]
+const ENOBUFS: i32 = libc::ENOBUFS;
+#[cfg(windows)]
+const ENOBUFS: i32 = 10055; // WSAENOBUFS
+
+/// The OS error of a datagram that is larger than the network allows.
+#[cfg(unix)]
+const EMSGSIZE: i32 = libc::EMSGSIZE;
+#[cfg(windows)]
+const EMSGSIZE: i32 = 10040; // WSAEMSGSIZE
+
+/// How often and how quickly a send is retried after a transient error.
+///
+/// Sends are retried after `ENOBUFS`, which macOS reports under load, and after `WouldBlock` and
+/// `Interrupted`. The first retry waits for the delay, every following one twice as long as the
+/// one before. Immediate sends wait in the sending thread, while the packets of a
+/// `ScheduledSender` are scheduled again by the clock of its context.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RetryPolicy {
+    /// The number of sends including the first one, at least one send is always made.
+    pub attempts: u32,
+    pub delay: Duration,
+}
+
+/// The send operation of a socket, which tests replace to inject failures.
+pub(crate) trait SendTo {
+    fn send_to(&self, buf: &[u8], dst: SocketAddr) -> io::Result<usize>;
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Err(ErrorKind::Unsupported.into())
+    }
+}
 
 /// A type that wraps a `UdpSocket` and a `SocketAddr` and implements the `NetworkStream`
 /// trait.
@@ -10,10 +49,125 @@ use std::sync::Arc;
 /// used for intercepting Http messages from Hyper and sending them out via Udp.
 /// The response(s) from client(s) are to be handled by some other object that
 /// has a cloned handle to our internal `UdpSocket` handle.
+///
+/// Clones share the socket, cloning never duplicates a file descriptor and thus can not fail.
+#[derive(Clone)]
 pub struct UdpSender {
     udp: Arc<UdpSocket>,
     dst: SocketAddr,
     buf: net::packet::PacketBuffer,
+    observer: Option<Arc<dyn SsdpObserver>>,
+    retry: RetryPolicy,
+}
+
+impl RetryPolicy {
+    /// Send the datagram, retrying after transient errors.
+    ///
+    /// A datagram the OS rejects as too large fails with a `PacketOverflow`, which converts to
+    /// `SSDPError::PacketTooLarge`.
+    pub(crate) fn send<S>(&self, socket: &S, data: &[u8], dst: SocketAddr) -> io::Result<usize>
+    where
+        S: SendTo + ?Sized,
+    {
+        for attempt in 1.. {
+            let err = match send_once(socket, data, dst) {
+                Ok(sent) => return Ok(sent),
+                Err(err) => err,
+            };
+
+            let Some(delay) = self.retry_delay(attempt, &err) else {
+                return Err(err);
+            };
+
+            trace!("Retrying send to {} in {:?} after {}", dst, delay, err);
+            thread::sleep(delay);
+        }
+
+        unreachable!("the attempts are bounded by `u32`")
+    }
+
+    /// Get the delay before retrying the attempt, counted from one, that failed with the error.
+    ///
+    /// Returns `None` if the error is not transient or no attempts are left.
+    pub(crate) fn retry_delay(&self, attempt: u32, err: &io::Error) -> Option<Duration> {
+        if attempt >= self.attempts || !is_transient(err) {
+            return None;
+        }
+
+        Some(self.delay.saturating_mul(2u32.saturating_pow(attempt - 1)))
+    }
+}
+
+/// Send the datagram once.
+///
+/// A datagram the OS rejects as too large fails with a `PacketOverflow`.
+pub(crate) fn send_once<S>(socket: &S, data: &[u8], dst: SocketAddr) -> io::Result<usize>
+where
+    S: SendTo + ?Sized,
+{
+    socket.send_to(data, dst).map_err(|err| {
+        if err.raw_os_error() != Some(EMSGSIZE) {
+            return err;
+        }
+
+        // The OS does not tell its limit, only that the datagram is over it.
+        let overflow = PacketOverflow {
+            size: data.len(),
+            limit: None,
+        };
+        io::Error::new(ErrorKind::InvalidInput, overflow)
+    })
+}
+
+/// Attach the addresses of the socket and the destination to the error of a send.
+///
+/// A `PacketOverflow` is passed on unchanged, such that it still converts to
+/// `SSDPError::PacketTooLarge`.
+pub(crate) fn send_error<S>(err: io::Error, socket: &S, dst: SocketAddr) -> io::Error
+where
+    S: SendTo + ?Sized,
+{
+    if err.get_ref().is_some_and(|inner| inner.is::<PacketOverflow>()) {
+        return err;
+    }
+
+    let local = socket.local_addr().ok().map(|addr| addr.ip().to_string());
+    IoAt::wrap(err, "send to", Some(dst), local)
+}
+
+/// Report a datagram that was sent through the socket to the observer, if any.
+pub(crate) fn observe_send<S>(observer: Option<&Arc<dyn SsdpObserver>>, socket: &S, data: &[u8])
+where
+    S: SendTo + ?Sized,
+{
+    if let (Some(observer), Ok(local)) = (observer, socket.local_addr()) {
+        observer.on_send(&local, data);
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            attempts: 3,
+            delay: Duration::from_millis(10),
+        }
+    }
+}
+
+impl SendTo for UdpSocket {
+    fn send_to(&self, buf: &[u8], dst: SocketAddr) -> io::Result<usize> {
+        UdpSocket::send_to(self, buf, dst)
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        UdpSocket::local_addr(self)
+    }
+}
+
+/// Check if a send failing with the error may succeed shortly after.
+fn is_transient(err: &io::Error) -> bool {
+    let kind = err.kind();
+    kind == ErrorKind::WouldBlock || kind == ErrorKind::Interrupted || err.raw_os_error() == Some(ENOBUFS)
 }
 
 impl UdpSender {
@@ -23,8 +177,22 @@ impl UdpSender {
             udp,
             dst,
             buf: Default::default(),
+            observer: None,
+            retry: RetryPolicy::default(),
         }
     }
+
+    /// Retry sends after transient errors as the policy says.
+    pub(crate) fn with_retry(mut self, retry: RetryPolicy) -> UdpSender {
+        self.retry = retry;
+        self
+    }
+
+    /// Report the packets sent to the observer.
+    pub(crate) fn with_observer(mut self, observer: Option<Arc<dyn SsdpObserver>>) -> UdpSender {
+        self.observer = observer;
+        self
+    }
 }
 
 impl NetworkStream for UdpSender {
@@ -45,28 +213,135 @@ impl Write for UdpSender {
         self.buf.write(buf)
     }
 
+    /// Send the buffered datagram.
+    ///
+    /// The buffer is only cleared once the datagram was sent, a failed flush can be repeated.
     fn flush(&mut self) -> io::Result<()> {
         let data = self.buf.as_slice();
-        let result = self.udp.send_to(data, self.dst);
+        self.retry
+            .send(&*self.udp, data, self.dst)
+            .map_err(|err| send_error(err, &*self.udp, self.dst))?;
+        observe_send(self.observer.as_ref(), &*self.udp, data);
 
         debug!("Sent HTTP Request:\n{}", String::from_utf8_lossy(data));
         self.buf.clear();
 
-        result.map(|_| ())
+        Ok(())
     }
 }
 
-impl Clone for UdpSender {
-    fn clone(&self) -> UdpSender {
-        UdpSender {
-            udp: Arc::clone(&self.udp),
-            dst: self.dst,
-            buf: self.buf.clone(),
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::io::{self, ErrorKind};
+    use std::io::Write as _;
+    use std::net::{SocketAddr, UdpSocket};
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    use super::{RetryPolicy, SendTo, UdpSender, EMSGSIZE, ENOBUFS};
+    use crate::SSDPError;
+
+    /// Fails with the scripted errors, then succeeds, recording every datagram.
+    struct FailingSocket {
+        errors: RefCell<Vec<io::Error>>,
+        sent: RefCell<Vec<Vec<u8>>>,
+    }
+
+    impl FailingSocket {
+        fn new(errors: Vec<io::Error>) -> FailingSocket {
+            FailingSocket {
+                errors: RefCell::new(errors),
+                sent: RefCell::new(Vec::new()),
+            }
         }
     }
 
-    fn clone_from(&mut self, source: &UdpSender) {
-        self.udp = Arc::clone(&self.udp);
-        self.dst = source.dst;
+    impl SendTo for FailingSocket {
+        fn send_to(&self, buf: &[u8], _: SocketAddr) -> io::Result<usize> {
+            self.sent.borrow_mut().push(buf.to_vec());
+
+            let mut errors = self.errors.borrow_mut();
+            if errors.is_empty() {
+                Ok(buf.len())
+            } else {
+                Err(errors.remove(0))
+            }
+        }
+    }
+
+    fn dst() -> SocketAddr {
+        "239.255.255.250:1900".parse().unwrap()
+    }
+
+    fn policy(attempts: u32) -> RetryPolicy {
+        RetryPolicy {
+            attempts,
+            delay: Duration::from_millis(1),
+        }
+    }
+
+    #[test]
+    fn positive_clone_shares_socket() {
+        let socket = Arc::new(UdpSocket::bind("127.0.0.1:0").unwrap());
+        let mut sender = UdpSender::new(Arc::clone(&socket), dst());
+        sender.write_all(b"NOTIFY").unwrap();
+
+        let clone = sender.clone();
+        assert!(Arc::ptr_eq(&clone.udp, &socket));
+        assert_eq!(clone.buf.as_slice(), b"NOTIFY");
+
+        let other = Arc::new(UdpSocket::bind("127.0.0.1:0").unwrap());
+        let mut target = UdpSender::new(other, "127.0.0.1:1900".parse().unwrap());
+        target.clone_from(&sender);
+        assert!(Arc::ptr_eq(&target.udp, &socket));
+        assert_eq!(target.dst, dst());
+        assert_eq!(target.buf.as_slice(), b"NOTIFY");
+    }
+
+    #[test]
+    fn positive_retry_transient() {
+        let socket = FailingSocket::new(vec![
+            io::Error::from_raw_os_error(ENOBUFS),
+            ErrorKind::WouldBlock.into(),
+        ]);
+
+        let start = Instant::now();
+        assert_eq!(policy(3).send(&socket, b"packet", dst()).unwrap(), 6);
+        // Waited for 1ms and then 2ms.
+        assert!(start.elapsed() >= Duration::from_millis(3));
+        assert_eq!(*socket.sent.borrow(), vec![b"packet".to_vec(); 3]);
+    }
+
+    #[test]
+    fn negative_retry_exhausted() {
+        let socket = FailingSocket::new((0..3).map(|_| ErrorKind::Interrupted.into()).collect());
+
+        let err = policy(3).send(&socket, b"packet", dst()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Interrupted);
+        assert_eq!(socket.sent.borrow().len(), 3);
+    }
+
+    #[test]
+    fn negative_no_retry() {
+        let socket = FailingSocket::new(vec![ErrorKind::PermissionDenied.into()]);
+        assert!(policy(3).send(&socket, b"packet", dst()).is_err());
+        assert_eq!(socket.sent.borrow().len(), 1);
+
+        let socket = FailingSocket::new(vec![ErrorKind::WouldBlock.into()]);
+        assert!(policy(0).send(&socket, b"packet", dst()).is_err());
+        assert_eq!(socket.sent.borrow().len(), 1);
+    }
+
+    #[test]
+    fn negative_message_size() {
+        let socket = FailingSocket::new(vec![io::Error::from_raw_os_error(EMSGSIZE)]);
+
+        let err = policy(3).send(&socket, &[0; 2000], dst()).unwrap_err();
+        assert_eq!(socket.sent.borrow().len(), 1);
+        match SSDPError::from(err) {
+            SSDPError::PacketTooLarge { size: 2000, limit: None } => {}
+            other => panic!("unexpected error: {}", other),
+        }
     }
 }
@@ -0,0 +1,129 @@
+//! The IPv6 interface indices of the network adapters, as Winsock expects them.
+//!
+//! `netdev` reports the `IfIndex` of an adapter, which is its IPv4 index. Winsock identifies the
+//! interface of IPv6 multicast by the `Ipv6IfIndex` instead, which may differ and which is the
+//! only index of an adapter without IPv4. Joining a group or choosing the multicast interface with
+//! the wrong one fails with `WSAEINVAL`.
+
+use std::ffi::c_void;
+use std::io;
+use std::net::Ipv6Addr;
+use std::ptr;
+
+const AF_INET6: u32 = 23;
+const ERROR_SUCCESS: u32 = 0;
+const ERROR_BUFFER_OVERFLOW: u32 = 111;
+const GAA_FLAG_SKIP_ANYCAST: u32 = 0x2;
+const GAA_FLAG_SKIP_MULTICAST: u32 = 0x4;
+const GAA_FLAG_SKIP_DNS_SERVER: u32 = 0x8;
+
+/// The adapters may change between sizing the buffer and filling it, so this is retried.
+const MAX_ATTEMPTS: usize = 3;
+
+/// The leading fields of `IP_ADAPTER_ADDRESSES_LH`, up to and including the IPv6 index.
+///
+/// Only some fields are read, the others define the layout.
+#[repr(C)]
+#[allow(dead_code)]
+struct AdapterAddresses {
+    length: u32,
+    if_index: u32,
+    next: *const AdapterAddresses,
+    adapter_name: *const u8,
+    first_unicast_address: *const UnicastAddress,
+    first_anycast_address: *const c_void,
+    first_multicast_address: *const c_void,
+    first_dns_server_address: *const c_void,
+    dns_suffix: *const u16,
+    description: *const u16,
+    friendly_name: *const u16,
+    physical_address: [u8; 8],
+    physical_address_length: u32,
+    flags: u32,
+    mtu: u32,
+    if_type: u32,
+    oper_status: i32,
+    ipv6_if_index: u32,
+}
+
+/// The leading fields of `IP_ADAPTER_UNICAST_ADDRESS_LH`, up to and including the address.
+#[repr(C)]
+#[allow(dead_code)]
+struct UnicastAddress {
+    length: u32,
+    flags: u32,
+    next: *const UnicastAddress,
+    sockaddr: *const SockaddrIn6,
+    sockaddr_length: i32,
+}
+
+#[repr(C)]
+#[allow(dead_code)]
+struct SockaddrIn6 {
+    family: u16,
+    port: u16,
+    flowinfo: u32,
+    addr: [u8; 16],
+    scope_id: u32,
+}
+
+#[link(name = "iphlpapi")]
+extern "system" {
+    fn GetAdaptersAddresses(
+        family: u32,
+        flags: u32,
+        reserved: *mut c_void,
+        addresses: *mut AdapterAddresses,
+        size: *mut u32,
+    ) -> u32;
+}
+
+/// Get the IPv6 addresses of all adapters with the IPv6 index of their adapter.
+pub(crate) fn ipv6_indices() -> io::Result<Vec<(Ipv6Addr, u32)>> {
+    let flags = GAA_FLAG_SKIP_ANYCAST | GAA_FLAG_SKIP_MULTICAST | GAA_FLAG_SKIP_DNS_SERVER;
+    // The recommended initial size, which avoids a second call on most systems.
+    let mut size: u32 = 15 * 1024;
+
+    for _ in 0..MAX_ATTEMPTS {
+        // Backed by `u64` for the alignment of the structures written into it.
+        let mut buffer = vec![0u64; (size as usize).div_ceil(8)];
+        let first = buffer.as_mut_ptr().cast::<AdapterAddresses>();
+
+        // SAFETY: The buffer is writable for `size` bytes and suitably aligned.
+        let result = unsafe { GetAdaptersAddresses(AF_INET6, flags, ptr::null_mut(), first, &mut size) };
+        match result {
+            ERROR_SUCCESS => {
+                // SAFETY: On success the buffer holds a valid list of adapters.
+                return Ok(unsafe { collect_indices(first) });
+            }
+            ERROR_BUFFER_OVERFLOW => continue,
+            code => return Err(io::Error::from_raw_os_error(code as i32)),
+        }
+    }
+
+    Err(io::Error::other("The Network Adapters Kept Changing"))
+}
+
+/// Walk the adapters and their unicast addresses.
+///
+/// # Safety
+///
+/// The pointer must be null or point to the list written by `GetAdaptersAddresses`.
+unsafe fn collect_indices(mut adapter: *const AdapterAddresses) -> Vec<(Ipv6Addr, u32)> {
+    let mut indices = Vec::new();
+
+    while let Some(current) = adapter.as_ref() {
+        let mut unicast = current.first_unicast_address;
+        while let Some(address) = unicast.as_ref() {
+            if let Some(sockaddr) = address.sockaddr.as_ref() {
+                if u32::from(sockaddr.family) == AF_INET6 && current.ipv6_if_index != 0 {
+                    indices.push((Ipv6Addr::from(sockaddr.addr), current.ipv6_if_index));
+                }
+            }
+            unicast = address.next;
+        }
+        adapter = current.next;
+    }
+
+    indices
+}
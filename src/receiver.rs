@@ -1,31 +1,170 @@
 //! Primitives for non-blocking SSDP message receiving.
 
+use std::collections::{HashSet, VecDeque};
+use std::fmt;
 use std::io;
-use std::net::{SocketAddr, UdpSocket};
+use std::iter::Map;
+use std::net::{IpAddr, SocketAddr, UdpSocket};
 use std::result::Result;
-use std::sync::mpsc::{self, Iter, Receiver, RecvError, Sender, TryRecvError};
-use std::sync::Arc;
-use std::thread;
-use std::time::Duration;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{
+    self, Iter, Receiver, RecvError, RecvTimeoutError, SendError, Sender, SyncSender, TryRecvError,
+};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-use crate::net::packet::PacketReceiver;
-use crate::SSDPResult;
+use crate::context::{SsdpContext, SsdpObserver};
+use crate::header::USN;
+use crate::limit::{RateLimit, RateLimiter};
+use crate::message::{self, Config, LocationPolicy, RawFilter, RawSSDPMessage};
+use crate::net::{self, Subnet};
+use crate::net::packet::{PacketInfo, PacketReceiver};
+use crate::{SSDPError, SSDPResult};
+
+/// How often the socket threads check whether the receiver was cancelled.
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How long dropping a receiver waits for its socket threads to exit.
+const DROP_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How long a deduplicating receiver remembers a USN, the max-age recommended by `UPnP`.
+const SEEN_USN_LIFETIME: Duration = Duration::from_secs(1800);
+
+/// The number of USNs a deduplicating receiver remembers at most.
+const MAX_SEEN_USNS: usize = 4096;
 
 /// Trait for constructing an object from some serialized SSDP message.
+///
+/// This trait is open for implementation, which allows receiving custom message types. Methods
+/// added in a minor release will have a default implementation.
+///
+/// To parse a message outside of a receiver, the message types of this crate also implement
+/// `TryFrom<&[u8]>` and `FromStr`.
+///
+/// ```
+/// use ssdp::{FromRawSSDP, SSDPResult};
+///
+/// /// Keeps the packet as it was received.
+/// struct RawPacket(Vec<u8>);
+///
+/// impl FromRawSSDP for RawPacket {
+///     fn from_packet(bytes: &[u8]) -> SSDPResult<Self> {
+///         Ok(RawPacket(bytes.to_vec()))
+///     }
+/// }
+/// ```
 pub trait FromRawSSDP: Sized {
     /// Construct from a request, i.e. an advertise packet sent to the multicast address or a
     /// search sent that or a search to us directly as a unicast or a result of a search.
     fn from_packet(bytes: &[u8]) -> SSDPResult<Self>;
+
+    /// Construct from a packet, parsing it within the given limits.
+    ///
+    /// The default implementation ignores the options.
+    fn from_packet_with_options(bytes: &[u8], options: &ParseOptions) -> SSDPResult<Self> {
+        let _ = options;
+        Self::from_packet(bytes)
+    }
+
+    /// Check if the message is plausible for the peer that sent it.
+    ///
+    /// Receivers drop messages that fail this check. The default implementation accepts all.
+    fn verify_peer(&self, peer: &SocketAddr, options: &ParseOptions) -> bool {
+        let _ = (peer, options);
+        true
+    }
+
+    /// Check if the message was sent by the device with the UUID.
+    ///
+    /// Receivers drop the messages of their own device, see `Config::set_self_uuid`. The default
+    /// implementation returns `false`.
+    fn is_from_device(&self, uuid: &str) -> bool {
+        let _ = uuid;
+        false
+    }
+
+    /// Get the unique service name of the message, if it identifies it among its copies.
+    ///
+    /// Receivers that deduplicate by USN deliver only the first message of each, see
+    /// `SSDPReceiverBuilder::set_dedup_by_usn`. The default implementation returns `None`, such
+    /// messages are never dropped as duplicates. This is the case for notify messages, whose
+    /// following messages of the same USN are byebyes, updates or refreshing alives.
+    fn usn(&self) -> Option<USN> {
+        None
+    }
+}
+
+/// Limits for parsing received packets.
+#[derive(Clone, Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default, deny_unknown_fields)
+)]
+#[non_exhaustive]
+pub struct ParseOptions {
+    pub max_headers: usize,
+    pub lenient: bool,
+    pub location_policy: LocationPolicy,
+}
+
+impl ParseOptions {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Set the maximum number of headers of a message.
+    ///
+    /// Messages with more headers are rejected as invalid HTTP.
+    pub fn set_max_headers(mut self, value: usize) -> Self {
+        self.max_headers = value;
+        self
+    }
+
+    /// Accept messages of devices that deviate from the specification.
+    ///
+    /// Responses with a status code other than 200 are accepted, see
+    /// `SearchResponse::status_code`. Bare LF line endings, whitespace between a header name
+    /// and its colon and whitespace around the `=` of CACHE-CONTROL directives are normalized
+    /// before parsing.
+    pub fn set_lenient(mut self, value: bool) -> Self {
+        self.lenient = value;
+        self
+    }
+
+    /// Drop search responses whose LOCATION does not match their source.
+    ///
+    /// See `SearchResponse::location_matches`.
+    pub fn set_location_policy(mut self, value: LocationPolicy) -> Self {
+        self.location_policy = value;
+        self
+    }
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        ParseOptions {
+            max_headers: 128,
+            lenient: false,
+            location_policy: LocationPolicy::Off,
+        }
+    }
 }
 
 /// Iterator for an `SSDPReceiver`.
 pub struct SSDPIter<T> {
     recv: SSDPReceiver<T>,
+    /// The number of messages left to yield before the receiver is cancelled.
+    remaining: Option<usize>,
 }
 
 impl<T> SSDPIter<T> {
-    fn new(recv: SSDPReceiver<T>) -> SSDPIter<T> {
-        SSDPIter { recv: recv }
+    fn new(recv: SSDPReceiver<T>, remaining: Option<usize>) -> SSDPIter<T> {
+        if remaining == Some(0) {
+            recv.cancel();
+        }
+
+        SSDPIter { recv, remaining }
     }
 }
 
@@ -33,13 +172,126 @@ impl<T> Iterator for SSDPIter<T> {
     type Item = (T, SocketAddr);
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.recv.recv().ok()
+        if self.remaining == Some(0) {
+            return None;
+        }
+
+        let item = self.recv.recv().ok()?;
+
+        if let Some(remaining) = &mut self.remaining {
+            *remaining -= 1;
+
+            if *remaining == 0 {
+                self.recv.cancel();
+            }
+        }
+
+        Some(item)
+    }
+}
+
+/// Iterator for an `SSDPReceiver` that yields the details of every message.
+pub struct SSDPDetailedIter<T> {
+    recv: SSDPReceiver<T>,
+}
+
+impl<T> Iterator for SSDPDetailedIter<T> {
+    type Item = ReceivedMessage<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.recv.recvr.recv().ok()
+    }
+}
+
+/// A message together with the details of its reception.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct ReceivedMessage<T> {
+    pub message: T,
+    /// The address the packet was sent from.
+    pub peer: SocketAddr,
+    /// The address the packet was sent to, one of our addresses or the multicast group.
+    ///
+    /// Only reported on Linux and Apple platforms, through `IP_PKTINFO` and `IPV6_RECVPKTINFO`.
+    /// This tells which of our addresses a unicast packet targeted if the socket is bound to the
+    /// wildcard address.
+    pub local_dst: Option<IpAddr>,
+    /// The index of the interface the packet arrived on, reported like `local_dst`.
+    pub iface_index: Option<u32>,
+    /// The time at which the packet arrived, taken from the clock of the context.
+    pub received_at: Instant,
+    /// The packet as it was received, if the receiver keeps raw packets.
+    ///
+    /// See `Config::set_keep_raw_packets`.
+    pub raw: Option<Vec<u8>>,
+}
+
+impl<T> ReceivedMessage<T> {
+    /// Discard the details, leaving the message and the peer.
+    pub fn into_parts(self) -> (T, SocketAddr) {
+        (self.message, self.peer)
     }
 }
 
 /// A non-blocking SSDP message receiver.
+///
+/// Dropping the receiver leaves the multicast groups it joined, stops its socket threads and waits
+/// for them to release their sockets. The threads notice this within a fraction of a second, even
+/// if the receiver has no timeout.
 pub struct SSDPReceiver<T> {
-    recvr: Receiver<(T, SocketAddr)>,
+    recvr: Receiver<ReceivedMessage<T>>,
+    /// The context that spawned the socket threads, a stream of the receiver uses it as well.
+    #[cfg(feature = "stream")]
+    context: SsdpContext,
+    stopped: Arc<AtomicBool>,
+    /// Disconnects once all socket threads exited.
+    done: Receiver<()>,
+    groups: Vec<JoinedGroup>,
+    rate_limited: Arc<AtomicUsize>,
+    filter: Arc<SharedFilter<T>>,
+    sockets: Arc<LocalSockets>,
+    /// The interfaces on which joining the multicast group failed, with their errors.
+    join_failures: Vec<(SocketAddr, SSDPError)>,
+}
+
+/// The local addresses of the sockets of a receiver, with the flags that detach them.
+type LocalSockets = Mutex<Vec<(SocketAddr, Arc<AtomicBool>)>>;
+
+/// A predicate on received messages, see `SSDPReceiver::set_filter`.
+///
+/// The socket threads of a receiver call it concurrently.
+pub type MessageFilter<T> = Arc<dyn Fn(&T, &SocketAddr) -> bool + Send + Sync>;
+
+/// The filter of a receiver, shared with its socket threads.
+struct SharedFilter<T> {
+    predicate: Mutex<Option<MessageFilter<T>>>,
+    /// Counts the messages the predicate rejected.
+    dropped: AtomicUsize,
+}
+
+/// A multicast group joined on one of the sockets of a receiver.
+pub(crate) struct JoinedGroup {
+    sock: Arc<UdpSocket>,
+    iface: SocketAddr,
+    group: IpAddr,
+}
+
+impl JoinedGroup {
+    pub(crate) fn new(sock: Arc<UdpSocket>, iface: SocketAddr, group: IpAddr) -> JoinedGroup {
+        JoinedGroup { sock, iface, group }
+    }
+
+    /// Check if the group was joined on the socket.
+    pub(crate) fn is_on(&self, sock: &Arc<UdpSocket>) -> bool {
+        Arc::ptr_eq(&self.sock, sock)
+    }
+
+    fn leave(&self) {
+        debug!("Leaving multicast {} at iface: {}", self.group, self.iface);
+        if let Err(err) = net::leave_multicast(&self.sock, &self.iface, &self.group) {
+            debug!("Failed to leave multicast at iface {}: {}", self.iface, err);
+        }
+    }
 }
 
 impl<T> SSDPReceiver<T>
@@ -51,65 +303,638 @@ where
     /// channel will be shutdown after the specified duration.
     ///
     /// Due to implementation details, none of the UdpSockets should be bound to
-    /// the default route, 0.0.0.0, address.
+    /// the default route, 0.0.0.0, address. See `SSDPReceiverBuilder` for further options.
     pub fn new(socks: Vec<Arc<UdpSocket>>, time: Option<Duration>) -> io::Result<SSDPReceiver<T>> {
-        let (send, recv) = mpsc::channel();
+        let mut builder = SSDPReceiverBuilder::new();
+        builder.socks = socks;
+        builder.timeout = time;
+        builder.spawn(SsdpContext::global())
+    }
+
+    /// Construct a receiver that buffers at most `capacity` messages.
+    ///
+    /// When the buffer is full the socket threads block until the consumer reads a message. In
+    /// the meantime further packets queue up in the receive buffer of the operating system, which
+    /// discards the newest packets once that is full as well. Memory use is thus bounded even if
+    /// the consumer falls behind a chatty network.
+    pub fn with_capacity(
+        socks: Vec<Arc<UdpSocket>>,
+        time: Option<Duration>,
+        capacity: usize,
+    ) -> io::Result<SSDPReceiver<T>> {
+        let mut builder = SSDPReceiverBuilder::new().set_capacity(capacity);
+        builder.socks = socks;
+        builder.timeout = time;
+        builder.spawn(SsdpContext::global())
+    }
 
-        // Ensure `receive_packets` times out in the event the timeout packet is not received
+    /// Construct a receiver whose threads are spawned by the context.
+    pub(crate) fn with_context(
+        context: &SsdpContext,
+        socks: Vec<Arc<UdpSocket>>,
+        time: Option<Duration>,
+        options: &ReceiverOptions,
+    ) -> io::Result<SSDPReceiver<T>> {
+        SSDPReceiver::with_filter(context, socks, time, options, None)
+    }
+
+    /// Construct a receiver that applies the filter from the first packet on.
+    pub(crate) fn with_filter(
+        context: &SsdpContext,
+        socks: Vec<Arc<UdpSocket>>,
+        time: Option<Duration>,
+        options: &ReceiverOptions,
+        filter: Option<MessageFilter<T>>,
+    ) -> io::Result<SSDPReceiver<T>> {
+        let (receiver, _) = SSDPReceiver::with_attacher(context, socks, time, options, filter)?;
+        Ok(receiver)
+    }
+
+    /// Construct a receiver together with a handle to attach further sockets to it.
+    ///
+    /// The channel stays open while the attacher exists, even if all sockets were detached.
+    pub(crate) fn with_attacher(
+        context: &SsdpContext,
+        socks: Vec<Arc<UdpSocket>>,
+        time: Option<Duration>,
+        options: &ReceiverOptions,
+        filter: Option<MessageFilter<T>>,
+    ) -> io::Result<(SSDPReceiver<T>, SocketAttacher<T>)> {
+        let (send, recv) = match options.capacity {
+            Some(capacity) => {
+                let (send, recv) = mpsc::sync_channel(capacity);
+                (MessageSender::Bounded(send), recv)
+            }
+            None => {
+                let (send, recv) = mpsc::channel();
+                (MessageSender::Unbounded(send), recv)
+            }
+        };
+
+        // Wake up regularly to notice the timeout as well as a cancellation
+        let poll = time.map_or(STOP_POLL_INTERVAL, |time| time.min(STOP_POLL_INTERVAL));
         for sock in socks.iter() {
-            sock.set_read_timeout(time)?;
+            sock.set_read_timeout(Some(poll))?;
         }
 
+        let stopped = Arc::new(AtomicBool::new(false));
+        let (done_send, done) = mpsc::channel();
+        let rate_limited = Arc::new(AtomicUsize::new(0));
+        let filter = Arc::new(SharedFilter::new(filter));
+        let sockets = Arc::new(LocalSockets::default());
+
         // Spawn Receiver Threads
-        spawn_receivers(socks, send);
+        let threads = Threads {
+            timeout: time,
+            parse: options.parse.clone(),
+            local_sources: options.local_sources.clone(),
+            limiter: options
+                .rate_limit
+                .map(|limit| Arc::new(RateLimiter::new(limit, context.now()))),
+            rate_limited: Arc::clone(&rate_limited),
+            own_addrs: options.own_addrs.clone(),
+            own_uuid: options.own_uuid.clone(),
+            seen_usns: options.dedup_by_usn.then(Default::default),
+            keep_raw: options.keep_raw,
+            raw_filter: options.raw_filter.clone(),
+            observer: options.observer.clone(),
+            stopped: Arc::clone(&stopped),
+            done: done_send,
+            released: options.released.clone(),
+        };
+        spawn_receivers(context, socks, send.clone(), threads.clone(), &filter, &sockets);
+
+        let receiver = SSDPReceiver {
+            recvr: recv,
+            #[cfg(feature = "stream")]
+            context: context.clone(),
+            stopped,
+            done,
+            groups: Vec::new(),
+            rate_limited,
+            filter: Arc::clone(&filter),
+            sockets: Arc::clone(&sockets),
+            join_failures: Vec::new(),
+        };
+
+        let attacher = SocketAttacher {
+            context: context.clone(),
+            sender: send,
+            threads,
+            filter,
+            sockets,
+        };
+
+        Ok((receiver, attacher))
+    }
+}
+
+/// Options for the socket threads of a receiver.
+#[derive(Clone, Default)]
+pub(crate) struct ReceiverOptions {
+    /// The channel is unbounded if no capacity is given.
+    pub(crate) capacity: Option<usize>,
+    pub(crate) parse: ParseOptions,
+    /// Only packets from these networks are accepted, if any are given.
+    pub(crate) local_sources: Option<Arc<[Subnet]>>,
+    pub(crate) rate_limit: Option<RateLimit>,
+    /// Packets from these addresses are dropped.
+    pub(crate) own_addrs: Option<Arc<[IpAddr]>>,
+    /// Messages whose USN carries this UUID are dropped.
+    pub(crate) own_uuid: Option<Arc<str>>,
+    /// Messages with the USN of an earlier message are dropped.
+    pub(crate) dedup_by_usn: bool,
+    pub(crate) keep_raw: bool,
+    pub(crate) raw_filter: Option<RawFilter>,
+    pub(crate) observer: Option<Arc<dyn SsdpObserver>>,
+    /// Held by every socket thread, so that the channel disconnects once all of them exited.
+    pub(crate) released: Option<Sender<()>>,
+}
+
+impl ReceiverOptions {
+    /// The options of receivers created for the config.
+    pub(crate) fn from_config(config: &Config) -> Self {
+        ReceiverOptions {
+            capacity: config.receive_capacity,
+            parse: config.parse_options.clone(),
+            local_sources: config
+                .accept_only_local_sources
+                .then(|| message::local_subnets().into()),
+            rate_limit: config.max_packets_per_second.map(|per_second| RateLimit {
+                per_second,
+                per_source: config.rate_limit_per_source,
+            }),
+            own_addrs: config.ignore_self.then(|| message::local_addrs().into()),
+            own_uuid: config.self_uuid.as_deref().map(Arc::from),
+            dedup_by_usn: false,
+            keep_raw: config.keep_raw_packets,
+            raw_filter: config.raw_filter.clone(),
+            observer: config.observer.clone(),
+            released: None,
+        }
+    }
+}
+
+/// Builds an `SSDPReceiver` that receives on sockets bound by the caller.
+///
+/// Every socket is read by a thread of its own, which parses the packets into messages. A thread
+/// exits once no packet arrived on its socket for the timeout, if one is set, or when the receiver
+/// is cancelled or dropped. The receiver ends once all threads exited.
+///
+/// ```no_run
+/// use std::net::UdpSocket;
+/// use std::sync::Arc;
+/// use std::time::Duration;
+///
+/// use ssdp::message::SearchResponse;
+/// use ssdp::{SSDPReceiver, SSDPReceiverBuilder};
+///
+/// let socket = Arc::new(UdpSocket::bind("0.0.0.0:0").unwrap());
+/// let receiver: SSDPReceiver<SearchResponse> = SSDPReceiverBuilder::new()
+///     .add_socket(socket)
+///     .set_timeout(Duration::from_secs(3))
+///     .set_dedup_by_usn(true)
+///     .build()
+///     .unwrap();
+/// ```
+#[derive(Clone, Default)]
+pub struct SSDPReceiverBuilder {
+    socks: Vec<Arc<UdpSocket>>,
+    timeout: Option<Duration>,
+    options: ReceiverOptions,
+}
+
+impl SSDPReceiverBuilder {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Receive on the socket as well.
+    pub fn add_socket(mut self, sock: Arc<UdpSocket>) -> Self {
+        self.socks.push(sock);
+        self
+    }
+
+    /// End the receiver once no packet arrived for the duration.
+    pub fn set_timeout(mut self, value: Duration) -> Self {
+        self.timeout = Some(value);
+        self
+    }
+
+    /// Buffer at most this many messages, see `SSDPReceiver::with_capacity`.
+    pub fn set_capacity(mut self, value: usize) -> Self {
+        self.options.capacity = Some(value);
+        self
+    }
+
+    /// Parse the received packets with the options.
+    pub fn set_parse_options(mut self, value: ParseOptions) -> Self {
+        self.options.parse = value;
+        self
+    }
+
+    /// Deliver only the first search response of each unique service name.
+    ///
+    /// Devices commonly answer a search once per interface or repeat their response, this drops
+    /// the copies. A USN is remembered for 30 minutes, the usual max-age, and for at most 4096 of
+    /// them, after which it is delivered again. Other messages are always delivered, see
+    /// `FromRawSSDP::usn`.
+    pub fn set_dedup_by_usn(mut self, value: bool) -> Self {
+        self.options.dedup_by_usn = value;
+        self
+    }
+
+    /// Keep the packet of every message, see `ReceivedMessage::raw`.
+    pub fn set_keep_raw(mut self, value: bool) -> Self {
+        self.options.keep_raw = value;
+        self
+    }
+
+    /// Drop packets for which the predicate returns false before parsing them.
+    ///
+    /// See `Config::set_raw_filter`.
+    pub fn set_raw_filter<F>(mut self, predicate: F) -> Self
+    where
+        F: Fn(&RawSSDPMessage<'_>, &SocketAddr) -> bool + Send + Sync + 'static,
+    {
+        self.options.raw_filter = Some(RawFilter::new(predicate));
+        self
+    }
+
+    /// Start receiving on the sockets.
+    ///
+    /// Fails if no socket was added.
+    pub fn build<T>(self) -> SSDPResult<SSDPReceiver<T>>
+    where
+        T: FromRawSSDP + Send + 'static,
+    {
+        if self.socks.is_empty() {
+            return Err(SSDPError::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "a receiver needs at least one socket",
+            )));
+        }
+
+        Ok(self.spawn(SsdpContext::global())?)
+    }
+
+    /// Start receiving on the sockets, which may be none, with threads spawned by the context.
+    fn spawn<T>(self, context: &SsdpContext) -> io::Result<SSDPReceiver<T>>
+    where
+        T: FromRawSSDP + Send + 'static,
+    {
+        SSDPReceiver::with_context(context, self.socks, self.timeout, &self.options)
+    }
+}
+
+/// Attaches sockets to a receiver after it was constructed.
+pub(crate) struct SocketAttacher<T> {
+    context: SsdpContext,
+    sender: MessageSender<ReceivedMessage<T>>,
+    threads: Threads,
+    filter: Arc<SharedFilter<T>>,
+    sockets: Arc<LocalSockets>,
+}
+
+/// A socket attached to a receiver, which is detached again when this is dropped.
+pub(crate) struct AttachedSocket {
+    detached: Arc<AtomicBool>,
+}
+
+impl<T> SocketAttacher<T>
+where
+    T: FromRawSSDP + Send + 'static,
+{
+    /// Start receiving on the socket.
+    ///
+    /// Once detached the socket thread exits within the poll interval and releases the socket.
+    pub(crate) fn attach(&self, sock: Arc<UdpSocket>) -> io::Result<AttachedSocket> {
+        let detached = Arc::new(AtomicBool::new(false));
+        self.spawn(sock, Arc::clone(&detached))?;
+        Ok(AttachedSocket { detached })
+    }
+
+    /// Start receiving on the socket until the receiver stops, like the sockets it was
+    /// constructed with.
+    pub(crate) fn adopt(&self, sock: Arc<UdpSocket>) -> io::Result<()> {
+        self.spawn(sock, Arc::new(AtomicBool::new(false)))
+    }
+
+    fn spawn(&self, sock: Arc<UdpSocket>, detached: Arc<AtomicBool>) -> io::Result<()> {
+        let poll = self
+            .threads
+            .timeout
+            .map_or(STOP_POLL_INTERVAL, |time| time.min(STOP_POLL_INTERVAL));
+        sock.set_read_timeout(Some(poll))?;
 
-        Ok(SSDPReceiver { recvr: recv })
+        let mut sockets = self.sockets.lock().unwrap();
+        sockets.retain(|(_, detached)| !detached.load(Ordering::Relaxed));
+        sockets.push((sock.local_addr()?, Arc::clone(&detached)));
+        drop(sockets);
+
+        spawn_receiver(
+            &self.context,
+            sock,
+            self.sender.clone(),
+            self.threads.clone(),
+            Arc::clone(&self.filter),
+            detached,
+        );
+
+        Ok(())
+    }
+
+    /// Check if the receiver was cancelled or dropped.
+    pub(crate) fn is_stopped(&self) -> bool {
+        self.threads.stopped.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for AttachedSocket {
+    fn drop(&mut self) {
+        self.detached.store(true, Ordering::Relaxed);
     }
 }
 
+impl<T> SharedFilter<T> {
+    fn new(predicate: Option<MessageFilter<T>>) -> Self {
+        SharedFilter {
+            predicate: Mutex::new(predicate),
+            dropped: AtomicUsize::new(0),
+        }
+    }
+
+    /// Check if the message passes the filter, counting it otherwise.
+    fn accepts(&self, message: &T, peer: &SocketAddr) -> bool {
+        // Not called under the lock, so the socket threads run the predicate in parallel.
+        let predicate = self.predicate.lock().unwrap().clone();
+        let accepted = match predicate {
+            Some(predicate) => predicate(message, peer),
+            None => true,
+        };
+
+        if !accepted {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+
+        accepted
+    }
+}
+
+/// The sending half of the channel between the socket threads and the receiver.
+enum MessageSender<T> {
+    Unbounded(Sender<T>),
+    /// Blocks the sending thread while the channel is full.
+    Bounded(SyncSender<T>),
+}
+
+impl<T> MessageSender<T> {
+    fn send(&self, value: T) -> Result<(), SendError<T>> {
+        match self {
+            MessageSender::Unbounded(send) => send.send(value),
+            MessageSender::Bounded(send) => send.send(value),
+        }
+    }
+}
+
+impl<T> Clone for MessageSender<T> {
+    fn clone(&self) -> Self {
+        match self {
+            MessageSender::Unbounded(send) => MessageSender::Unbounded(send.clone()),
+            MessageSender::Bounded(send) => MessageSender::Bounded(send.clone()),
+        }
+    }
+}
+
+/// The state shared by the socket threads of one receiver.
+#[derive(Clone)]
+struct Threads {
+    /// The time without any packet after which a thread exits.
+    timeout: Option<Duration>,
+    parse: ParseOptions,
+    local_sources: Option<Arc<[Subnet]>>,
+    limiter: Option<Arc<RateLimiter>>,
+    /// Counts the packets dropped by the limiter.
+    rate_limited: Arc<AtomicUsize>,
+    own_addrs: Option<Arc<[IpAddr]>>,
+    own_uuid: Option<Arc<str>>,
+    /// The USNs delivered recently, if messages are deduplicated.
+    seen_usns: Option<Arc<Mutex<SeenUsns>>>,
+    keep_raw: bool,
+    raw_filter: Option<RawFilter>,
+    observer: Option<Arc<dyn SsdpObserver>>,
+    stopped: Arc<AtomicBool>,
+    /// Held until the thread exits.
+    done: Sender<()>,
+    /// Held until the thread exits, for the owner of the sockets.
+    released: Option<Sender<()>>,
+}
+
 /// Spawn a number of receiver threads that will receive packets, forward the
 /// bytes on to T, and send successfully constructed objects through the sender.
-fn spawn_receivers<T>(socks: Vec<Arc<UdpSocket>>, sender: Sender<(T, SocketAddr)>)
-where
+fn spawn_receivers<T>(
+    context: &SsdpContext,
+    socks: Vec<Arc<UdpSocket>>,
+    sender: MessageSender<ReceivedMessage<T>>,
+    threads: Threads,
+    filter: &Arc<SharedFilter<T>>,
+    sockets: &LocalSockets,
+) where
     T: FromRawSSDP + Send + 'static,
 {
     for sock in socks {
-        let pckt_recv = PacketReceiver::new(sock);
-        let sender = sender.clone();
-
-        thread::spawn(move || {
-            receive_packets(pckt_recv, sender);
-        });
+        let detached = Arc::new(AtomicBool::new(false));
+        if let Ok(addr) = sock.local_addr() {
+            sockets.lock().unwrap().push((addr, Arc::clone(&detached)));
+        }
+        spawn_receiver(context, sock, sender.clone(), threads.clone(), Arc::clone(filter), detached);
     }
 }
 
+/// Spawn a single receiver thread, which exits once the socket is detached.
+fn spawn_receiver<T>(
+    context: &SsdpContext,
+    sock: Arc<UdpSocket>,
+    sender: MessageSender<ReceivedMessage<T>>,
+    threads: Threads,
+    filter: Arc<SharedFilter<T>>,
+    detached: Arc<AtomicBool>,
+) where
+    T: FromRawSSDP + Send + 'static,
+{
+    let pckt_recv = PacketReceiver::new(sock);
+    let thread_context = context.clone();
+
+    context.spawn(move || {
+        receive_packets(&thread_context, pckt_recv, sender, threads, &filter, detached);
+    });
+}
+
 impl<T> SSDPReceiver<T> {
+    /// Leave the multicast groups when the receiver is dropped.
+    pub(crate) fn leave_on_drop(&mut self, groups: Vec<JoinedGroup>) {
+        self.groups.extend(groups);
+    }
+
+    /// Record the interfaces on which the multicast group could not be joined.
+    pub(crate) fn report_join_failures(&mut self, failures: Vec<(SocketAddr, SSDPError)>) {
+        self.join_failures.extend(failures);
+    }
+
+    /// Get the interfaces on which a listener could not join the multicast group, with the errors.
+    ///
+    /// Listening continues on the other interfaces, it only fails if no group could be joined at
+    /// all, see `SSDPError::AllJoinsFailed`. Messages sent to the group through these interfaces
+    /// are not received.
+    pub fn join_failures(&self) -> &[(SocketAddr, SSDPError)] {
+        &self.join_failures
+    }
+
     /// Non-blocking method that attempts to read a value from the receiver.
     pub fn try_recv(&self) -> Result<(T, SocketAddr), TryRecvError> {
-        self.recvr.try_recv()
+        self.recvr.try_recv().map(ReceivedMessage::into_parts)
     }
 
     /// Blocking method that reads a value from the receiver until one is available.
     pub fn recv(&self) -> Result<(T, SocketAddr), RecvError> {
-        self.recvr.recv()
+        self.recvr.recv().map(ReceivedMessage::into_parts)
+    }
+
+    /// Blocking method that reads a value from the receiver until one is available or the
+    /// timeout elapsed.
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<(T, SocketAddr), RecvTimeoutError> {
+        self.recvr.recv_timeout(timeout).map(ReceivedMessage::into_parts)
+    }
+
+    /// Like `recv_timeout`, but with the details of the reception.
+    pub fn recv_detailed_timeout(&self, timeout: Duration) -> Result<ReceivedMessage<T>, RecvTimeoutError> {
+        self.recvr.recv_timeout(timeout)
+    }
+
+    /// Drop received messages for which the filter returns false.
+    ///
+    /// The filter runs on the socket threads, rejected messages never reach the channel. It
+    /// replaces any previous filter and applies to messages parsed after the call, see
+    /// `NotifyListener::listen_for_nt` for a receiver that is filtered from the start.
+    pub fn set_filter(&self, filter: MessageFilter<T>) {
+        *self.filter.predicate.lock().unwrap() = Some(filter);
+    }
+
+    /// Get the number of messages that were dropped by the filter.
+    ///
+    /// See `SSDPReceiver::set_filter`.
+    pub fn messages_filtered(&self) -> usize {
+        self.filter.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Get the number of packets that were dropped for exceeding the rate limit.
+    ///
+    /// See `Config::set_max_packets_per_second`.
+    pub fn packets_rate_limited(&self) -> usize {
+        self.rate_limited.load(Ordering::Relaxed)
+    }
+
+    /// Iterate over the messages together with the details of their reception.
+    pub fn into_detailed_iter(self) -> SSDPDetailedIter<T> {
+        SSDPDetailedIter { recv: self }
+    }
+
+    /// Receive the first message, then stop receiving.
+    ///
+    /// Returns `None` if the timeout of the receiver elapsed without any message.
+    pub fn first(self) -> Option<(T, SocketAddr)> {
+        self.take_until(1).next()
+    }
+
+    /// Iterate over at most `n` messages.
+    ///
+    /// The receiver is cancelled as soon as the last message was yielded, so the iteration ends
+    /// early instead of waiting out the timeout.
+    pub fn take_until(self, n: usize) -> SSDPIter<T> {
+        SSDPIter::new(self, Some(n))
+    }
+
+    /// Stop the socket threads and wait until they released their sockets.
+    ///
+    /// Messages that were not yet read are discarded.
+    pub fn cancel(&self) {
+        self.stop(None);
+    }
+
+    /// The local addresses of the sockets the receiver reads from.
+    ///
+    /// These are the addresses responses to a search must be sent to. Sockets that were detached
+    /// again are not part of them.
+    pub fn local_addrs(&self) -> Vec<SocketAddr> {
+        let sockets = self.sockets.lock().unwrap();
+        sockets
+            .iter()
+            .filter(|(_, detached)| !detached.load(Ordering::Relaxed))
+            .map(|&(addr, _)| addr)
+            .collect()
+    }
+
+    /// The flag that stops the socket threads once set, without waiting for them.
+    pub(crate) fn stop_flag(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.stopped)
+    }
+
+    /// The context that spawned the socket threads.
+    #[cfg(feature = "stream")]
+    pub(crate) fn context(&self) -> &SsdpContext {
+        &self.context
+    }
+
+    /// Stop the socket threads and wait until they exited, or the deadline passed.
+    fn stop(&self, deadline: Option<Instant>) -> bool {
+        self.stopped.store(true, Ordering::Relaxed);
+
+        loop {
+            // Make room for threads that are blocked on a full channel.
+            while self.recvr.try_recv().is_ok() {}
+
+            match self.done.recv_timeout(STOP_POLL_INTERVAL) {
+                Err(RecvTimeoutError::Disconnected) => return true,
+                Ok(()) | Err(RecvTimeoutError::Timeout) => {
+                    if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                        return false;
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<T> Drop for SSDPReceiver<T> {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::Relaxed);
+
+        for group in self.groups.drain(..) {
+            group.leave();
+        }
+
+        // A custom spawner might never run the threads, so do not wait forever.
+        if !self.stop(Some(Instant::now() + DROP_TIMEOUT)) {
+            debug!("Socket threads of a dropped receiver did not exit in time");
+        }
     }
 }
 
 impl<'a, T> IntoIterator for &'a SSDPReceiver<T> {
     type Item = (T, SocketAddr);
-    type IntoIter = Iter<'a, (T, SocketAddr)>;
+    type IntoIter = Map<Iter<'a, ReceivedMessage<T>>, fn(ReceivedMessage<T>) -> (T, SocketAddr)>;
 
     fn into_iter(self) -> Self::IntoIter {
-        self.recvr.iter()
+        self.recvr.iter().map(ReceivedMessage::into_parts)
     }
 }
 
 impl<'a, T> IntoIterator for &'a mut SSDPReceiver<T> {
     type Item = (T, SocketAddr);
-    type IntoIter = Iter<'a, (T, SocketAddr)>;
+    type IntoIter = Map<Iter<'a, ReceivedMessage<T>>, fn(ReceivedMessage<T>) -> (T, SocketAddr)>;
 
     fn into_iter(self) -> Self::IntoIter {
-        self.recvr.iter()
+        self.recvr.iter().map(ReceivedMessage::into_parts)
     }
 }
 
@@ -118,44 +943,838 @@ impl<T> IntoIterator for SSDPReceiver<T> {
     type IntoIter = SSDPIter<T>;
 
     fn into_iter(self) -> Self::IntoIter {
-        SSDPIter::new(self)
+        SSDPIter::new(self, None)
+    }
+}
+
+/// A source of packets for the receive loop.
+trait PacketSource: fmt::Display {
+    fn recv_pckt(&self) -> io::Result<(Vec<u8>, SocketAddr)>;
+
+    /// Receive a packet with the info of where it arrived, which sources may not know.
+    fn recv_pckt_info(&self) -> io::Result<(Vec<u8>, SocketAddr, PacketInfo)> {
+        self.recv_pckt().map(|(bytes, addr)| (bytes, addr, PacketInfo::default()))
+    }
+}
+
+impl PacketSource for PacketReceiver {
+    fn recv_pckt(&self) -> io::Result<(Vec<u8>, SocketAddr)> {
+        PacketReceiver::recv_pckt(self)
+    }
+
+    fn recv_pckt_info(&self) -> io::Result<(Vec<u8>, SocketAddr, PacketInfo)> {
+        PacketReceiver::recv_pckt_info(self)
+    }
+}
+
+/// How the receive loop reacts to an error of the socket.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum RecvErrorAction {
+    /// The read timed out, check the timeout of the receiver.
+    Timeout,
+    /// A transient error, read again.
+    Retry,
+    /// An error not known to be transient, such as an oversized datagram, log and read again.
+    Unknown,
+    /// The socket is unusable, exit the thread.
+    Fatal,
+}
+
+/// Raw OS errors reporting that the descriptor is no longer a valid socket.
+#[cfg(unix)]
+const INVALID_SOCKET_ERRORS: [i32; 2] = [libc::EBADF, libc::ENOTSOCK];
+/// Raw OS errors reporting that the descriptor is no longer a valid socket, `WSAEBADF` and
+/// `WSAENOTSOCK`.
+#[cfg(windows)]
+const INVALID_SOCKET_ERRORS: [i32; 2] = [10009, 10038];
+#[cfg(not(any(unix, windows)))]
+const INVALID_SOCKET_ERRORS: [i32; 0] = [];
+
+/// Classify an error returned when reading from a socket.
+fn classify_recv_error(err: &io::Error) -> RecvErrorAction {
+    match err.kind() {
+        // Unix returns WouldBlock on timeout while Windows returns TimedOut
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => RecvErrorAction::Timeout,
+        // Windows reports an ICMP port unreachable of an earlier send as a reset connection
+        io::ErrorKind::Interrupted | io::ErrorKind::ConnectionReset | io::ErrorKind::ConnectionRefused => {
+            RecvErrorAction::Retry
+        }
+        io::ErrorKind::NotConnected => RecvErrorAction::Fatal,
+        _ if err
+            .raw_os_error()
+            .is_some_and(|code| INVALID_SOCKET_ERRORS.contains(&code)) =>
+        {
+            RecvErrorAction::Fatal
+        }
+        _ => RecvErrorAction::Unknown,
     }
 }
 
 /// Receives bytes and attempts to construct a T which will be sent through the supplied channel.
 ///
 /// This should almost always be run in it's own thread.
-fn receive_packets<T>(recv: PacketReceiver, send: Sender<(T, SocketAddr)>)
-where
+fn receive_packets<T, R>(
+    context: &SsdpContext,
+    recv: R,
+    send: MessageSender<ReceivedMessage<T>>,
+    threads: Threads,
+    filter: &SharedFilter<T>,
+    detached: Arc<AtomicBool>,
+) where
     T: FromRawSSDP + Send,
+    R: PacketSource,
 {
+    // Declared before the socket so that it is only dropped once the socket was released.
+    let _done = threads.done;
+    let _released = threads.released;
+    let recv = recv;
+    let mut last_packet = Instant::now();
+    enter_span!("ssdp_receiver", interface = %recv);
+
     // TODO: Add logging to this function. Maybe forward sender IP Address along
     // so that we can do some checks when we parse the http.
     loop {
+        if threads.stopped.load(Ordering::Relaxed) || detached.load(Ordering::Relaxed) {
+            trace!("Receiver at {} cancelled", recv);
+            return;
+        }
+
         trace!("Waiting on packet at {}...", recv);
-        let (msg_bytes, addr) = match recv.recv_pckt() {
-            Ok((bytes, addr)) => (bytes, addr),
-            // Unix returns WouldBlock on timeout while Windows returns TimedOut
-            Err(ref err)
-                if err.kind() == io::ErrorKind::WouldBlock || err.kind() == io::ErrorKind::TimedOut =>
-            {
-                // We have waited for at least the desired timeout (or possibly longer)
-                trace!("Receiver at {} timed out", recv);
-                return;
-            }
-            Err(_) => {
-                continue;
-            }
+        let (msg_bytes, addr, info) = match recv.recv_pckt_info() {
+            Ok(packet) => packet,
+            Err(err) => match classify_recv_error(&err) {
+                RecvErrorAction::Timeout => {
+                    if threads
+                        .timeout
+                        .is_some_and(|timeout| last_packet.elapsed() >= timeout)
+                    {
+                        // We have waited for at least the desired timeout (or possibly longer)
+                        trace!("Receiver at {} timed out", recv);
+                        return;
+                    }
+
+                    continue;
+                }
+                RecvErrorAction::Retry => {
+                    trace!("Retrying receive at {} after {}", recv, err);
+                    continue;
+                }
+                RecvErrorAction::Unknown => {
+                    debug!("Ignoring error of receiver at {}: {}", recv, err);
+                    continue;
+                }
+                RecvErrorAction::Fatal => {
+                    warn!("Receiver at {} failed: {}", recv, err);
+                    return;
+                }
+            },
         };
 
+        let received_at = context.now();
+        enter_span!("ssdp_packet", peer = %addr, bytes = msg_bytes.len());
         trace!("Received packet with {} bytes", msg_bytes.len());
+        context.stats().add_packet_received();
+        if let Some(observer) = &threads.observer {
+            observer.on_recv(&addr, &msg_bytes);
+        }
+
+        if let Some(subnets) = &threads.local_sources {
+            if !net::is_local_source(addr.ip(), subnets) {
+                trace!("Dropping packet from non-local source {}", addr);
+                context.stats().add_packet_filtered();
+                continue;
+            }
+        }
 
-        // Unwrap Will Cause A Panic If Receiver Hung Up Which Is Desired
-        match T::from_packet(&msg_bytes[..]) {
-            Ok(n) => send.send((n, addr)).unwrap(),
-            Err(_) => {
+        if let Some(own_addrs) = &threads.own_addrs {
+            if own_addrs.contains(&addr.ip().to_canonical()) {
+                trace!("Dropping packet from own address {}", addr);
+                context.stats().add_packet_filtered();
                 continue;
             }
+        }
+
+        if let Some(limiter) = &threads.limiter {
+            if !limiter.allow(addr.ip(), received_at) {
+                trace!("Dropping packet from {} over the rate limit", addr);
+                threads.rate_limited.fetch_add(1, Ordering::Relaxed);
+                continue;
+            }
+        }
+
+        last_packet = Instant::now();
+
+        if let Some(filter) = &threads.raw_filter {
+            // Packets without a raw view are left to the parser, which reports the error.
+            if let Ok(raw) = RawSSDPMessage::parse(&msg_bytes) {
+                if !filter.accepts(&raw, &addr) {
+                    trace!("Dropping packet from {} rejected by the raw filter", addr);
+                    context.stats().add_packet_filtered();
+                    continue;
+                }
+            }
+        }
+
+        match T::from_packet_with_options(&msg_bytes[..], &threads.parse) {
+            Ok(n) if !n.verify_peer(&addr, &threads.parse) => {
+                trace!("Dropping message that does not match its source {}", addr);
+                context.stats().add_packet_filtered();
+                continue;
+            }
+            Ok(n) if threads.own_uuid.as_deref().is_some_and(|uuid| n.is_from_device(uuid)) => {
+                trace!("Dropping message of our own device from {}", addr);
+                context.stats().add_packet_filtered();
+                continue;
+            }
+            Ok(n) if !filter.accepts(&n, &addr) => {
+                trace!("Dropping message from {} rejected by the filter", addr);
+                continue;
+            }
+            Ok(n) if threads.seen_usns.as_ref().is_some_and(|seen| is_duplicate(seen, &n, received_at)) => {
+                trace!("Dropping duplicate message from {}", addr);
+                continue;
+            }
+            Ok(n) => {
+                let message = ReceivedMessage {
+                    message: n,
+                    peer: addr,
+                    local_dst: info.local_dst,
+                    iface_index: info.iface_index,
+                    received_at,
+                    raw: threads.keep_raw.then_some(msg_bytes),
+                };
+
+                if send.send(message).is_err() {
+                    trace!("Receiver at {} hung up", recv);
+                    return;
+                }
+            }
+            Err(err) => {
+                if let Some(observer) = &threads.observer {
+                    observer.on_parse_error(&addr, &err);
+                }
+                continue;
+            }
+        };
+    }
+}
+
+/// Check if a message with the USN of the message was delivered recently, remembering it if not.
+fn is_duplicate<T: FromRawSSDP>(seen: &Mutex<SeenUsns>, message: &T, now: Instant) -> bool {
+    match message.usn() {
+        Some(usn) => !seen.lock().unwrap().insert(usn, now),
+        None => false,
+    }
+}
+
+/// The USNs delivered recently, forgotten in the order they were delivered.
+#[derive(Default)]
+struct SeenUsns {
+    usns: HashSet<USN>,
+    order: VecDeque<(USN, Instant)>,
+}
+
+impl SeenUsns {
+    /// Remember the USN, returns `false` if it was already remembered.
+    fn insert(&mut self, usn: USN, now: Instant) -> bool {
+        while let Some((_, at)) = self.order.front() {
+            if now.saturating_duration_since(*at) < SEEN_USN_LIFETIME && self.order.len() < MAX_SEEN_USNS {
+                break;
+            }
+            if let Some((oldest, _)) = self.order.pop_front() {
+                self.usns.remove(&oldest);
+            }
+        }
+
+        if !self.usns.insert(usn.clone()) {
+            return false;
+        }
+
+        self.order.push_back((usn, now));
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+    use std::fmt;
+    use std::io::{self, Write as _};
+    use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::{mpsc, Arc, Mutex};
+    use std::time::{Duration, Instant};
+
+    use super::{
+        classify_recv_error, receive_packets, JoinedGroup, MessageSender, PacketSource, ReceiverOptions,
+        RecvErrorAction, SSDPReceiver, SSDPReceiverBuilder, SeenUsns, SharedFilter, Threads,
+        INVALID_SOCKET_ERRORS, MAX_SEEN_USNS, SEEN_USN_LIFETIME,
+    };
+    use crate::net::connector::UdpConnector;
+    use crate::net::{NetworkConnector as _, Subnet};
+    use crate::context::{SsdpContext, SsdpObserver};
+    use crate::limit::RateLimit;
+    use crate::header::USN;
+    use crate::message::{Config, IncomingMessage, NotifyMessage, RawFilter};
+    use crate::{FieldMap, SSDPError};
+
+    /// The options of a socket thread without a timeout, limits or filters.
+    fn threads(done: mpsc::Sender<()>) -> Threads {
+        Threads {
+            timeout: None,
+            parse: Default::default(),
+            local_sources: None,
+            limiter: None,
+            rate_limited: Default::default(),
+            own_addrs: None,
+            own_uuid: None,
+            seen_usns: None,
+            keep_raw: false,
+            raw_filter: None,
+            observer: None,
+            stopped: Arc::new(AtomicBool::new(false)),
+            done,
+            released: None,
+        }
+    }
+
+    #[test]
+    fn positive_bounded_queue() {
+        let context = SsdpContext::new();
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        let receiver = SSDPReceiver::<NotifyMessage>::with_context(
+            &context,
+            vec![Arc::new(socket)],
+            Some(Duration::from_millis(500)),
+            &ReceiverOptions {
+                capacity: Some(2),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        for _ in 0..10 {
+            let packet = b"NOTIFY * HTTP/1.1\r\nHOST: 239.255.255.250:1900\r\n\r\n";
+            sender.send_to(packet, addr).unwrap();
+        }
+
+        // Two messages are buffered and the socket thread blocks while sending the third.
+        std::thread::sleep(Duration::from_millis(200));
+        assert_eq!(context.stats().packets_received(), 3);
+
+        // The remaining packets waited in the socket and are received once there is room.
+        assert_eq!(receiver.into_iter().count(), 10);
+    }
+
+    #[test]
+    fn positive_rate_limit() {
+        let context = SsdpContext::new();
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        let receiver = SSDPReceiver::<NotifyMessage>::with_context(
+            &context,
+            vec![Arc::new(socket)],
+            Some(Duration::from_millis(300)),
+            &ReceiverOptions {
+                rate_limit: Some(RateLimit {
+                    per_second: 10,
+                    per_source: false,
+                }),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        let start = Instant::now();
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        for _ in 0..200 {
+            let packet = b"NOTIFY * HTTP/1.1\r\nHOST: 239.255.255.250:1900\r\n\r\n";
+            sender.send_to(packet, addr).unwrap();
+        }
+
+        let mut received = 0;
+        while receiver.recv_timeout(Duration::from_millis(300)).is_ok() {
+            received += 1;
+        }
+
+        // The burst of a full bucket plus whatever was refilled while receiving.
+        let allowed = 10 + (start.elapsed().as_secs_f64() * 10.0).ceil() as usize;
+        assert!(received >= 10, "received {}", received);
+        assert!(received <= allowed, "received {} of {} allowed", received, allowed);
+        assert_eq!(receiver.packets_rate_limited(), context.stats().packets_received() - received);
+    }
+
+    #[test]
+    fn positive_detailed_timestamps() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        let receiver =
+            SSDPReceiver::<NotifyMessage>::new(vec![Arc::new(socket)], Some(Duration::from_millis(300)))
+                .unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut sent = Vec::new();
+        for _ in 0..3 {
+            let packet = b"NOTIFY * HTTP/1.1\r\nHOST: 239.255.255.250:1900\r\n\r\n";
+            sent.push(Instant::now());
+            sender.send_to(packet, addr).unwrap();
+            std::thread::sleep(Duration::from_millis(50));
+        }
+
+        // Read late, the timestamps still reflect the time of arrival.
+        std::thread::sleep(Duration::from_millis(100));
+        let received: Vec<_> = receiver.into_detailed_iter().collect();
+        assert_eq!(received.len(), 3);
+
+        for (message, sent) in received.iter().zip(&sent) {
+            assert_eq!(message.peer, sender.local_addr().unwrap());
+            assert!(message.received_at >= *sent);
+            assert!(message.received_at - *sent < Duration::from_millis(50));
+        }
+
+        assert!(received
+            .windows(2)
+            .all(|pair| pair[0].received_at <= pair[1].received_at));
+        assert!(received.iter().all(|message| message.raw.is_none()));
+    }
+
+    #[test]
+    fn positive_leave_groups_on_drop() {
+        let socket = Arc::new(UdpSocket::bind("0.0.0.0:0").unwrap());
+        let group = Ipv4Addr::new(239, 255, 255, 250);
+        socket.join_multicast_v4(&group, &Ipv4Addr::LOCALHOST).unwrap();
+
+        let mut receiver = SSDPReceiver::<NotifyMessage>::new(vec![Arc::clone(&socket)], None).unwrap();
+        let iface = SocketAddr::from((Ipv4Addr::LOCALHOST, 0));
+        receiver.leave_on_drop(vec![JoinedGroup::new(Arc::clone(&socket), iface, group.into())]);
+        drop(receiver);
+
+        // The group was already left, so leaving it again fails.
+        assert!(socket.leave_multicast_v4(&group, &Ipv4Addr::LOCALHOST).is_err());
+    }
+
+    #[test]
+    fn positive_drop_joins_threads() {
+        let live = Arc::new(AtomicUsize::new(0));
+        let spawn_live = Arc::clone(&live);
+        let context = SsdpContext::new().set_spawner(move |task: crate::Task| {
+            let live = Arc::clone(&spawn_live);
+            live.fetch_add(1, Ordering::SeqCst);
+            std::thread::spawn(move || {
+                task();
+                live.fetch_sub(1, Ordering::SeqCst);
+            });
+        });
+
+        for _ in 0..100 {
+            let socket = Arc::new(UdpSocket::bind("127.0.0.1:0").unwrap());
+            let options = ReceiverOptions::default();
+            let receiver =
+                SSDPReceiver::<NotifyMessage>::with_context(&context, vec![socket], None, &options);
+            drop(receiver.unwrap());
+        }
+
+        // The counter is only decremented after a task returned, which may lag the drop slightly.
+        let start = Instant::now();
+        while live.load(Ordering::SeqCst) != 0 && start.elapsed() < Duration::from_secs(1) {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        assert_eq!(live.load(Ordering::SeqCst), 0);
+    }
+
+    type ReadResult = io::Result<(Vec<u8>, SocketAddr)>;
+
+    /// Replays a list of read results, then fails fatally.
+    struct ScriptedSource(Mutex<VecDeque<ReadResult>>);
+
+    impl fmt::Display for ScriptedSource {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("scripted")
+        }
+    }
+
+    impl PacketSource for ScriptedSource {
+        fn recv_pckt(&self) -> ReadResult {
+            let next = self.0.lock().unwrap().pop_front();
+            next.unwrap_or_else(|| Err(io::ErrorKind::NotConnected.into()))
+        }
+    }
+
+    #[test]
+    fn positive_classify_recv_error() {
+        let classify = |kind: io::ErrorKind| classify_recv_error(&kind.into());
+
+        assert_eq!(classify(io::ErrorKind::WouldBlock), RecvErrorAction::Timeout);
+        assert_eq!(classify(io::ErrorKind::TimedOut), RecvErrorAction::Timeout);
+        assert_eq!(classify(io::ErrorKind::Interrupted), RecvErrorAction::Retry);
+        assert_eq!(classify(io::ErrorKind::ConnectionReset), RecvErrorAction::Retry);
+        // An oversized datagram must not stop the receiver.
+        assert_eq!(classify(io::ErrorKind::Other), RecvErrorAction::Unknown);
+        assert_eq!(classify(io::ErrorKind::PermissionDenied), RecvErrorAction::Unknown);
+    }
+
+    #[test]
+    fn negative_classify_recv_error() {
+        let classify = |kind: io::ErrorKind| classify_recv_error(&kind.into());
+
+        assert_eq!(classify(io::ErrorKind::NotConnected), RecvErrorAction::Fatal);
+        for code in INVALID_SOCKET_ERRORS {
+            let err = io::Error::from_raw_os_error(code);
+            assert_eq!(classify_recv_error(&err), RecvErrorAction::Fatal);
+        }
+    }
+
+    #[test]
+    fn positive_receive_after_interrupted_read() {
+        let peer = SocketAddr::from((Ipv4Addr::LOCALHOST, 1900));
+        let packet = b"NOTIFY * HTTP/1.1\r\nHOST: 239.255.255.250:1900\r\n\r\n".to_vec();
+        let source = ScriptedSource(Mutex::new(VecDeque::from([
+            Err(io::ErrorKind::Interrupted.into()),
+            Err(io::ErrorKind::ConnectionReset.into()),
+            Ok((packet, peer)),
+        ])));
+
+        let (send, recv) = mpsc::channel();
+        let (done, _) = mpsc::channel();
+        let threads = threads(done);
+
+        // Returns on the fatal error after the script, instead of spinning.
+        receive_packets::<NotifyMessage, _>(
+            SsdpContext::global(),
+            source,
+            MessageSender::Unbounded(send),
+            threads,
+            &SharedFilter::new(None),
+            Arc::new(AtomicBool::new(false)),
+        );
+
+        let messages: Vec<_> = recv.try_iter().collect();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].peer, peer);
+    }
+
+    #[test]
+    fn positive_raw_filter() {
+        let peer = SocketAddr::from((Ipv4Addr::LOCALHOST, 1900));
+        let notify = |nt: &str| {
+            format!("NOTIFY * HTTP/1.1\r\nHOST: 239.255.255.250:1900\r\nNT: {nt}\r\nNTS: ssdp:alive\r\n\r\n")
+        };
+        let renderer = "urn:schemas-upnp-org:device:MediaRenderer:1";
+        let source = ScriptedSource(Mutex::new(VecDeque::from([
+            Ok((notify("upnp:rootdevice").into_bytes(), peer)),
+            Ok((notify(renderer).into_bytes(), peer)),
+            Ok((notify("urn:schemas-upnp-org:device:MediaServer:1").into_bytes(), peer)),
+        ])));
+
+        let context = SsdpContext::new();
+        let (send, recv) = mpsc::channel();
+        let (done, _) = mpsc::channel();
+        let threads = Threads {
+            raw_filter: Some(RawFilter::new(move |raw, _| raw.header("nt") == Some(renderer.as_bytes()))),
+            ..threads(done)
+        };
+
+        receive_packets::<NotifyMessage, _>(
+            &context,
+            source,
+            MessageSender::Unbounded(send),
+            threads,
+            &SharedFilter::new(None),
+            Arc::new(AtomicBool::new(false)),
+        );
+
+        let messages: Vec<_> = recv.try_iter().collect();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].message.headers()["nt"], renderer);
+        assert_eq!(context.stats().packets_filtered(), 2);
+    }
+
+    #[test]
+    #[cfg(feature = "tracing")]
+    fn positive_receiver_spans() {
+        let peer = SocketAddr::from((Ipv4Addr::LOCALHOST, 1900));
+        let packet = b"NOTIFY * HTTP/1.1\r\nHOST: 239.255.255.250:1900\r\n\r\n".to_vec();
+        let source = ScriptedSource(Mutex::new(VecDeque::from([Ok((packet, peer))])));
+
+        let (send, _recv) = mpsc::channel();
+        let (done, _) = mpsc::channel();
+        let threads = threads(done);
+
+        let spans = crate::testing::capture_spans(|| {
+            receive_packets::<NotifyMessage, _>(
+                SsdpContext::global(),
+                source,
+                MessageSender::Unbounded(send),
+                threads,
+                &SharedFilter::new(None),
+                Arc::new(AtomicBool::new(false)),
+            );
+        });
+
+        assert!(spans.contains("ssdp_receiver{interface=scripted}"), "{spans}");
+        assert!(spans.contains("ssdp_packet{peer=127.0.0.1:1900 bytes=49}"), "{spans}");
+    }
+
+    #[test]
+    fn negative_non_local_source() {
+        let local_sources = |addr: &str, prefix_len| {
+            let subnet = Subnet {
+                addr: addr.parse().unwrap(),
+                prefix_len,
+            };
+            ReceiverOptions {
+                local_sources: Some(vec![subnet].into()),
+                ..Default::default()
+            }
         };
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let packet = b"NOTIFY * HTTP/1.1\r\nHOST: 239.255.255.250:1900\r\n\r\n";
+        let context = SsdpContext::new();
+
+        // The loopback sender is not on the only local network, as if its address was spoofed.
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        sender.send_to(packet, socket.local_addr().unwrap()).unwrap();
+        let options = local_sources("192.0.2.2", 24);
+        let socks = vec![Arc::new(socket)];
+        let receiver = SSDPReceiver::<NotifyMessage>::with_context(&context, socks, None, &options).unwrap();
+        assert!(receiver.recv_timeout(Duration::from_millis(500)).is_err());
+        assert_eq!(context.stats().packets_filtered(), 1);
+
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        sender.send_to(packet, socket.local_addr().unwrap()).unwrap();
+        let options = local_sources("127.0.0.1", 8);
+        let socks = vec![Arc::new(socket)];
+        let receiver = SSDPReceiver::<NotifyMessage>::with_context(&context, socks, None, &options).unwrap();
+        assert!(receiver.recv_timeout(Duration::from_secs(5)).is_ok());
+        assert_eq!(context.stats().packets_filtered(), 1);
+    }
+
+    #[test]
+    fn positive_builder_dedup_by_usn() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        let receiver = SSDPReceiverBuilder::new()
+            .add_socket(Arc::new(socket))
+            .set_timeout(Duration::from_millis(500))
+            .set_capacity(8)
+            .set_dedup_by_usn(true)
+            .build::<IncomingMessage>()
+            .unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let respond = |usn: &str| {
+            let packet = format!("HTTP/1.1 200 OK\r\nUSN: uuid:{usn}\r\n\r\n");
+            sender.send_to(packet.as_bytes(), addr).unwrap();
+        };
+        respond("first");
+        respond("first");
+        respond("second");
+        sender.send_to(b"HTTP/1.1 200 OK\r\n\r\n", addr).unwrap();
+
+        // Notify messages announce changes of the device, so they are never duplicates.
+        let notify = b"NOTIFY * HTTP/1.1\r\nHOST: 192.168.1.1\r\nNTS: ssdp:alive\r\nUSN: uuid:first\r\n\r\n";
+        sender.send_to(notify, addr).unwrap();
+        sender.send_to(notify, addr).unwrap();
+
+        // Messages without a USN are never duplicates.
+        assert_eq!(receiver.into_iter().count(), 5);
+    }
+
+    #[test]
+    fn positive_seen_usns_forgotten() {
+        let now = Instant::now();
+        let usn = |index: usize| USN(FieldMap::uuid(format!("device-{index}")), None);
+        let mut seen = SeenUsns::default();
+
+        assert!(seen.insert(usn(0), now));
+        assert!(!seen.insert(usn(0), now + Duration::from_secs(1)));
+        assert!(seen.insert(usn(0), now + SEEN_USN_LIFETIME));
+
+        for index in 1..=MAX_SEEN_USNS {
+            assert!(seen.insert(usn(index), now + SEEN_USN_LIFETIME));
+        }
+        assert_eq!(seen.usns.len(), MAX_SEEN_USNS);
+        assert_eq!(seen.order.len(), MAX_SEEN_USNS);
+        assert!(seen.insert(usn(0), now + SEEN_USN_LIFETIME));
+    }
+
+    #[test]
+    fn positive_keep_raw_packets() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        let receiver = SSDPReceiverBuilder::new()
+            .add_socket(Arc::new(socket))
+            .set_timeout(Duration::from_millis(300))
+            .set_keep_raw(true)
+            .build::<NotifyMessage>()
+            .unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let packet = b"NOTIFY * HTTP/1.1\r\nhost:239.255.255.250:1900\r\nX-Vendor: 1\r\n\r\n";
+        sender.send_to(packet, addr).unwrap();
+
+        let received: Vec<_> = receiver.into_detailed_iter().collect();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].raw.as_deref(), Some(&packet[..]));
+    }
+
+    /// Records the observed packets by their kind, address and length.
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<(&'static str, SocketAddr, usize)>>);
+
+    impl Recorder {
+        fn events(&self, kind: &str) -> Vec<(SocketAddr, usize)> {
+            let events = self.0.lock().unwrap();
+            events.iter().filter(|event| event.0 == kind).map(|event| (event.1, event.2)).collect()
+        }
+    }
+
+    impl SsdpObserver for Recorder {
+        fn on_send(&self, iface: &SocketAddr, bytes: &[u8]) {
+            self.0.lock().unwrap().push(("send", *iface, bytes.len()));
+        }
+
+        fn on_recv(&self, peer: &SocketAddr, bytes: &[u8]) {
+            self.0.lock().unwrap().push(("recv", *peer, bytes.len()));
+        }
+
+        fn on_parse_error(&self, peer: &SocketAddr, _: &SSDPError) {
+            self.0.lock().unwrap().push(("parse error", *peer, 0));
+        }
+    }
+
+    #[test]
+    fn positive_observer_roundtrip() {
+        let recorder = Arc::new(Recorder::default());
+        let config = Config::new().set_observer(recorder.clone());
+        let context = SsdpContext::new();
+
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        let options = ReceiverOptions::from_config(&config);
+        let timeout = Some(Duration::from_millis(300));
+        let socks = vec![Arc::new(socket)];
+        let receiver = SSDPReceiver::<NotifyMessage>::with_context(&context, socks, timeout, &options);
+        let receiver = receiver.unwrap();
+
+        let connector = UdpConnector::new(("127.0.0.1", 0), 0, &Default::default())
+            .unwrap()
+            .with_observer(config.observer.clone());
+        let local = connector.local_addr().unwrap();
+        let garbage = b"NOTIFY";
+        let packet = b"NOTIFY * HTTP/1.1\r\nHOST: 239.255.255.250:1900\r\n\r\n";
+        for datagram in [&garbage[..], &packet[..]] {
+            let mut stream = connector.connect_addr(addr).unwrap();
+            stream.write_all(datagram).unwrap();
+            stream.flush().unwrap();
+        }
+
+        assert_eq!(receiver.into_iter().count(), 1);
+        assert_eq!(recorder.events("send"), vec![(local, garbage.len()), (local, packet.len())]);
+        assert_eq!(recorder.events("recv"), vec![(local, garbage.len()), (local, packet.len())]);
+        assert_eq!(recorder.events("parse error"), vec![(local, 0)]);
+    }
+
+    #[test]
+    fn positive_set_filter() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        let receiver = SSDPReceiverBuilder::new()
+            .add_socket(Arc::new(socket))
+            .set_timeout(Duration::from_millis(300))
+            .build::<NotifyMessage>()
+            .unwrap();
+        receiver.set_filter(Arc::new(|notify: &NotifyMessage, _: &SocketAddr| {
+            notify.headers().contains_key("nt")
+        }));
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let without_nt = b"NOTIFY * HTTP/1.1\r\nHOST: 239.255.255.250:1900\r\n\r\n";
+        let with_nt = b"NOTIFY * HTTP/1.1\r\nHOST: 239.255.255.250:1900\r\nNT: upnp:rootdevice\r\n\r\n";
+        for packet in [&without_nt[..], &with_nt[..], &without_nt[..]] {
+            sender.send_to(packet, addr).unwrap();
+        }
+
+        let filtered = Arc::clone(&receiver.filter);
+        let messages: Vec<_> = receiver.into_iter().collect();
+        assert_eq!(messages.len(), 1);
+        assert!(messages[0].0.headers().contains_key("nt"));
+        assert_eq!(filtered.dropped.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn positive_local_addrs() {
+        let sockets: Vec<_> = (0..2).map(|_| Arc::new(UdpSocket::bind("127.0.0.1:0").unwrap())).collect();
+        let addrs: Vec<_> = sockets.iter().map(|socket| socket.local_addr().unwrap()).collect();
+        let (receiver, attacher) = SSDPReceiver::<NotifyMessage>::with_attacher(
+            SsdpContext::global(),
+            vec![Arc::clone(&sockets[0])],
+            Some(Duration::from_secs(5)),
+            &ReceiverOptions::default(),
+            None,
+        )
+        .unwrap();
+        assert_eq!(receiver.local_addrs(), vec![addrs[0]]);
+
+        let attached = attacher.attach(Arc::clone(&sockets[1])).unwrap();
+        assert_eq!(receiver.local_addrs(), addrs);
+
+        drop(attached);
+        assert_eq!(receiver.local_addrs(), vec![addrs[0]]);
+    }
+
+    #[test]
+    fn negative_builder_without_sockets() {
+        let result = SSDPReceiverBuilder::new()
+            .set_timeout(Duration::from_secs(1))
+            .build::<NotifyMessage>();
+
+        assert!(matches!(result, Err(SSDPError::Io(ref err)) if err.kind() == io::ErrorKind::InvalidInput));
+    }
+
+    #[test]
+    fn positive_take_until_releases_socket() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        let receiver = SSDPReceiver::<NotifyMessage>::new(vec![Arc::new(socket)], None).unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        for _ in 0..3 {
+            let packet = b"NOTIFY * HTTP/1.1\r\nHOST: 239.255.255.250:1900\r\n\r\n";
+            sender.send_to(packet, addr).unwrap();
+        }
+
+        // Without a timeout this only ends because the receiver is cancelled.
+        assert_eq!(receiver.take_until(2).count(), 2);
+        assert!(UdpSocket::bind(addr).is_ok());
+    }
+
+    #[test]
+    fn positive_first() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        let receiver = SSDPReceiver::<NotifyMessage>::new(vec![Arc::new(socket)], None).unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let packet = b"NOTIFY * HTTP/1.1\r\nHOST: 239.255.255.250:1900\r\n\r\n";
+        sender.send_to(packet, addr).unwrap();
+
+        let (_, src) = receiver.first().unwrap();
+        assert_eq!(src, sender.local_addr().unwrap());
+        assert!(UdpSocket::bind(addr).is_ok());
+    }
+
+    #[test]
+    fn negative_first_timeout() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let receiver =
+            SSDPReceiver::<NotifyMessage>::new(vec![Arc::new(socket)], Some(Duration::from_millis(200)))
+                .unwrap();
+
+        assert!(receiver.first().is_none());
     }
 }
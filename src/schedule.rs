@@ -0,0 +1,576 @@
+//! Sending prepared packets at a later time.
+
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender, TryRecvError};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::context::{SsdpContext, SsdpObserver};
+use crate::error::{SSDPError, SSDPResult};
+use crate::message::Config;
+use crate::net::sender::{self, RetryPolicy, SendTo};
+
+/// The socket a scheduled packet is sent through.
+pub type ConnectorRef = Arc<UdpSocket>;
+
+/// Longest time the sending thread waits before consulting the clock again.
+///
+/// The clock may be replaced by one that does not advance in real time, so the thread can not
+/// rely on sleeping exactly until the next deadline.
+const MAX_WAIT: Duration = Duration::from_millis(100);
+
+/// Sends prepared packets at a given time unless they were cancelled.
+///
+/// All packets are sent by a single background thread, which is spawned by the context and
+/// waits for the earliest deadline according to the context's clock. A send that failed with a
+/// transient error is scheduled again as the `RetryPolicy` says, without blocking the thread. The
+/// number of packets waiting to be sent is bounded, scheduling more fails instead of blocking.
+/// Dropping the sender discards all packets that were not sent yet.
+pub struct ScheduledSender {
+    shared: Arc<Shared>,
+}
+
+/// A packet that was scheduled to be sent.
+///
+/// Dropping the handle does not cancel the packet.
+pub struct ScheduleHandle {
+    id: u64,
+    shared: Arc<Shared>,
+    done: Receiver<io::Result<()>>,
+}
+
+struct Shared {
+    context: SsdpContext,
+    capacity: usize,
+    retry: RetryPolicy,
+    observer: Option<Arc<dyn SsdpObserver>>,
+    state: Mutex<State>,
+    wakeup: Condvar,
+}
+
+#[derive(Default)]
+struct State {
+    queue: BinaryHeap<Reverse<Entry>>,
+    next_id: u64,
+    shutdown: bool,
+}
+
+struct Entry {
+    at: Instant,
+    id: u64,
+    packet: Arc<[u8]>,
+    dst: SocketAddr,
+    via: Arc<dyn SendTo + Send + Sync>,
+    /// The number of sends that failed so far.
+    failed: u32,
+    done: Sender<io::Result<()>>,
+}
+
+impl ScheduledSender {
+    /// Construct a sender holding at most `capacity` packets that were not sent yet.
+    pub fn new(capacity: usize) -> ScheduledSender {
+        ScheduledSender::with_context(SsdpContext::global(), capacity)
+    }
+
+    /// Construct a sender whose thread is spawned by the context and follows its clock.
+    pub(crate) fn with_context(context: &SsdpContext, capacity: usize) -> ScheduledSender {
+        ScheduledSender::with_config(context, capacity, &Config::default())
+    }
+
+    /// Construct a sender that retries sends and reports them to the observer as the config says.
+    pub(crate) fn with_config(context: &SsdpContext, capacity: usize, config: &Config) -> ScheduledSender {
+        let shared = Arc::new(Shared {
+            context: context.clone(),
+            capacity,
+            retry: config.send_retry,
+            observer: config.observer.clone(),
+            state: Mutex::new(State::default()),
+            wakeup: Condvar::new(),
+        });
+
+        let thread_shared = Arc::clone(&shared);
+        context.spawn(move || thread_shared.run());
+
+        ScheduledSender { shared }
+    }
+
+    /// Send the packet to the destination through the socket once the clock reached the time.
+    ///
+    /// Fails with `SSDPError::QueueFull` if the capacity of the sender is exhausted.
+    pub fn schedule(
+        &self,
+        packet: Arc<[u8]>,
+        dst: SocketAddr,
+        via: ConnectorRef,
+        at: Instant,
+    ) -> SSDPResult<ScheduleHandle> {
+        self.schedule_via(packet, dst, via, at)
+    }
+
+    /// Schedule the packet like `schedule`, through any socket.
+    pub(crate) fn schedule_via(
+        &self,
+        packet: Arc<[u8]>,
+        dst: SocketAddr,
+        via: Arc<dyn SendTo + Send + Sync>,
+        at: Instant,
+    ) -> SSDPResult<ScheduleHandle> {
+        let mut state = self.shared.state.lock().unwrap();
+
+        if state.queue.len() >= self.shared.capacity {
+            return Err(SSDPError::QueueFull);
+        }
+
+        let id = state.next_id;
+        state.next_id += 1;
+
+        let (done_send, done) = mpsc::channel();
+        state.queue.push(Reverse(Entry {
+            at,
+            id,
+            packet,
+            dst,
+            via,
+            failed: 0,
+            done: done_send,
+        }));
+
+        self.shared.wakeup.notify_one();
+
+        Ok(ScheduleHandle {
+            id,
+            shared: Arc::clone(&self.shared),
+            done,
+        })
+    }
+
+    /// Get the number of packets that were not sent yet.
+    pub fn pending(&self) -> usize {
+        self.shared.state.lock().unwrap().queue.len()
+    }
+
+    /// Cancel all packets that were not sent yet, returning their number.
+    pub(crate) fn cancel_all(&self) -> usize {
+        let mut state = self.shared.state.lock().unwrap();
+        let cancelled = state.queue.len();
+
+        state.queue.clear();
+        cancelled
+    }
+}
+
+impl Drop for ScheduledSender {
+    fn drop(&mut self) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.shutdown = true;
+        state.queue.clear();
+        self.shared.wakeup.notify_one();
+    }
+}
+
+impl ScheduleHandle {
+    /// Cancel sending the packet.
+    ///
+    /// Returns `false` if the packet was already sent or discarded.
+    pub fn cancel(&self) -> bool {
+        let mut state = self.shared.state.lock().unwrap();
+        let before = state.queue.len();
+
+        state.queue.retain(|entry| entry.0.id != self.id);
+        state.queue.len() != before
+    }
+
+    /// Non-blocking method that checks whether the packet was sent.
+    ///
+    /// Returns `None` while the packet is waiting, and also if it was cancelled or discarded.
+    pub fn try_wait(&self) -> Option<io::Result<()>> {
+        match self.done.try_recv() {
+            Ok(result) => Some(result),
+            Err(TryRecvError::Empty) | Err(TryRecvError::Disconnected) => None,
+        }
+    }
+
+    /// Blocking method that waits until the packet was sent.
+    ///
+    /// Returns `None` if the packet was cancelled or discarded.
+    pub fn wait(&self) -> Option<io::Result<()>> {
+        self.done.recv().ok()
+    }
+
+    /// Blocking method that waits until the packet was sent or the timeout elapsed.
+    ///
+    /// Returns `None` if the packet was cancelled, discarded or the timeout elapsed.
+    pub fn wait_timeout(&self, timeout: Duration) -> Option<io::Result<()>> {
+        match self.done.recv_timeout(timeout) {
+            Ok(result) => Some(result),
+            Err(RecvTimeoutError::Timeout) | Err(RecvTimeoutError::Disconnected) => None,
+        }
+    }
+}
+
+impl Shared {
+    /// Send the queued packets in order of their deadlines until shut down.
+    fn run(&self) {
+        let mut state = self.state.lock().unwrap();
+
+        loop {
+            if state.shutdown {
+                return;
+            }
+
+            let now = self.context.now();
+            let wait = match state.queue.peek() {
+                Some(entry) if entry.0.at <= now => {
+                    let Some(Reverse(entry)) = state.queue.pop() else {
+                        continue;
+                    };
+
+                    // Do not hold the lock while sending, such that scheduling does not block.
+                    drop(state);
+                    self.send(entry);
+                    state = self.state.lock().unwrap();
+                    continue;
+                }
+                Some(entry) => (entry.0.at - now).min(MAX_WAIT),
+                None => MAX_WAIT,
+            };
+
+            state = self.wakeup.wait_timeout(state, wait).unwrap().0;
+        }
+    }
+
+    fn send(&self, mut entry: Entry) {
+        // The same path as an immediate send through `UdpSender`, but without waiting to retry.
+        let result = match sender::send_once(&*entry.via, &entry.packet, entry.dst) {
+            Ok(_) => {
+                sender::observe_send(self.observer.as_ref(), &*entry.via, &entry.packet);
+                Ok(())
+            }
+            Err(err) => {
+                entry.failed += 1;
+                if let Some(delay) = self.retry.retry_delay(entry.failed, &err) {
+                    trace!("Retrying scheduled packet to {} in {:?} after {}", entry.dst, delay, err);
+                    entry.at = self.context.now() + delay;
+                    let mut state = self.state.lock().unwrap();
+                    if !state.shutdown {
+                        state.queue.push(Reverse(entry));
+                    }
+                    return;
+                }
+
+                Err(sender::send_error(err, &*entry.via, entry.dst))
+            }
+        };
+
+        match result {
+            Ok(()) => self.context.stats().add_message_sent(),
+            Err(ref err) => debug!("Failed to send scheduled packet to {}: {}", entry.dst, err),
+        }
+
+        // The handle may have been dropped, no one is interested in the result then.
+        let _ = entry.done.send(result);
+    }
+}
+
+impl PartialEq for Entry {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Entry {}
+
+impl PartialOrd for Entry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Entry {
+    /// Order by deadline, packets with the same deadline are sent in the order they were scheduled.
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.at, self.id).cmp(&(other.at, other.id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::net::{SocketAddr, UdpSocket};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+
+    use super::ScheduledSender;
+    use crate::context::{SsdpContext, SsdpObserver, Task};
+    use crate::message::Config;
+    use crate::net::sender::{RetryPolicy, SendTo};
+    use crate::SSDPError;
+
+    /// A clock that only advances when told to.
+    #[derive(Clone)]
+    struct FakeClock(Arc<Mutex<Instant>>);
+
+    impl FakeClock {
+        fn advance(&self, duration: Duration) {
+            *self.0.lock().unwrap() += duration;
+        }
+
+        fn at(&self, offset: u64) -> Instant {
+            *self.0.lock().unwrap() + Duration::from_secs(offset)
+        }
+    }
+
+    fn setup() -> (SsdpContext, FakeClock, Arc<AtomicUsize>) {
+        let clock = FakeClock(Arc::new(Mutex::new(Instant::now())));
+        let threads = Arc::new(AtomicUsize::new(0));
+
+        let thread_clock = clock.clone();
+        let thread_count = Arc::clone(&threads);
+        let context = SsdpContext::new()
+            .set_clock(move || *thread_clock.0.lock().unwrap())
+            .set_spawner(move |task: Task| {
+                thread_count.fetch_add(1, Ordering::Relaxed);
+                std::thread::spawn(task);
+            });
+
+        (context, clock, threads)
+    }
+
+    fn sockets() -> (Arc<UdpSocket>, UdpSocket, SocketAddr) {
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let dst = receiver.local_addr().unwrap();
+
+        (Arc::new(sender), receiver, dst)
+    }
+
+    fn recv(receiver: &UdpSocket) -> Vec<u8> {
+        let mut buffer = [0; 64];
+        let (len, _) = receiver.recv_from(&mut buffer).unwrap();
+        buffer[..len].to_vec()
+    }
+
+    #[test]
+    fn positive_ordering() {
+        let (context, clock, threads) = setup();
+        let (via, receiver, dst) = sockets();
+        let scheduler = ScheduledSender::with_context(&context, 16);
+
+        let late = scheduler
+            .schedule(Arc::from(&b"late"[..]), dst, via.clone(), clock.at(2))
+            .unwrap();
+        let early = scheduler
+            .schedule(Arc::from(&b"early"[..]), dst, via.clone(), clock.at(1))
+            .unwrap();
+        let tie = scheduler
+            .schedule(Arc::from(&b"tie"[..]), dst, via, clock.at(1))
+            .unwrap();
+
+        assert!(early.wait_timeout(Duration::from_millis(300)).is_none());
+
+        clock.advance(Duration::from_secs(1));
+        assert!(early.wait().unwrap().is_ok());
+        assert!(tie.wait().unwrap().is_ok());
+        assert!(late.try_wait().is_none());
+
+        clock.advance(Duration::from_secs(1));
+        assert!(late.wait().unwrap().is_ok());
+
+        assert_eq!(recv(&receiver), b"early");
+        assert_eq!(recv(&receiver), b"tie");
+        assert_eq!(recv(&receiver), b"late");
+        assert_eq!(threads.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn positive_cancel() {
+        let (context, clock, _) = setup();
+        let (via, receiver, dst) = sockets();
+        let scheduler = ScheduledSender::with_context(&context, 16);
+
+        let cancelled = scheduler
+            .schedule(Arc::from(&b"cancelled"[..]), dst, via.clone(), clock.at(1))
+            .unwrap();
+        let sent = scheduler
+            .schedule(Arc::from(&b"sent"[..]), dst, via, clock.at(1))
+            .unwrap();
+
+        assert!(cancelled.cancel());
+        assert!(!cancelled.cancel());
+        assert_eq!(scheduler.pending(), 1);
+
+        clock.advance(Duration::from_secs(1));
+        assert!(sent.wait().unwrap().is_ok());
+        assert!(cancelled.wait().is_none());
+        assert!(!sent.cancel());
+
+        assert_eq!(recv(&receiver), b"sent");
+    }
+
+    #[test]
+    fn positive_burst_one_thread() {
+        let (context, clock, threads) = setup();
+        let (via, receiver, dst) = sockets();
+        let scheduler = ScheduledSender::with_context(&context, 64);
+
+        let handles: Vec<_> = (0..64u8)
+            .map(|n| {
+                scheduler
+                    .schedule(Arc::from(&[n][..]), dst, via.clone(), clock.at(1))
+                    .unwrap()
+            })
+            .collect();
+
+        clock.advance(Duration::from_secs(1));
+        for handle in &handles {
+            assert!(handle.wait().unwrap().is_ok());
+        }
+
+        for n in 0..64u8 {
+            assert_eq!(recv(&receiver), [n]);
+        }
+
+        assert_eq!(threads.load(Ordering::Relaxed), 1);
+        assert_eq!(context.stats().messages_sent(), 64);
+    }
+
+    /// Fails with `WouldBlock` a number of times, then sends through the socket.
+    struct FlakySocket {
+        failures: AtomicUsize,
+        attempts: AtomicUsize,
+        socket: Arc<UdpSocket>,
+    }
+
+    impl SendTo for FlakySocket {
+        fn send_to(&self, buf: &[u8], dst: SocketAddr) -> io::Result<usize> {
+            self.attempts.fetch_add(1, Ordering::Relaxed);
+            match self.failures.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| n.checked_sub(1)) {
+                Ok(_) => Err(io::ErrorKind::WouldBlock.into()),
+                Err(_) => self.socket.send_to(buf, dst),
+            }
+        }
+    }
+
+    type Flaky = (ScheduledSender, FakeClock, Arc<FlakySocket>, UdpSocket, SocketAddr);
+
+    fn flaky_scheduler(failures: usize) -> Flaky {
+        let (context, clock, _) = setup();
+        let (via, receiver, dst) = sockets();
+        let config = Config::new().set_send_retry(RetryPolicy {
+            attempts: 3,
+            delay: Duration::from_secs(1),
+        });
+        let scheduler = ScheduledSender::with_config(&context, 16, &config);
+        let flaky = Arc::new(FlakySocket {
+            failures: AtomicUsize::new(failures),
+            attempts: AtomicUsize::new(0),
+            socket: via,
+        });
+
+        (scheduler, clock, flaky, receiver, dst)
+    }
+
+    #[test]
+    fn positive_retry_by_clock() {
+        let (scheduler, clock, flaky, receiver, dst) = flaky_scheduler(2);
+        let handle = scheduler
+            .schedule_via(Arc::from(&b"retried"[..]), dst, flaky.clone(), clock.at(0))
+            .unwrap();
+
+        // The retries wait for one and then two seconds of the clock, not of real time.
+        assert!(handle.wait_timeout(Duration::from_millis(300)).is_none());
+        assert_eq!(flaky.attempts.load(Ordering::Relaxed), 1);
+        clock.advance(Duration::from_secs(1));
+        assert!(handle.wait_timeout(Duration::from_millis(300)).is_none());
+        assert_eq!(flaky.attempts.load(Ordering::Relaxed), 2);
+        clock.advance(Duration::from_secs(2));
+
+        assert!(handle.wait().unwrap().is_ok());
+        assert_eq!(flaky.attempts.load(Ordering::Relaxed), 3);
+        assert_eq!(recv(&receiver), b"retried");
+    }
+
+    #[test]
+    fn negative_retry_exhausted() {
+        let (scheduler, clock, flaky, _receiver, dst) = flaky_scheduler(3);
+        let handle = scheduler
+            .schedule_via(Arc::from(&b"dropped"[..]), dst, flaky.clone(), clock.at(0))
+            .unwrap();
+
+        assert!(handle.wait_timeout(Duration::from_millis(300)).is_none());
+        clock.advance(Duration::from_secs(1));
+        assert!(handle.wait_timeout(Duration::from_millis(300)).is_none());
+        clock.advance(Duration::from_secs(2));
+
+        let err = handle.wait().unwrap().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+        assert_eq!(flaky.attempts.load(Ordering::Relaxed), 3);
+    }
+
+    /// Records the packets reported as sent.
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<(SocketAddr, Vec<u8>)>>);
+
+    impl SsdpObserver for Recorder {
+        fn on_send(&self, local: &SocketAddr, bytes: &[u8]) {
+            self.0.lock().unwrap().push((*local, bytes.to_vec()));
+        }
+    }
+
+    #[test]
+    fn positive_observed() {
+        let (context, clock, _) = setup();
+        let (via, receiver, dst) = sockets();
+        let recorder = Arc::new(Recorder::default());
+        let config = Config::new().set_observer(recorder.clone());
+        let scheduler = ScheduledSender::with_config(&context, 16, &config);
+
+        let handle = scheduler.schedule(Arc::from(&b"observed"[..]), dst, via.clone(), clock.at(0)).unwrap();
+        assert!(handle.wait().unwrap().is_ok());
+        assert_eq!(recv(&receiver), b"observed");
+
+        let local = via.local_addr().unwrap();
+        assert_eq!(*recorder.0.lock().unwrap(), vec![(local, b"observed".to_vec())]);
+    }
+
+    #[test]
+    fn negative_packet_too_large() {
+        let (context, clock, _) = setup();
+        let (via, _receiver, dst) = sockets();
+        let recorder = Arc::new(Recorder::default());
+        let config = Config::new().set_observer(recorder.clone());
+        let scheduler = ScheduledSender::with_config(&context, 16, &config);
+
+        // Larger than any UDP datagram, the OS rejects it with EMSGSIZE.
+        let packet: Arc<[u8]> = vec![0; 70_000].into();
+        let handle = scheduler.schedule(packet, dst, via, clock.at(0)).unwrap();
+
+        let err = handle.wait().unwrap().unwrap_err();
+        match SSDPError::from(err) {
+            SSDPError::PacketTooLarge { size: 70_000, limit: None } => {}
+            other => panic!("Unexpected error {:?}", other),
+        }
+        assert!(recorder.0.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn negative_queue_full() {
+        let (context, clock, _) = setup();
+        let (via, _receiver, dst) = sockets();
+        let scheduler = ScheduledSender::with_context(&context, 1);
+
+        scheduler
+            .schedule(Arc::from(&b"first"[..]), dst, via.clone(), clock.at(1))
+            .unwrap();
+
+        match scheduler.schedule(Arc::from(&b"second"[..]), dst, via, clock.at(1)) {
+            Err(SSDPError::QueueFull) => {}
+            _ => panic!("Expected the queue to be full"),
+        }
+    }
+}
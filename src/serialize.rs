@@ -0,0 +1,265 @@
+//! Serialization of messages with `serde`.
+//!
+//! A message is represented by its headers, a list of name and value pairs in the order of the
+//! header map, and by the status code if it is a response. The kind of message is implied by the
+//! type that is deserialized. `FieldMap` is represented by its text, as in a header.
+
+use std::borrow::Cow;
+
+use headers::{CacheControl, Header, HeaderMap, HeaderMapExt as _, HeaderName, HeaderValue, Location};
+use serde::de::{Deserializer, Error as _};
+use serde::ser::{Error as _, Serializer};
+use serde::{Deserialize, Serialize};
+
+use crate::header::{BootID, ConfigID, Man, NextBootID, Nls, Opt, SearchPort, MX, NT, NTS, ST, USN};
+use crate::message::ssdp::SSDPMessage;
+use crate::message::MessageType;
+use crate::{FieldMap, SSDPError};
+
+#[derive(Serialize)]
+struct MessageRef<'a> {
+    headers: Vec<(&'a str, &'a str)>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status_code: Option<u16>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MessageData {
+    headers: Vec<(String, String)>,
+    #[serde(default)]
+    status_code: Option<u16>,
+}
+
+impl Serialize for SSDPMessage {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let headers = self
+            .headers()
+            .iter()
+            .map(|(name, value)| {
+                let value = value
+                    .to_str()
+                    .map_err(|_| S::Error::custom(format!("header {} is not a string", name)))?;
+                Ok((name.as_str(), value))
+            })
+            .collect::<Result<_, S::Error>>()?;
+
+        let status_code = match self.message_type() {
+            MessageType::Response => Some(self.status_code()),
+            MessageType::Notify | MessageType::Search => None,
+        };
+
+        MessageRef { headers, status_code }.serialize(serializer)
+    }
+}
+
+/// Deserialize a message of the given type, validating the headers known to this crate.
+pub(crate) fn deserialize_message<'de, D>(
+    deserializer: D,
+    method: MessageType,
+) -> Result<SSDPMessage, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let data = MessageData::deserialize(deserializer)?;
+
+    let status_code = match (method, data.status_code) {
+        (MessageType::Response, code) => code.unwrap_or(200),
+        (_, None) => 200,
+        (_, Some(_)) => return Err(D::Error::custom("a request has no status code")),
+    };
+
+    let mut headers = HeaderMap::new();
+    for (name, value) in data.headers {
+        let invalid = || SSDPError::InvalidHeader {
+            name: name.clone(),
+            value: value.clone().into_bytes(),
+        };
+
+        let key = HeaderName::from_bytes(name.as_bytes()).map_err(|_| D::Error::custom(invalid()))?;
+        let value = HeaderValue::from_str(&value).map_err(|_| D::Error::custom(invalid()))?;
+        headers.append(key, value);
+    }
+
+    validate_headers(&headers).map_err(D::Error::custom)?;
+    Ok(SSDPMessage::from_parts(method, headers, status_code))
+}
+
+/// Check that the headers with a typed representation can be decoded.
+fn validate_headers(headers: &HeaderMap) -> Result<(), SSDPError> {
+    fn decodes<H: Header>(headers: &HeaderMap) -> Result<(), SSDPError> {
+        match headers.typed_try_get::<H>() {
+            Ok(_) => Ok(()),
+            Err(_) => Err(SSDPError::InvalidHeader {
+                name: H::name().to_string(),
+                value: headers
+                    .get(H::name())
+                    .map_or(vec![], |value| value.as_bytes().to_vec()),
+            }),
+        }
+    }
+
+    decodes::<USN>(headers)?;
+    decodes::<ST>(headers)?;
+    decodes::<NT>(headers)?;
+    decodes::<NTS>(headers)?;
+    decodes::<MX>(headers)?;
+    decodes::<Man>(headers)?;
+    decodes::<BootID>(headers)?;
+    decodes::<ConfigID>(headers)?;
+    decodes::<NextBootID>(headers)?;
+    decodes::<SearchPort>(headers)?;
+    decodes::<Nls>(headers)?;
+    decodes::<Opt>(headers)?;
+    decodes::<Location>(headers)?;
+    decodes::<CacheControl>(headers)?;
+    Ok(())
+}
+
+impl Serialize for FieldMap {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for FieldMap {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let text = Cow::<str>::deserialize(deserializer)?;
+        FieldMap::new(text.as_ref()).ok_or_else(|| D::Error::custom(format!("invalid field '{}'", text)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use headers::{CacheControl, Header as _, HeaderValue, Location};
+
+    use crate::header::{HeaderMut, ST, USN};
+    use crate::message::{Config, Ipv6Scope, LocationPolicy, NotifyMessage, SearchResponse};
+    use crate::net::IpVersionMode;
+    use crate::FieldMap;
+
+    fn response() -> SearchResponse {
+        let location = HeaderValue::from_static("http://192.168.1.2:8080/description.xml");
+
+        let mut response = SearchResponse::new();
+        response.set(USN(FieldMap::uuid("2fac1234-31f8-11b4-a222-08002b34c003"), None));
+        response.set(ST::All);
+        response.set(Location::decode(&mut [location].iter()).unwrap());
+        response.set(CacheControl::new().with_max_age(Duration::from_secs(1800)));
+        response
+    }
+
+    #[test]
+    fn positive_response_roundtrip() {
+        let response = response();
+
+        let json = serde_json::to_string(&response).unwrap();
+        let decoded: SearchResponse = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(decoded, response);
+    }
+
+    #[test]
+    fn positive_header_pairs() {
+        let json = serde_json::to_value(response()).unwrap();
+
+        assert_eq!(json["status_code"], 200);
+        assert_eq!(json["headers"][1], serde_json::json!(["st", "ssdp:all"]));
+    }
+
+    #[test]
+    fn negative_invalid_typed_header() {
+        let json = r#"{"headers": [["host", "239.255.255.250:1900"], ["nts", "ssdp:sleeping"]]}"#;
+
+        assert!(serde_json::from_str::<NotifyMessage>(json).is_err());
+    }
+
+    #[test]
+    fn negative_request_status_code() {
+        let json = r#"{"headers": [["host", "239.255.255.250:1900"]], "status_code": 200}"#;
+
+        assert!(serde_json::from_str::<NotifyMessage>(json).is_err());
+    }
+
+    #[test]
+    fn positive_config_defaults() {
+        let config: Config = serde_json::from_str(r#"{"port": 1901, "ignore_self": true}"#).unwrap();
+
+        assert_eq!(config.port, 1901);
+        assert!(config.ignore_self);
+        assert_eq!(config.ttl, Config::default().ttl);
+    }
+
+    #[test]
+    fn positive_config_table() {
+        let table = r#"
+            ipv4_addr = "239.255.255.251"
+            ipv6_addr = "ff02::c"
+            ipv6_scope = "SiteLocal"
+            port = 1901
+            ttl = 4
+            mode = "v6"
+            timeout_overhead = { secs = 2, nanos = 0 }
+            interfaces = ["fd00::2"]
+            receive_capacity = 64
+            multicast_loop = true
+            include_loopback = true
+            allow_global_v6 = true
+            reuse_port = false
+            bind_to_device = true
+            accept_only_local_sources = true
+            max_packets_per_second = 100
+            rate_limit_per_source = true
+            ignore_self = true
+            self_uuid = "2fac1234-31f8-11b4-a222-08002b34c003"
+
+            [parse_options]
+            max_headers = 16
+            lenient = true
+            location_policy = "SameSubnet"
+        "#;
+
+        let config: Config = toml::from_str(table).unwrap();
+
+        assert_eq!(config.ipv4_addr, "239.255.255.251");
+        assert_eq!(config.ipv6_multicast_addr(), "ff02::c");
+        assert_eq!(config.ipv6_scope, Ipv6Scope::SiteLocal);
+        assert_eq!(config.port, 1901);
+        assert_eq!(config.mode, IpVersionMode::V6Only);
+        assert_eq!(config.timeout_overhead, Duration::from_secs(2));
+        assert_eq!(config.interfaces, Some(vec!["fd00::2".parse().unwrap()]));
+        assert_eq!(config.max_packets_per_second, Some(100));
+        assert_eq!(config.parse_options.max_headers, 16);
+        assert_eq!(config.parse_options.location_policy, LocationPolicy::SameSubnet);
+        assert_eq!(config.self_uuid.as_deref(), Some("2fac1234-31f8-11b4-a222-08002b34c003"));
+    }
+
+    #[test]
+    fn positive_config_default_roundtrip() {
+        let config = Config::default();
+
+        let table = toml::to_string(&config).unwrap();
+        let decoded: Config = toml::from_str(&table).unwrap();
+
+        assert_eq!(format!("{:?}", decoded), format!("{:?}", config));
+    }
+
+    #[test]
+    fn negative_config_unknown_field() {
+        assert!(toml::from_str::<Config>("prot = 1901").is_err());
+        assert!(toml::from_str::<Config>("[parse_options]\nlenietn = true").is_err());
+        assert!(toml::from_str::<Config>("mode = \"V4Only\"").is_err());
+    }
+
+    #[test]
+    fn positive_field_map() {
+        let field = FieldMap::urn("schemas-upnp-org:device:MediaServer:1");
+
+        let json = serde_json::to_string(&field).unwrap();
+        assert_eq!(json, r#""urn:schemas-upnp-org:device:MediaServer:1""#);
+        assert_eq!(serde_json::from_str::<FieldMap>(&json).unwrap(), field);
+        assert!(serde_json::from_str::<FieldMap>(r#""no separator""#).is_err());
+    }
+}
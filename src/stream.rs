@@ -0,0 +1,231 @@
+//! Adapter consuming an `SSDPReceiver` as a `futures_core::Stream`.
+
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::task::{Context, Poll, Waker};
+
+use futures_core::Stream;
+
+use crate::receiver::SSDPReceiver;
+
+/// Number of messages a stream holds that were not polled yet.
+const STREAM_CAPACITY: usize = 64;
+
+/// A stream of the messages of an `SSDPReceiver`.
+///
+/// The stream ends once the receiver's timeout fired, or never if it has none. Dropping the stream
+/// stops the receiver.
+pub struct SSDPStream<T> {
+    shared: Arc<Shared<T>>,
+    /// Stops the socket threads of the receiver once set.
+    stopped: Arc<AtomicBool>,
+}
+
+struct Shared<T> {
+    state: Mutex<State<T>>,
+    /// Notified whenever a message was polled or the stream was dropped.
+    space: Condvar,
+}
+
+struct State<T> {
+    queue: VecDeque<(T, SocketAddr)>,
+    done: bool,
+    /// Set once the stream was dropped, no one polls the messages anymore.
+    closed: bool,
+    waker: Option<Waker>,
+}
+
+impl<T: Send + 'static> SSDPReceiver<T> {
+    /// Consume the receiver as a stream.
+    ///
+    /// A background thread, spawned by the context of the receiver, forwards the messages of the
+    /// receiver and wakes the task polling the stream. While the stream holds as many messages as
+    /// it can the thread waits for them to be polled, such that the receiver buffers the following
+    /// ones as its capacity allows.
+    pub fn into_stream(self) -> SSDPStream<T> {
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                queue: VecDeque::new(),
+                done: false,
+                closed: false,
+                waker: None,
+            }),
+            space: Condvar::new(),
+        });
+        let stopped = self.stop_flag();
+        let context = self.context().clone();
+
+        let thread_shared = Arc::clone(&shared);
+        context.spawn(move || {
+            for item in self {
+                let mut state = thread_shared.state.lock().unwrap();
+                while state.queue.len() >= STREAM_CAPACITY && !state.closed {
+                    state = thread_shared.space.wait(state).unwrap();
+                }
+
+                if state.closed {
+                    return;
+                }
+
+                state.queue.push_back(item);
+                state.wake();
+            }
+
+            let mut state = thread_shared.state.lock().unwrap();
+            state.done = true;
+            state.wake();
+        });
+
+        SSDPStream { shared, stopped }
+    }
+}
+
+impl<T> State<T> {
+    fn wake(&mut self) {
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl<T> Stream for SSDPStream<T> {
+    type Item = (T, SocketAddr);
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut state = self.shared.state.lock().unwrap();
+
+        if let Some(item) = state.queue.pop_front() {
+            self.shared.space.notify_one();
+            Poll::Ready(Some(item))
+        } else if state.done {
+            Poll::Ready(None)
+        } else {
+            state.waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+impl<T> Drop for SSDPStream<T> {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::Relaxed);
+        self.shared.state.lock().unwrap().closed = true;
+        self.shared.space.notify_one();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::poll_fn;
+    use std::net::{SocketAddr, UdpSocket};
+    use std::pin::Pin;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    use futures_core::Stream;
+    use futures_executor::block_on;
+
+    use super::{SSDPStream, STREAM_CAPACITY};
+    use crate::context::{SsdpContext, Task};
+    use crate::message::NotifyMessage;
+    use crate::receiver::{ReceiverOptions, SSDPReceiver};
+
+    const NOTIFY: &[u8] = b"NOTIFY * HTTP/1.1\r\nHOST: 239.255.255.250:1900\r\n\r\n";
+
+    type Notifies = (SSDPReceiver<NotifyMessage>, SocketAddr);
+
+    fn receiver(context: &SsdpContext, timeout: Option<Duration>) -> Notifies {
+        let socket = Arc::new(UdpSocket::bind("127.0.0.1:0").unwrap());
+        let addr = socket.local_addr().unwrap();
+        let options = ReceiverOptions::default();
+        let receiver = SSDPReceiver::with_context(context, vec![socket], timeout, &options).unwrap();
+
+        (receiver, addr)
+    }
+
+    fn count(mut stream: SSDPStream<NotifyMessage>) -> usize {
+        let mut received = 0;
+        while block_on(poll_fn(|cx| Pin::new(&mut stream).poll_next(cx))).is_some() {
+            received += 1;
+        }
+        received
+    }
+
+    #[test]
+    fn positive_collect_until_timeout() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        let receiver =
+            SSDPReceiver::<NotifyMessage>::new(vec![Arc::new(socket)], Some(Duration::from_millis(500)))
+                .unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        for _ in 0..2 {
+            let packet = b"NOTIFY * HTTP/1.1\r\nHOST: 239.255.255.250:1900\r\n\r\n";
+            sender.send_to(packet, addr).unwrap();
+        }
+
+        let mut stream = receiver.into_stream();
+        let mut received = 0;
+
+        while block_on(poll_fn(|cx| Pin::new(&mut stream).poll_next(cx))).is_some() {
+            received += 1;
+        }
+
+        assert_eq!(received, 2);
+    }
+
+    #[test]
+    fn positive_spawned_by_receiver_context() {
+        let spawned = Arc::new(AtomicUsize::new(0));
+        let thread_spawned = Arc::clone(&spawned);
+        let context = SsdpContext::new().set_spawner(move |task: Task| {
+            thread_spawned.fetch_add(1, Ordering::Relaxed);
+            std::thread::spawn(task);
+        });
+
+        let (receiver, _) = receiver(&context, Some(Duration::from_millis(100)));
+        let before = spawned.load(Ordering::Relaxed);
+        let stream = receiver.into_stream();
+
+        assert_eq!(spawned.load(Ordering::Relaxed), before + 1);
+        assert_eq!(count(stream), 0);
+    }
+
+    #[test]
+    fn positive_drop_stops_receiver() {
+        let (receiver, _) = receiver(&SsdpContext::new(), None);
+        let stopped = receiver.stop_flag();
+        let stream = receiver.into_stream();
+
+        assert!(!stopped.load(Ordering::Relaxed));
+        drop(stream);
+        assert!(stopped.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn positive_bounded_queue() {
+        let (receiver, addr) = receiver(&SsdpContext::new(), Some(Duration::from_secs(1)));
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        for _ in 0..STREAM_CAPACITY + 8 {
+            sender.send_to(NOTIFY, addr).unwrap();
+        }
+
+        let stream = receiver.into_stream();
+        let queued = || stream.shared.state.lock().unwrap().queue.len();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while queued() < STREAM_CAPACITY {
+            assert!(Instant::now() < deadline, "The stream did not fill up");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        // The remaining messages wait in the receiver until the stream is polled.
+        std::thread::sleep(Duration::from_millis(100));
+        assert_eq!(queued(), STREAM_CAPACITY);
+        assert_eq!(count(stream), STREAM_CAPACITY + 8);
+    }
+}
@@ -0,0 +1,184 @@
+//! Transports for testing code that sends SSDP messages.
+//!
+//! A `MockConnector` can be passed wherever a `NetworkConnector` is expected, for example to
+//! `SearchRequest::unicast_via`, and records the datagrams instead of sending them.
+//!
+//! Enabled by the `test-util` feature.
+
+use std::io::{self, ErrorKind, Read, Write};
+use std::mem;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::mpsc::{self, Receiver, Sender};
+
+use crate::net::{NetworkConnector, NetworkStream};
+
+/// A connector that records the datagrams sent through its streams.
+pub struct MockConnector {
+    sender: Sender<(SocketAddr, Vec<u8>)>,
+    receiver: Receiver<(SocketAddr, Vec<u8>)>,
+}
+
+impl MockConnector {
+    pub fn new() -> MockConnector {
+        let (sender, receiver) = mpsc::channel();
+        MockConnector { sender, receiver }
+    }
+
+    /// Take the datagrams sent since the last call, with their destination.
+    pub fn sent(&self) -> Vec<(SocketAddr, Vec<u8>)> {
+        self.receiver.try_iter().collect()
+    }
+}
+
+impl Default for MockConnector {
+    fn default() -> Self {
+        MockConnector::new()
+    }
+}
+
+impl NetworkConnector for MockConnector {
+    type Stream = MockStream;
+
+    fn connect(&self, host: &str, port: u16) -> io::Result<Self::Stream> {
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        let ip: IpAddr = host
+            .parse()
+            .map_err(|err| io::Error::new(ErrorKind::InvalidInput, err))?;
+
+        self.connect_addr(SocketAddr::new(ip, port))
+    }
+
+    fn connect_addr(&self, addr: SocketAddr) -> io::Result<Self::Stream> {
+        Ok(MockStream {
+            dst: addr,
+            buffer: Vec::new(),
+            sender: self.sender.clone(),
+        })
+    }
+}
+
+/// A stream of a `MockConnector`, which records a datagram on every flush.
+pub struct MockStream {
+    dst: SocketAddr,
+    buffer: Vec<u8>,
+    sender: Sender<(SocketAddr, Vec<u8>)>,
+}
+
+impl NetworkStream for MockStream {
+    fn peer_addr(&mut self) -> io::Result<SocketAddr> {
+        Ok(self.dst)
+    }
+}
+
+impl Read for MockStream {
+    fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+        Err(io::Error::new(ErrorKind::ConnectionAborted, "MockStream Can Not Be Read From"))
+    }
+}
+
+impl Write for MockStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+
+        // The connector may have been dropped, no one is interested in the datagram then.
+        let _ = self.sender.send((self.dst, mem::take(&mut self.buffer)));
+        Ok(())
+    }
+}
+
+/// A context whose only interface is the IPv4 loopback, which can not reach other hosts.
+#[cfg(test)]
+pub(crate) fn loopback_context() -> crate::SsdpContext {
+    use std::net::Ipv4Addr;
+
+    use crate::net::NetifAddr;
+
+    crate::SsdpContext::new().set_interfaces(|| -> io::Result<Vec<NetifAddr>> {
+        Ok(vec![NetifAddr {
+            sock: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            index: 1,
+        }])
+    })
+}
+
+/// A response to `ssdp:all` from the device with the uuid.
+#[cfg(test)]
+pub(crate) fn search_response(uuid: &str) -> Vec<u8> {
+    format!(
+        "HTTP/1.1 200 OK\r\nCACHE-CONTROL: max-age=1800\r\nEXT:\r\n\
+         LOCATION: http://127.0.0.1/description.xml\r\nST: ssdp:all\r\nUSN: uuid:{uuid}\r\n\r\n"
+    )
+    .into_bytes()
+}
+
+/// Run the closure with a `tracing` subscriber and return its formatted output.
+///
+/// Entering a span is recorded as an event, such that the names and fields of spans show up.
+#[cfg(all(test, feature = "tracing"))]
+pub(crate) fn capture_spans(f: impl FnOnce()) -> String {
+    use std::sync::{Arc, Mutex};
+    use tracing_subscriber::fmt::format::FmtSpan;
+
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let captured = Captured::default();
+    let writer = captured.clone();
+    let subscriber = tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::TRACE)
+        .with_span_events(FmtSpan::ENTER)
+        .with_writer(move || writer.clone())
+        .finish();
+
+    tracing::subscriber::with_default(subscriber, f);
+    let lines = captured.0.lock().unwrap();
+    String::from_utf8_lossy(&lines).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write as _;
+    use std::net::SocketAddr;
+
+    use super::MockConnector;
+    use crate::net::NetworkConnector;
+
+    #[test]
+    fn positive_datagram_per_flush() {
+        let connector = MockConnector::new();
+        let mut stream = connector.connect("[::1]", 1900).unwrap();
+
+        stream.write_all(b"NOTIFY").unwrap();
+        stream.write_all(b" *").unwrap();
+        stream.flush().unwrap();
+        stream.flush().unwrap();
+
+        let dst: SocketAddr = "[::1]:1900".parse().unwrap();
+        assert_eq!(connector.sent(), vec![(dst, b"NOTIFY *".to_vec())]);
+        assert!(connector.sent().is_empty());
+    }
+
+    #[test]
+    fn negative_connect_host_name() {
+        let connector = MockConnector::new();
+
+        assert!(connector.connect("localhost", 1900).is_err());
+    }
+}
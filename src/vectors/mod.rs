@@ -0,0 +1,211 @@
+//! Canonical wire-format examples of SSDP messages.
+//!
+//! The packets are produced by the same serialization code that is used when sending, such that
+//! other implementations can check their parsers against exactly what this crate emits. A golden
+//! copy is checked by the tests of this module, so any change to the serialized form is visible.
+//!
+//! Enabled by the `vectors` feature.
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use headers::{CacheControl, HeaderValue, Location, Server};
+
+use crate::header::{BootID, ConfigID, Ext, HeaderMut, Man, MX, NT, NTS, ST, USN};
+use crate::message::ssdp::SSDPMessage;
+use crate::message::{MessageType, UPNP_MULTICAST_PORT};
+use crate::net::httpu::Style;
+use crate::FieldMap;
+
+/// The multicast group that requests are addressed to.
+fn multicast_group() -> SocketAddr {
+    SocketAddr::from(([239, 255, 255, 250], UPNP_MULTICAST_PORT))
+}
+
+/// Get the packets emitted by this crate, with a description of each.
+pub fn emitted() -> Vec<(&'static str, Vec<u8>)> {
+    vec![
+        ("minimal M-SEARCH for all devices and services", minimal_search()),
+        ("ssdp:alive of a root device with all UPnP 1.1 headers", alive()),
+        ("ssdp:byebye of a root device", byebye()),
+        ("ssdp:update of a root device", update()),
+        ("search response for a root device", response()),
+    ]
+}
+
+/// Get packets of devices that deviate from the canonical form but are still accepted.
+pub fn lenient() -> Vec<(&'static str, Vec<u8>)> {
+    vec![
+        (
+            "lowercase header names",
+            b"NOTIFY * HTTP/1.1\r\nhost: 239.255.255.250:1900\r\nnt: upnp:rootdevice\r\n\
+              nts: ssdp:alive\r\n\r\n"
+                .to_vec(),
+        ),
+        (
+            "missing and surplus whitespace after the colon",
+            b"NOTIFY * HTTP/1.1\r\nHOST:239.255.255.250:1900\r\nNT:   upnp:rootdevice\r\n\
+              NTS:ssdp:byebye\r\n\r\n"
+                .to_vec(),
+        ),
+        (
+            "LF-only line endings",
+            b"M-SEARCH * HTTP/1.1\nHOST: 239.255.255.250:1900\nMAN: \"ssdp:discover\"\nMX: 1\n\
+              ST: ssdp:all\n\n"
+                .to_vec(),
+        ),
+        (
+            "search response with an unknown vendor header",
+            b"HTTP/1.1 200 OK\r\nCACHE-CONTROL: max-age=1800\r\nEXT:\r\n\
+              X-VENDOR-EXTENSION: 1\r\nST: upnp:rootdevice\r\n\
+              USN: uuid:2fac1234-31f8-11b4-a222-08002b34c003::upnp:rootdevice\r\n\r\n"
+                .to_vec(),
+        ),
+    ]
+}
+
+/// Get all packets, the emitted ones first.
+pub fn all() -> Vec<(&'static str, Vec<u8>)> {
+    let mut vectors = emitted();
+    vectors.extend(lenient());
+    vectors
+}
+
+fn usn() -> USN {
+    USN(
+        FieldMap::uuid("2fac1234-31f8-11b4-a222-08002b34c003"),
+        Some(FieldMap::upnp("rootdevice")),
+    )
+}
+
+fn location() -> Location {
+    let value = HeaderValue::from_static("http://192.168.1.2:8080/description.xml");
+    headers::Header::decode(&mut [value].iter()).expect("valid location")
+}
+
+fn server() -> Server {
+    "Linux/5.10 UPnP/1.1 ssdp/0.7".parse().expect("valid server")
+}
+
+fn serialize(message: &SSDPMessage) -> Vec<u8> {
+    let packet = message.to_packet(multicast_group(), Style::default()).expect("vector fits into a packet");
+    packet.as_slice().to_vec()
+}
+
+fn minimal_search() -> Vec<u8> {
+    let mut message = SSDPMessage::new(MessageType::Search);
+    message.set(Man);
+    message.set(MX(1));
+    message.set(ST::All);
+    serialize(&message)
+}
+
+fn alive() -> Vec<u8> {
+    let mut message = SSDPMessage::new(MessageType::Notify);
+    message.set(CacheControl::new().with_max_age(Duration::from_secs(1800)));
+    message.set(location());
+    message.set(NT(FieldMap::upnp("rootdevice")));
+    message.set(NTS::Alive);
+    message.set(server());
+    message.set(usn());
+    message.set(BootID(1));
+    message.set(ConfigID(1));
+    serialize(&message)
+}
+
+fn byebye() -> Vec<u8> {
+    let mut message = SSDPMessage::new(MessageType::Notify);
+    message.set(NT(FieldMap::upnp("rootdevice")));
+    message.set(NTS::ByeBye);
+    message.set(usn());
+    message.set(BootID(1));
+    message.set(ConfigID(1));
+    serialize(&message)
+}
+
+fn update() -> Vec<u8> {
+    let mut message = SSDPMessage::new(MessageType::Notify);
+    message.set(location());
+    message.set(NT(FieldMap::upnp("rootdevice")));
+    message.set(NTS::Update);
+    message.set(usn());
+    message.set(BootID(1));
+    message.set(ConfigID(1));
+    serialize(&message)
+}
+
+fn response() -> Vec<u8> {
+    let mut message = SSDPMessage::new(MessageType::Response);
+    message.set(CacheControl::new().with_max_age(Duration::from_secs(1800)));
+    message.set(Ext);
+    message.set(location());
+    message.set(server());
+    message.set(ST::Target(FieldMap::upnp("rootdevice")));
+    message.set(usn());
+    message.set(BootID(1));
+    message.set(ConfigID(1));
+    serialize(&message)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fmt::Write as _;
+    use std::net::SocketAddr;
+
+    use crate::message::ssdp::SSDPMessage;
+    use crate::net::httpu::Style;
+    use crate::receiver::FromRawSSDP;
+
+    /// The expected serialization of all vectors, see `render`.
+    const GOLDEN: &str = include_str!("golden.txt");
+
+    /// Render the vectors as text, one escaped packet per description.
+    fn render(vectors: &[(&'static str, Vec<u8>)]) -> String {
+        let mut text = String::new();
+
+        for (description, bytes) in vectors {
+            let _ = writeln!(text, "# {}", description);
+            let _ = writeln!(text, "{}", bytes.escape_ascii());
+        }
+
+        text
+    }
+
+    fn reserialize(message: &SSDPMessage, bytes: &[u8]) -> Vec<u8> {
+        let host = std::str::from_utf8(bytes)
+            .unwrap()
+            .lines()
+            .find_map(|line| line.strip_prefix("HOST: "))
+            .map(|host| host.trim().parse::<SocketAddr>().unwrap())
+            .unwrap_or_else(super::multicast_group);
+
+        message.to_packet(host, Style::default()).unwrap().as_slice().to_vec()
+    }
+
+    #[test]
+    fn positive_golden() {
+        assert_eq!(render(&super::all()), GOLDEN);
+    }
+
+    #[test]
+    fn positive_emitted_roundtrip() {
+        for (description, bytes) in super::emitted() {
+            let message = SSDPMessage::from_packet(&bytes).expect(description);
+
+            assert_eq!(reserialize(&message, &bytes), bytes, "{}", description);
+        }
+    }
+
+    #[test]
+    fn positive_lenient_equivalent() {
+        for (description, bytes) in super::lenient() {
+            let message = SSDPMessage::from_packet(&bytes).expect(description);
+            let canonical = SSDPMessage::from_packet(&reserialize(&message, &bytes)).expect(description);
+
+            assert_eq!(canonical.message_type(), message.message_type(), "{}", description);
+            for (name, value) in message.headers() {
+                assert_eq!(canonical.headers().get(name), Some(value), "{}", description);
+            }
+        }
+    }
+}